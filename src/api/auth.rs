@@ -0,0 +1,51 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
+use axum::{extract::{Request, State}, middleware::Next, response::Response};
+use governor::{clock::{Clock, DefaultClock}, DefaultDirectRateLimiter, Quota, RateLimiter};
+use log::debug;
+
+use crate::config::ApiKeyConfig;
+
+use super::{error::ApiError, ApiState};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+struct Client {
+    name: String,
+    limiter: DefaultDirectRateLimiter,
+}
+
+/// The configured API keys, each with its own rate limiter.
+pub struct ApiKeys {
+    keys: HashMap<String, Client>,
+}
+
+impl ApiKeys {
+    pub fn new(config: &[ApiKeyConfig]) -> Self {
+        let keys = config
+            .iter()
+            .map(|k| {
+                let quota = Quota::per_minute(NonZeroU32::new(k.per_minute).unwrap_or(NonZeroU32::MIN));
+                (k.key.clone(), Client { name: k.name.clone(), limiter: RateLimiter::direct(quota) })
+            })
+            .collect();
+        Self { keys }
+    }
+
+    /// Name of the owner of `key`, if it is valid and within its rate limit.
+    pub fn check(&self, key: Option<&str>) -> Result<&str, ApiError> {
+        let client = key.and_then(|k| self.keys.get(k)).ok_or(ApiError::Unauthorized)?;
+        client.limiter
+            .check()
+            .map_err(|n| ApiError::RateLimited(n.wait_time_from(DefaultClock::default().now())))?;
+        Ok(&client.name)
+    }
+}
+
+/// Middleware refusing requests without a valid API key, or above its rate limit.
+pub async fn require_key(State(state): State<ApiState>, req: Request, next: Next) -> Result<Response, ApiError> {
+    let key = req.headers().get(API_KEY_HEADER).and_then(|k| k.to_str().ok());
+    let client = state.keys.check(key)?;
+    debug!("{} {} from {}", req.method(), req.uri(), client);
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,23 @@
+use log::{info, warn};
+use power_pizza_bot::{api, config::CONFIG, db, migrations};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pretty_env_logger::init();
+    let db = db::connect(&CONFIG.db);
+    info!("ensuring database indexes");
+    db.ensure_index().await?;
+    info!("applying database migrations");
+    migrations::run(db.as_ref()).await?;
+
+    if CONFIG.api.keys.is_empty() {
+        warn!("no API keys configured, every request will be refused");
+    }
+    let app = api::router(db, &CONFIG.api);
+    let listener = tokio::net::TcpListener::bind(&CONFIG.api.listen).await?;
+    info!("listening on {}", CONFIG.api.listen);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap_or_default() })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use axum::{http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+use log::error;
+
+use crate::bot::SearchError;
+use crate::db::StoreError;
+
+use super::types::ErrorResponse;
+
+#[derive(Debug)]
+pub enum ApiError {
+    Store(StoreError),
+    Search(SearchError),
+    NotFound,
+    BadRequest(&'static str),
+    Unauthorized,
+    /// Retry after the given time.
+    RateLimited(Duration),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Store(_) | Self::Search(SearchError::Store(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Search(SearchError::EpisodeNotFound(_) | SearchError::NoResults) | Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Search(SearchError::Regex(_)) | Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // don't leak database details
+            Self::Store(_) | Self::Search(SearchError::Store(_)) => write!(f, "Database error"),
+            Self::Search(SearchError::EpisodeNotFound(id)) => write!(f, "Episode {} not found", id),
+            Self::Search(SearchError::Regex(e)) => write!(f, "Invalid regex: {}", e),
+            Self::Search(SearchError::NoResults) => write!(f, "No results"),
+            Self::NotFound => write!(f, "Not found"),
+            Self::BadRequest(m) => write!(f, "{}", m),
+            Self::Unauthorized => write!(f, "Missing or invalid API key"),
+            Self::RateLimited(d) => write!(f, "Rate limit exceeded, retry in {} seconds", d.as_secs() + 1),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let Self::Store(e) | Self::Search(SearchError::Store(e)) = &self {
+            error!("database error: {}", e);
+        }
+        let body = Json(ErrorResponse { error: self.to_string() });
+        match self {
+            Self::RateLimited(d) => (self.status(), [(header::RETRY_AFTER, (d.as_secs() + 1).to_string())], body).into_response(),
+            _ => (self.status(), body).into_response(),
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<SearchError> for ApiError {
+    fn from(e: SearchError) -> Self {
+        Self::Search(e)
+    }
+}
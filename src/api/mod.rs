@@ -0,0 +1,155 @@
+mod auth;
+mod error;
+mod types;
+
+pub use auth::{ApiKeys, API_KEY_HEADER};
+pub use error::ApiError;
+pub use types::*;
+
+use std::{cmp::Reverse, sync::Arc};
+
+use axum::{extract::{Path, Query, State}, middleware, routing::get, Json, Router};
+use utoipa::{openapi::security::{ApiKey, ApiKeyValue, SecurityScheme}, Modify, OpenApi};
+
+use crate::bot::SearchError;
+use crate::config::ApiConfig;
+use crate::db::Store;
+use crate::spreaker::Episode;
+use crate::transcript::EpisodeTranscript;
+
+/// Minimum length of a search query, as in the bot.
+const MIN_QUERY_LEN: usize = 3;
+/// Snippets returned for each episode found by a transcript search.
+const SNIPPETS_PER_RESULT: usize = 2;
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub db: Arc<dyn Store>,
+    pub keys: Arc<ApiKeys>,
+}
+
+/// Routes of `ppp_api`. Everything under `/v1` requires an API key, `/openapi.json` is public.
+pub fn router(db: Arc<dyn Store>, config: &ApiConfig) -> Router {
+    let state = ApiState { db, keys: Arc::new(ApiKeys::new(&config.keys)) };
+    let v1 = Router::new()
+        .route("/episodes", get(list_episodes))
+        .route("/episodes/{id}", get(get_episode))
+        .route("/episodes/{id}/transcript", get(get_transcript))
+        .route("/episodes/{id}/search", get(search_episode))
+        .route("/search/meta", get(search_meta))
+        .route("/search/transcripts", get(search_transcripts))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_key))
+        .with_state(state);
+    Router::new()
+        .route("/openapi.json", get(|| async { Json(ApiDoc::openapi()) }))
+        .nest("/v1", v1)
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Power Pizza Bot API", description = "Episodes, transcripts and search of the Power Pizza podcast"),
+    paths(list_episodes, get_episode, get_transcript, search_episode, search_meta, search_transcripts),
+    modifiers(&ApiKeyAuth),
+    security(("api_key" = [])),
+)]
+pub struct ApiDoc;
+
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("api_key", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))));
+    }
+}
+
+fn check_query(q: &str) -> Result<(), ApiError> {
+    if q.chars().count() < MIN_QUERY_LEN {
+        Err(ApiError::BadRequest("The query must be at least 3 characters long"))
+    } else {
+        Ok(())
+    }
+}
+
+/// No results is an empty page, not an error.
+fn or_empty<T>(r: Result<Vec<T>, SearchError>) -> Result<Vec<T>, SearchError> {
+    match r {
+        Err(SearchError::NoResults) => Ok(vec![]),
+        r => r,
+    }
+}
+
+/// Episodes, most recent first.
+#[utoipa::path(get, path = "/v1/episodes", params(Pagination), responses(
+    (status = 200, body = Page<EpisodeSummary>),
+))]
+async fn list_episodes(State(s): State<ApiState>, Query(page): Query<Pagination>) -> Result<Json<Page<EpisodeSummary>>, ApiError> {
+    let mut episodes = s.db.get_all::<Episode>().await?;
+    episodes.sort_by_key(|e| Reverse(e.published_at));
+    Ok(Json(page.page(episodes, |e| (&e).into())))
+}
+
+#[utoipa::path(get, path = "/v1/episodes/{id}", params(("id" = u32, Path)), responses(
+    (status = 200, body = EpisodeDetail),
+    (status = 404, body = ErrorResponse),
+))]
+async fn get_episode(State(s): State<ApiState>, Path(id): Path<u32>) -> Result<Json<EpisodeDetail>, ApiError> {
+    let e = s.db.get::<Episode>(id).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json((&e).into()))
+}
+
+/// Full text of the transcript with the timestamps of its segments.
+#[utoipa::path(get, path = "/v1/episodes/{id}/transcript", params(("id" = u32, Path)), responses(
+    (status = 200, body = TranscriptResponse),
+    (status = 404, body = ErrorResponse),
+))]
+async fn get_transcript(State(s): State<ApiState>, Path(id): Path<u32>) -> Result<Json<TranscriptResponse>, ApiError> {
+    let t = s.db.get::<EpisodeTranscript>(id).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(t.into()))
+}
+
+/// Case insensitive regex search within the transcript of one episode, like `/sae` in the bot.
+#[utoipa::path(get, path = "/v1/episodes/{id}/search", params(("id" = u32, Path), SearchQuery, Pagination), responses(
+    (status = 200, body = EpisodeSearchResponse),
+    (status = 400, body = ErrorResponse),
+    (status = 404, body = ErrorResponse),
+))]
+async fn search_episode(
+    State(s): State<ApiState>,
+    Path(id): Path<u32>,
+    Query(q): Query<SearchQuery>,
+    Query(page): Query<Pagination>,
+) -> Result<Json<EpisodeSearchResponse>, ApiError> {
+    check_query(&q.q)?;
+    match s.db.search_transcript_one(id, q.q).await {
+        Ok(r) => Ok(Json(EpisodeSearchResponse::new(r, &page))),
+        Err(SearchError::NoResults) => {
+            let e = s.db.get::<Episode>(id).await?.ok_or(ApiError::NotFound)?;
+            Ok(Json(EpisodeSearchResponse { episode: (&e).into(), matches: page.page(Vec::<MatchResponse>::new(), |m| m) }))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Search episodes by title and description, like `/s` in the bot.
+#[utoipa::path(get, path = "/v1/search/meta", params(SearchQuery, Pagination), responses(
+    (status = 200, body = Page<EpisodeSummary>),
+    (status = 400, body = ErrorResponse),
+))]
+async fn search_meta(State(s): State<ApiState>, Query(q): Query<SearchQuery>, Query(page): Query<Pagination>) -> Result<Json<Page<EpisodeSummary>>, ApiError> {
+    check_query(&q.q)?;
+    let results = or_empty(s.db.search_meta(q.q).await)?;
+    Ok(Json(page.page(results, |r| (&r.episode).into())))
+}
+
+/// Full-text search across all transcripts, like `/sa` in the bot.
+#[utoipa::path(get, path = "/v1/search/transcripts", params(SearchQuery, Pagination), responses(
+    (status = 200, body = Page<TranscriptHitResponse>),
+    (status = 400, body = ErrorResponse),
+))]
+async fn search_transcripts(State(s): State<ApiState>, Query(q): Query<SearchQuery>, Query(page): Query<Pagination>) -> Result<Json<Page<TranscriptHitResponse>>, ApiError> {
+    check_query(&q.q)?;
+    let mut results = page.page(or_empty(s.db.search_transcript_all(q.q.clone()).await)?, |r| r);
+    s.db.add_snippets(&mut results.items, &q.q, SNIPPETS_PER_RESULT).await?;
+    Ok(Json(results.map(TranscriptHitResponse::from)))
+}
@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::bot::{EpisodeOffsetMatch, OffsetSearchResult, TranscriptSearchResult};
+use crate::spreaker::{Chapter, Episode};
+use crate::transcript::EpisodeTranscript;
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    /// Number of items to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// Items per page, 20 by default and at most 100.
+    pub limit: Option<usize>,
+}
+
+impl Pagination {
+    /// The requested page of `items`, converted with `f`.
+    pub fn page<T, U>(&self, items: Vec<T>, f: impl Fn(T) -> U) -> Page<U> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        Page {
+            total: items.len(),
+            offset: self.offset,
+            limit,
+            items: items.into_iter().skip(self.offset).take(limit).map(f).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Items across all the pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> Page<U> {
+        Page { items: self.items.into_iter().map(f).collect(), total: self.total, offset: self.offset, limit: self.limit }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Text to search, at least 3 characters. Meta search accepts `key:value` filters, in-episode search a regex.
+    pub q: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EpisodeSummary {
+    pub id: u32,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub duration_ms: u32,
+    pub url: String,
+    pub image_url: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl From<&Episode> for EpisodeSummary {
+    fn from(e: &Episode) -> Self {
+        Self {
+            id: e.id,
+            title: e.title.clone(),
+            published_at: e.published_at,
+            duration_ms: e.duration,
+            url: e.url(),
+            image_url: e.image_url.clone(),
+            tags: e.tags.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EpisodeDetail {
+    #[serde(flatten)]
+    pub summary: EpisodeSummary,
+    pub show_id: u32,
+    pub description: String,
+    pub download_url: String,
+    pub explicit: bool,
+    pub plays: u64,
+    pub likes: u64,
+    pub downloads: u64,
+    pub chapters: Vec<ChapterResponse>,
+    /// Named entities first, then keywords.
+    pub topics: Vec<String>,
+}
+
+impl From<&Episode> for EpisodeDetail {
+    fn from(e: &Episode) -> Self {
+        Self {
+            summary: e.into(),
+            show_id: e.show_id,
+            description: e.description.clone(),
+            download_url: e.download_url.clone(),
+            explicit: e.explicit,
+            plays: e.plays.total,
+            likes: e.plays.likes,
+            downloads: e.plays.downloads,
+            chapters: e.chapters.iter().map(ChapterResponse::from).collect(),
+            topics: e.topics.iter().map(|t| t.name.clone()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChapterResponse {
+    pub starts_at_ms: u64,
+    pub title: String,
+    pub external_url: Option<String>,
+}
+
+impl From<&Chapter> for ChapterResponse {
+    fn from(c: &Chapter) -> Self {
+        Self { starts_at_ms: c.starts_at, title: c.title.clone(), external_url: c.external_url.clone() }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TranscriptResponse {
+    pub episode_id: u32,
+    pub text: String,
+    pub segments: Vec<SegmentResponse>,
+}
+
+/// A timed span of the transcript: `start` and `end` are character (not byte) offsets into `text`.
+#[derive(Serialize, ToSchema)]
+pub struct SegmentResponse {
+    pub from_ms: u64,
+    pub to_ms: u64,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl From<EpisodeTranscript> for TranscriptResponse {
+    fn from(t: EpisodeTranscript) -> Self {
+        Self {
+            episode_id: t.episode_id,
+            segments: t.timestamps
+                .iter()
+                .map(|s| SegmentResponse {
+                    from_ms: s.time.from.as_millis() as u64,
+                    to_ms: s.time.to.as_millis() as u64,
+                    start: s.offsets.0,
+                    end: s.offsets.1,
+                })
+                .collect(),
+            text: t.data,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MatchResponse {
+    pub from_ms: u64,
+    pub to_ms: u64,
+    /// Text around the match.
+    pub hint: String,
+    /// Character (not byte) offsets of the matched text in `hint`, absent for full-text snippets.
+    pub highlight: Option<HighlightResponse>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HighlightResponse {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl From<EpisodeOffsetMatch> for MatchResponse {
+    fn from(m: EpisodeOffsetMatch) -> Self {
+        let chars = |bytes: usize| m.hint[..bytes].chars().count();
+        Self {
+            from_ms: m.time.from.as_millis() as u64,
+            to_ms: m.time.to.as_millis() as u64,
+            highlight: m.highlight.as_ref().map(|h| HighlightResponse { start: chars(h.start), end: chars(h.end) }),
+            hint: m.hint,
+        }
+    }
+}
+
+/// An episode matching a full-text search, see `TranscriptSearchResult`.
+#[derive(Serialize, ToSchema)]
+pub struct TranscriptHitResponse {
+    #[serde(flatten)]
+    pub episode: EpisodeSummary,
+    /// Relevance from 0 to 1, combining text score, hits and recency. Only comparable within the same search.
+    pub score: f64,
+    /// Occurrences of the words of the query in the transcript.
+    pub hits: u32,
+    /// Best segments matching the query.
+    pub snippets: Vec<MatchResponse>,
+}
+
+impl From<TranscriptSearchResult> for TranscriptHitResponse {
+    fn from(r: TranscriptSearchResult) -> Self {
+        Self {
+            episode: (&r.episode).into(),
+            score: r.score,
+            hits: r.hits,
+            snippets: r.snippets.into_iter().map(MatchResponse::from).collect(),
+        }
+    }
+}
+
+/// Matches of a search within one episode, see `OffsetSearchResult`.
+#[derive(Serialize, ToSchema)]
+pub struct EpisodeSearchResponse {
+    pub episode: EpisodeSummary,
+    pub matches: Page<MatchResponse>,
+}
+
+impl EpisodeSearchResponse {
+    pub fn new(r: OffsetSearchResult, page: &Pagination) -> Self {
+        Self { episode: (&r.episode).into(), matches: page.page(r.matches, MatchResponse::from) }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+}
@@ -0,0 +1,334 @@
+use std::{cmp::Reverse, collections::{HashMap, HashSet}, fmt::Display, fs::read_dir, path::{Path, PathBuf}, time::SystemTime};
+
+use chrono::{DateTime, Utc};
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+use reqwest::Client;
+
+use crate::audio::{self, AudioError};
+use crate::config::{Retention, CONFIG};
+use crate::db::{Store, StoreError};
+use crate::progress::Progress;
+use crate::spreaker::{download_file_with_progress, Episode, SpreakerError};
+use crate::transcript::{EpisodeTranscript, Transcript};
+
+/// Files produced by the import pipeline for each episode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Artifact {
+    Mp3,
+    Wav,
+    Transcript,
+}
+
+pub const ARTIFACTS: [Artifact; 3] = [Artifact::Mp3, Artifact::Wav, Artifact::Transcript];
+
+impl Artifact {
+    pub fn dir(&self) -> &'static str {
+        match self {
+            Self::Mp3 => &CONFIG.import.download_dir,
+            Self::Wav => &CONFIG.import.wav_dir,
+            Self::Transcript => &CONFIG.import.transcript_dir,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Wav => "wav",
+            Self::Transcript => "json",
+        }
+    }
+
+    pub fn retention(&self) -> Retention {
+        let r = &CONFIG.import.retention;
+        match self {
+            Self::Mp3 => r.mp3,
+            Self::Wav => r.wav,
+            Self::Transcript => r.transcript,
+        }
+    }
+
+    pub fn path(&self, id: u32) -> PathBuf {
+        Path::new(self.dir()).join(format!("{}.{}", id, self.extension()))
+    }
+
+    /// The artifacts on disk, ordered by episode id.
+    pub fn scan(&self) -> Result<Vec<ArtifactFile>, ArtifactError> {
+        let mut files = vec![];
+        for entry in read_dir(self.dir())? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|e| e != self.extension()) {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u32>().ok()) else {
+                continue;
+            };
+            let meta = path.metadata()?;
+            // atime is often disabled, so take the latest of the two
+            let used = match (meta.accessed(), meta.modified()) {
+                (Ok(a), Ok(m)) => a.max(m),
+                (Ok(t), _) | (_, Ok(t)) => t,
+                _ => SystemTime::UNIX_EPOCH,
+            };
+            files.push(ArtifactFile { id, path, size: meta.len(), used });
+        }
+        files.sort_by_key(|f| f.id);
+        Ok(files)
+    }
+}
+
+impl Display for Artifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mp3 => write!(f, "mp3"),
+            Self::Wav => write!(f, "wav"),
+            Self::Transcript => write!(f, "transcript"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArtifactFile {
+    pub id: u32,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Last access, used for LRU eviction.
+    pub used: SystemTime,
+}
+
+/// Files of `files` to delete according to `policy`. `inserted` are the episodes whose transcript is in the database,
+/// `published` the publication dates of the episodes.
+///
+/// Except for `Keep`, the files of an episode whose transcript isn't inserted yet are never evicted, since they are still
+/// needed to transcribe it or insert it.
+pub fn evictable(policy: Retention, files: &[ArtifactFile], inserted: &HashSet<u32>, published: &HashMap<u32, DateTime<Utc>>) -> Vec<ArtifactFile> {
+    let done = |f: &&ArtifactFile| inserted.contains(&f.id);
+    match policy {
+        Retention::Keep => vec![],
+        Retention::DeleteAfterInsert => files.iter().filter(done).cloned().collect(),
+        Retention::KeepLast { count } => {
+            let mut recent: Vec<&ArtifactFile> = files.iter().collect();
+            // most recent first, episodes missing from the database last
+            recent.sort_by_key(|f| (Reverse(published.get(&f.id)), Reverse(f.id)));
+            recent.into_iter().skip(count).filter(done).cloned().collect()
+        }
+        Retention::MaxSize { bytes } => {
+            let mut total: u64 = files.iter().map(|f| f.size).sum();
+            let mut lru: Vec<&ArtifactFile> = files.iter().filter(done).collect();
+            lru.sort_by_key(|f| f.used);
+            lru.into_iter()
+                .take_while(|f| {
+                    let over = total > bytes;
+                    total -= f.size;
+                    over
+                })
+                .cloned()
+                .collect()
+        }
+    }
+}
+
+/// Space used and reclaimed for one artifact kind.
+#[derive(Debug)]
+pub struct GcStats {
+    pub artifact: Artifact,
+    pub policy: Retention,
+    pub files: usize,
+    pub bytes: u64,
+    pub evicted: usize,
+    pub reclaimed: u64,
+}
+
+impl Display for GcStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} files, {} MiB, policy {:?}: {} files, {} MiB reclaimable",
+            self.artifact, self.files, self.bytes >> 20, self.policy, self.evicted, self.reclaimed >> 20
+        )
+    }
+}
+
+/// Apply the retention policies of every artifact kind. With `dry_run` only report what would be deleted.
+pub async fn gc(db: &dyn Store, dry_run: bool) -> Result<Vec<GcStats>, ArtifactError> {
+    let inserted: HashSet<u32> = db.get_ids::<EpisodeTranscript>().await?.into_iter().collect();
+    let published: HashMap<u32, DateTime<Utc>> = db.get_all::<Episode>().await?.into_iter().map(|e| (e.id, e.published_at)).collect();
+    let mut stats = vec![];
+    for a in ARTIFACTS {
+        let files = a.scan()?;
+        let policy = a.retention();
+        let evict = evictable(policy, &files, &inserted, &published);
+        if !dry_run {
+            for f in &evict {
+                debug!("evicting {}", f.path.display());
+                std::fs::remove_file(&f.path)?;
+            }
+        }
+        stats.push(GcStats {
+            artifact: a,
+            policy,
+            files: files.len(),
+            bytes: files.iter().map(|f| f.size).sum(),
+            evicted: evict.len(),
+            reclaimed: evict.iter().map(|f| f.size).sum(),
+        });
+    }
+    Ok(stats)
+}
+
+/// Delete the artifacts of episode `id` with the `DeleteAfterInsert` policy, called once its transcript is inserted.
+pub fn after_insert(id: u32) -> Result<(), ArtifactError> {
+    for a in ARTIFACTS {
+        let p = a.path(id);
+        if a.retention() == Retention::DeleteAfterInsert && p.exists() {
+            debug!("evicting {}", p.display());
+            std::fs::remove_file(p)?;
+        }
+    }
+    Ok(())
+}
+
+/// Path of the `artifact` of episode `id`, fetching it again if it was evicted: audio is downloaded (and converted)
+/// again, the transcript is rebuilt from the database.
+pub async fn fetch(db: &dyn Store, cli: &Client, artifact: Artifact, id: u32, progress: &Progress) -> Result<PathBuf, ArtifactError> {
+    let path = artifact.path(id);
+    match artifact {
+        Artifact::Mp3 if !path.is_file() => {
+            let e = db.get::<Episode>(id).await?.ok_or(ArtifactError::UnknownEpisode(id))?;
+            download_file_with_progress(cli, &e.download_url, &path, &progress.download_callback(id)).await?;
+        }
+        Artifact::Wav if !path.is_file() => {
+            let mp3 = Box::pin(fetch(db, cli, Artifact::Mp3, id, progress)).await?;
+            audio::convert(&mp3, &path, CONFIG.import.audio_backend, CONFIG.import.ffmpeg_fallback).await?;
+        }
+        Artifact::Transcript if !path.is_file() => {
+            let t = db.get::<EpisodeTranscript>(id).await?.ok_or(ArtifactError::UnknownEpisode(id))?;
+            let t: Transcript = (&t).into();
+            serde_json::to_writer(std::fs::File::create(&path)?, &t)?;
+        }
+        _ => {}
+    }
+    Ok(path)
+}
+
+#[derive(Debug)]
+pub enum ArtifactError {
+    Io(std::io::Error),
+    Store(StoreError),
+    Download(SpreakerError),
+    Audio(AudioError),
+    Serde(serde_json::Error),
+    UnknownEpisode(u32),
+}
+
+impl Display for ArtifactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {}", e),
+            Self::Store(e) => write!(f, "Database error: {}", e),
+            Self::Download(e) => write!(f, "Download error: {}", e),
+            Self::Audio(e) => write!(f, "Audio error: {}", e),
+            Self::Serde(e) => write!(f, "Serde error: {}", e),
+            Self::UnknownEpisode(id) => write!(f, "Episode {} not in database", id),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {}
+
+impl From<std::io::Error> for ArtifactError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<StoreError> for ArtifactError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<SpreakerError> for ArtifactError {
+    fn from(e: SpreakerError) -> Self {
+        Self::Download(e)
+    }
+}
+
+impl From<AudioError> for ArtifactError {
+    fn from(e: AudioError) -> Self {
+        Self::Audio(e)
+    }
+}
+
+impl From<serde_json::Error> for ArtifactError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serde(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// File of episode `id` of `mib` MiB, used `used` seconds after the epoch.
+    fn file(id: u32, mib: u64, used: u64) -> ArtifactFile {
+        ArtifactFile { id, path: PathBuf::from(format!("{}.mp3", id)), size: mib << 20, used: SystemTime::UNIX_EPOCH + Duration::from_secs(used) }
+    }
+
+    fn ids(files: Vec<ArtifactFile>) -> Vec<u32> {
+        let mut ids: Vec<u32> = files.into_iter().map(|f| f.id).collect();
+        ids.sort();
+        ids
+    }
+
+    /// Episodes 1 to 5, the higher the id the older, except for 5 which is the most recent.
+    fn published() -> HashMap<u32, DateTime<Utc>> {
+        [(1, 40), (2, 30), (3, 20), (4, 10), (5, 50)].into_iter().map(|(id, day)| (id, Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::days(day))).collect()
+    }
+
+    fn files() -> Vec<ArtifactFile> {
+        vec![file(1, 10, 5), file(2, 10, 4), file(3, 10, 3), file(4, 10, 1), file(5, 10, 2), file(6, 10, 0)]
+    }
+
+    #[test]
+    fn keep_evicts_nothing() {
+        let inserted = (1..=6).collect();
+        assert!(evictable(Retention::Keep, &files(), &inserted, &published()).is_empty());
+    }
+
+    #[test]
+    fn delete_after_insert() {
+        let inserted = [1, 3, 7].into();
+        assert_eq!(ids(evictable(Retention::DeleteAfterInsert, &files(), &inserted, &published())), vec![1, 3]);
+    }
+
+    #[test]
+    fn keep_last_by_publication_date() {
+        let inserted = (1..=6).collect();
+        // 5 and 1 are the most recent, 6 isn't in the database
+        assert_eq!(ids(evictable(Retention::KeepLast { count: 2 }, &files(), &inserted, &published())), vec![2, 3, 4, 6]);
+        assert!(evictable(Retention::KeepLast { count: 6 }, &files(), &inserted, &published()).is_empty());
+        // the audio of episodes not inserted yet stays
+        let inserted = [1, 2, 5].into();
+        assert_eq!(ids(evictable(Retention::KeepLast { count: 1 }, &files(), &inserted, &published())), vec![1, 2]);
+    }
+
+    #[test]
+    fn max_size_evicts_the_least_recently_used() {
+        let inserted = (1..=6).collect();
+        assert_eq!(ids(evictable(Retention::MaxSize { bytes: 40 << 20 }, &files(), &inserted, &published())), vec![4, 6]);
+        assert_eq!(ids(evictable(Retention::MaxSize { bytes: 35 << 20 }, &files(), &inserted, &published())), vec![4, 5, 6]);
+        assert!(evictable(Retention::MaxSize { bytes: 60 << 20 }, &files(), &inserted, &published()).is_empty());
+        // 6 and 4 aren't inserted, the next ones go in their place
+        let inserted = [1, 2, 3, 5].into();
+        assert_eq!(ids(evictable(Retention::MaxSize { bytes: 40 << 20 }, &files(), &inserted, &published())), vec![3, 5]);
+        // even if that's not enough
+        let inserted = [1].into();
+        assert_eq!(ids(evictable(Retention::MaxSize { bytes: 0 }, &files(), &inserted, &published())), vec![1]);
+    }
+}
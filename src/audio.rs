@@ -0,0 +1,275 @@
+use std::{fmt::Display, fs::File, io::BufWriter, path::{Path, PathBuf}, process::ExitStatus};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+use rubato::{FftFixedInOut, Resampler};
+use symphonia::core::{audio::SampleBuffer, codecs::{DecoderOptions, CODEC_TYPE_NULL}, errors::Error as SymphoniaError, formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
+
+use crate::config::AudioBackend;
+
+/// Sample rate expected by the transcriber.
+pub const SAMPLE_RATE: u32 = 16000;
+const CHUNK_SIZE: usize = 4096;
+
+/// Convert the audio file at `input` (MP3, M4A, OGG, ...) to a 16 kHz mono 16 bit PCM WAV at `output`.
+///
+/// With `fallback` set, ffmpeg is tried when the native decoder fails. The output is written to a temporary file
+/// first, so `output` exists only if the conversion succeeded.
+pub async fn convert(input: &Path, output: &Path, backend: AudioBackend, fallback: bool) -> Result<(), AudioError> {
+    match backend {
+        AudioBackend::Ffmpeg => ffmpeg(input, output).await,
+        AudioBackend::Native => {
+            let (i, o) = (input.to_owned(), output.to_owned());
+            match tokio::task::spawn_blocking(move || decode(&i, &o)).await? {
+                Err(e) if fallback => {
+                    warn!("couldn't decode {}, falling back to ffmpeg: {}", input.display(), e);
+                    ffmpeg(input, output).await
+                }
+                r => r,
+            }
+        }
+    }
+}
+
+fn part_path(output: &Path) -> PathBuf {
+    let mut p = output.as_os_str().to_owned();
+    p.push(".part");
+    p.into()
+}
+
+async fn ffmpeg(input: &Path, output: &Path) -> Result<(), AudioError> {
+    let part = part_path(output);
+    let status = tokio::process::Command::new("ffmpeg")
+        .arg("-y")
+        .arg("-i").arg(input)
+        .args(["-ar", &SAMPLE_RATE.to_string(), "-ac", "1", "-c:a", "pcm_s16le", "-f", "wav"])
+        .arg(&part)
+        .status()
+        .await
+        .map_err(AudioError::FfmpegSpawn)?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&part);
+        return Err(AudioError::Ffmpeg(status));
+    }
+    std::fs::rename(part, output)?;
+    Ok(())
+}
+
+/// Decode `input` in process, mixing all the channels down to mono and resampling to `SAMPLE_RATE`.
+pub fn decode(input: &Path, output: &Path) -> Result<(), AudioError> {
+    let part = part_path(output);
+    match decode_to(input, &part) {
+        Ok(()) => {
+            std::fs::rename(part, output)?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&part);
+            Err(e)
+        }
+    }
+}
+
+/// Write the decoded `input` to the WAV file `part`.
+fn decode_to(input: &Path, part: &Path) -> Result<(), AudioError> {
+    let mss = MediaSourceStream::new(Box::new(File::open(input)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = input.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?
+        .format;
+    let track = format.tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(AudioError::NoTrack)?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut out: Option<MonoWriter> = None;
+    let mut buf: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(p) => p,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(d) => d,
+            Err(SymphoniaError::DecodeError(e)) => {
+                debug!("skipping corrupt packet of {}: {}", input.display(), e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        if buf.as_ref().is_none_or(|b| b.capacity() < decoded.capacity() * channels) {
+            buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buf = buf.as_mut().unwrap();
+        buf.copy_interleaved_ref(decoded);
+        let mono: Vec<f32> = buf.samples()
+            .chunks(channels)
+            .map(|f| f.iter().sum::<f32>() / channels as f32)
+            .collect();
+
+        let out = match &mut out {
+            Some(o) => o,
+            None => out.insert(MonoWriter::new(part, spec.rate)?),
+        };
+        out.push(&mono)?;
+    }
+
+    out.ok_or(AudioError::Empty)?.finish()
+}
+
+/// Resamples mono audio to `SAMPLE_RATE` in fixed chunks and writes it to a WAV file.
+struct MonoWriter {
+    wav: WavWriter<BufWriter<File>>,
+    resampler: Option<FftFixedInOut<f32>>,
+    pending: Vec<f32>,
+    rate: u32,
+    /// Frames received and written, used to drop the delay of the resampler and its padding at the end.
+    read: u64,
+    written: u64,
+    skip: usize,
+}
+
+impl MonoWriter {
+    fn new(path: &Path, rate: u32) -> Result<Self, AudioError> {
+        let wav = WavWriter::create(path, WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        })?;
+        let resampler = if rate == SAMPLE_RATE {
+            None
+        } else {
+            Some(FftFixedInOut::new(rate as usize, SAMPLE_RATE as usize, CHUNK_SIZE, 1)?)
+        };
+        let skip = resampler.as_ref().map_or(0, |r| r.output_delay());
+        Ok(Self { wav, resampler, pending: vec![], rate, read: 0, written: 0, skip })
+    }
+
+    fn push(&mut self, samples: &[f32]) -> Result<(), AudioError> {
+        self.read += samples.len() as u64;
+        let Some(resampler) = &mut self.resampler else {
+            return self.write(samples.to_vec());
+        };
+        self.pending.extend_from_slice(samples);
+        let mut out = vec![];
+        let mut start = 0;
+        while self.pending.len() - start >= resampler.input_frames_next() {
+            let n = resampler.input_frames_next();
+            out.extend(resampler.process(&[&self.pending[start..start + n]], None)?.remove(0));
+            start += n;
+        }
+        self.pending.drain(..start);
+        self.write(out)
+    }
+
+    fn finish(mut self) -> Result<(), AudioError> {
+        if let Some(resampler) = &mut self.resampler {
+            let mut out = vec![];
+            if !self.pending.is_empty() {
+                out = resampler.process_partial(Some(&[&self.pending]), None)?.remove(0);
+            }
+            // flush the samples still held back by the resampler
+            out.extend(resampler.process_partial::<&[f32]>(None, None)?.remove(0));
+            self.pending.clear();
+            self.write(out)?;
+        }
+        self.wav.finalize()?;
+        Ok(())
+    }
+
+    fn write(&mut self, samples: Vec<f32>) -> Result<(), AudioError> {
+        let expected = self.read * SAMPLE_RATE as u64 / self.rate as u64;
+        let skip = self.skip.min(samples.len());
+        self.skip -= skip;
+        for s in &samples[skip..] {
+            if self.written >= expected {
+                break;
+            }
+            self.wav.write_sample((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum AudioError {
+    Io(std::io::Error),
+    Decode(SymphoniaError),
+    NoTrack,
+    Empty,
+    Resampler(rubato::ResamplerConstructionError),
+    Resample(rubato::ResampleError),
+    Wav(hound::Error),
+    FfmpegSpawn(std::io::Error),
+    Ffmpeg(ExitStatus),
+    Tokio(tokio::task::JoinError),
+}
+
+impl Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {}", e),
+            Self::Decode(e) => write!(f, "Decode error: {}", e),
+            Self::NoTrack => write!(f, "No audio track found"),
+            Self::Empty => write!(f, "No audio decoded"),
+            Self::Resampler(e) => write!(f, "Resampler error: {}", e),
+            Self::Resample(e) => write!(f, "Resample error: {}", e),
+            Self::Wav(e) => write!(f, "WAV error: {}", e),
+            Self::FfmpegSpawn(e) => write!(f, "Couldn't run ffmpeg: {}", e),
+            Self::Ffmpeg(s) => write!(f, "ffmpeg failed: {}", s),
+            Self::Tokio(e) => write!(f, "Tokio error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<std::io::Error> for AudioError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<SymphoniaError> for AudioError {
+    fn from(e: SymphoniaError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<rubato::ResamplerConstructionError> for AudioError {
+    fn from(e: rubato::ResamplerConstructionError) -> Self {
+        Self::Resampler(e)
+    }
+}
+
+impl From<rubato::ResampleError> for AudioError {
+    fn from(e: rubato::ResampleError) -> Self {
+        Self::Resample(e)
+    }
+}
+
+impl From<hound::Error> for AudioError {
+    fn from(e: hound::Error) -> Self {
+        Self::Wav(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for AudioError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Self::Tokio(e)
+    }
+}
@@ -1,21 +1,49 @@
-use std::{fmt::Display, time::Instant};
+use std::{cmp::Reverse, collections::HashSet, fmt::Display, sync::Arc, time::{Duration, Instant}};
 
 use log::{debug, error, info, trace};
 use regex::Regex;
-use teloxide::{prelude::Requester, repls::CommandReplExt, types::{ChatId, Message, ParseMode, User, UserId}, utils::{command::BotCommands, markdown}, Bot};
-use teloxide::payloads::SendMessageSetters;
-use power_pizza_bot::{bot::strings::HELP_MESSAGE, config::CONFIG};
-use power_pizza_bot::{bot::{BotError, BotUser}, db::DB};
+use teloxide::{dispatching::{HandlerExt, UpdateFilterExt}, dptree, prelude::{Dispatcher, Requester}, types::{ChatId, InputFile, Message, ParseMode, Update, User, UserId}, utils::{command::BotCommands, markdown}, Bot};
+use teloxide::payloads::{SendMessageSetters, SendPhotoSetters};
+use power_pizza_bot::{bot::strings::{command_help, HELP_MESSAGE}, config::CONFIG, metrics::{self, MetricLabel}};
+use power_pizza_bot::{bot::{trend::{self, TrendQuery, TrendScale}, BotError, BotUser, EpisodeOffsetMatch, NearQuery, OffsetSearchResult, SearchError}, db::{self, segments::{IndexedStore, SegmentIndex}, stats::{EpisodeStat, ShowStats}, Store}, migrations, spreaker::{Episode, Show}, status::ImportRun, transcript::EpisodeTranscript};
+
+/// Number of import runs shown by `/status`.
+const STATUS_RUNS: usize = 5;
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
+    let db = db::connect(&CONFIG.db);
     info!("ensuring database indexes");
-    DB.ensure_index().await.expect("Failed to ensure index");
+    db.ensure_index().await.expect("Failed to ensure index");
+    info!("applying database migrations");
+    migrations::run(db.as_ref()).await.expect("Failed to apply migrations");
+    let db: Arc<dyn Store> = if CONFIG.index.enabled {
+        let index = Arc::new(SegmentIndex::new());
+        info!("indexing transcripts");
+        index.refresh(db.as_ref()).await.expect("Failed to index the transcripts");
+        info!("{} transcripts indexed", index.len());
+        index.spawn_refresh(db.clone(), Duration::from_secs(CONFIG.index.refresh_secs));
+        Arc::new(IndexedStore::new(db, index))
+    } else {
+        db
+    };
+    if let Some(addr) = &CONFIG.metrics.bot {
+        metrics::serve(addr).await.expect("Failed to start the metrics server");
+    }
+    metrics::set_ready(true);
 
     let bot = Bot::new(CONFIG.tg.token.clone());
     log::info!("bot created, startring...");
-    Command::repl(bot, reply).await;
+    let handler = Update::filter_message()
+        .filter_command::<Command>()
+        .endpoint(reply);
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![db])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
 }
 
 fn represent_user(u: &Option<User>) -> String {
@@ -29,13 +57,21 @@ fn represent_user(u: &Option<User>) -> String {
 #[command(description = "Sono supportati i seguenti messaggi:")]
 enum Command {
     #[command(rename = "help", aliases = ["start"])]
-    Help,
+    Help(String),
     #[command(rename = "s", aliases = ["search", "c", "cerca"])]
     Search(String),
     #[command(rename = "sa", aliases = ["searchAdvanced", "cercaAvanzato", "ca"])]
     SearchAdvanced(String),
     #[command(rename = "sae", aliases = ["searchAdvancedEpisode", "cercaAvanzatoEpisodio", "cae"])]
     SearchAdvancedEpisode(String),
+    #[command(rename = "episodio", aliases = ["e", "episode", "info"])]
+    Episode(String),
+    #[command(rename = "simili", aliases = ["similar", "sim"])]
+    Similar(String),
+    #[command(rename = "trend", aliases = ["andamento"])]
+    Trend(String),
+    #[command(rename = "stats", aliases = ["statistiche"])]
+    Stats,
     #[command(rename = "beta")]
     Beta,
     #[command(rename = "betalist")]
@@ -44,39 +80,59 @@ enum Command {
     BetaWaitList,
     #[command(rename = "betaaccept")]
     BetaAccept(String),
+    #[command(rename = "status")]
+    Status,
 }
 
 impl Command {
     fn admin_access(&self) -> bool {
-        matches!(self, Self::BetaList | Self::BetaWaitList | Self::BetaAccept(..))
+        matches!(self, Self::BetaList | Self::BetaWaitList | Self::BetaAccept(..) | Self::Status)
+    }
+
+    /// Name of the command without its arguments, used as a metric label.
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Help(_) => "help",
+            Command::Search(_) => "search",
+            Command::SearchAdvanced(_) => "searchAdvanced",
+            Command::SearchAdvancedEpisode(_) => "searchAdvancedEpisode",
+            Command::Episode(_) => "episode",
+            Command::Similar(_) => "similar",
+            Command::Trend(_) => "trend",
+            Command::Stats => "stats",
+            Command::Beta => "beta",
+            Command::BetaList => "betaList",
+            Command::BetaWaitList => "betaWaitList",
+            Command::BetaAccept(_) => "betaAccept",
+            Command::Status => "status",
+        }
     }
 
     fn unrestricted(&self) -> bool {
-        matches!(self, Self::Beta | Self::BetaList | Self::BetaWaitList | Self::BetaAccept(..))
+        matches!(self, Self::Beta | Self::BetaList | Self::BetaWaitList | Self::BetaAccept(..) | Self::Status)
     }
 }
 
 impl Display for Command {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Command::Help => write!(f, "help"),
-            Command::Search(q) => write!(f, "search {}", q),
-            Command::SearchAdvanced(q) => write!(f, "searchAdvanced {}", q),
-            Command::SearchAdvancedEpisode(q) => write!(f, "searchAdvancedEpisode {}", q),
-            Command::Beta => write!(f, "beta"),
-            Command::BetaList => write!(f, "betaList"),
-            Command::BetaWaitList => write!(f, "betaWaitList"),
-            Command::BetaAccept(q) => write!(f, "betaAccept {}", q),
+            Command::Search(q) | Command::SearchAdvanced(q) | Command::SearchAdvancedEpisode(q) | Command::Episode(q)
+                | Command::Similar(q) | Command::Trend(q) | Command::BetaAccept(q) => {
+                write!(f, "{} {}", self.name(), q)
+            }
+            _ => write!(f, "{}", self.name()),
         }
     }
 }
 
-async fn reply(bot: Bot, msg: Message, cmd: Command) -> Result<(), teloxide::RequestError> {
+async fn reply(bot: Bot, msg: Message, cmd: Command, db: Arc<dyn Store>) -> Result<(), teloxide::RequestError> {
     info!("replying to command `{}` (id {}) from {}", cmd, msg.id, represent_user(&msg.from));
-    match reply_inner(&bot, &msg, cmd.clone()).await {
+    metrics::COMMANDS.with_label_values(&[cmd.name()]).inc();
+    match reply_inner(&bot, &msg, cmd.clone(), db.as_ref()).await {
         Ok(_) => info!("successfully replied to {} from {}", msg.id, represent_user(&msg.from)),
         Err(e) => {
             error!("failed to reply to message {} from {}: {:?}", msg.id, represent_user(&msg.from), e);
+            metrics::BOT_ERRORS.with_label_values(&[e.label()]).inc();
             bot.send_message(msg.chat.id, e.respond_client()).await?;
         }
     }
@@ -104,16 +160,194 @@ fn split_quoted_args(s: &str) -> Option<Vec<String>> {
     let mut args = vec![];
     let r = Regex::new(r#"("([^"]+)"|(\S+)")|(\S+)"#).unwrap();
     for cap in r.captures_iter(s) {
-        let cap = match cap.get(2).or(cap.get(4)) {
-            Some(c) => c,
-            None => return None,
-        };
+        let cap = cap.get(2).or(cap.get(4))?;
         args.push(cap.as_str().to_string());
     }
     Some(args)
 }
 
 static MAX_RESULTS: usize = 50;
+/// Snippets shown for each episode found by `/sa`.
+const SNIPPETS_PER_RESULT: usize = 2;
+/// Topics shown on the `/episodio` card.
+const CARD_TOPICS: usize = 10;
+/// Episodes suggested by `/simili`, and the shared words shown for each of them.
+const SIMILAR_RESULTS: usize = 10;
+const SIMILAR_TERMS: usize = 5;
+/// Episodes saying the term the most listed by `/trend`.
+const TREND_PEAKS: usize = 5;
+/// Most said words listed by `/stats`.
+const STATS_WORDS: usize = 10;
+
+fn format_duration(ms: u64) -> String {
+    let s = ms / 1000;
+    if s >= 3600 {
+        format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+    } else {
+        format!("{:02}:{:02}", s / 60, s % 60)
+    }
+}
+
+/// Markdown formatted info card of an episode. Kept short enough to be used as a photo caption.
+fn episode_card(e: &Episode, show: Option<&Show>) -> String {
+    let mut lines = vec![
+        format!("*{}*", markdown::link(&e.url(), &markdown::escape(&e.title))),
+    ];
+    if let Some(s) = show {
+        lines.push(markdown::italic(&markdown::escape(&s.title)));
+    }
+    lines.push(markdown::escape(&format!(
+        "📅 {} · ⏱ {}{}",
+        e.published_at.format("%d/%m/%Y"),
+        format_duration(e.duration as u64),
+        if e.explicit { " · 🔞" } else { "" },
+    )));
+    lines.push(markdown::escape(&format!("▶️ {} ascolti · ❤️ {} like", e.plays.total, e.plays.likes)));
+    if !e.tags.is_empty() {
+        lines.push(markdown::escape(&format!("🏷 {}", e.tags.join(", "))));
+    }
+    if !e.topics.is_empty() {
+        let topics: Vec<&str> = e.topics.iter().take(CARD_TOPICS).map(|t| t.name.as_str()).collect();
+        lines.push(markdown::escape(&format!("💬 {}", topics.join(", "))));
+    }
+    lines.push(format!("ID: {}", markdown::code_inline(&e.id.to_string())));
+    lines.join("\n")
+}
+
+/// Markdown formatted list of `episodes`, titled `title`, with their value formatted by `value`.
+fn ranked_episodes<'a>(title: &str, episodes: impl Iterator<Item = &'a EpisodeStat>, value: impl Fn(f64) -> String) -> String {
+    let mut lines = vec![markdown::bold(&markdown::escape(title))];
+    for e in episodes {
+        lines.push(format!(
+            "{}: {} {}",
+            markdown::escape(&e.episode_id.to_string()),
+            markdown::link(&e.url, &markdown::escape(&e.title)),
+            markdown::italic(&markdown::escape(&format!("({})", value(e.value)))),
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Markdown formatted statistics of the show, sections separated by an empty line.
+fn stats_report(s: &ShowStats) -> String {
+    let hours = |ms: u64| format!("{:.0}", ms as f64 / 3_600_000.);
+    let duration = |ms: f64| format_duration(ms as u64);
+    let rate = |wpm: f64| format!("{:.0} parole/min", wpm);
+    let mut sections = vec![
+        format!(
+            "{}\n{}",
+            markdown::bold("Statistiche del podcast"),
+            markdown::escape(&format!(
+                "🎙 {} puntate, {} ore\n📝 {} trascritte ({:.0}%), {} ore ({:.0}%)\n🗣 {:.0} parole al minuto in media",
+                s.episodes,
+                hours(s.duration_ms),
+                s.transcripts,
+                s.coverage() * 100.,
+                hours(s.transcribed_ms),
+                s.duration_coverage() * 100.,
+                s.words_per_minute,
+            )),
+        ),
+        format!(
+            "{}\n{}",
+            markdown::bold("Durata media per anno:"),
+            markdown::escape(&s.years.iter().map(|y| format!("{}: {} ({} puntate)", y.year, format_duration(y.average_ms), y.episodes)).collect::<Vec<_>>().join("\n")),
+        ),
+        ranked_episodes("Puntate più lunghe:", s.longest.iter(), duration),
+        ranked_episodes("Puntate più corte:", s.shortest.iter(), duration),
+        ranked_episodes("Puntate più veloci:", s.fastest(), rate),
+        ranked_episodes("Puntate più lente:", s.slowest(), rate),
+    ];
+    if !s.words.is_empty() {
+        sections.push(format!(
+            "{}\n{}",
+            markdown::bold("Parole più dette:"),
+            markdown::escape(&s.words.iter().take(STATS_WORDS).map(|w| format!("{} ({})", w.word, w.count)).collect::<Vec<_>>().join(", ")),
+        ));
+    }
+    sections.push(markdown::italic(&markdown::escape(&format!("Aggiornate al {}", s.computed_at.format("%d/%m/%Y %H:%M")))));
+    sections.join("\n\n")
+}
+
+fn episode_chapters(e: &Episode) -> String {
+    format!(
+        "{}\n{}",
+        markdown::bold("Capitoli:"),
+        e.chapters
+            .iter()
+            .map(|c| markdown::escape(&format!("{} - {}", format_duration(c.starts_at), c.title)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+/// Query of a typo-tolerant search, written as `~{query}` to `/sa` and `/sae`.
+fn fuzzy_query(query: &str) -> Option<String> {
+    query.trim().strip_prefix('~').map(|q| q.trim().to_owned()).filter(|q| !q.is_empty())
+}
+
+/// Episode id and query of the `/sae` command. The query is the second argument, or all the following
+/// ones if they form a `NEAR` or a typo-tolerant query.
+async fn episode_query_args(db: &dyn Store, query: &str) -> Result<(u32, String), BotError> {
+    let args = split_quoted_args(query).ok_or(BotError::MalformedQuery)?;
+    let id = db.magic_episode_search(args
+        .first()
+        .ok_or(BotError::MalformedQuery)?.to_string()).await?;
+    let rest = args.get(1..).unwrap_or_default().join(" ");
+    if NearQuery::parse(&rest).is_some() || fuzzy_query(&rest).is_some() {
+        return Ok((id, rest));
+    }
+    let query = args
+        .get(1)
+        .ok_or(BotError::MalformedQuery)?
+        .to_string();
+    Ok((id, query))
+}
+
+/// Reply with the matches of a search within an episode, with their time.
+async fn send_episode_matches(bot: &Bot, chat_id: ChatId, results: &OffsetSearchResult) -> Result<(), BotError> {
+    if results.len() > MAX_RESULTS {
+        bot.send_message(chat_id, format!("Troppi risultati trovati ({}), per favore affina la ricerca", results.len())).await?;
+        return Ok(());
+    }
+    if results.matches.is_empty() {
+        bot.send_message(chat_id, "No matches found").await?;
+    } else {
+        let response = format!("{}{}\n{}",
+            markdown::escape("Risultati per "),
+            markdown::link(&results.episode.url(), &markdown::escape(&results.episode.title)),
+            results.matches
+                .iter()
+                .map(|m| format!(
+                    "{}\n{}",
+                    markdown::escape(&format!("{:02}:{:02} - {:02}:{:02}",
+                        m.time.from.as_secs() / 60,
+                        m.time.from.as_secs() % 60,
+                        m.time.to.as_secs() / 60,
+                        m.time.to.as_secs() % 60
+                    )),
+                    markdown::blockquote(&match_hint(m))
+                ))
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        );
+        paginate_response(bot, chat_id, response).await?;
+    }
+    Ok(())
+}
+
+/// Markdown formatted hint of a match, with the matched text in bold.
+fn match_hint(m: &EpisodeOffsetMatch) -> String {
+    match m.highlight.clone().filter(|h| !h.is_empty()) {
+        Some(h) => format!(
+            "{}{}{}",
+            markdown::escape(&format!("...{}", &m.hint[..h.start])),
+            markdown::bold(&markdown::escape(&m.hint[h.clone()])),
+            markdown::escape(&format!("{}...", &m.hint[h.end..])),
+        ),
+        None => markdown::escape(&format!("...{}...", m.hint)),
+    }
+}
 
 fn is_admin(u: &Option<User>) -> bool {
     if let Some(u) = u {
@@ -123,11 +357,12 @@ fn is_admin(u: &Option<User>) -> bool {
     }
 }
 
-async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotError> {
+async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command, db: &dyn Store) -> Result<(), BotError> {
     let t = Instant::now();
     if !cmd.unrestricted() {
         if let Some(u) = msg.from.clone() {
-            if !DB.whitelisted(u.id.0 as i64).await? {
+            if !db.whitelisted(u.id.0 as i64).await? {
+                metrics::WHITELIST_DENIALS.inc();
                 bot.send_message(msg.chat.id, "Ciao, mi dispiace ma il bot è attualmente in sviluppo. Grazie per l'interesse. Riceverai una notifica quando sarà pronto. Utilizza il comando /beta per richiedere ingresso in waitlist.").await?;
                 return Ok(());
             }
@@ -138,8 +373,12 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
         return Ok(());
     }
     match cmd {
-        Command::Help => {
-            bot.send_message(msg.chat.id, &*HELP_MESSAGE)
+        Command::Help(command) => {
+            let help = match command_help(&command) {
+                Some(h) => h,
+                None => HELP_MESSAGE.clone(),
+            };
+            bot.send_message(msg.chat.id, help)
                 .parse_mode(ParseMode::MarkdownV2)
                 .await?;
         }
@@ -147,7 +386,7 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
             if query.len() < 3 {
                 bot.send_message(msg.chat.id, "La query deve essere di almeno 3 caratteri").await?;
             } else {
-                let results = DB.search_meta(query).await?;
+                let results = db.search_meta(query).await?;
                 if results.len() > MAX_RESULTS {
                     bot.send_message(msg.chat.id, format!("Troppi risultati trovati ({}), per favore affina la ricerca", results.len())).await?;
                     return Ok(());
@@ -157,19 +396,18 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
                     .map(|r| format!(
                             "{}: {}",
                             markdown::escape(&r.episode.id.to_string()),
-                            markdown::link(&format!("https://www.spreaker.com/episode/{}", r.episode.id), &markdown::escape(&r.episode.title))
+                            markdown::link(&r.episode.url(), &markdown::escape(&r.episode.title))
                     ))
                     .collect::<Vec<_>>()
                     .join("\n");
                 paginate_response(bot, msg.chat.id, response).await?;
             }
         }
-        Command::SearchAdvanced(query) => {
-            info!("received search query: {}", query);
+        Command::SearchAdvanced(query) if NearQuery::parse(&query).is_some() => {
+            info!("received proximity search query: {}", query);
             bot.send_message(msg.chat.id, "Searching...").await?;
-            debug!("querying db");
-            let results = DB.search_transcript_all(query).await?;
-            debug!("found {} results", results.len());
+            let near = NearQuery::parse(&query).ok_or(BotError::MalformedQuery)?;
+            let results = db.search_transcript_near(&near).await?;
             if results.len() > MAX_RESULTS {
                 bot.send_message(msg.chat.id, format!("Troppi risultati trovati ({}), per favore affina la ricerca", results.len())).await?;
                 return Ok(());
@@ -180,61 +418,194 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
                 results
                     .iter()
                     .map(|r| format!(
-                            "{}: {}",
+                            "{}: {}\n{}",
                             markdown::escape(&r.episode.id.to_string()),
-                            markdown::link(&format!("https://www.spreaker.com/episode/{}", r.episode.id), &markdown::escape(&r.episode.title))
+                            markdown::link(&r.episode.url(), &markdown::escape(&r.episode.title)),
+                            markdown::escape(&r.matches
+                                .iter()
+                                .map(|m| format!("{} - {}", format_duration(m.time.from.as_millis() as u64), format_duration(m.time.to.as_millis() as u64)))
+                                .collect::<Vec<_>>()
+                                .join(", "))
                     ))
                     .collect::<Vec<_>>()
                     .join("\n")
             );
             paginate_response(bot, msg.chat.id, response).await?;
         }
-        Command::SearchAdvancedEpisode(query) => {
-            bot.send_message(msg.chat.id, "searching episode transcripts...").await?;
-            let args = split_quoted_args(&query).ok_or(BotError::MalformedQuery)?;
-            let id = DB.magic_episode_search(args
-                .first()
-                .ok_or(BotError::MalformedQuery)?.to_string()).await?;
-            let query = args
-                .get(1)
-                .ok_or(BotError::MalformedQuery)?
-                .to_string();
-
-            info!("parsed arguments: id: {}, query: {}", id, query.as_str());
-            let results = DB.search_transcript_one(id, query.as_str().to_string()).await?;
+        Command::SearchAdvanced(query) if fuzzy_query(&query).is_some() => {
+            let query = fuzzy_query(&query).unwrap_or_default();
+            info!("received fuzzy search query: {}", query);
+            let results = db.search_transcript_fuzzy(query).await?;
             if results.len() > MAX_RESULTS {
                 bot.send_message(msg.chat.id, format!("Troppi risultati trovati ({}), per favore affina la ricerca", results.len())).await?;
                 return Ok(());
             }
-            if results.matches.is_empty() {
-                bot.send_message(msg.chat.id, "No matches found").await?;
-            } else {
-                let response = format!("{}{}\n{}",
-                    markdown::escape("Risultati per "),
-                    markdown::link(&format!("https://www.spreaker.com/episode/{}", results.episode.id), &markdown::escape(&results.episode.title)),
-                    results.matches
-                        .iter()
-                        .map(|m| format!(
-                            "{}\n{}",
-                            markdown::escape(&format!("{:02}:{:02} - {:02}:{:02}",
-                                m.time.from.as_secs() / 60,
-                                m.time.from.as_secs() % 60,
-                                m.time.to.as_secs() / 60,
-                                m.time.to.as_secs() % 60
-                            )),
-                            markdown::blockquote(&markdown::escape(&format!("...{}...", m.hint)))
-                        ))
-                        .collect::<Vec<_>>()
-                        .join("\n\n")
-                );
-                paginate_response(bot, msg.chat.id, response).await?;
+            let response = format!(
+                "{}\n{}",
+                markdown::escape("Found episodes:"),
+                results
+                    .iter()
+                    .map(|r| format!(
+                            "{}: {} {}",
+                            markdown::escape(&r.episode.id.to_string()),
+                            markdown::link(&r.episode.url(), &markdown::escape(&r.episode.title)),
+                            markdown::italic(&markdown::escape(&format!("({})", r.words.join(", "))))
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+            paginate_response(bot, msg.chat.id, response).await?;
+        }
+        Command::SearchAdvanced(query) => {
+            info!("received search query: {}", query);
+            bot.send_message(msg.chat.id, "Searching...").await?;
+            debug!("querying db");
+            let mut results = db.search_transcript_all(query.clone()).await?;
+            debug!("found {} results", results.len());
+            if results.len() > MAX_RESULTS {
+                bot.send_message(msg.chat.id, format!("Troppi risultati trovati ({}), per favore affina la ricerca", results.len())).await?;
+                return Ok(());
+            }
+            db.add_snippets(&mut results, &query, SNIPPETS_PER_RESULT).await?;
+            let response = format!(
+                "{}\n\n{}",
+                markdown::escape("Found episodes:"),
+                results
+                    .iter()
+                    .map(|r| {
+                        let mut lines = vec![format!(
+                            "{}: {} {}",
+                            markdown::escape(&r.episode.id.to_string()),
+                            markdown::link(&r.episode.url(), &markdown::escape(&r.episode.title)),
+                            markdown::italic(&markdown::escape(&format!("({} occorrenze, rilevanza {:.0}%)", r.hits, r.score * 100.))),
+                        )];
+                        for m in &r.snippets {
+                            lines.push(markdown::blockquote(&format!("{} {}", markdown::escape(&format_duration(m.time.from.as_millis() as u64)), match_hint(m))));
+                        }
+                        lines.join("\n")
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            );
+            paginate_response(bot, msg.chat.id, response).await?;
+        }
+        Command::SearchAdvancedEpisode(query) => {
+            bot.send_message(msg.chat.id, "searching episode transcripts...").await?;
+            let (id, query) = episode_query_args(db, &query).await?;
+            info!("parsed arguments: id: {}, query: {}", id, query);
+            let results = match (fuzzy_query(&query), NearQuery::parse(&query)) {
+                (Some(fuzzy), _) => db.search_transcript_one_fuzzy(id, fuzzy).await?,
+                (None, Some(near)) => db.search_transcript_one_near(id, &near).await?,
+                (None, None) => db.search_transcript_one(id, query).await?,
+            };
+            send_episode_matches(bot, msg.chat.id, &results).await?;
+        }
+        Command::Episode(query) => {
+            if query.trim().is_empty() {
+                return Err(BotError::MalformedQuery);
+            }
+            let id = db.magic_episode_search(query.trim().to_string()).await?;
+            let e = db.get::<Episode>(id).await?.ok_or(BotError::MalformedQuery)?;
+            let show = db.get::<Show>(e.show_id).await?;
+            let card = episode_card(&e, show.as_ref());
+            match &e.image_url {
+                Some(url) => match url.parse() {
+                    Ok(url) => {
+                        bot.send_photo(msg.chat.id, InputFile::url(url))
+                            .caption(card)
+                            .parse_mode(ParseMode::MarkdownV2)
+                            .await?;
+                    }
+                    Err(_) => {
+                        bot.send_message(msg.chat.id, card).parse_mode(ParseMode::MarkdownV2).await?;
+                    }
+                }
+                None => {
+                    bot.send_message(msg.chat.id, card).parse_mode(ParseMode::MarkdownV2).await?;
+                }
+            }
+            if !e.chapters.is_empty() {
+                paginate_response(bot, msg.chat.id, episode_chapters(&e)).await?;
+            }
+        }
+        Command::Similar(query) => {
+            if query.trim().is_empty() {
+                return Err(BotError::MalformedQuery);
+            }
+            let id = db.magic_episode_search(query.trim().to_string()).await?;
+            let e = db.get::<Episode>(id).await?.ok_or(BotError::MalformedQuery)?;
+            let results = db.search_similar(id, SIMILAR_RESULTS).await?;
+            let response = format!(
+                "{} {}\n{}",
+                markdown::escape("Puntate simili a"),
+                markdown::link(&e.url(), &markdown::escape(&e.title)),
+                results
+                    .iter()
+                    .map(|r| format!(
+                            "{}: {} {}",
+                            markdown::escape(&r.episode.id.to_string()),
+                            markdown::link(&r.episode.url(), &markdown::escape(&r.episode.title)),
+                            markdown::italic(&markdown::escape(&format!(
+                                "({}%, {})",
+                                (r.score * 100.).round(),
+                                r.terms.iter().take(SIMILAR_TERMS).cloned().collect::<Vec<_>>().join(", ")
+                            )))
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+            paginate_response(bot, msg.chat.id, response).await?;
+        }
+        Command::Trend(query) => {
+            let query = TrendQuery::parse(&query);
+            if query.term.is_empty() {
+                return Err(BotError::MalformedQuery);
             }
+            let result = db.search_trend(&query.term).await?;
+            let summary = format!(
+                "«{}» è stato detto {} volte in {} puntate",
+                query.term,
+                result.total(),
+                result.episodes.iter().filter(|e| e.count > 0).count(),
+            );
+            let title = format!("«{}» per {}", query.term, if query.scale == TrendScale::Month { "mese" } else { "puntata" });
+            let (series, scale) = (trend::series(&result, query.scale), query.scale);
+            // drawing takes a while, out of the async runtime
+            match tokio::task::spawn_blocking(move || trend::render(&title, &series, scale)).await {
+                Ok(Ok(png)) => {
+                    bot.send_photo(msg.chat.id, InputFile::memory(png).file_name("trend.png")).caption(&summary).await?;
+                }
+                Ok(Err(e)) => {
+                    error!("failed to render the chart of `{}`: {}", query.term, e);
+                    bot.send_message(msg.chat.id, &summary).await?;
+                }
+                Err(e) => {
+                    error!("chart task of `{}` failed: {}", query.term, e);
+                    bot.send_message(msg.chat.id, &summary).await?;
+                }
+            }
+            let mut lines = vec![markdown::bold("Puntate in cui è stato detto di più:")];
+            for p in result.peaks(TREND_PEAKS) {
+                if let Some(e) = db.get::<Episode>(p.episode_id).await? {
+                    lines.push(format!(
+                        "{}: {} {}",
+                        markdown::escape(&e.id.to_string()),
+                        markdown::link(&e.url(), &markdown::escape(&e.title)),
+                        markdown::italic(&markdown::escape(&format!("({} volte, {})", p.count, p.published_at.format("%d/%m/%Y")))),
+                    ));
+                }
+            }
+            paginate_response(bot, msg.chat.id, lines.join("\n")).await?;
+        }
+        Command::Stats => {
+            let stats = db.get::<ShowStats>(ShowStats::ID.to_owned()).await?.ok_or(SearchError::NoResults)?;
+            paginate_response(bot, msg.chat.id, stats_report(&stats)).await?;
         }
         Command::Beta => {
             info!("user {} requested beta access", represent_user(&msg.from));
             match &msg.from {
                 Some(u) => {
-                    match DB.get::<BotUser>(u.id.0 as i64).await? {
+                    match db.get::<BotUser>(u.id.0 as i64).await? {
                         Some(mut user) => {
                             if user.beta {
                                 info!("user {} already has beta access", user.identify());
@@ -245,7 +616,7 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
                             } else {
                                 user.waitlist = true;
                                 info!("inserting user {} into waitlist", user.identify());
-                                DB.update_one_stateless(user.id, &user).await?;
+                                db.update_one_stateless(user.id, &user).await?;
                                 bot.send_message(msg.chat.id, "Richiesta di entrare in beta inviata").await?;
                             }
                         }
@@ -253,7 +624,7 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
                             let mut user = BotUser::from(u);
                             user.waitlist = true;
                             info!("inserting user {} into waitlist", user.identify());
-                            DB.update_one_stateless(user.id, &user).await?;
+                            db.update_one_stateless(user.id, &user).await?;
                             bot.send_message(msg.chat.id, "Richiesta di entrare in beta inviata").await?;
                         }
                     }
@@ -265,9 +636,9 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
         }
         Command::BetaWaitList | Command::BetaList => {
             let list = if matches!(cmd, Command::BetaWaitList) {
-                DB.waitlist().await?
+                db.waitlist().await?
             } else {
-                DB.beta_list().await?
+                db.beta_list().await?
             };
 
             bot.send_message(msg.chat.id, format!(
@@ -285,19 +656,31 @@ async fn reply_inner(bot: &Bot, msg: &Message, cmd: Command) -> Result<(), BotEr
         }
         Command::BetaAccept(query) => {
             let id = query.parse::<i64>().map_err(|_| BotError::MalformedQuery)?;
-            let mut user = DB.get::<BotUser>(id).await?.ok_or(BotError::MalformedQuery)?;
+            let mut user = db.get::<BotUser>(id).await?.ok_or(BotError::MalformedQuery)?;
             if user.beta {
                 bot.send_message(msg.chat.id, "User already in beta").await?;
                 return Ok(());
             } else {
                 user.beta = true;
                 let id = user.id;
-                DB.update_one_stateless(id, &user).await?;
+                db.update_one_stateless(id, &user).await?;
                 info!("sending beta accepted to user {}", user.identify());
                 bot.send_message(UserId(id as u64), "Richiesta di entrare in beta accettata!").await?;
                 bot.send_message(msg.chat.id, format!("User {} accepted into beta", id)).await?;
             }
         }
+        Command::Status => {
+            let mut runs = db.get_all::<ImportRun>().await?;
+            runs.sort_by_key(|r| Reverse(r.id));
+            let transcripts: HashSet<u32> = db.get_ids::<EpisodeTranscript>().await?.into_iter().collect();
+            let backlog = db.get_ids::<Episode>().await?.into_iter().filter(|e| !transcripts.contains(e)).count();
+
+            let mut text = format!("{} import runs, {} episodes without transcript", runs.len(), backlog);
+            for r in runs.iter().take(STATUS_RUNS) {
+                text.push_str(&format!("\n\n{}", r));
+            }
+            bot.send_message(msg.chat.id, markdown::escape(&text)).parse_mode(ParseMode::MarkdownV2).await?;
+        }
     };
     trace!("replied in {:?}", t.elapsed());
 
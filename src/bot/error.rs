@@ -1,10 +1,12 @@
 use std::fmt::{self, Display, Formatter};
 
+use crate::{db::StoreError, metrics::MetricLabel};
+
 use super::search::SearchError;
 
 #[derive(Debug)]
 pub enum BotError {
-    Mongo(mongodb::error::Error),
+    Store(StoreError),
     Serde(serde_json::Error),
     Teloxide(teloxide::RequestError),
     NotImplemented,
@@ -23,7 +25,7 @@ impl BotError {
         let r = format!(
             "c'è stato un problema nel generare la risposta: {}",
             match self {
-                BotError::Mongo(_) => "errore database",
+                BotError::Store(_) => "errore database",
                 BotError::Serde(_) => "errore di serializzazione",
                 BotError::Teloxide(_) => "errore telegram",
                 BotError::NotImplemented => "questa funzionalità non è implementata",
@@ -37,6 +39,19 @@ impl BotError {
     }
 }
 
+impl MetricLabel for BotError {
+    fn label(&self) -> &'static str {
+        match self {
+            BotError::Store(_) => "store",
+            BotError::Serde(_) => "serde",
+            BotError::Teloxide(_) => "teloxide",
+            BotError::NotImplemented => "not_implemented",
+            BotError::SearchError(_) => "search",
+            BotError::MalformedQuery => "malformed_query",
+        }
+    }
+}
+
 impl Display for BotError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "BotError")
@@ -45,9 +60,9 @@ impl Display for BotError {
 
 impl std::error::Error for BotError {}
 
-impl From<mongodb::error::Error> for BotError {
-    fn from(e: mongodb::error::Error) -> Self {
-        BotError::Mongo(e)
+impl From<StoreError> for BotError {
+    fn from(e: StoreError) -> Self {
+        BotError::Store(e)
     }
 }
 
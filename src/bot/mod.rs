@@ -1,7 +1,12 @@
 mod user;
 mod error;
 mod search;
+mod near;
+pub mod trend;
 pub mod strings;
 
 pub use error::BotError;
 pub use user::BotUser;
+pub use near::{NearQuery, NearWindow};
+pub use search::{MagicQuery, MetaQuery, SearchError, SearchResult, TranscriptSearchResult, FuzzySearchResult, SimilarEpisode, OffsetSearchResult, EpisodeOffsetMatch, Timings};
+pub(crate) use search::ByteOffsets;
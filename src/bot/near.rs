@@ -0,0 +1,211 @@
+use std::{ops::Range, time::Duration};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{normalize::{fold, Normalized}, transcript::{EpisodeTranscript, FromTo, Timestamp}};
+
+use super::search::{char_bounds, segment_at, EpisodeOffsetMatch};
+
+/// Words between the two terms when the window isn't given.
+const DEFAULT_WINDOW_WORDS: usize = 10;
+
+/// Two terms said close to each other: `{term} NEAR/{window} {term}`.
+///
+/// The window is a number of words (`NEAR/5`) or of seconds (`NEAR/30s`), `NEAR` alone means `NEAR/10`. Terms
+/// are words or phrases, optionally quoted, matched regardless of case and accents, e.g. `nintendo NEAR/30s "switch 2"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearQuery {
+    pub left: String,
+    pub right: String,
+    pub window: NearWindow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NearWindow {
+    /// Most words between the two terms.
+    Words(usize),
+    /// Longest pause between the two terms.
+    Seconds(u64),
+}
+
+impl NearQuery {
+    /// `None` if `text` has no `NEAR` operator or one of its terms is empty.
+    pub fn parse(text: &str) -> Option<Self> {
+        let op = Regex::new(r"(?:^|\s)NEAR(?:/(\d+)(s?))?(?:\s|$)").unwrap();
+        let c = op.captures(text)?;
+        let m = c.get(0)?;
+        let window = match (c.get(1).map(|n| n.as_str().parse::<u64>()), c.get(2).is_some_and(|s| !s.as_str().is_empty())) {
+            (None, _) => NearWindow::Words(DEFAULT_WINDOW_WORDS),
+            (Some(Ok(n)), false) => NearWindow::Words(n as usize),
+            (Some(Ok(n)), true) => NearWindow::Seconds(n),
+            (Some(Err(_)), _) => return None,
+        };
+        let term = |t: &str| t.trim().trim_matches('"').trim().to_owned();
+        let (left, right) = (term(&text[..m.start()]), term(&text[m.end()..]));
+        if term_regex(&left).is_none() || term_regex(&right).is_none() {
+            return None;
+        }
+        Some(Self { left, right, window })
+    }
+
+    /// Full-text queries selecting the episodes that contain each term.
+    pub fn phrases(&self) -> [String; 2] {
+        [format!("\"{}\"", self.left), format!("\"{}\"", self.right)]
+    }
+
+    /// Time windows of `t` in which both terms are said within the window, in order of time. Overlapping windows
+    /// are merged, the hint of a window spans both terms.
+    pub fn windows(&self, t: &EpisodeTranscript) -> Vec<EpisodeOffsetMatch> {
+        let n = Normalized::new(&t.data);
+        let occurrences = |term: &str| -> Vec<Range<usize>> {
+            term_regex(term).map(|r| r.find_iter(&n.text).map(|m| n.original_range(m.range())).collect()).unwrap_or_default()
+        };
+        let (left, right) = (occurrences(&self.left), occurrences(&self.right));
+        if left.is_empty() || right.is_empty() {
+            return vec![];
+        }
+        // character offsets where the words of the transcript start
+        let words: Vec<usize> = Regex::new(r"\w+").unwrap().find_iter(&n.text).map(|m| n.original_range(m.range()).start).collect();
+        let words_before = |c: usize| words.partition_point(|&w| w < c);
+
+        let mut spans: Vec<Range<usize>> = vec![];
+        for l in &left {
+            for r in &right {
+                if l.start < r.end && r.start < l.end {
+                    continue;
+                }
+                let (first, second) = if l.start < r.start { (l, r) } else { (r, l) };
+                let near = match self.window {
+                    NearWindow::Words(w) => words_before(second.start).saturating_sub(words_before(first.end)) <= w,
+                    NearWindow::Seconds(s) => {
+                        let (a, b) = (time_at(&t.timestamps, first.end), time_at(&t.timestamps, second.start));
+                        b.saturating_sub(a) <= Duration::from_secs(s)
+                    }
+                };
+                if near {
+                    spans.push(first.start..second.end);
+                }
+            }
+        }
+        spans.sort_by_key(|s| s.start);
+        let mut merged: Vec<Range<usize>> = vec![];
+        for s in spans {
+            match merged.last_mut() {
+                Some(m) if s.start < m.end => m.end = m.end.max(s.end),
+                _ => merged.push(s),
+            }
+        }
+
+        let bounds = char_bounds(&t.data);
+        merged
+            .into_iter()
+            .filter_map(|s| {
+                let from = segment_at(&t.timestamps, s.start)?;
+                let to = segment_at(&t.timestamps, s.end.saturating_sub(1)).unwrap_or(from);
+                Some(EpisodeOffsetMatch::around(FromTo { from: from.time.from, to: to.time.to }, s, bounds.as_slice(), &t.data))
+            })
+            .collect()
+    }
+}
+
+/// Regex matching the words of `term` in a `Normalized` text, in order and separated by anything but words.
+fn term_regex(term: &str) -> Option<Regex> {
+    let words: Vec<String> = fold(term)
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(regex::escape)
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    RegexBuilder::new(&format!(r"\b{}\b", words.join(r"\W+"))).case_insensitive(true).build().ok()
+}
+
+/// Time at which the character `offset` is said, interpolated within its segment.
+fn time_at(timestamps: &[Timestamp], offset: usize) -> Duration {
+    match segment_at(timestamps, offset).or(timestamps.last()) {
+        Some(Timestamp { time, offsets }) => {
+            let len = offsets.1.saturating_sub(offsets.0).max(1) as f64;
+            let pos = offset.clamp(offsets.0, offsets.1).saturating_sub(offsets.0) as f64;
+            time.from + time.to.saturating_sub(time.from).mul_f64(pos / len)
+        }
+        None => Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transcript::{Segment, Transcript};
+
+    use super::*;
+
+    /// Transcript made of `segments`, the i-th one said from second 10*i to 10*(i+1).
+    fn transcript(segments: &[&str]) -> EpisodeTranscript {
+        let transcription = segments
+            .iter()
+            .enumerate()
+            .map(|(i, text)| Segment {
+                timestamps: FromTo { from: Duration::from_secs(10 * i as u64), to: Duration::from_secs(10 * (i as u64 + 1)) },
+                text: format!(" {}", text),
+            })
+            .collect();
+        (1, Transcript { transcription }).into()
+    }
+
+    fn query(left: &str, right: &str, window: NearWindow) -> NearQuery {
+        NearQuery { left: left.to_owned(), right: right.to_owned(), window }
+    }
+
+    #[test]
+    fn parse_windows() {
+        assert_eq!(NearQuery::parse("mario NEAR luigi"), Some(query("mario", "luigi", NearWindow::Words(DEFAULT_WINDOW_WORDS))));
+        assert_eq!(NearQuery::parse("mario NEAR/3 luigi"), Some(query("mario", "luigi", NearWindow::Words(3))));
+        assert_eq!(NearQuery::parse("mario NEAR/30s luigi"), Some(query("mario", "luigi", NearWindow::Seconds(30))));
+    }
+
+    #[test]
+    fn parse_quoted_phrases() {
+        assert_eq!(
+            NearQuery::parse("\"super mario\" NEAR/5 \"switch 2\""),
+            Some(query("super mario", "switch 2", NearWindow::Words(5))),
+        );
+    }
+
+    #[test]
+    fn parse_rejects_missing_operator_or_terms() {
+        assert_eq!(NearQuery::parse("mario luigi"), None);
+        // the operator is case sensitive, `near` is a word
+        assert_eq!(NearQuery::parse("mario near luigi"), None);
+        assert_eq!(NearQuery::parse("NEAR luigi"), None);
+        assert_eq!(NearQuery::parse("mario NEAR/5 \"\""), None);
+        assert_eq!(NearQuery::parse("mario NEAR/99999999999999999999 luigi"), None);
+    }
+
+    #[test]
+    fn windows_by_words() {
+        let t = transcript(&["mario e luigi", "poi tante altre parole in mezzo a tutto", "e alla fine luigi"]);
+        let w = query("mario", "luigi", NearWindow::Words(1)).windows(&t);
+        assert_eq!(w.len(), 1);
+        assert_eq!((w[0].time.from, w[0].time.to), (Duration::ZERO, Duration::from_secs(10)));
+        // both occurrences of luigi are close enough, the windows are merged
+        let w = query("mario", "luigi", NearWindow::Words(20)).windows(&t);
+        assert_eq!(w.len(), 1);
+        assert_eq!((w[0].time.from, w[0].time.to), (Duration::ZERO, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn windows_by_seconds() {
+        let t = transcript(&["parliamo di zelda", "niente", "niente", "niente", "e di link"]);
+        assert!(query("zelda", "link", NearWindow::Seconds(10)).windows(&t).is_empty());
+        assert_eq!(query("link", "zelda", NearWindow::Seconds(60)).windows(&t).len(), 1);
+    }
+
+    #[test]
+    fn windows_ignore_accents_and_need_both_terms() {
+        let t = transcript(&["Pokémon Rosso è uscito", "pokemon blu no"]);
+        assert_eq!(query("pokemon", "rosso", NearWindow::Words(0)).windows(&t).len(), 1);
+        assert!(query("pokemon", "giallo", NearWindow::Words(10)).windows(&t).is_empty());
+        // a term doesn't match inside the other one
+        assert!(query("pokemon rosso", "rosso", NearWindow::Words(10)).windows(&t).is_empty());
+    }
+}
@@ -1,12 +1,12 @@
-use std::{cmp::{min,max}, collections::VecDeque, time::Instant};
-use futures_util::{StreamExt, TryStreamExt};
+use std::{cmp::Reverse, collections::{HashMap, HashSet}, fmt::Display, ops::Range, time::{Duration, Instant}};
+use chrono::Utc;
 use log::{debug, trace};
-use mongodb::bson::{doc, from_document};
-use regex::bytes::RegexBuilder;
-use substring::Substring;
-use unidecode::unidecode;
+use mongodb::bson::{doc, Document};
+use regex::RegexBuilder;
 
-use crate::{db::PPPDatabase, spreaker::Episode, transcript::{EpisodeTranscript, FromTo, Timestamp}};
+use crate::{db::{fuzzy, similarity::EpisodeVector, trends::{self, TermTrend}, ScoredEpisode, Store, StoreError}, metrics::{self, MetricLabel}, normalize::{normalize_pattern, Normalized}, spreaker::Episode, transcript::{EpisodeTranscript, FromTo, Timestamp}};
+
+use super::NearQuery;
 
 /// # Queries:
 /// Get audio timestamp from text offset
@@ -14,79 +14,356 @@ use crate::{db::PPPDatabase, spreaker::Episode, transcript::{EpisodeTranscript,
 ///
 /// Get episode id from search string
 /// db.transcripts.aggregate([{ $match: {$text: {$search: "undertale"} }}, {$project: {episode_id: 1, _id: 0}}, {$lookup: {from: "episodes", localField: "episode_id", foreignField: "id", as: "episodeDetails"}}, {$project: {name: "$episodeDetails.title", id: "$episode_id"}}])
-impl PPPDatabase {
+impl dyn Store + '_ {
     /// Perform a full-text search across all transcripts in the database.
-    /// Returns a list of episodes in which the search string was found.
-    /// It does not return the actual matches nor the timestamps, take a look at `search_transcript_one` for that.
-    pub async fn search_transcript_all(&self, text: String) -> Result<Vec<SearchResult>, SearchError> {
-        self._ensure_status().await;
-        let _t = Instant::now();
-        let episodes = self.db
-            .collection::<EpisodeTranscript>("transcripts")
-            .aggregate(vec![
-                doc!{"$match": {"$text": {"$search": text}}},
-                doc!{"$project": {"episode_id": 1, "_id": 0}},
-                doc!{"$sort": {"sort": {"$meta": "textScore"}}},
-                doc!{"$lookup": {"from": "episodes", "localField": "episode_id", "foreignField": "id", "as": "episodeDetails"}},
-                doc!{"$unwind": "$episodeDetails"},
-                doc!{"$replaceRoot": {"newRoot": "$episodeDetails"}},
-            ])
-            .await?
-            // unwrap safe: as long as the schema and query are correct, this should not fail after this point
-            .map(|d| d.map(|d| from_document::<Episode>(d.clone()).unwrap()))
-            .try_collect::<Vec<Episode>>()
-            .await?;
+    /// Returns the episodes in which the search string was found, most relevant first, see `TranscriptSearchResult`.
+    /// It does not return the actual matches, take a look at `add_snippets` and `search_transcript_one` for that.
+    pub async fn search_transcript_all(&self, text: String) -> Result<Vec<TranscriptSearchResult>, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_transcript_all_timed(text, &mut t).await;
+        trace!("timings: search_text: {}", t);
+        metrics::observe_search("all", t.start, &r, Vec::len);
+        r
+    }
+
+    /// `search_transcript_all`, recording the duration of each step in `timings`.
+    pub async fn search_transcript_all_timed(&self, text: String, timings: &mut Timings) -> Result<Vec<TranscriptSearchResult>, SearchError> {
+        let episodes = self.search_transcripts(&text).await?;
+        timings.lap("full-text query");
         if episodes.is_empty() {
-            Err(SearchError::NoResults)
-        } else {
-            let r = Ok(episodes.into_iter().map(|episode| SearchResult { episode }).collect());
-            trace!("timings: search_text: {:?}", _t.elapsed());
-            r
+            return Err(SearchError::NoResults);
         }
+
+        // occurrences of the (not excluded) words of the query, by episode
+        let included: Vec<&str> = text.split_whitespace().filter(|w| !w.starts_with('-')).collect();
+        let hits = match self.segment_index().and_then(|i| i.occurrences(&included.join(" "))) {
+            Some(hits) => hits,
+            None => {
+                let mut hits: HashMap<u32, u32> = HashMap::new();
+                for w in fuzzy::words(&included.join(" ")).into_iter().collect::<HashSet<_>>() {
+                    for c in self.get::<fuzzy::IndexedWord>(w).await?.map(|w| w.episodes).unwrap_or_default() {
+                        *hits.entry(c.episode_id).or_default() += c.count;
+                    }
+                }
+                hits
+            }
+        };
+        timings.lap("term frequencies");
+
+        let max_score = episodes.iter().map(|e| e.score).fold(0., f64::max);
+        let max_hits = hits.values().copied().max().unwrap_or(0);
+        let now = Utc::now();
+        let mut res: Vec<TranscriptSearchResult> = episodes
+            .into_iter()
+            .map(|ScoredEpisode { episode, score }| {
+                let hits = hits.get(&episode.id).copied().unwrap_or(0);
+                let text = if max_score > 0. { score / max_score } else { 0. };
+                let frequency = if max_hits > 0 { (1. + hits as f64).ln() / (1. + max_hits as f64).ln() } else { 0. };
+                let age_days = (now - episode.published_at).num_days().max(0) as f64;
+                let recency = 0.5f64.powf(age_days / RECENCY_HALF_LIFE_DAYS);
+                TranscriptSearchResult {
+                    score: TEXT_SCORE_WEIGHT * text + HITS_WEIGHT * frequency + RECENCY_WEIGHT * recency,
+                    hits,
+                    episode,
+                    snippets: vec![],
+                }
+            })
+            .collect();
+        res.sort_by(|a, b| b.score.total_cmp(&a.score));
+        timings.lap("ranking");
+        Ok(res)
+    }
+
+    /// Fill the `snippets` of `results` with the `limit` best segments of each episode matching `text`.
+    pub async fn add_snippets(&self, results: &mut [TranscriptSearchResult], text: &str, limit: usize) -> Result<(), SearchError> {
+        for r in results {
+            r.snippets = self.transcript_snippets(r.episode.id, text, limit).await?;
+        }
+        Ok(())
     }
-    
+
     /// Perform a full-text regex based search across a single transcript.
     /// Returns a list of matches with their timestamps and text in the neighborhood of the match for context.
+    /// The transcript is searched in the `SegmentIndex` of the store if it has one, instead of being loaded.
     pub async fn search_transcript_one(&self, id: u32, text: String) -> Result<OffsetSearchResult, SearchError> {
-        self._ensure_status().await;
-        let _t = Instant::now();
-        let e = self.get::<Episode>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
-        let transcript = match self.db
-            .collection::<EpisodeTranscript>("transcripts")
-            .find_one(doc!{"episode_id": id})
-            .await? {
-                Some(t) => t,
-                None => return Err(SearchError::EpisodeNotFound(id)),
-            };
+        let mut t = Timings::new();
+        let r = self.search_transcript_one_timed(id, text, &mut t).await;
+        trace!("timings: search_transcript_offset: {}", t);
+        metrics::observe_search("one", t.start, &r, OffsetSearchResult::len);
+        r
+    }
 
+    /// `search_transcript_one`, recording the duration of each step in `timings`.
+    pub async fn search_transcript_one_timed(&self, id: u32, text: String, timings: &mut Timings) -> Result<OffsetSearchResult, SearchError> {
+        let e = self.get::<Episode>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
+        timings.lap("fetch episode");
 
-        let r = RegexBuilder::new(&text)
+        // both the pattern and the transcript are transliterated, so that accents don't matter
+        let r = RegexBuilder::new(&normalize_pattern(&text))
             .case_insensitive(true)
             .build()
             .map_err(SearchError::Regex)?;
-        
-        let data = unidecode(&transcript.data);
-        let mut matches = VecDeque::new();
-        for pos in r.find_iter(data.as_ref()) {
-            matches.push_back(pos.start());
+
+        if let Some(matches) = self.segment_index().and_then(|i| i.find(id, &text, &r)) {
+            timings.lap("index");
+            if matches.is_empty() {
+                return Err(SearchError::NoResults);
+            }
+            return Ok(OffsetSearchResult { matches, episode: e });
         }
+
+        let transcript = match self.get::<EpisodeTranscript>(id).await? {
+            Some(t) => t,
+            None => return Err(SearchError::EpisodeNotFound(id)),
+        };
+        timings.lap("fetch transcript");
+
+        let data = Normalized::new(&transcript.data);
+        // like the index, don't return the empty matches of patterns such as `a*`
+        let matches: Vec<Range<usize>> = r.find_iter(&data.text).filter(|m| !m.is_empty()).map(|m| data.original_range(m.range())).collect();
+        timings.lap("regex");
         if matches.is_empty() {
             return Err(SearchError::NoResults);
         }
-        let r = Ok(OffsetSearchResult::from(e, matches, transcript.timestamps, &transcript.data));
-        trace!("timings: search_transcript_offset: {:?}", _t.elapsed());
+        let r = OffsetSearchResult::from(e, matches, transcript.timestamps, &transcript.data);
+        timings.lap("timestamps");
+        Ok(r)
+    }
+
+    /// Typo-tolerant search across all transcripts: the words of `text` also match the words said in the episodes
+    /// that are spelled or sound alike, see `db::fuzzy`.
+    /// Episodes are ranked by how close their words are to the query, then by the number of occurrences.
+    pub async fn search_transcript_fuzzy(&self, text: String) -> Result<Vec<FuzzySearchResult>, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_transcript_fuzzy_timed(text, &mut t).await;
+        trace!("timings: search_transcript_fuzzy: {}", t);
+        metrics::observe_search("fuzzy", t.start, &r, Vec::len);
         r
     }
 
+    /// `search_transcript_fuzzy`, recording the duration of each step in `timings`.
+    pub async fn search_transcript_fuzzy_timed(&self, text: String, timings: &mut Timings) -> Result<Vec<FuzzySearchResult>, SearchError> {
+        // episode id -> (sum of the best closeness of each query word, occurrences, matched words)
+        let mut scored: HashMap<u32, (f64, u32, Vec<String>)> = HashMap::new();
+        for q in fuzzy::words(&text) {
+            let mut best: HashMap<u32, f64> = HashMap::new();
+            for w in self.similar_words(&q).await? {
+                for c in &w.episodes {
+                    let s = scored.entry(c.episode_id).or_default();
+                    s.1 += c.count;
+                    if !s.2.contains(&w.word) {
+                        s.2.push(w.word.clone());
+                    }
+                    let b = best.entry(c.episode_id).or_default();
+                    *b = b.max(w.closeness);
+                }
+            }
+            for (id, b) in best {
+                scored.entry(id).or_default().0 += b;
+            }
+        }
+        timings.lap("similar words");
+        let mut scored: Vec<_> = scored.into_iter().collect();
+        scored.sort_by(|(_, a), (_, b)| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+        let mut episodes: HashMap<u32, Episode> = self.get_all::<Episode>().await?.into_iter().map(|e| (e.id, e)).collect();
+        let res: Vec<FuzzySearchResult> = scored
+            .into_iter()
+            .filter_map(|(id, (_, _, words))| episodes.remove(&id).map(|episode| FuzzySearchResult { episode, words }))
+            .collect();
+        timings.lap("fetch episodes");
+        if res.is_empty() {
+            Err(SearchError::NoResults)
+        } else {
+            Ok(res)
+        }
+    }
+
+    /// Typo-tolerant search in a single transcript, see `search_transcript_fuzzy`.
+    /// Returns the occurrences of the words close to the query, the closest first and then by time.
+    pub async fn search_transcript_one_fuzzy(&self, id: u32, text: String) -> Result<OffsetSearchResult, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_transcript_one_fuzzy_timed(id, text, &mut t).await;
+        trace!("timings: search_transcript_one_fuzzy: {}", t);
+        metrics::observe_search("one_fuzzy", t.start, &r, OffsetSearchResult::len);
+        r
+    }
+
+    /// `search_transcript_one_fuzzy`, recording the duration of each step in `timings`.
+    pub async fn search_transcript_one_fuzzy_timed(&self, id: u32, text: String, timings: &mut Timings) -> Result<OffsetSearchResult, SearchError> {
+        let e = self.get::<Episode>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
+        timings.lap("fetch episode");
+        let transcript = self.get::<EpisodeTranscript>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
+        timings.lap("fetch transcript");
+
+        let mut closeness: HashMap<String, f64> = HashMap::new();
+        for q in fuzzy::words(&text) {
+            for w in self.similar_words(&q).await? {
+                if w.episodes.iter().any(|c| c.episode_id == id) {
+                    let c = closeness.entry(w.word).or_default();
+                    *c = c.max(w.closeness);
+                }
+            }
+        }
+        timings.lap("similar words");
+        if closeness.is_empty() {
+            return Err(SearchError::NoResults);
+        }
+
+        let words: Vec<String> = closeness.keys().map(|w| regex::escape(w)).collect();
+        let r = RegexBuilder::new(&format!(r"\b(?:{})\b", words.join("|")))
+            .case_insensitive(true)
+            .build()
+            .map_err(SearchError::Regex)?;
+        let data = Normalized::new(&transcript.data);
+        let mut matches: Vec<(f64, Range<usize>)> = r
+            .find_iter(&data.text)
+            .map(|m| (closeness.get(&m.as_str().to_lowercase()).copied().unwrap_or_default(), data.original_range(m.range())))
+            .collect();
+        // stable, occurrences of equally close words stay in order of time
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        timings.lap("regex");
+        let r = OffsetSearchResult::from(e, matches.into_iter().map(|(_, m)| m).collect(), transcript.timestamps, &transcript.data);
+        timings.lap("timestamps");
+        Ok(r)
+    }
+
+    /// Search across all transcripts for episodes where the two terms of `query` are said close to each other.
+    /// Returns the time windows containing both terms, episodes with more windows first.
+    pub async fn search_transcript_near(&self, query: &NearQuery) -> Result<Vec<OffsetSearchResult>, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_transcript_near_timed(query, &mut t).await;
+        trace!("timings: search_transcript_near: {}", t);
+        metrics::observe_search("near", t.start, &r, Vec::len);
+        r
+    }
+
+    /// `search_transcript_near`, recording the duration of each step in `timings`.
+    pub async fn search_transcript_near_timed(&self, query: &NearQuery, timings: &mut Timings) -> Result<Vec<OffsetSearchResult>, SearchError> {
+        // full-text backends may match phrases only within a segment, so each term is searched on its own
+        let [left, right] = query.phrases();
+        let left: HashSet<u32> = self.search_transcripts(&left).await?.into_iter().map(|e| e.episode.id).collect();
+        let candidates: Vec<Episode> = self.search_transcripts(&right).await?.into_iter().map(|e| e.episode).filter(|e| left.contains(&e.id)).collect();
+        timings.lap("full-text query");
+        let mut res = vec![];
+        for episode in candidates {
+            let Some(t) = self.get::<EpisodeTranscript>(episode.id).await? else { continue };
+            let matches = query.windows(&t);
+            if !matches.is_empty() {
+                res.push(OffsetSearchResult { matches, episode });
+            }
+        }
+        timings.lap("windows");
+        if res.is_empty() {
+            return Err(SearchError::NoResults);
+        }
+        // stable, ties keep the full-text ranking
+        res.sort_by_key(|r| Reverse(r.len()));
+        Ok(res)
+    }
+
+    /// Time windows of a single transcript in which the two terms of `query` are said close to each other.
+    pub async fn search_transcript_one_near(&self, id: u32, query: &NearQuery) -> Result<OffsetSearchResult, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_transcript_one_near_timed(id, query, &mut t).await;
+        trace!("timings: search_transcript_one_near: {}", t);
+        metrics::observe_search("one_near", t.start, &r, OffsetSearchResult::len);
+        r
+    }
+
+    /// `search_transcript_one_near`, recording the duration of each step in `timings`.
+    pub async fn search_transcript_one_near_timed(&self, id: u32, query: &NearQuery, timings: &mut Timings) -> Result<OffsetSearchResult, SearchError> {
+        let episode = self.get::<Episode>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
+        timings.lap("fetch episode");
+        let transcript = self.get::<EpisodeTranscript>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
+        timings.lap("fetch transcript");
+        let matches = query.windows(&transcript);
+        timings.lap("windows");
+        if matches.is_empty() {
+            Err(SearchError::NoResults)
+        } else {
+            Ok(OffsetSearchResult { matches, episode })
+        }
+    }
+
+    /// The `limit` episodes whose content is the most similar to the one of the episode `id`, see `db::similarity`.
+    pub async fn search_similar(&self, id: u32, limit: usize) -> Result<Vec<SimilarEpisode>, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_similar_timed(id, limit, &mut t).await;
+        trace!("timings: search_similar: {}", t);
+        metrics::observe_search("similar", t.start, &r, Vec::len);
+        r
+    }
+
+    /// `search_similar`, recording the duration of each step in `timings`.
+    pub async fn search_similar_timed(&self, id: u32, limit: usize, timings: &mut Timings) -> Result<Vec<SimilarEpisode>, SearchError> {
+        let vector = self.get::<EpisodeVector>(id).await?.ok_or(SearchError::EpisodeNotFound(id))?;
+        timings.lap("fetch vector");
+        let mut scored: Vec<(u32, f64, Vec<String>)> = self
+            .get_all::<EpisodeVector>()
+            .await?
+            .into_iter()
+            .filter(|v| v.episode_id != id)
+            .map(|v| {
+                let (score, terms) = vector.similarity(&v);
+                (v.episode_id, score, terms)
+            })
+            .filter(|s| s.1 > 0.)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        timings.lap("similarity");
+        let mut episodes: HashMap<u32, Episode> = self.get_all::<Episode>().await?.into_iter().map(|e| (e.id, e)).collect();
+        let res: Vec<SimilarEpisode> = scored
+            .into_iter()
+            .filter_map(|(id, score, terms)| episodes.remove(&id).map(|episode| SimilarEpisode { episode, score, terms }))
+            .collect();
+        timings.lap("fetch episodes");
+        if res.is_empty() {
+            Err(SearchError::NoResults)
+        } else {
+            Ok(res)
+        }
+    }
+
+    /// How many times `term`, a word or a phrase, is said in each episode.
+    pub async fn search_trend(&self, term: &str) -> Result<TermTrend, SearchError> {
+        let mut t = Timings::new();
+        let r = self.search_trend_timed(term, &mut t).await;
+        trace!("timings: search_trend: {}", t);
+        metrics::observe_search("trend", t.start, &r, |r| r.episodes.iter().filter(|e| e.count > 0).count());
+        r
+    }
+
+    /// `search_trend`, recording the duration of each step in `timings`.
+    pub async fn search_trend_timed(&self, term: &str, timings: &mut Timings) -> Result<TermTrend, SearchError> {
+        let res = trends::trend(self, term).await?;
+        timings.lap("count");
+        match res {
+            Some(t) if t.total() > 0 => Ok(t),
+            _ => Err(SearchError::NoResults),
+        }
+    }
+
+    /// Search episodes by title and description. The query can contain `key:value` filters, see `MetaQuery`.
     pub async fn search_meta(&self, text: String) -> Result<Vec<SearchResult>, SearchError> {
-        let r = mongodb::bson::Regex { pattern: regex::escape(&text), options: "i".to_string() };
-        let res = self.db
-            .collection::<Episode>("episodes")
-            .find(doc!{"$or": [{"title": r.clone()}, {"description": r.clone()}]})
+        let mut t = Timings::new();
+        let r = self.search_meta_timed(text, &mut t).await;
+        trace!("timings: search_meta: {}", t);
+        metrics::observe_search("meta", t.start, &r, Vec::len);
+        r
+    }
+
+    /// `search_meta`, recording the duration of each step in `timings`.
+    pub async fn search_meta_timed(&self, text: String, timings: &mut Timings) -> Result<Vec<SearchResult>, SearchError> {
+        let query = MetaQuery::parse(&text);
+        timings.lap("parse");
+        let res: Vec<SearchResult> = self
+            .search_episodes(&query)
             .await?
-            .map(|d| d.map(|d| SearchResult { episode: d }))
-            .try_collect::<Vec<SearchResult>>()
-            .await?;
+            .into_iter()
+            .map(|episode| SearchResult { episode })
+            .collect();
+        timings.lap("episodes query");
         if res.is_empty() {
             Err(SearchError::NoResults)
         } else {
@@ -97,34 +374,159 @@ impl PPPDatabase {
     /// Perform a search for a specific episode by its id/name/number or Magic Identifier™.
     /// Returns an optional u32 representing the episode id.
     pub async fn magic_episode_search(&self, query: String) -> Result<u32, SearchError> {
+        match MagicQuery::parse(&query) {
+            MagicQuery::Id(id) => Ok(id),
+            MagicQuery::Title(pattern) => self.find_episode_by_title(&pattern)
+                .await?
+                .map(|e| e.id)
+                .ok_or(SearchError::NoResults),
+        }
+    }
+}
+
+/// How `magic_episode_search` interprets its query.
+#[derive(Debug, PartialEq)]
+pub enum MagicQuery {
+    Id(u32),
+    /// Case-insensitive regex matched against the titles.
+    Title(String),
+}
+
+impl MagicQuery {
+    pub fn parse(query: &str) -> Self {
         match query.parse::<u32>() {
             Ok(num) => {
                 debug!("parsed number: {}", num);
                 if num > 10000 {
                     debug!("assuming this is an episode id");
                     // we assume that this is the episode id
-                    Ok(num)
+                    Self::Id(num)
                 } else {
                     debug!("assuming this is an episode number, searching by title");
                     // we assume that this is the episode number
-                    self.db
-                        .collection::<Episode>("episodes")
-                        .find_one(doc!{"title": mongodb::bson::Regex { pattern: num.to_string(), options: "i".to_string() }})
-                        .await?
-                        .map(|e| e.id)
-                        .ok_or(SearchError::NoResults)
+                    Self::Title(num.to_string())
                 }
             }
             Err(_) => {
                 debug!("not a number, searching by title");
-                self.db
-                    .collection::<Episode>("episodes")
-                    .find_one(doc!{"title": mongodb::bson::Regex { pattern: query, options: "i".to_string() }})
-                    .await?
-                    .map(|e| e.id)
-                    .ok_or(SearchError::NoResults)
+                Self::Title(query.to_owned())
+            }
+        }
+    }
+}
+
+/// Duration of the steps of a search, see the `*_timed` search functions.
+#[derive(Debug, Clone)]
+pub struct Timings {
+    pub start: Instant,
+    last: Instant,
+    pub steps: Vec<(&'static str, Duration)>,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self { start: now, last: now, steps: vec![] }
+    }
+
+    /// Record the time elapsed since the previous step as `step`.
+    pub fn lap(&mut self, step: &'static str) {
+        let now = Instant::now();
+        self.steps.push((step, now - self.last));
+        self.last = now;
+    }
+
+    pub fn total(&self) -> Duration {
+        self.last - self.start
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (step, d) in &self.steps {
+            write!(f, "{} {:?}, ", step, d)?;
+        }
+        write!(f, "total {:?}", self.total())
+    }
+}
+
+/// Parsed query of a metadata search.
+/// Words are matched case-insensitively against title and description, while these filters restrict the results:
+/// - `tag:{tag}` episodes tagged with `{tag}` (can be repeated)
+/// - `capitolo:{title}` episodes with a chapter whose title contains `{title}`
+/// - `explicit:si|no` only explicit or non-explicit episodes
+/// - `topic:{topic}` episodes with a topic containing `{topic}`, `_` standing for a space (can be repeated), see
+///   `db::topics`
+#[derive(Debug, Default, PartialEq)]
+pub struct MetaQuery {
+    pub text: String,
+    pub tags: Vec<String>,
+    pub topics: Vec<String>,
+    pub chapter: Option<String>,
+    pub explicit: Option<bool>,
+}
+
+impl MetaQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut q = Self::default();
+        let mut words = vec![];
+        for word in query.split_whitespace() {
+            match word.split_once(':') {
+                Some(("tag", v)) if !v.is_empty() => q.tags.push(v.to_lowercase()),
+                Some(("topic" | "argomento", v)) if !v.is_empty() => q.topics.push(v.replace('_', " ")),
+                Some(("capitolo" | "chapter", v)) if !v.is_empty() => q.chapter = Some(v.to_owned()),
+                Some(("explicit", v)) => match v {
+                    "si" | "sì" | "true" | "yes" => q.explicit = Some(true),
+                    "no" | "false" => q.explicit = Some(false),
+                    _ => words.push(word),
+                },
+                _ => words.push(word),
             }
         }
+        q.text = words.join(" ");
+        q
+    }
+
+    /// Evaluate the query on an episode, for backends that can't run `to_filter`.
+    pub fn matches(&self, e: &Episode) -> bool {
+        let contains = |h: &str, n: &str| h.to_lowercase().contains(&n.to_lowercase());
+        (self.text.is_empty() || contains(&e.title, &self.text) || contains(&e.description, &self.text))
+            && self.tags.iter().all(|t| e.tags.iter().any(|et| et.to_lowercase() == *t))
+            && self.topics.iter().all(|t| e.topics.iter().any(|et| contains(&et.name, t)))
+            && self.chapter.as_ref().is_none_or(|c| e.chapters.iter().any(|ec| contains(&ec.title, c)))
+            && self.explicit.is_none_or(|x| e.explicit == x)
+    }
+
+    /// MongoDB filter equivalent to the query.
+    pub fn to_filter(&self) -> Document {
+        let regex = |s: &str| mongodb::bson::Regex { pattern: regex::escape(s), options: "i".to_string() };
+        let mut filters = vec![];
+        if !self.text.is_empty() {
+            let r = regex(&self.text);
+            filters.push(doc!{"$or": [{"title": r.clone()}, {"description": r}]});
+        }
+        for t in &self.tags {
+            filters.push(doc!{"tags": mongodb::bson::Regex { pattern: format!("^{}$", regex::escape(t)), options: "i".to_string() }});
+        }
+        for t in &self.topics {
+            filters.push(doc!{"topics.name": regex(t)});
+        }
+        if let Some(c) = &self.chapter {
+            filters.push(doc!{"chapters.title": regex(c)});
+        }
+        if let Some(e) = self.explicit {
+            filters.push(doc!{"explicit": e});
+        }
+        match filters.len() {
+            0 => doc!{},
+            _ => doc!{"$and": filters},
+        }
     }
 }
 
@@ -133,6 +535,43 @@ pub struct SearchResult {
     pub episode: Episode,
 }
 
+/// Weights of the full-text score, of the number of hits and of the recency in the relevance of a transcript.
+const TEXT_SCORE_WEIGHT: f64 = 0.5;
+const HITS_WEIGHT: f64 = 0.3;
+const RECENCY_WEIGHT: f64 = 0.2;
+/// Age at which the recency of an episode is halved.
+const RECENCY_HALF_LIFE_DAYS: f64 = 365.;
+
+/// An episode found by `search_transcript_all`.
+#[derive(Debug)]
+pub struct TranscriptSearchResult {
+    pub episode: Episode,
+    /// Relevance from 0 to 1: the full-text score of the backend combined with the number of hits and the age of the
+    /// episode. Only comparable within the same search.
+    pub score: f64,
+    /// Occurrences of the words of the query in the transcript.
+    pub hits: u32,
+    /// Best segments matching the query, empty until filled by `add_snippets`.
+    pub snippets: Vec<EpisodeOffsetMatch>,
+}
+
+/// An episode found by `search_transcript_fuzzy`, with the words close to the query said in it, the closest first.
+#[derive(Debug)]
+pub struct FuzzySearchResult {
+    pub episode: Episode,
+    pub words: Vec<String>,
+}
+
+/// An episode found by `search_similar`.
+#[derive(Debug)]
+pub struct SimilarEpisode {
+    pub episode: Episode,
+    /// Cosine similarity of the contents, from 0 to 1.
+    pub score: f64,
+    /// Words the two episodes have in common, the ones contributing the most to the similarity first.
+    pub terms: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct OffsetSearchResult {
     pub matches: Vec<EpisodeOffsetMatch>,
@@ -140,62 +579,110 @@ pub struct OffsetSearchResult {
 }
 
 impl OffsetSearchResult {
-    pub fn from(episode: Episode, mut input: VecDeque<usize>, timestamps: Vec<Timestamp>, data: &String) -> Self {
-        const HINT_RADIUS: usize = 50;
-
-        let mut curr = match input.pop_front() {
-            Some(m) => m,
-            None => return Self { episode, matches: vec![] },
-        };
-        let mut matches = vec![];
-        // let mut prev_time = Duration::from_secs(0);
-        'a: for Timestamp { time, offsets } in timestamps {
-            debug!("checking timestamp: Timestamp {{ time: {:?}, offsets: {:?} }}", time, offsets);
-            loop {
-                if curr >= offsets.0 && curr < offsets.1 {
-                    let m = EpisodeOffsetMatch {
-                        time: time.clone(),
-                        hint: data.substring(max(0, curr as isize - HINT_RADIUS as isize) as usize, min(data.len(), curr + HINT_RADIUS)).to_string(),
-                    };
-                    debug!("found match: {:?}", m);
-                    matches.push(m);
-                    curr = match input.pop_front() {
-                        Some(m) => m,
-                        None => break 'a,
-                    };
-                } else if curr < offsets.0 && curr < offsets.1 {
-                    panic!("this should never happen... curr: {:?}, offsets: {:?}", curr, offsets);
-                } else { break }
-            }
-        }
+    /// `matches` are character ranges of `data`, the text of the transcript split by `timestamps`.
+    pub fn from(episode: Episode, matches: Vec<Range<usize>>, timestamps: Vec<Timestamp>, data: &str) -> Self {
         Self {
+            matches: EpisodeOffsetMatch::locate(&matches, &timestamps, data),
             episode,
-            matches,
         }
     }
 
     pub fn len(&self) -> usize {
         self.matches.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
 }
 
 #[derive(Debug)]
 pub struct EpisodeOffsetMatch {
     pub time: FromTo,
     pub hint: String,
+    /// Bytes of `hint` that matched the query, when known.
+    pub highlight: Option<Range<usize>>,
+}
+
+impl EpisodeOffsetMatch {
+    /// Characters of context on each side of a match in its hint.
+    const HINT_RADIUS: usize = 50;
+
+    /// Time and hint of each of `matches`, character ranges of `data`, the text of the transcript split by
+    /// `timestamps`. A match belongs to the segment it starts in, matches past the last segment are dropped.
+    pub fn locate(matches: &[Range<usize>], timestamps: &[Timestamp], data: &str) -> Vec<Self> {
+        Self::locate_with(matches, timestamps, data, char_bounds(data).as_slice())
+    }
+
+    /// `locate` with the `bounds` of `data` at hand.
+    pub(crate) fn locate_with(matches: &[Range<usize>], timestamps: &[Timestamp], data: &str, bounds: &(impl ByteOffsets + ?Sized)) -> Vec<Self> {
+        let mut located = vec![];
+        for m in matches {
+            let Some(Timestamp { time, offsets }) = segment_at(timestamps, m.start) else {
+                debug!("match {:?} past the last timestamp", m);
+                continue;
+            };
+            debug!("match {:?} in timestamp {:?}", m, offsets);
+            located.push(Self::around(time.clone(), m.clone(), bounds, data));
+        }
+        located
+    }
+
+    /// Match of the characters `range` of `data` at `time`, `bounds` are the byte offsets of `data`.
+    pub(super) fn around(time: FromTo, range: Range<usize>, bounds: &(impl ByteOffsets + ?Sized), data: &str) -> Self {
+        let last = bounds.chars();
+        let (start, end) = (range.start.min(last), range.end.min(last));
+        let from = bounds.byte(start.saturating_sub(Self::HINT_RADIUS));
+        let to = bounds.byte((end + Self::HINT_RADIUS).min(last));
+        Self {
+            time,
+            hint: data[from..to].to_owned(),
+            highlight: Some(bounds.byte(start) - from..bounds.byte(end) - from),
+        }
+    }
+}
+
+/// Byte offsets of the characters of a text.
+pub(crate) trait ByteOffsets {
+    /// Length of the text in characters.
+    fn chars(&self) -> usize;
+    /// Byte offset of the character `i`, the length of the text for `i == chars()`.
+    fn byte(&self, i: usize) -> usize;
+}
+
+/// The `char_bounds` of a text.
+impl ByteOffsets for [usize] {
+    fn chars(&self) -> usize {
+        self.len() - 1
+    }
+
+    fn byte(&self, i: usize) -> usize {
+        self[i]
+    }
+}
+
+/// Byte offset of each character of `data`, and of its end.
+pub(super) fn char_bounds(data: &str) -> Vec<usize> {
+    data.char_indices().map(|(i, _)| i).chain(std::iter::once(data.len())).collect()
+}
+
+/// Segment containing the character `offset`.
+pub(super) fn segment_at(timestamps: &[Timestamp], offset: usize) -> Option<&Timestamp> {
+    // timestamps are sorted and contiguous, the first one ending after the offset contains it
+    timestamps.get(timestamps.partition_point(|t| t.offsets.1 <= offset))
 }
 
 #[derive(Debug)]
 pub enum SearchError {
     EpisodeNotFound(u32),
-    Mongo(mongodb::error::Error),
+    Store(StoreError),
     Regex(regex::Error),
     NoResults,
 }
 
-impl From<mongodb::error::Error> for SearchError {
-    fn from(e: mongodb::error::Error) -> Self {
-        SearchError::Mongo(e)
+impl From<StoreError> for SearchError {
+    fn from(e: StoreError) -> Self {
+        SearchError::Store(e)
     }
 }
 
@@ -209,9 +696,20 @@ impl SearchError {
     pub fn respond_client(&self) -> &str {
         match self {
             SearchError::EpisodeNotFound(_) => "l'episodio richiesto non esiste",
-            SearchError::Mongo(_) => "errore del database",
+            SearchError::Store(_) => "errore del database",
             SearchError::Regex(_) => "errore nella query",
             SearchError::NoResults => "nessun risultato trovato",
         }
     }
 }
+
+impl MetricLabel for SearchError {
+    fn label(&self) -> &'static str {
+        match self {
+            SearchError::EpisodeNotFound(_) => "episode_not_found",
+            SearchError::Store(_) => "store",
+            SearchError::Regex(_) => "regex",
+            SearchError::NoResults => "no_results",
+        }
+    }
+}
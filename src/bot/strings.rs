@@ -9,7 +9,20 @@ pub static DESC_COMMAND_SEARCH: &str = concat!(
     "La query è case-insensitive.\n",
     "Es. \n",
     "- `/s pokemon` trova tutte le puntate con \"pokemon\" nel titolo o nella descrizione.\n",
-    "- `/s green oaks` trova la puntata \"PPP Speciale: PGdR™ - Green Oaks\".",
+    "- `/s green oaks` trova la puntata \"PPP Speciale: PGdR™ - Green Oaks\".\n",
+    "Puoi restringere la ricerca con dei filtri:\n",
+    "- `tag:{tag}` solo le puntate con il tag indicato.\n",
+    "- `capitolo:{testo}` solo le puntate con un capitolo che contiene il testo indicato.\n",
+    "- `explicit:si` o `explicit:no` solo le puntate (non) esplicite.\n",
+    "- `topic:{argomento}` solo le puntate che parlano dell'argomento indicato, ricavato dalla trascrizione ",
+    "(usa `_` al posto degli spazi).\n",
+    "Es. `/s tag:nintendo zelda`, `/s topic:zelda` per tutte le puntate in cui si parla di Zelda.",
+);
+
+pub static DESC_COMMAND_EPISODE: &str = concat!(
+    "Scheda episodio: mostra copertina, data, durata, tag, ascolti e capitoli di una puntata.\n",
+    "Sintassi `/episodio {episodio}`.\n",
+    "`{episodio}` può essere il numero dell'episodio, il titolo o il codice identificativo spreaker (avanzato).",
 );
 
 pub static DESC_COMMAND_SEARCH_ADVANCED: &str = concat!(
@@ -22,7 +35,14 @@ pub static DESC_COMMAND_SEARCH_ADVANCED: &str = concat!(
     "- `nick sio`: cerca tutte le puntate in cui viene detto \"nick\" e quelle in cui viene detto \"sio\".\n",
     "- `\"nick lorro\"`: cerca tutte le puntate in cui viene detto \"nick\" e subito dopo \"lorro\".\n",
     "Es. se voglio cercare \"pokemon rosso\", devo scrivere `/sa \"pokemon rosso\"`, se scrivo `/sa pokemon rosso` la ",
-    "ricerca sarà su tutte le puntate in cui viene detto \"pokemon\", ma anche **tutte** le puntate in cui viene detto \"rosso\"!.",
+    "ricerca sarà su tutte le puntate in cui viene detto \"pokemon\", ma anche **tutte** le puntate in cui viene detto \"rosso\"!.\n",
+    "Con `NEAR` cerchi due parole (o frasi) dette vicine, entro un numero di parole (`NEAR/5`, 10 se omesso) o di secondi ",
+    "(`NEAR/30s`), e ottieni i minuti in cui vengono dette. Funziona anche con /sae.\n",
+    "- `nintendo NEAR/30s \"switch 2\"`: cerca le puntate in cui \"nintendo\" e \"switch 2\" vengono detti a meno di 30 secondi di distanza.\n",
+    "Inizia la query con `~` per una ricerca tollerante agli errori: trova anche le parole scritte o pronunciate in modo ",
+    "simile, utile per i nomi che la trascrizione (o chi cerca) sbaglia. Le puntate sono ordinate dalla più simile alla ",
+    "query, le parole più corte di 3 lettere vengono ignorate. Funziona anche con /sae.\n",
+    "- `~zeldah`: trova anche le puntate in cui viene detto \"zelda\".",
 );
 
 pub static DESC_COMMAND_SEARCH_ADVANCED_EPISODE: &str = concat!(
@@ -33,18 +53,61 @@ pub static DESC_COMMAND_SEARCH_ADVANCED_EPISODE: &str = concat!(
     "La query non supporta le keywords di /sa. Ma supporta ricerca tramite regex (avanzato).\n",
     "Gli argomenti possono essere racchiusi tra virgolette `\"` per cercare frasi intere.\n",
     "Es.\n",
-    "- `/sae 1 \"pokemon rosso\"`: cerca la frase \"pokemon rosso\" all'interno della puntata",
+    "- `/sae 1 \"pokemon rosso\"`: cerca la frase \"pokemon rosso\" all'interno della puntata\n",
+    "- `/sae 1 ~zeldah`: cerca anche le parole simili a \"zeldah\", come in /sa",
 );
 
-pub static WELCOME_STRING: &str = concat!(
-    "Ciao! Sono il bot di PPP, posso aiutarti a trovare le puntate in cui si parla di un argomento specifico.",
+pub static DESC_COMMAND_SIMILAR: &str = concat!(
+    "Puntate simili: trova le puntate che parlano delle stesse cose di una puntata, confrontando le trascrizioni, ",
+    "i titoli e le descrizioni.\n",
+    "Sintassi `/simili {episodio}`.\n",
+    "`{episodio}` può essere il numero dell'episodio, il titolo o il codice identificativo spreaker (avanzato).\n",
+    "Per ogni puntata trovata vengono mostrate le parole in comune che la rendono simile.",
+);
+pub static DESC_COMMAND_TREND: &str = concat!(
+    "Andamento: quante volte una parola o una frase è stata detta nella storia del podcast, con un grafico e le ",
+    "puntate in cui è stata detta di più.\n",
+    "Sintassi `/trend {testo}`, per esempio `/trend zelda`.\n",
+    "Di default le occorrenze sono contate per mese, con `per:puntata` puntata per puntata: `/trend per:puntata zelda`.",
+);
+pub static DESC_COMMAND_STATS: &str = concat!(
+    "Statistiche: puntate e ore di podcast, quante sono trascritte, durata media per anno, puntate più lunghe e più ",
+    "corte, parole più dette e parole al minuto.\n",
+    "Sintassi `/stats`, aggiornate dopo ogni importazione.",
 );
 
+pub static WELCOME_STRING: &str = "Ciao! Sono il bot di PPP, posso aiutarti a trovare le puntate in cui si parla di un argomento specifico.";
+
 /// Note: the footer string must be **markdown** formatted!
-pub static FOOTER_STRING: &str = concat!(
-    "Questo bot è sviluppato da @topongo ed è open\\-source\\! [topongo/ppp\\-bot](https://github.com/topongo/ppp\\-bot)",
-);
+pub static FOOTER_STRING: &str = "Questo bot è sviluppato da @topongo ed è open\\-source\\! [topongo/ppp\\-bot](https://github.com/topongo/ppp\\-bot)";
+
+
+/// Commands described by `/help`: their names and aliases, a one line summary for the overview and the description
+/// shown by `/help {comando}`.
+pub static HELP_COMMANDS: &[(&[&str], &str, &str)] = &[
+    (&["s", "search", "c", "cerca"], "ricerca nei titoli e nelle descrizioni, con filtri per tag, capitoli e argomenti", DESC_COMMAND_SEARCH),
+    (&["sa", "searchadvanced", "cercaavanzato", "ca"], "ricerca nelle trascrizioni di tutte le puntate, anche di parole vicine o simili", DESC_COMMAND_SEARCH_ADVANCED),
+    (&["sae", "searchadvancedepisode", "cercaavanzatoepisodio", "cae"], "ricerca nella trascrizione di una puntata", DESC_COMMAND_SEARCH_ADVANCED_EPISODE),
+    (&["episodio", "e", "episode", "info"], "scheda di una puntata", DESC_COMMAND_EPISODE),
+    (&["simili", "similar", "sim"], "puntate che parlano delle stesse cose di una puntata", DESC_COMMAND_SIMILAR),
+    (&["trend", "andamento"], "quante volte una parola è stata detta nel tempo, con un grafico", DESC_COMMAND_TREND),
+    (&["stats", "statistiche"], "statistiche del podcast", DESC_COMMAND_STATS),
+];
 
+/// Escape the MarkdownV2 characters of a description, keeping its formatting.
+fn escape_description(s: &str) -> String {
+    s.chars().map(|c| ESCAPE_CHARS.get(&c).map(|c| c.to_string()).unwrap_or(c.to_string())).collect()
+}
+
+/// Markdown formatted description of the command `name` (with or without the `/`, any of its aliases), shown by
+/// `/help {comando}`.
+pub fn command_help(name: &str) -> Option<String> {
+    let name = name.trim().trim_start_matches('/').to_lowercase();
+    HELP_COMMANDS
+        .iter()
+        .find(|(names, _, _)| names.contains(&name.as_str()))
+        .map(|(_, _, desc)| escape_description(desc))
+}
 
 lazy_static!{
         // (':', '.', '(', ')', '-', '!'].iter().cloned().collect();
@@ -56,18 +119,40 @@ lazy_static!{
         ('-', "\\-"),
         ('!', "\\!"),
     ].iter().cloned().collect();
+    /// Overview of the commands, the description of each one is too long to fit in a single message.
     pub static ref HELP_MESSAGE: String = format!(
-        "{}\n\n{}\n\n{}",
+        "{}\n\n{}\n\n{}\n\n{}",
         markdown::escape(WELCOME_STRING),
-        [DESC_COMMAND_SEARCH, DESC_COMMAND_SEARCH_ADVANCED, DESC_COMMAND_SEARCH_ADVANCED_EPISODE]
+        HELP_COMMANDS
             .iter()
-            .map(|s| s
-                .chars()
-                .map(|c| ESCAPE_CHARS.get(&c).map(|c| c.to_string()).unwrap_or(c.to_string()))
-                .collect::<String>()
-            )
+            .map(|(names, summary, _)| markdown::escape(&format!("/{} - {}", names[0], summary)))
             .collect::<Vec<String>>()
-            .join("\n\n"),
+            .join("\n"),
+        markdown::escape("Usa /help {comando} per i dettagli e gli esempi di un comando, per esempio /help sa."),
         FOOTER_STRING,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Longest text Telegram accepts in a message.
+    const MAX_MESSAGE_LEN: usize = 4096;
+
+    #[test]
+    fn help_fits_in_a_message() {
+        assert!(HELP_MESSAGE.chars().count() < MAX_MESSAGE_LEN, "help is {} characters", HELP_MESSAGE.chars().count());
+        for (names, _, _) in HELP_COMMANDS {
+            let help = command_help(names[0]).unwrap();
+            assert!(help.chars().count() < MAX_MESSAGE_LEN, "help of /{} is {} characters", names[0], help.chars().count());
+        }
+    }
+
+    #[test]
+    fn command_help_by_alias() {
+        assert_eq!(command_help("sa"), command_help("/cercaAvanzato"));
+        assert_eq!(command_help("cae"), Some(escape_description(DESC_COMMAND_SEARCH_ADVANCED_EPISODE)));
+        assert_eq!(command_help("nope"), None);
+    }
+}
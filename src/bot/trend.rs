@@ -0,0 +1,204 @@
+//! Charts of `/trend`: how often a word or phrase is said over the history of the show.
+use std::{fmt::{self, Display, Formatter}, io::Cursor, sync::OnceLock};
+
+use chrono::{Datelike, Months, NaiveDate};
+use image::{ImageFormat, RgbImage};
+use plotters::{drawing::DrawingAreaErrorKind, prelude::*, style::register_font};
+
+use crate::{config::CONFIG, db::trends::TermTrend};
+
+/// Size of the chart, in pixels.
+const WIDTH: u32 = 1000;
+const HEIGHT: u32 = 500;
+/// Dates on the x axis.
+const X_LABELS: usize = 10;
+/// The links of the web pages.
+const COLOR: RGBColor = RGBColor(0xb3, 0x26, 0x1e);
+
+/// Whether the font of the labels has been loaded, once for all the charts.
+static FONT: OnceLock<Result<(), String>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrendScale {
+    Month,
+    Episode,
+}
+
+/// Query of `/trend`: the term, and the `per:mese` (the default) or `per:puntata` filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendQuery {
+    pub term: String,
+    pub scale: TrendScale,
+}
+
+impl TrendQuery {
+    pub fn parse(text: &str) -> Self {
+        let mut scale = TrendScale::Month;
+        let mut words = vec![];
+        for w in text.split_whitespace() {
+            match w.to_lowercase().as_str() {
+                "per:mese" | "per:month" => scale = TrendScale::Month,
+                "per:puntata" | "per:episodio" | "per:episode" => scale = TrendScale::Episode,
+                _ => words.push(w),
+            }
+        }
+        Self { term: words.join(" ").trim_matches('"').to_owned(), scale }
+    }
+}
+
+/// Points of the chart of `trend`: the occurrences in each episode, or in each month from the first episode to the
+/// last, by date.
+pub fn series(trend: &TermTrend, scale: TrendScale) -> Vec<(NaiveDate, u32)> {
+    let episodes = trend.episodes.iter().map(|e| (e.published_at.date_naive(), e.count));
+    match scale {
+        TrendScale::Episode => episodes.collect(),
+        TrendScale::Month => {
+            let mut months: Vec<(NaiveDate, u32)> = vec![];
+            for (date, count) in episodes {
+                let month = date.with_day(1).unwrap();
+                while months.last().is_some_and(|m| m.0 < month) {
+                    let next = months.last().unwrap().0 + Months::new(1);
+                    months.push((next, 0));
+                }
+                match months.last_mut() {
+                    Some(m) if m.0 == month => m.1 += count,
+                    _ => months.push((month, count)),
+                }
+            }
+            months
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ChartError {
+    Font(String),
+    Draw(String),
+    Encode(image::ImageError),
+}
+
+impl Display for ChartError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ChartError::Font(e) => write!(f, "font: {}", e),
+            ChartError::Draw(e) => write!(f, "drawing: {}", e),
+            ChartError::Encode(e) => write!(f, "encoding: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChartError {}
+
+impl<E: std::error::Error + Send + Sync> From<DrawingAreaErrorKind<E>> for ChartError {
+    fn from(e: DrawingAreaErrorKind<E>) -> Self {
+        ChartError::Draw(e.to_string())
+    }
+}
+
+impl From<image::ImageError> for ChartError {
+    fn from(e: image::ImageError) -> Self {
+        ChartError::Encode(e)
+    }
+}
+
+fn load_font() -> Result<(), ChartError> {
+    FONT.get_or_init(|| {
+        let bytes = std::fs::read(&CONFIG.trend.font).map_err(|e| format!("{}: {}", CONFIG.trend.font, e))?;
+        register_font("sans-serif", FontStyle::Normal, Box::leak(bytes.into_boxed_slice()))
+            .map_err(|_| format!("{}: not a TrueType font", CONFIG.trend.font))
+    })
+    .clone()
+    .map_err(ChartError::Font)
+}
+
+/// PNG line chart of `series`, titled `title`.
+pub fn render(title: &str, series: &[(NaiveDate, u32)], scale: TrendScale) -> Result<Vec<u8>, ChartError> {
+    load_font()?;
+    let first = series.first().map(|p| p.0).unwrap_or_default();
+    let days = |d: NaiveDate| (d - first).num_days();
+    let last = series.last().map_or(1, |p| days(p.0).max(1));
+    let max = series.iter().map(|p| p.1).max().unwrap_or(0).max(1);
+
+    let mut buf = vec![0; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buf, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 24))
+            .margin(20)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(0..last, 0..max + max / 10 + 1)?;
+        let label = |x: &i64| (first + chrono::Duration::days(*x)).format("%m/%Y").to_string();
+        chart
+            .configure_mesh()
+            .x_labels(X_LABELS)
+            .x_label_formatter(&label)
+            .y_desc("occorrenze")
+            .light_line_style(WHITE)
+            .draw()?;
+        chart.draw_series(LineSeries::new(series.iter().map(|p| (days(p.0), p.1)), COLOR.stroke_width(2)))?;
+        if scale == TrendScale::Episode {
+            chart.draw_series(series.iter().filter(|p| p.1 > 0).map(|p| Circle::new((days(p.0), p.1), 3, COLOR.filled())))?;
+        }
+        root.present()?;
+    }
+
+    let mut png = Cursor::new(vec![]);
+    RgbImage::from_raw(WIDTH, HEIGHT, buf).expect("buffer of the chart size").write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::db::trends::TermCount;
+
+    use super::*;
+
+    fn trend(counts: &[((i32, u32, u32), u32)]) -> TermTrend {
+        let episodes = counts
+            .iter()
+            .enumerate()
+            .map(|(i, &((y, m, d), count))| TermCount { episode_id: i as u32, published_at: Utc.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap(), count })
+            .collect();
+        TermTrend { term: "zelda".to_owned(), revision: 1, episodes }
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn parse_scale() {
+        assert_eq!(TrendQuery::parse("zelda"), TrendQuery { term: "zelda".to_owned(), scale: TrendScale::Month });
+        assert_eq!(TrendQuery::parse("zelda per:puntata"), TrendQuery { term: "zelda".to_owned(), scale: TrendScale::Episode });
+        assert_eq!(TrendQuery::parse("PER:Episode zelda"), TrendQuery { term: "zelda".to_owned(), scale: TrendScale::Episode });
+        // the last one wins
+        assert_eq!(TrendQuery::parse("zelda per:puntata per:mese").scale, TrendScale::Month);
+    }
+
+    #[test]
+    fn parse_phrases() {
+        assert_eq!(TrendQuery::parse("\"breath of the wild\" per:episodio").term, "breath of the wild");
+        assert_eq!(TrendQuery::parse("  breath   of the wild ").term, "breath of the wild");
+        assert_eq!(TrendQuery::parse("per:mese").term, "");
+    }
+
+    #[test]
+    fn series_by_episode() {
+        let t = trend(&[((2020, 1, 5), 2), ((2020, 1, 20), 0), ((2020, 3, 1), 1)]);
+        assert_eq!(series(&t, TrendScale::Episode), vec![(date(2020, 1, 5), 2), (date(2020, 1, 20), 0), (date(2020, 3, 1), 1)]);
+    }
+
+    #[test]
+    fn series_by_month_fills_the_gaps() {
+        let t = trend(&[((2020, 11, 5), 2), ((2020, 11, 20), 3), ((2021, 2, 1), 1)]);
+        assert_eq!(
+            series(&t, TrendScale::Month),
+            vec![(date(2020, 11, 1), 5), (date(2020, 12, 1), 0), (date(2021, 1, 1), 0), (date(2021, 2, 1), 1)],
+        );
+        assert!(series(&trend(&[]), TrendScale::Month).is_empty());
+    }
+}
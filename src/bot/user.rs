@@ -1,7 +1,5 @@
-use futures_util::TryStreamExt;
-use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
-use crate::db::{PPPData, PPPDatabase};
+use crate::db::PPPData;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BotUser {
@@ -10,7 +8,6 @@ pub struct BotUser {
     pub first_name: String,
     pub beta: bool,
     pub waitlist: bool,
-    pub notified: bool,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
@@ -28,7 +25,6 @@ impl From<&teloxide::types::User> for BotUser {
             first_name: u.first_name.clone(),
             waitlist: false,
             beta: false,
-            notified: false,
             timestamp: chrono::Utc::now(),
         }
     }
@@ -46,31 +42,3 @@ impl BotUser {
         }
     }
 }
-
-impl PPPDatabase {
-    pub async fn whitelisted(&self, id: i64) -> Result<bool, mongodb::error::Error> {
-        Ok(self.db
-            .collection::<()>("users")
-            .count_documents(doc! { "id": id, "beta": true })
-            .await? != 0)
-    }
-    
-    pub async fn waitlist(&self) -> Result<Vec<BotUser>, mongodb::error::Error> {
-        self.db
-            .collection::<BotUser>("users")
-            .find(doc! { "waitlist": true, "beta": false })
-            .await?
-            .try_collect()
-            .await
-    }
-
-    pub async fn beta_list(&self) -> Result<Vec<BotUser>, mongodb::error::Error> {
-        self.db
-            .collection::<BotUser>("users")
-            .find(doc! { "beta": true })
-            .await?
-            .try_collect()
-            .await
-    }
-}
-
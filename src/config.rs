@@ -1,19 +1,114 @@
 use std::{fs::read_to_string, io::{Read, Write}, path::Path, process::exit};
 
 use lazy_static::lazy_static;
-use log::{debug, error};
+use log::debug;
 use mongodb::options::ClientOptions;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Config {
     pub db: DbConfig,
     pub tg: TgConfig,
     pub import: ImportConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub web: WebConfig,
+    #[serde(default)]
+    pub index: IndexConfig,
+    #[serde(default)]
+    pub trend: TrendConfig,
+}
+
+/// In-process transcript index of `ppp_bot`, see `db::segments`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexConfig {
+    pub enabled: bool,
+    /// Seconds between two checks for transcripts written by other processes.
+    pub refresh_secs: u64,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self { enabled: true, refresh_secs: 300 }
+    }
+}
+
+/// Charts of `/trend`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrendConfig {
+    /// TrueType font of the labels. Without it the bot replies with the numbers only.
+    pub font: String,
+}
+
+impl Default for TrendConfig {
+    fn default() -> Self {
+        Self { font: "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_owned() }
+    }
+}
+
+/// Settings of `ppp_web`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebConfig {
+    pub listen: String,
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self { listen: "127.0.0.1:8001".to_owned() }
+    }
+}
+
+/// Settings of `ppp_api`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiConfig {
+    pub listen: String,
+    /// Keys accepted in the `X-Api-Key` header. With none configured every request is refused.
+    #[serde(default)]
+    pub keys: Vec<ApiKeyConfig>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:8000".to_owned(),
+            keys: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeyConfig {
+    /// Who the key was given to, for the logs.
+    pub name: String,
+    pub key: String,
+    /// Requests allowed per minute, with bursts up to the same amount.
+    #[serde(default = "default_rate_limit")]
+    pub per_minute: u32,
+}
+
+fn default_rate_limit() -> u32 {
+    60
+}
+
+/// Addresses of the Prometheus endpoints, see `metrics`. Disabled if unset.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct MetricsConfig {
+    /// e.g. `0.0.0.0:9100`
+    #[serde(default)]
+    pub bot: Option<String>,
+    #[serde(default)]
+    pub import: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DbConfig {
+    #[serde(default)]
+    pub backend: DbBackend,
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
     pub host: String,
     pub port: u16,
     pub user: String,
@@ -21,6 +116,19 @@ pub struct DbConfig {
     pub password: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DbBackend {
+    #[default]
+    Mongo,
+    /// Embedded database, requires the `sqlite` feature.
+    Sqlite,
+}
+
+fn default_sqlite_path() -> String {
+    "ppp.sqlite".to_owned()
+}
+
 impl DbConfig {
     pub fn client(&self) -> mongodb::Client {
         mongodb::Client::with_options(ClientOptions::builder()
@@ -37,6 +145,8 @@ impl DbConfig {
 impl Default for DbConfig {
     fn default() -> Self {
         Self {
+            backend: DbBackend::default(),
+            sqlite_path: default_sqlite_path(),
             host: "localhost".to_owned(),
             port: 27017,
             user: "ppp".to_owned(),
@@ -50,6 +160,9 @@ pub struct TgConfig {
     #[serde(default = "token_from_env")]
     pub token: String,
     pub admin: String,
+    /// Chat where `ppp_import` keeps a live progress message, disabled if unset.
+    #[serde(default)]
+    pub progress_chat: Option<i64>,
 }
 
 fn token_from_env() -> String {
@@ -79,6 +192,80 @@ pub struct ImportConfig {
     pub wav_dir: String,
     pub transcript_dir: String,
     pub transcriber_url: String,
+    #[serde(default)]
+    pub audio_backend: AudioBackend,
+    /// Retry with ffmpeg when the native decoder fails.
+    #[serde(default)]
+    pub ffmpeg_fallback: bool,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+}
+
+/// Settings of `ppp_import --daemon`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonConfig {
+    /// Seconds between two imports, ignored when `cron` is set.
+    pub interval: u64,
+    /// Cron expression with seconds, e.g. `0 0 */6 * * *`.
+    pub cron: Option<String>,
+    /// Unix socket of the control interface.
+    pub control_socket: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval: 3600,
+            cron: None,
+            control_socket: "ppp_import.sock".to_owned(),
+        }
+    }
+}
+
+/// How long the files of each artifact kind are kept, see `artifacts`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RetentionConfig {
+    #[serde(default)]
+    pub mp3: Retention,
+    #[serde(default)]
+    pub wav: Retention,
+    #[serde(default)]
+    pub transcript: Retention,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            mp3: Retention::Keep,
+            wav: Retention::Keep,
+            transcript: Retention::Keep,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum Retention {
+    #[default]
+    Keep,
+    /// Delete once the transcript of the episode is in the database.
+    DeleteAfterInsert,
+    /// Keep the files of the `count` most recent episodes.
+    KeepLast { count: usize },
+    /// Evict the least recently used files above `bytes` in total.
+    MaxSize { bytes: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioBackend {
+    /// Decode and resample in process, see `audio`.
+    #[default]
+    Native,
+    /// Run the `ffmpeg` executable.
+    Ffmpeg,
 }
 
 impl Default for ImportConfig {
@@ -89,6 +276,10 @@ impl Default for ImportConfig {
             wav_dir: "audio/wav".to_owned(),
             transcript_dir: "transcripts".to_owned(),
             transcriber_url: "http://localhost:8080/inference".to_owned(),
+            audio_backend: AudioBackend::default(),
+            ffmpeg_fallback: false,
+            retention: RetentionConfig::default(),
+            daemon: DaemonConfig::default(),
         }
     }
 }
@@ -0,0 +1,271 @@
+use std::{fmt::Display, path::Path, str::FromStr, sync::{Arc, Mutex}, time::Duration};
+
+use chrono::{DateTime, Utc};
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{UnixListener, UnixStream}, sync::{mpsc, watch}, task::JoinHandle};
+
+use crate::config::DaemonConfig;
+use crate::db::{lock_owner, Store, StoreError};
+
+/// When the daemon imports, from `DaemonConfig`.
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    pub fn from_config(config: &DaemonConfig) -> Result<Self, DaemonError> {
+        match &config.cron {
+            Some(c) => Ok(Self::Cron(Box::new(cron::Schedule::from_str(c)?))),
+            None => Ok(Self::Interval(Duration::from_secs(config.interval))),
+        }
+    }
+
+    /// The first run strictly after `after`.
+    pub fn next(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Interval(d) => Some(after + *d),
+            Self::Cron(c) => c.after(&after).next(),
+        }
+    }
+}
+
+/// Time a lock stays valid without being refreshed, so that a crashed importer doesn't block the next ones forever.
+const LOCK_TTL: Duration = Duration::from_secs(300);
+
+/// Database lock held for the duration of an import, refreshed in the background until released.
+pub struct RunLock {
+    db: Arc<dyn Store>,
+    name: &'static str,
+    owner: String,
+    refresh: JoinHandle<()>,
+    lost: watch::Receiver<bool>,
+}
+
+impl RunLock {
+    /// Take the lock `name`, `None` if another process holds it.
+    pub async fn acquire(db: Arc<dyn Store>, name: &'static str) -> Result<Option<Self>, StoreError> {
+        let owner = lock_owner();
+        if !db.acquire_lock(name, &owner, Utc::now() + LOCK_TTL).await? {
+            return Ok(None);
+        }
+        debug!("acquired lock {} as {}", name, owner);
+        let (tx, lost) = watch::channel(false);
+        let refresh = {
+            let (db, owner) = (db.clone(), owner.clone());
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(LOCK_TTL / 3).await;
+                    match db.acquire_lock(name, &owner, Utc::now() + LOCK_TTL).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            error!("lock {} was taken by someone else", name);
+                            let _ = tx.send(true);
+                            break;
+                        }
+                        Err(e) => warn!("couldn't refresh lock {}: {}", name, e),
+                    }
+                }
+            })
+        };
+        Ok(Some(Self { db, name, owner, refresh, lost }))
+    }
+
+    /// Resolves when another process takes the lock, which happens if it couldn't be refreshed before expiring: the
+    /// work it protects must stop.
+    pub async fn lost(&self) {
+        let mut lost = self.lost.clone();
+        if lost.wait_for(|l| *l).await.is_err() {
+            // the refresh stopped without losing the lock
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub async fn release(self) -> Result<(), StoreError> {
+        self.refresh.abort();
+        debug!("releasing lock {}", self.name);
+        self.db.release_lock(self.name, &self.owner).await
+    }
+}
+
+/// Commands accepted on the control socket, one per line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlCommand {
+    /// Start an import now.
+    Run,
+    /// Reply with the state of the daemon.
+    Status,
+    /// Stop after the current import, like SIGTERM.
+    Stop,
+}
+
+impl FromStr for ControlCommand {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "run" => Ok(Self::Run),
+            "status" => Ok(Self::Status),
+            "stop" => Ok(Self::Stop),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DaemonState {
+    pub running: bool,
+    pub next_run: Option<DateTime<Utc>>,
+    /// Id of the last import run.
+    pub last_run: Option<i64>,
+}
+
+impl Display for DaemonState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.running {
+            write!(f, "running")?;
+        } else {
+            write!(f, "idle")?;
+        }
+        if let Some(n) = self.next_run {
+            write!(f, ", next run at {}", n.format("%Y-%m-%d %H:%M:%S"))?;
+        }
+        if let Some(l) = self.last_run {
+            write!(f, ", last run {}", l)?;
+        }
+        Ok(())
+    }
+}
+
+/// Listen on the unix socket at `path`, forwarding `run` and `stop` to `tx`.
+/// A `run` received while an import is in progress is refused.
+pub fn serve_control(path: &Path, tx: mpsc::Sender<ControlCommand>, state: Arc<Mutex<DaemonState>>) -> Result<JoinHandle<()>, DaemonError> {
+    if path.exists() {
+        // left over by a previous instance
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("control interface listening on {}", path.display());
+    Ok(tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((s, _)) => s,
+                Err(e) => {
+                    warn!("control interface: {}", e);
+                    continue;
+                }
+            };
+            let (tx, state) = (tx.clone(), state.clone());
+            tokio::spawn(async move {
+                if let Err(e) = handle_control(stream, tx, state).await {
+                    warn!("control interface: {}", e);
+                }
+            });
+        }
+    }))
+}
+
+async fn handle_control(stream: UnixStream, tx: mpsc::Sender<ControlCommand>, state: Arc<Mutex<DaemonState>>) -> Result<(), DaemonError> {
+    let (r, mut w) = stream.into_split();
+    let mut lines = BufReader::new(r).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match line.trim().parse::<ControlCommand>() {
+            Ok(ControlCommand::Status) => state.lock().unwrap().to_string(),
+            Ok(ControlCommand::Run) if state.lock().unwrap().running => "already running".to_owned(),
+            Ok(c) => {
+                debug!("control command {:?}", c);
+                tx.send(c).await.map_err(|_| DaemonError::Closed)?;
+                "ok".to_owned()
+            }
+            Err(_) => format!("unknown command {:?}, expected run, status or stop", line.trim()),
+        };
+        w.write_all(format!("{}\n", reply).as_bytes()).await?;
+    }
+    Ok(())
+}
+
+/// Send `command` to the daemon listening at `path`, returning its reply.
+pub async fn send_control(path: &Path, command: &str) -> Result<String, DaemonError> {
+    let stream = UnixStream::connect(path).await?;
+    let (r, mut w) = stream.into_split();
+    w.write_all(format!("{}\n", command).as_bytes()).await?;
+    w.shutdown().await?;
+    Ok(BufReader::new(r).lines().next_line().await?.unwrap_or_default())
+}
+
+#[derive(Debug)]
+pub enum DaemonError {
+    Io(std::io::Error),
+    Cron(cron::error::Error),
+    Closed,
+}
+
+impl Display for DaemonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {}", e),
+            Self::Cron(e) => write!(f, "Invalid cron expression: {}", e),
+            Self::Closed => write!(f, "Daemon is shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+impl From<std::io::Error> for DaemonError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<cron::error::Error> for DaemonError {
+    fn from(e: cron::error::Error) -> Self {
+        Self::Cron(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn config(interval: u64, cron: Option<&str>) -> DaemonConfig {
+        DaemonConfig { interval, cron: cron.map(String::from), ..DaemonConfig::default() }
+    }
+
+    #[test]
+    fn interval_schedule() {
+        let s = Schedule::from_config(&config(600, None)).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 1, 30, 0).unwrap();
+        assert_eq!(s.next(now), Some(now + chrono::Duration::minutes(10)));
+    }
+
+    #[test]
+    fn cron_schedule() {
+        // the interval is ignored
+        let schedule = Schedule::from_config(&config(600, Some("0 0 */6 * * *"))).unwrap();
+        let next = |h, m, s| schedule.next(Utc.with_ymd_and_hms(2024, 1, 1, h, m, s).unwrap());
+        assert_eq!(next(1, 30, 0), Some(Utc.with_ymd_and_hms(2024, 1, 1, 6, 0, 0).unwrap()));
+        // strictly after
+        assert_eq!(next(6, 0, 0), Some(Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()));
+        assert_eq!(next(23, 59, 59), Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn invalid_cron() {
+        assert!(matches!(Schedule::from_config(&config(600, Some("every day"))), Err(DaemonError::Cron(_))));
+        // the seconds are required
+        assert!(Schedule::from_config(&config(600, Some("0 */6 * * *"))).is_err());
+    }
+
+    #[test]
+    fn control_commands() {
+        assert_eq!("run".parse(), Ok(ControlCommand::Run));
+        assert_eq!("status".parse(), Ok(ControlCommand::Status));
+        assert_eq!("stop".parse(), Ok(ControlCommand::Stop));
+        assert_eq!("STOP".parse::<ControlCommand>(), Err(()));
+        assert_eq!("".parse::<ControlCommand>(), Err(()));
+    }
+}
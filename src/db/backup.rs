@@ -0,0 +1,217 @@
+use std::{fmt::Display, fs::File, io::{BufRead, BufReader, Read, Write}, path::Path};
+
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+use mongodb::bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::migrations::{self, AppliedMigration};
+use super::{bulk_write, PPPData, Store, StoreError, COLLECTIONS};
+
+/// Version of the archive layout, bumped on incompatible changes.
+pub const ARCHIVE_FORMAT: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+
+/// First entry of every archive, describes the collections that follow it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub created_at: DateTime<Utc>,
+    /// Version of the crate that wrote the archive.
+    pub importer: String,
+    /// Database schema version at export time, see `migrations`.
+    pub schema: u32,
+    pub collections: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub key: String,
+    /// Name of the gzipped JSONL file inside the archive.
+    pub file: String,
+    pub documents: usize,
+}
+
+/// Resolve the collections requested on the command line, an empty list selects all of them.
+pub fn select_collections(names: &[String]) -> Result<Vec<(&'static str, &'static str)>, BackupError> {
+    if names.is_empty() {
+        return Ok(COLLECTIONS.to_vec());
+    }
+    names
+        .iter()
+        .map(|n| COLLECTIONS
+            .iter()
+            .find(|(c, _)| c == n)
+            .copied()
+            .ok_or_else(|| BackupError::UnknownCollection(n.clone())))
+        .collect()
+}
+
+/// Write `collections` of `db` to a tar archive at `path`: a manifest followed by one gzipped JSONL file per
+/// collection, each line a document in canonical extended JSON so that types survive the round trip.
+pub async fn export(db: &dyn Store, path: &Path, collections: &[(&str, &str)]) -> Result<Manifest, BackupError> {
+    let mut files = vec![];
+    let mut entries = vec![];
+    for (collection, key) in collections {
+        let docs = db.all_docs(collection).await?;
+        info!("exporting {} documents of {}", docs.len(), collection);
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        for doc in &docs {
+            serde_json::to_writer(&mut gz, &Bson::Document(doc.clone()).into_canonical_extjson())?;
+            gz.write_all(b"\n")?;
+        }
+        let file = format!("{}.jsonl.gz", collection);
+        files.push((file.clone(), gz.finish()?));
+        entries.push(ManifestEntry { name: collection.to_string(), key: key.to_string(), file, documents: docs.len() });
+    }
+
+    let manifest = Manifest {
+        format: ARCHIVE_FORMAT,
+        created_at: Utc::now(),
+        importer: env!("CARGO_PKG_VERSION").to_string(),
+        schema: migrations::version(db).await?,
+        collections: entries,
+    };
+
+    let mut tar = tar::Builder::new(File::create(path)?);
+    append(&mut tar, MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+    for (file, data) in files {
+        append(&mut tar, &file, &data)?;
+    }
+    tar.into_inner()?.flush()?;
+    Ok(manifest)
+}
+
+fn append(tar: &mut tar::Builder<File>, name: &str, data: &[u8]) -> Result<(), BackupError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Load `collections` from the archive at `path` into `db`, replacing documents with the same id.
+/// Collections of the selection missing from the archive are skipped with a warning.
+///
+/// The applied migrations are replaced as a whole instead, so that the schema version is the one of the archive and
+/// the migrations it lacks run again. The data derived from the episodes and the transcripts must be computed again
+/// afterwards, like `ppp_import rebuild` does.
+///
+/// Restoring the same archive twice leaves the database unchanged.
+pub async fn restore(db: &dyn Store, path: &Path, collections: &[(&str, &str)]) -> Result<Manifest, BackupError> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut entries = archive.entries()?;
+
+    let manifest: Manifest = match entries.next() {
+        Some(e) => {
+            let e = e?;
+            if e.path()?.to_str() != Some(MANIFEST) {
+                return Err(BackupError::Format(format!("first entry must be {}", MANIFEST)));
+            }
+            serde_json::from_reader(e)?
+        }
+        None => return Err(BackupError::Format("empty archive".to_string())),
+    };
+    if manifest.format > ARCHIVE_FORMAT {
+        return Err(BackupError::Format(format!("unsupported archive format {}", manifest.format)));
+    }
+    info!("restoring archive created at {} by {} (schema {})", manifest.created_at, manifest.importer, manifest.schema);
+    for (collection, _) in collections {
+        if !manifest.collections.iter().any(|c| c.name == *collection) {
+            warn!("collection {} not present in archive", collection);
+        }
+    }
+
+    db.ensure_index().await?;
+    bulk_write(db, async {
+        for e in entries {
+            let e = e?;
+            let file = e.path()?.to_string_lossy().to_string();
+            let Some(entry) = manifest.collections.iter().find(|c| c.file == file) else {
+                warn!("skipping unknown archive entry {}", file);
+                continue;
+            };
+            if !collections.iter().any(|(c, _)| *c == entry.name) {
+                debug!("skipping collection {}", entry.name);
+                continue;
+            }
+
+            let docs = read_jsonl(e)?;
+            if docs.len() != entry.documents {
+                return Err(BackupError::Format(format!("{}: expected {} documents, found {}", file, entry.documents, docs.len())));
+            }
+            info!("restoring {} documents of {}", docs.len(), entry.name);
+            if entry.name == AppliedMigration::COLLECTION {
+                db.drop_collection(AppliedMigration::COLLECTION).await?;
+            }
+            for doc in docs {
+                let id = doc.get(&entry.key).cloned().ok_or_else(|| StoreError::InvalidDocument(format!("{} document without {}", entry.name, entry.key)))?;
+                db.replace_doc(&entry.name, &entry.key, id, doc).await?;
+            }
+        }
+        Ok::<_, BackupError>(())
+    }).await?;
+    Ok(manifest)
+}
+
+fn read_jsonl(r: impl Read) -> Result<Vec<Document>, BackupError> {
+    let mut docs = vec![];
+    for line in BufReader::new(GzDecoder::new(r)).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match Bson::try_from(serde_json::from_str::<serde_json::Value>(&line)?) {
+            Ok(Bson::Document(d)) => docs.push(d),
+            Ok(_) => return Err(BackupError::Format("expected a document".to_string())),
+            Err(e) => return Err(BackupError::Format(e.to_string())),
+        }
+    }
+    Ok(docs)
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Store(StoreError),
+    Format(String),
+    UnknownCollection(String),
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {}", e),
+            Self::Json(e) => write!(f, "JSON error: {}", e),
+            Self::Store(e) => write!(f, "Database error: {}", e),
+            Self::Format(e) => write!(f, "Invalid archive: {}", e),
+            Self::UnknownCollection(c) => write!(f, "Unknown collection: {}", c),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<std::io::Error> for BackupError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for BackupError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<StoreError> for BackupError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
@@ -0,0 +1,321 @@
+//! Typo-tolerant lookup of the words said in the transcripts.
+//!
+//! The vocabulary of the transcripts is kept in two collections, updated by the backends whenever a transcript is
+//! written: `words` maps every folded word to the episodes saying it, `word_keys` maps the trigrams and the Italian
+//! phonetic code of each word back to it. A query word is looked up through its own keys and the candidates are
+//! then compared with it.
+use std::collections::{BTreeSet, HashMap};
+
+use lazy_static::lazy_static;
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use mongodb::bson::{self, Document};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{normalize::fold, transcript::EpisodeTranscript};
+
+use super::{segments::TranscriptRevision, PPPData, Store, StoreError};
+
+/// Shorter words aren't indexed.
+pub const MIN_WORD_LEN: usize = 3;
+/// Closeness of two different words with the same phonetic code.
+const PHONETIC_CLOSENESS: f64 = 0.8;
+
+lazy_static! {
+    /// Index updates read and replace shared documents, so they are serialized.
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexedWord {
+    pub word: String,
+    pub episodes: Vec<WordCount>,
+}
+
+impl PPPData for IndexedWord {
+    const COLLECTION: &'static str = "words";
+    const ID_KEY: &'static str = "word";
+    type IdType = String;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WordCount {
+    pub episode_id: u32,
+    pub count: u32,
+}
+
+/// A trigram, or a phonetic code prefixed with `=`, and the words having it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WordKey {
+    pub key: String,
+    pub words: Vec<String>,
+}
+
+impl PPPData for WordKey {
+    const COLLECTION: &'static str = "word_keys";
+    const ID_KEY: &'static str = "key";
+    type IdType = String;
+}
+
+/// An indexed word close to a query word.
+#[derive(Debug, Clone)]
+pub struct SimilarWord {
+    pub word: String,
+    /// From 0 to 1, 1 being the query word itself.
+    pub closeness: f64,
+    pub episodes: Vec<WordCount>,
+}
+
+/// Folded words of `text` long enough to be indexed.
+pub fn words(text: &str) -> Vec<String> {
+    fold(text)
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| w.len() >= MIN_WORD_LEN)
+        .map(String::from)
+        .collect()
+}
+
+/// Trigrams of a folded word, padded so that its start and end weigh more.
+pub fn trigrams(word: &str) -> Vec<String> {
+    let padded = format!("  {} ", word);
+    let mut t: Vec<String> = padded.as_bytes().windows(3).map(|w| String::from_utf8_lossy(w).into_owned()).collect();
+    t.sort();
+    t.dedup();
+    t
+}
+
+/// Rough phonetic code of a folded Italian word: letters written differently but pronounced the same (`ch`/`k`/`q`,
+/// `gn`/`n`, silent `h`, double consonants, ...) get the same code.
+pub fn phonetic(word: &str) -> String {
+    let w = word.as_bytes();
+    let at = |i: usize| w.get(i).copied().unwrap_or(0);
+    let soft = |c: u8| c == b'e' || c == b'i';
+    let vowel = |c: u8| matches!(c, b'a' | b'e' | b'i' | b'o' | b'u');
+    // the `i` of `cia`, `gia`, `scia`, `glia` only softens the consonant
+    let silent_i = |i: usize| at(i) == b'i' && vowel(at(i + 1));
+    let mut out = String::with_capacity(word.len());
+    let mut i = 0;
+    while i < w.len() {
+        let (code, len) = match at(i) {
+            b's' if at(i + 1) == b'c' && soft(at(i + 2)) => ("x", if silent_i(i + 2) { 3 } else { 2 }),
+            b'c' | b'k' | b'q' if at(i + 1) == b'h' => ("k", 2),
+            b'c' if soft(at(i + 1)) => ("c", if silent_i(i + 1) { 2 } else { 1 }),
+            b'c' | b'k' | b'q' => ("k", 1),
+            b'g' if at(i + 1) == b'h' => ("g", 2),
+            b'g' if at(i + 1) == b'l' && at(i + 2) == b'i' => ("l", if silent_i(i + 2) { 3 } else { 2 }),
+            b'g' if at(i + 1) == b'n' => ("n", 2),
+            b'g' if soft(at(i + 1)) => ("j", if silent_i(i + 1) { 2 } else { 1 }),
+            b'j' => ("j", 1),
+            b'h' => ("", 1),
+            b'y' => ("i", 1),
+            b'w' => ("v", 1),
+            b'x' => ("ks", 1),
+            _ => (&word[i..i + 1], 1),
+        };
+        if !out.ends_with(code) {
+            out.push_str(code);
+        }
+        i += len;
+    }
+    out
+}
+
+/// Keys under which `word` is indexed in `word_keys`.
+fn keys(word: &str) -> Vec<String> {
+    let mut k = trigrams(word);
+    k.push(format!("={}", phonetic(word)));
+    k
+}
+
+/// Largest edit distance between a query word and the words it matches.
+fn max_distance(word: &str) -> usize {
+    match word.len() {
+        n if n < MIN_WORD_LEN => 0,
+        ..=5 => 1,
+        _ => 2,
+    }
+}
+
+/// Closeness of the folded words `query` and `word`, `None` if they are too different.
+pub fn closeness(query: &str, word: &str) -> Option<f64> {
+    if query == word {
+        return Some(1.);
+    }
+    let similar = strsim::damerau_levenshtein(query, word) <= max_distance(query);
+    let sounds_alike = query.len() >= MIN_WORD_LEN && phonetic(query) == phonetic(word);
+    if !similar && !sounds_alike {
+        return None;
+    }
+    let c = strsim::normalized_damerau_levenshtein(query, word);
+    Some(if sounds_alike { c.max(PHONETIC_CLOSENESS) } else { c })
+}
+
+/// Occurrences of each word in `docs`, transcripts documents, by episode.
+fn count_words(docs: &[Document]) -> Result<HashMap<String, Vec<WordCount>>, StoreError> {
+    let mut words: HashMap<String, Vec<WordCount>> = HashMap::new();
+    for d in docs {
+        let t: EpisodeTranscript = bson::from_document(d.clone())?;
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for w in self::words(&t.data) {
+            *counts.entry(w).or_default() += 1;
+        }
+        for (w, count) in counts {
+            words.entry(w).or_default().push(WordCount { episode_id: t.episode_id, count });
+        }
+    }
+    Ok(words)
+}
+
+/// Add the transcripts `docs`, just written to the database, to the index.
+///
+/// The entries of the words and keys involved are read and written in one batch each. Finding the words a transcript
+/// written again no longer contains means going through the whole vocabulary, so the index is rebuilt instead:
+/// transcripts are seldom written twice.
+pub(crate) async fn index_transcripts(db: &dyn Store, docs: &[Document]) -> Result<(), StoreError> {
+    // the revision is bumped after indexing, only the transcripts written before have one
+    let ids = docs
+        .iter()
+        .map(|d| bson::from_bson(d.get(EpisodeTranscript::ID_KEY).cloned().unwrap_or(bson::Bson::Null)))
+        .collect::<Result<Vec<u32>, _>>()?;
+    if !db.get_many::<TranscriptRevision>(ids).await?.is_empty() {
+        debug!("transcripts written again, rebuilding the index");
+        return rebuild(db).await;
+    }
+    let words = count_words(docs)?;
+    let _lock = INDEX_LOCK.lock().await;
+    debug!("indexing {} words of {} transcripts", words.len(), docs.len());
+    let mut entries: HashMap<String, IndexedWord> = db
+        .get_many::<IndexedWord>(words.keys().cloned().collect())
+        .await?
+        .into_iter()
+        .map(|w| (w.word.clone(), w))
+        .collect();
+    let mut new_keys: HashMap<String, Vec<String>> = HashMap::new();
+    for (word, counts) in words {
+        match entries.get_mut(&word) {
+            Some(e) => {
+                e.episodes.retain(|c| !counts.iter().any(|n| n.episode_id == c.episode_id));
+                e.episodes.extend(counts);
+            }
+            None => {
+                for k in keys(&word) {
+                    new_keys.entry(k).or_default().push(word.clone());
+                }
+                entries.insert(word.clone(), IndexedWord { word, episodes: counts });
+            }
+        }
+    }
+    db.update_many_stateless(&entries.into_values().collect::<Vec<_>>()).await?;
+    let mut key_entries: HashMap<String, WordKey> = db
+        .get_many::<WordKey>(new_keys.keys().cloned().collect())
+        .await?
+        .into_iter()
+        .map(|k| (k.key.clone(), k))
+        .collect();
+    for (key, words) in new_keys {
+        key_entries.entry(key.clone()).or_insert_with(|| WordKey { key, words: vec![] }).words.extend(words);
+    }
+    db.update_many_stateless(&key_entries.into_values().collect::<Vec<_>>()).await
+}
+
+/// Build the index again from every transcript.
+pub async fn rebuild(db: &dyn Store) -> Result<(), StoreError> {
+    let docs = db.all_docs(EpisodeTranscript::COLLECTION).await?;
+    let words = count_words(&docs)?;
+    let mut keys: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for w in words.keys() {
+        for k in self::keys(w) {
+            keys.entry(k).or_default().insert(w.clone());
+        }
+    }
+    let _lock = INDEX_LOCK.lock().await;
+    info!("indexing {} words of {} transcripts", words.len(), docs.len());
+    db.drop_collection(IndexedWord::COLLECTION).await?;
+    db.drop_collection(WordKey::COLLECTION).await?;
+    let words: Vec<IndexedWord> = words.into_iter().map(|(word, episodes)| IndexedWord { word, episodes }).collect();
+    let keys: Vec<WordKey> = keys.into_iter().map(|(key, words)| WordKey { key, words: words.into_iter().collect() }).collect();
+    if !words.is_empty() {
+        db.insert_stateless(&words).await?;
+        db.insert_stateless(&keys).await?;
+    }
+    Ok(())
+}
+
+impl dyn Store + '_ {
+    /// Indexed words close to `word`, the closest first.
+    pub async fn similar_words(&self, word: &str) -> Result<Vec<SimilarWord>, StoreError> {
+        let query = fold(word);
+        let mut candidates = BTreeSet::new();
+        for k in keys(&query) {
+            if let Some(k) = self.get::<WordKey>(k).await? {
+                candidates.extend(k.words);
+            }
+        }
+        trace!("{} candidates for {}", candidates.len(), query);
+        let mut similar = vec![];
+        for w in candidates {
+            let Some(closeness) = closeness(&query, &w) else { continue };
+            // words no longer said in any episode are kept with no episodes
+            if let Some(e) = self.get::<IndexedWord>(w).await?.filter(|e| !e.episodes.is_empty()) {
+                similar.push(SimilarWord { word: e.word, closeness, episodes: e.episodes });
+            }
+        }
+        similar.sort_by(|a, b| b.closeness.total_cmp(&a.closeness).then_with(|| a.word.cmp(&b.word)));
+        Ok(similar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phonetic_spellings_of_the_same_sound() {
+        assert_eq!(phonetic("chiave"), phonetic("kiave"));
+        assert_eq!(phonetic("quadro"), phonetic("kuadro"));
+        // the `i` only softens the `c`
+        assert_eq!(phonetic("ciao"), "cao");
+        assert_eq!(phonetic("cielo"), phonetic("celo"));
+        assert_eq!(phonetic("giallo"), phonetic("jallo"));
+        assert_eq!(phonetic("sciarpa"), "xarpa");
+        assert_eq!(phonetic("scienza"), phonetic("scenza"));
+        assert_eq!(phonetic("gnocchi"), phonetic("nocki"));
+        assert_eq!(phonetic("figlio"), phonetic("filo"));
+        assert_eq!(phonetic("hotel"), phonetic("otel"));
+        assert_eq!(phonetic("pizza"), phonetic("piza"));
+        assert_eq!(phonetic("wario"), phonetic("vario"));
+        assert_eq!(phonetic("xbox"), "ksboks");
+    }
+
+    #[test]
+    fn phonetic_keeps_different_sounds_apart() {
+        // hard and soft c and g
+        assert_ne!(phonetic("cena"), phonetic("kena"));
+        assert_ne!(phonetic("gelo"), phonetic("ghelo"));
+        assert_ne!(phonetic("pizza"), phonetic("pazza"));
+    }
+
+    #[test]
+    fn closeness_of_typos() {
+        assert_eq!(closeness("nintendo", "nintendo"), Some(1.));
+        // one edit for short words, two for longer ones
+        assert!(closeness("mario", "maro").is_some());
+        assert!(closeness("mario", "moro").is_none());
+        assert!(closeness("nintendo", "nitnedo").is_some());
+        assert!(closeness("nintendo", "nitendoo").is_some());
+        assert!(closeness("nintendo", "sega").is_none());
+        // the closer, the higher
+        assert!(closeness("nintendo", "nintend").unwrap() > closeness("nintendo", "nitend").unwrap());
+    }
+
+    #[test]
+    fn closeness_of_words_sounding_alike() {
+        // too many edits, but the same sound
+        let c = closeness("kiakkiera", "chiacchiera").unwrap();
+        assert!(c >= PHONETIC_CLOSENESS);
+        assert!(c < 1.);
+        // short words only match exactly
+        assert_eq!(closeness("ka", "ca"), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, doc, Bson, Document};
+use tokio::sync::RwLock;
+use crate::{bot::{BotUser, MetaQuery}, normalize::fold, spreaker::Episode, transcript::EpisodeTranscript};
+
+use super::{transcripts_written, PPPData, ScoredEpisode, Store, StoreError, LOCKS};
+
+/// In-memory implementation of `Store`, meant for tests and throwaway instances.
+///
+/// Every collection is a plain list of documents, ids are unique only in the collections that have a unique index in
+/// MongoDB (`episodes` and `users`).
+#[derive(Default)]
+pub struct MemoryDatabase {
+    collections: RwLock<HashMap<String, Vec<Document>>>,
+}
+
+const UNIQUE_COLLECTIONS: [&str; 2] = [Episode::COLLECTION, BotUser::COLLECTION];
+
+/// Compare two bson values, treating integers of different widths as equal.
+fn bson_eq(a: &Bson, b: &Bson) -> bool {
+    match (bson_int(a), bson_int(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn bson_int(v: &Bson) -> Option<i64> {
+    match v {
+        Bson::Int32(i) => Some(*i as i64),
+        Bson::Int64(i) => Some(*i),
+        _ => None,
+    }
+}
+
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn typed<T: PPPData>(&self) -> Result<Vec<T>, StoreError> {
+        self.collections
+            .read()
+            .await
+            .get(T::COLLECTION)
+            .map(|c| c.iter().map(|d| bson::from_document(d.clone()).map_err(StoreError::from)).collect())
+            .unwrap_or(Ok(vec![]))
+    }
+}
+
+#[async_trait]
+impl Store for MemoryDatabase {
+    async fn ensure_index(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn get_doc(&self, collection: &str, key: &str, id: Bson) -> Result<Option<Document>, StoreError> {
+        Ok(self.collections
+            .read()
+            .await
+            .get(collection)
+            .and_then(|c| c.iter().find(|d| d.get(key).is_some_and(|v| bson_eq(v, &id))).cloned()))
+    }
+
+    async fn get_ids_raw(&self, collection: &str, key: &str) -> Result<Vec<i64>, StoreError> {
+        Ok(self.collections
+            .read()
+            .await
+            .get(collection)
+            .map(|c| c.iter().filter_map(|d| d.get(key).and_then(bson_int)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn all_docs(&self, collection: &str) -> Result<Vec<Document>, StoreError> {
+        Ok(self.collections.read().await.get(collection).cloned().unwrap_or_default())
+    }
+
+    async fn insert_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        let mut collections = self.collections.write().await;
+        let c = collections.entry(collection.to_owned()).or_default();
+        if UNIQUE_COLLECTIONS.contains(&collection) {
+            let mut seen: Vec<&Bson> = c.iter().filter_map(|d| d.get(key)).collect();
+            for id in docs.iter().filter_map(|d| d.get(key)) {
+                if seen.iter().any(|s| bson_eq(s, id)) {
+                    return Err(StoreError::DuplicateKey(collection.to_owned()))
+                }
+                seen.push(id);
+            }
+        }
+        c.extend(docs);
+        drop(collections);
+        match transcripts {
+            Some(t) => transcripts_written(self, &t).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn replace_doc(&self, collection: &str, key: &str, id: Bson, doc: Document) -> Result<(), StoreError> {
+        let transcript = (collection == EpisodeTranscript::COLLECTION).then(|| doc.clone());
+        let mut collections = self.collections.write().await;
+        let c = collections.entry(collection.to_owned()).or_default();
+        match c.iter_mut().find(|d| d.get(key).is_some_and(|v| bson_eq(v, &id))) {
+            Some(d) => *d = doc,
+            None => c.push(doc),
+        }
+        drop(collections);
+        if let Some(t) = transcript {
+            transcripts_written(self, &[t]).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_doc(&self, collection: &str, key: &str, id: Bson) -> Result<(), StoreError> {
+        if let Some(c) = self.collections.write().await.get_mut(collection) {
+            c.retain(|d| !d.get(key).is_some_and(|v| bson_eq(v, &id)));
+        }
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection: &str) -> Result<(), StoreError> {
+        self.collections.write().await.remove(collection);
+        Ok(())
+    }
+
+    /// Approximates the semantics of MongoDB `$text` queries: unquoted words are or-ed, `"quoted phrases"` are
+    /// required and `-words` exclude a transcript. Episodes are sorted by number of occurrences.
+    async fn search_transcripts(&self, text: &str) -> Result<Vec<ScoredEpisode>, StoreError> {
+        let query = fold(text);
+        let mut phrases = vec![];
+        let mut rest = String::new();
+        for (i, part) in query.split('"').enumerate() {
+            if i % 2 == 1 {
+                phrases.push(part.trim().to_owned());
+            } else {
+                rest.push_str(part);
+                rest.push(' ');
+            }
+        }
+        let (excluded, words): (Vec<&str>, Vec<&str>) = rest.split_whitespace().partition(|w| w.starts_with('-'));
+        let excluded: Vec<&str> = excluded.into_iter().map(|w| &w[1..]).filter(|w| !w.is_empty()).collect();
+
+        let mut scored = vec![];
+        for t in self.typed::<EpisodeTranscript>().await? {
+            let data = fold(&t.data);
+            if excluded.iter().any(|w| data.contains(w)) || !phrases.iter().all(|p| data.contains(p.as_str())) {
+                continue
+            }
+            let score: usize = words.iter().copied().chain(phrases.iter().map(|p| p.as_str())).map(|w| data.matches(w).count()).sum();
+            if score > 0 {
+                scored.push((score, t.episode_id));
+            }
+        }
+        scored.sort_by_key(|s| std::cmp::Reverse(s.0));
+        let mut episodes: HashMap<u32, Episode> = self.typed::<Episode>().await?.into_iter().map(|e| (e.id, e)).collect();
+        Ok(scored.into_iter().filter_map(|(score, id)| episodes.remove(&id).map(|episode| ScoredEpisode { episode, score: score as f64 })).collect())
+    }
+
+    async fn search_episodes(&self, query: &MetaQuery) -> Result<Vec<Episode>, StoreError> {
+        Ok(self.typed::<Episode>().await?.into_iter().filter(|e| query.matches(e)).collect())
+    }
+
+    async fn find_episode_by_title(&self, pattern: &str) -> Result<Option<Episode>, StoreError> {
+        let r = match regex::RegexBuilder::new(pattern).case_insensitive(true).build() {
+            Ok(r) => r,
+            Err(_) => return Ok(None),
+        };
+        Ok(self.typed::<Episode>().await?.into_iter().find(|e| r.is_match(&e.title)))
+    }
+
+    async fn whitelisted(&self, id: i64) -> Result<bool, StoreError> {
+        Ok(self.typed::<BotUser>().await?.iter().any(|u| u.id == id && u.beta))
+    }
+
+    async fn waitlist(&self) -> Result<Vec<BotUser>, StoreError> {
+        Ok(self.typed::<BotUser>().await?.into_iter().filter(|u| u.waitlist && !u.beta).collect())
+    }
+
+    async fn beta_list(&self) -> Result<Vec<BotUser>, StoreError> {
+        Ok(self.typed::<BotUser>().await?.into_iter().filter(|u| u.beta).collect())
+    }
+
+    async fn acquire_lock(&self, name: &str, owner: &str, expires: DateTime<Utc>) -> Result<bool, StoreError> {
+        let mut collections = self.collections.write().await;
+        let c = collections.entry(LOCKS.to_owned()).or_default();
+        let lock = doc!{"id": name, "owner": owner, "expires_at": bson::DateTime::from_millis(expires.timestamp_millis())};
+        match c.iter_mut().find(|d| d.get_str("id") == Ok(name)) {
+            Some(d) if d.get_str("owner") == Ok(owner) || d.get_datetime("expires_at").is_ok_and(|e| *e < bson::DateTime::now()) => *d = lock,
+            Some(_) => return Ok(false),
+            None => c.push(lock),
+        }
+        Ok(true)
+    }
+
+    async fn release_lock(&self, name: &str, owner: &str) -> Result<(), StoreError> {
+        if let Some(c) = self.collections.write().await.get_mut(LOCKS) {
+            c.retain(|d| d.get_str("id") != Ok(name) || d.get_str("owner") != Ok(owner));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+pub mod backup;
+pub mod fuzzy;
+mod memory;
+mod mongo;
+pub mod segments;
+pub mod similarity;
+pub mod stats;
+pub mod topics;
+pub mod trends;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use memory::MemoryDatabase;
+pub use mongo::PPPDatabase;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteDatabase;
+
+use std::{fmt::Display, future::Future, sync::Arc};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use mongodb::bson::{self, Bson, Document};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use segments::SegmentIndex;
+use crate::{bot::{BotUser, EpisodeOffsetMatch, MetaQuery}, config::{DbBackend, DbConfig}, migrations::AppliedMigration, normalize::fold, spreaker::{Episode, Show}, status::ImportRun, transcript::EpisodeTranscript};
+
+pub trait PPPData: Serialize + DeserializeOwned + std::marker::Send + std::marker::Sync {
+    const COLLECTION: &'static str;
+    const ID_KEY: &'static str;
+    type IdType: DeserializeOwned + std::fmt::Display + std::marker::Send + std::marker::Sync;
+}
+
+/// Storage backend of the bot.
+///
+/// The trait works on raw BSON documents so that it can be used as a trait object, typed access to `PPPData` is
+/// provided by the inherent methods on `dyn Store` (`get`, `insert_stateless`, ...).
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Create the indexes (or tables) needed by the queries below.
+    async fn ensure_index(&self) -> Result<(), StoreError>;
+
+    async fn get_doc(&self, collection: &str, key: &str, id: Bson) -> Result<Option<Document>, StoreError>;
+    /// Documents with `key` in `ids`, in no particular order, the missing ones are skipped.
+    ///
+    /// The default implementation gets them one by one, backends should override it with a single query.
+    async fn get_docs(&self, collection: &str, key: &str, ids: Vec<Bson>) -> Result<Vec<Document>, StoreError> {
+        let mut docs = Vec::with_capacity(ids.len());
+        for id in ids {
+            docs.extend(self.get_doc(collection, key, id).await?);
+        }
+        Ok(docs)
+    }
+    async fn get_ids_raw(&self, collection: &str, key: &str) -> Result<Vec<i64>, StoreError>;
+    async fn all_docs(&self, collection: &str) -> Result<Vec<Document>, StoreError>;
+    /// Insert new documents, `key` is the name of the id field of the collection.
+    async fn insert_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError>;
+    /// Replace the document with `key == id`, inserting it if missing.
+    async fn replace_doc(&self, collection: &str, key: &str, id: Bson, doc: Document) -> Result<(), StoreError>;
+    /// `replace_doc` for each of `docs`, with `key` their id field.
+    ///
+    /// The default implementation replaces them one by one, backends should override it with a single write.
+    async fn replace_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        for doc in docs {
+            let id = doc.get(key).cloned().ok_or_else(|| StoreError::InvalidDocument(format!("missing key {}", key)))?;
+            self.replace_doc(collection, key, id, doc).await?;
+        }
+        Ok(())
+    }
+    /// Remove the document with `key == id`, if any. The data derived from the transcripts isn't updated, so it's
+    /// not meant for them.
+    async fn delete_doc(&self, collection: &str, key: &str, id: Bson) -> Result<(), StoreError>;
+    /// Remove every document of a collection.
+    async fn drop_collection(&self, collection: &str) -> Result<(), StoreError>;
+
+    /// Full-text search across all transcripts, returns the matching episodes, most relevant first.
+    async fn search_transcripts(&self, text: &str) -> Result<Vec<ScoredEpisode>, StoreError>;
+    /// Segments of the transcript of `episode_id` matching the full-text query `text`, best matches first.
+    ///
+    /// The default implementation loads the transcript and ranks its segments by the number of query words they
+    /// contain, backends with a full-text index should override it.
+    async fn transcript_snippets(&self, episode_id: u32, text: &str, limit: usize) -> Result<Vec<EpisodeOffsetMatch>, StoreError> {
+        let t: EpisodeTranscript = match self.get_doc(EpisodeTranscript::COLLECTION, EpisodeTranscript::ID_KEY, Bson::Int64(episode_id as i64)).await? {
+            Some(d) => bson::from_document(d)?,
+            None => return Ok(vec![]),
+        };
+        let words: Vec<String> = fold(text)
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(String::from)
+            .collect();
+        let mut scored: Vec<(usize, EpisodeOffsetMatch)> = t
+            .segments()
+            .into_iter()
+            .filter_map(|(time, s)| {
+                let norm = fold(s);
+                let score: usize = words.iter().map(|w| norm.matches(w.as_str()).count()).sum();
+                (score > 0).then(|| (score, EpisodeOffsetMatch { time: time.clone(), hint: s.trim().to_owned(), highlight: None }))
+            })
+            .collect();
+        scored.sort_by_key(|(s, _)| std::cmp::Reverse(*s));
+        Ok(scored.into_iter().take(limit).map(|(_, m)| m).collect())
+    }
+    /// Search episodes by their metadata.
+    async fn search_episodes(&self, query: &MetaQuery) -> Result<Vec<Episode>, StoreError>;
+    /// First episode whose title matches the case-insensitive regex `pattern`.
+    async fn find_episode_by_title(&self, pattern: &str) -> Result<Option<Episode>, StoreError>;
+
+    async fn whitelisted(&self, id: i64) -> Result<bool, StoreError>;
+    async fn waitlist(&self) -> Result<Vec<BotUser>, StoreError>;
+    async fn beta_list(&self) -> Result<Vec<BotUser>, StoreError>;
+
+    /// Atomically take the lock `name` for `owner` until `expires`, or extend it if `owner` already holds it.
+    /// Returns `false` if another owner holds a lock that hasn't expired yet.
+    async fn acquire_lock(&self, name: &str, owner: &str, expires: DateTime<Utc>) -> Result<bool, StoreError>;
+    /// Release the lock `name` if held by `owner`.
+    async fn release_lock(&self, name: &str, owner: &str) -> Result<(), StoreError>;
+
+    /// The database command run for `query` and its execution plan, `None` if the backend can't explain queries.
+    async fn explain(&self, _query: ExplainQuery<'_>) -> Result<Option<Explanation>, StoreError> {
+        Ok(None)
+    }
+
+    /// In-process index of the transcripts kept by the store, see `segments::IndexedStore`.
+    fn segment_index(&self) -> Option<&SegmentIndex> {
+        None
+    }
+}
+
+/// An episode found by `Store::search_transcripts`.
+#[derive(Debug, Deserialize)]
+pub struct ScoredEpisode {
+    pub episode: Episode,
+    /// Relevance computed by the backend, higher is better. Only comparable within the same search.
+    pub score: f64,
+}
+
+/// Queries run by the search functions, see `Store::explain`.
+#[derive(Debug, Clone, Copy)]
+pub enum ExplainQuery<'a> {
+    /// `search_transcripts`
+    Transcripts(&'a str),
+    /// `search_episodes`
+    Episodes(&'a MetaQuery),
+    /// `find_episode_by_title`
+    EpisodeByTitle(&'a str),
+    /// Fetching the transcript of an episode
+    Transcript(u32),
+}
+
+#[derive(Debug)]
+pub struct Explanation {
+    pub command: Document,
+    pub plan: Document,
+}
+
+/// Collection of the locks taken with `Store::acquire_lock`, documents are `{id, owner, expires_at}`.
+pub const LOCKS: &str = "locks";
+
+/// Owner of the locks taken by this process.
+pub fn lock_owner() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_owned());
+    format!("{}:{}", host, std::process::id())
+}
+
+impl dyn Store + '_ {
+    pub async fn get_ids<T>(&self) -> Result<Vec<u32>, StoreError> where T: PPPData {
+        Ok(self.get_ids_raw(T::COLLECTION, T::ID_KEY).await?.into_iter().map(|v| v as u32).collect())
+    }
+
+    pub async fn get<T>(&self, id: T::IdType) -> Result<Option<T>, StoreError> where T: PPPData, <T as PPPData>::IdType: Into<Bson> {
+        debug!("get {} from collection {} from db", id, T::COLLECTION);
+        match self.get_doc(T::COLLECTION, T::ID_KEY, id.into()).await? {
+            Some(d) => Ok(Some(bson::from_document(d)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The documents of `ids` found in the collection, in no particular order.
+    pub async fn get_many<T>(&self, ids: Vec<T::IdType>) -> Result<Vec<T>, StoreError> where T: PPPData, <T as PPPData>::IdType: Into<Bson> {
+        debug!("get {} documents from collection {} from db", ids.len(), T::COLLECTION);
+        self.get_docs(T::COLLECTION, T::ID_KEY, ids.into_iter().map(Into::into).collect())
+            .await?
+            .into_iter()
+            .map(|d| bson::from_document(d).map_err(StoreError::from))
+            .collect()
+    }
+
+    pub async fn get_all<T>(&self) -> Result<Vec<T>, StoreError> where T: PPPData {
+        self.all_docs(T::COLLECTION)
+            .await?
+            .into_iter()
+            .map(|d| bson::from_document(d).map_err(StoreError::from))
+            .collect()
+    }
+
+    pub async fn insert_stateless<T>(&self, data: &[T]) -> Result<(), StoreError> where T: PPPData {
+        let docs = data.iter().map(bson::to_document).collect::<Result<Vec<_>, _>>()?;
+        self.insert_docs(T::COLLECTION, T::ID_KEY, docs).await
+    }
+
+    pub async fn update_one_stateless<T>(&self, id: T::IdType, data: &T) -> Result<(), StoreError> where T: PPPData, <T as PPPData>::IdType: Into<Bson> {
+        self.replace_doc(T::COLLECTION, T::ID_KEY, id.into(), bson::to_document(data)?).await
+    }
+
+    /// Replace (or insert) all of `data` at once.
+    pub async fn update_many_stateless<T>(&self, data: &[T]) -> Result<(), StoreError> where T: PPPData {
+        let docs = data.iter().map(bson::to_document).collect::<Result<Vec<_>, _>>()?;
+        self.replace_docs(T::COLLECTION, T::ID_KEY, docs).await
+    }
+
+    pub async fn delete<T>(&self, id: T::IdType) -> Result<(), StoreError> where T: PPPData, <T as PPPData>::IdType: Into<Bson> {
+        self.delete_doc(T::COLLECTION, T::ID_KEY, id.into()).await
+    }
+}
+
+/// Update the data derived from the transcripts `docs`, just written to the transcripts collection by a backend.
+/// Within `bulk_write` the fuzzy index is left alone, it's rebuilt at the end.
+pub(crate) async fn transcripts_written(db: &dyn Store, docs: &[Document]) -> Result<(), StoreError> {
+    if BULK_WRITE.try_with(|_| ()).is_err() {
+        fuzzy::index_transcripts(db, docs).await?;
+    }
+    segments::bump_revisions(db, docs).await
+}
+
+tokio::task_local! {
+    /// Set while running `bulk_write`.
+    static BULK_WRITE: ();
+}
+
+/// Run `f`, which writes many transcripts to `db`, rebuilding the fuzzy index once at the end instead of updating it
+/// for each transcript. The index is rebuilt even if `f` fails, as some transcripts may have been written.
+pub(crate) async fn bulk_write<T, E: From<StoreError>>(db: &dyn Store, f: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    if BULK_WRITE.try_with(|_| ()).is_ok() {
+        // the outer `bulk_write` rebuilds the index
+        return f.await;
+    }
+    let r = BULK_WRITE.scope((), f).await;
+    fuzzy::rebuild(db).await?;
+    r
+}
+
+/// Connect to the database described by the configuration.
+pub fn connect(config: &DbConfig) -> Arc<dyn Store> {
+    match config.backend {
+        DbBackend::Mongo => Arc::new(PPPDatabase::new(config)),
+        #[cfg(feature = "sqlite")]
+        DbBackend::Sqlite => Arc::new(SqliteDatabase::open(&config.sqlite_path).expect("Failed to open sqlite database")),
+        #[cfg(not(feature = "sqlite"))]
+        DbBackend::Sqlite => panic!("the sqlite backend requires the `sqlite` feature"),
+    }
+}
+
+/// Collections holding `PPPData`, as `(collection, id key)` pairs.
+pub const COLLECTIONS: [(&str, &str); 6] = [
+    (Episode::COLLECTION, Episode::ID_KEY),
+    (EpisodeTranscript::COLLECTION, EpisodeTranscript::ID_KEY),
+    (BotUser::COLLECTION, BotUser::ID_KEY),
+    (Show::COLLECTION, Show::ID_KEY),
+    (AppliedMigration::COLLECTION, AppliedMigration::ID_KEY),
+    (ImportRun::COLLECTION, ImportRun::ID_KEY),
+];
+
+/// Copy every collection of `from` into `to`, replacing documents with the same id.
+/// Running it twice is harmless, so it can be used to resume an interrupted copy.
+pub async fn copy_store(from: &dyn Store, to: &dyn Store) -> Result<(), StoreError> {
+    to.ensure_index().await?;
+    bulk_write(to, async {
+        for (collection, key) in COLLECTIONS {
+            let docs = from.all_docs(collection).await?;
+            info!("copying {} documents of {}", docs.len(), collection);
+            for doc in docs {
+                let id = doc.get(key).cloned().ok_or_else(|| StoreError::InvalidDocument(format!("{} document without {}", collection, key)))?;
+                to.replace_doc(collection, key, id, doc).await?;
+            }
+        }
+        Ok(())
+    }).await
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Mongo(mongodb::error::Error),
+    Serialize(bson::ser::Error),
+    Deserialize(bson::de::Error),
+    DuplicateKey(String),
+    InvalidDocument(String),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mongo(e) => write!(f, "MongoDB error: {}", e),
+            Self::Serialize(e) => write!(f, "Serialization error: {}", e),
+            Self::Deserialize(e) => write!(f, "Deserialization error: {}", e),
+            Self::DuplicateKey(k) => write!(f, "Duplicate key: {}", k),
+            Self::InvalidDocument(e) => write!(f, "Invalid document: {}", e),
+            #[cfg(feature = "sqlite")]
+            Self::Sqlite(e) => write!(f, "SQLite error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<mongodb::error::Error> for StoreError {
+    fn from(e: mongodb::error::Error) -> Self {
+        Self::Mongo(e)
+    }
+}
+
+impl From<bson::ser::Error> for StoreError {
+    fn from(e: bson::ser::Error) -> Self {
+        Self::Serialize(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Sqlite(e)
+    }
+}
+
+impl From<bson::de::Error> for StoreError {
+    fn from(e: bson::de::Error) -> Self {
+        Self::Deserialize(e)
+    }
+}
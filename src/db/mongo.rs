@@ -0,0 +1,295 @@
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use chrono::{DateTime, Utc};
+use mongodb::{bson::{self, doc, from_document, Bson, Document}, error::{ErrorKind, WriteFailure}, options::IndexOptions, Database, IndexModel};
+use futures_util::stream::{StreamExt, TryStreamExt};
+use crate::{bot::{BotUser, MetaQuery}, config::DbConfig, spreaker::Episode, transcript::EpisodeTranscript};
+
+use super::{fuzzy::{IndexedWord, WordKey}, segments::TranscriptRevision, similarity::EpisodeVector, stats::ShowStats, trends::TermTrend, transcripts_written, ExplainQuery, Explanation, PPPData, ScoredEpisode, Store, StoreError, LOCKS};
+
+/// Aggregation of `search_transcripts`: episodes whose transcript matches the full-text query `text`, as
+/// `ScoredEpisode` documents sorted by text score.
+fn transcripts_pipeline(text: &str) -> Vec<Document> {
+    vec![
+        doc!{"$match": {"$text": {"$search": text}}},
+        doc!{"$project": {"episode_id": 1, "_id": 0, "score": {"$meta": "textScore"}}},
+        doc!{"$sort": {"score": -1}},
+        doc!{"$lookup": {"from": "episodes", "localField": "episode_id", "foreignField": "id", "as": "episodeDetails"}},
+        doc!{"$unwind": "$episodeDetails"},
+        doc!{"$replaceRoot": {"newRoot": {"episode": "$episodeDetails", "score": "$score"}}},
+    ]
+}
+
+fn title_filter(pattern: &str) -> Document {
+    doc!{"title": mongodb::bson::Regex { pattern: pattern.to_owned(), options: "i".to_string() }}
+}
+
+/// MongoDB implementation of `Store`.
+/// Documents replaced by each command of `replace_docs`.
+const REPLACE_BATCH: usize = 500;
+
+pub struct PPPDatabase {
+    pub(crate) db: Database,
+}
+
+impl PPPDatabase {
+    pub fn new(config: &DbConfig) -> Self {
+        Self {
+            db: config.client().database("ppp"),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for PPPDatabase {
+    async fn ensure_index(&self) -> Result<(), StoreError> {
+        self.db
+            .collection::<()>("transcripts")
+            .create_index(IndexModel::builder()
+            .keys(doc!{"data": "text"})
+            .options(IndexOptions::builder().default_language("italian".to_owned()).build())
+            .build()
+        ).await?;
+        self.db
+            .collection::<()>("episodes")
+            .create_index(IndexModel::builder()
+                .keys(doc!{"id": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build()
+        ).await?;
+        self.db
+            .collection::<()>("users")
+            .create_index(IndexModel::builder()
+                .keys(doc!{"id": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build()
+        ).await?;
+        self.db
+            .collection::<()>(LOCKS)
+            .create_index(IndexModel::builder()
+                .keys(doc!{"id": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build()
+        ).await?;
+        for (collection, key) in [
+            (IndexedWord::COLLECTION, IndexedWord::ID_KEY),
+            (WordKey::COLLECTION, WordKey::ID_KEY),
+            (TranscriptRevision::COLLECTION, TranscriptRevision::ID_KEY),
+            (EpisodeVector::COLLECTION, EpisodeVector::ID_KEY),
+            (TermTrend::COLLECTION, TermTrend::ID_KEY),
+            (ShowStats::COLLECTION, ShowStats::ID_KEY),
+        ] {
+            self.db
+                .collection::<()>(collection)
+                .create_index(IndexModel::builder()
+                    .keys(doc!{key: 1})
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build()
+            ).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_doc(&self, collection: &str, key: &str, id: Bson) -> Result<Option<Document>, StoreError> {
+        Ok(self.db
+            .collection::<Document>(collection)
+            .find_one(doc!{key: id})
+            .await?)
+    }
+
+    async fn get_docs(&self, collection: &str, key: &str, ids: Vec<Bson>) -> Result<Vec<Document>, StoreError> {
+        Ok(self.db
+            .collection::<Document>(collection)
+            .find(doc!{key: {"$in": ids}})
+            .projection(doc!{"_id": 0})
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn get_ids_raw(&self, collection: &str, key: &str) -> Result<Vec<i64>, StoreError> {
+        Ok(self.db
+            .collection::<Document>(collection)
+            .aggregate(vec![
+                doc!{"$match": {}},
+                doc!{"$project": {"_id": 0, key: 1}},
+            ])
+            .await?
+            .map(|d| d.unwrap().get_i64(key))
+            .try_collect::<Vec<i64>>()
+            .await
+            .map_err(mongodb::error::Error::custom)?)
+    }
+
+    async fn all_docs(&self, collection: &str) -> Result<Vec<Document>, StoreError> {
+        Ok(self.db
+            .collection::<Document>(collection)
+            .find(doc!{})
+            .projection(doc!{"_id": 0})
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn insert_docs(&self, collection: &str, _key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        match self.db
+            .collection::<Document>(collection)
+            .insert_many(docs)
+            .await {
+            Ok(_) => match transcripts {
+                Some(t) => transcripts_written(self, &t).await,
+                None => Ok(()),
+            },
+            Err(e) => match *e.kind {
+                ErrorKind::InsertMany(ref f) if f.write_errors.as_ref().is_some_and(|w| w.iter().any(|w| w.code == 11000)) => {
+                    Err(StoreError::DuplicateKey(collection.to_owned()))
+                }
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == 11000 => Err(StoreError::DuplicateKey(collection.to_owned())),
+                _ => Err(e.into()),
+            }
+        }
+    }
+
+    async fn replace_doc(&self, collection: &str, key: &str, id: Bson, doc: Document) -> Result<(), StoreError> {
+        let transcript = (collection == EpisodeTranscript::COLLECTION).then(|| doc.clone());
+        self.db
+            .collection::<Document>(collection)
+            .replace_one(doc!{key: id}, doc)
+            .upsert(true)
+            .await?;
+        if let Some(t) = transcript {
+            transcripts_written(self, &[t]).await?;
+        }
+        Ok(())
+    }
+
+    async fn replace_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        let updates = docs
+            .into_iter()
+            .map(|d| match d.get(key) {
+                Some(id) => Ok(doc!{"q": {key: id.clone()}, "u": d, "upsert": true}),
+                None => Err(StoreError::InvalidDocument(format!("missing key {}", key))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // a command is at most 16MB
+        for batch in updates.chunks(REPLACE_BATCH) {
+            let r = self.db.run_command(doc!{"update": collection, "updates": batch.to_vec(), "ordered": false}).await?;
+            if let Ok(errors) = r.get_array("writeErrors") {
+                return Err(StoreError::InvalidDocument(format!("{} documents not replaced in {}: {:?}", errors.len(), collection, errors.first())));
+            }
+        }
+        match transcripts {
+            Some(t) => transcripts_written(self, &t).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn delete_doc(&self, collection: &str, key: &str, id: Bson) -> Result<(), StoreError> {
+        self.db
+            .collection::<Document>(collection)
+            .delete_one(doc!{key: id})
+            .await?;
+        Ok(())
+    }
+
+    async fn drop_collection(&self, collection: &str) -> Result<(), StoreError> {
+        self.db
+            .collection::<Document>(collection)
+            .drop()
+            .await?;
+        Ok(())
+    }
+
+    async fn search_transcripts(&self, text: &str) -> Result<Vec<ScoredEpisode>, StoreError> {
+        Ok(self.db
+            .collection::<EpisodeTranscript>("transcripts")
+            .aggregate(transcripts_pipeline(text))
+            .await?
+            // unwrap safe: as long as the schema and query are correct, this should not fail after this point
+            .map(|d| d.map(|d| from_document::<ScoredEpisode>(d.clone()).unwrap()))
+            .try_collect::<Vec<ScoredEpisode>>()
+            .await?)
+    }
+
+    async fn search_episodes(&self, query: &MetaQuery) -> Result<Vec<Episode>, StoreError> {
+        Ok(self.db
+            .collection::<Episode>("episodes")
+            .find(query.to_filter())
+            .await?
+            .try_collect::<Vec<Episode>>()
+            .await?)
+    }
+
+    async fn find_episode_by_title(&self, pattern: &str) -> Result<Option<Episode>, StoreError> {
+        Ok(self.db
+            .collection::<Episode>("episodes")
+            .find_one(title_filter(pattern))
+            .await?)
+    }
+
+    async fn whitelisted(&self, id: i64) -> Result<bool, StoreError> {
+        Ok(self.db
+            .collection::<()>("users")
+            .count_documents(doc! { "id": id, "beta": true })
+            .await? != 0)
+    }
+
+    async fn waitlist(&self) -> Result<Vec<BotUser>, StoreError> {
+        Ok(self.db
+            .collection::<BotUser>("users")
+            .find(doc! { "waitlist": true, "beta": false })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    async fn beta_list(&self) -> Result<Vec<BotUser>, StoreError> {
+        Ok(self.db
+            .collection::<BotUser>("users")
+            .find(doc! { "beta": true })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// The upsert only matches a free or expired lock, when someone else holds it the insert fails on the unique index.
+    async fn acquire_lock(&self, name: &str, owner: &str, expires: DateTime<Utc>) -> Result<bool, StoreError> {
+        let res = self.db
+            .collection::<Document>(LOCKS)
+            .update_one(
+                doc!{"id": name, "$or": [{"owner": owner}, {"expires_at": {"$lt": bson::DateTime::now()}}]},
+                doc!{"$set": {"owner": owner, "expires_at": bson::DateTime::from_millis(expires.timestamp_millis())}},
+            )
+            .upsert(true)
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == 11000 => Ok(false),
+                _ => Err(e.into()),
+            }
+        }
+    }
+
+    async fn release_lock(&self, name: &str, owner: &str) -> Result<(), StoreError> {
+        self.db
+            .collection::<Document>(LOCKS)
+            .delete_one(doc!{"id": name, "owner": owner})
+            .await?;
+        Ok(())
+    }
+
+    async fn explain(&self, query: ExplainQuery<'_>) -> Result<Option<Explanation>, StoreError> {
+        let command = match query {
+            ExplainQuery::Transcripts(text) => doc!{"aggregate": "transcripts", "pipeline": transcripts_pipeline(text), "cursor": {}},
+            ExplainQuery::Episodes(q) => doc!{"find": "episodes", "filter": q.to_filter()},
+            ExplainQuery::EpisodeByTitle(pattern) => doc!{"find": "episodes", "filter": title_filter(pattern), "limit": 1},
+            ExplainQuery::Transcript(id) => doc!{"find": "transcripts", "filter": {"episode_id": id}, "limit": 1},
+        };
+        let plan = self.db.run_command(doc!{"explain": command.clone(), "verbosity": "executionStats"}).await?;
+        Ok(Some(Explanation { command, plan }))
+    }
+}
@@ -0,0 +1,552 @@
+//! In-process inverted index of the transcripts.
+//!
+//! Answering `/sa` and `/sae` from the database means a full-text query and loading a whole transcript for every
+//! search: `ppp_bot` instead keeps the transcripts in memory, indexed by word, and searches them through an
+//! `IndexedStore`. Matches are located in the transcript segments as usual, so they come with their `FromTo`.
+//!
+//! The index follows the writes made through the `IndexedStore`, the writes of other processes (`ppp_import`) are
+//! picked up by `SegmentIndex::refresh` through the revision each backend records whenever a transcript is written.
+use std::{collections::{HashMap, HashSet}, ops::Range, sync::{Arc, RwLock}, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace};
+use mongodb::bson::{self, Bson, Document};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::{bot::{BotUser, ByteOffsets, EpisodeOffsetMatch, MetaQuery}, normalize::{fold, Normalized}, spreaker::Episode, transcript::{EpisodeTranscript, Timestamp}};
+
+use super::{ExplainQuery, Explanation, PPPData, ScoredEpisode, Store, StoreError};
+
+/// Characters between two `IndexedTranscript::checkpoints`.
+const CHECKPOINT_CHARS: usize = 64;
+
+lazy_static! {
+    static ref WORD: Regex = Regex::new(r"[A-Za-z0-9]+").unwrap();
+}
+
+/// Number of times the transcript of an episode has been written, a transcript without one has revision 0.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TranscriptRevision {
+    pub episode_id: u32,
+    pub revision: u64,
+}
+
+impl PPPData for TranscriptRevision {
+    const COLLECTION: &'static str = "transcript_revisions";
+    const ID_KEY: &'static str = "episode_id";
+    type IdType = u32;
+}
+
+/// Increment the revision of the transcripts `docs`, just written to the database.
+pub(crate) async fn bump_revisions(db: &dyn Store, docs: &[Document]) -> Result<(), StoreError> {
+    for d in docs {
+        let id: u32 = bson::from_bson(d.get(EpisodeTranscript::ID_KEY).cloned().unwrap_or(Bson::Null))?;
+        let revision = db.get::<TranscriptRevision>(id).await?.map_or(0, |r| r.revision) + 1;
+        db.update_one_stateless(id, &TranscriptRevision { episode_id: id, revision }).await?;
+    }
+    Ok(())
+}
+
+/// Folded words of a query.
+pub(crate) fn query_words(text: &str) -> Vec<String> {
+    fold(text)
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+struct IndexedTranscript {
+    revision: u64,
+    transcript: EpisodeTranscript,
+    /// Character range of each word of the transcript, in order.
+    words: Vec<Range<u32>>,
+    /// Positions in `words` of each folded word, in order.
+    terms: HashMap<String, Vec<u32>>,
+    /// Byte offset of every `CHECKPOINT_CHARS`-th character of the transcript, to slice it without going through it.
+    checkpoints: Vec<u32>,
+    chars: usize,
+}
+
+impl IndexedTranscript {
+    fn new(revision: u64, transcript: EpisodeTranscript) -> Self {
+        let n = Normalized::new(&transcript.data);
+        let mut words = vec![];
+        let mut terms: HashMap<String, Vec<u32>> = HashMap::new();
+        for (i, m) in WORD.find_iter(&n.text).enumerate() {
+            let r = n.original_range(m.range());
+            words.push(r.start as u32..r.end as u32);
+            terms.entry(m.as_str().to_ascii_lowercase()).or_default().push(i as u32);
+        }
+        let checkpoints = transcript.data.char_indices().step_by(CHECKPOINT_CHARS).map(|(i, _)| i as u32).collect();
+        let chars = transcript.data.chars().count();
+        Self { revision, transcript, words, terms, checkpoints, chars }
+    }
+
+    fn count(&self, term: &str) -> usize {
+        self.terms.get(term).map_or(0, Vec::len)
+    }
+
+    /// Positions of the words matching `accept`, in order.
+    fn positions(&self, accept: impl Fn(&str) -> bool) -> Vec<u32> {
+        let mut p: Vec<u32> = self.terms.iter().filter(|(t, _)| accept(t)).flat_map(|(_, p)| p.iter().copied()).collect();
+        p.sort_unstable();
+        p
+    }
+
+    /// Positions at which the words of `phrase` are said one after the other.
+    fn phrase(&self, phrase: &[String]) -> Vec<u32> {
+        let Some(first) = phrase.first().and_then(|w| self.terms.get(w)) else { return vec![] };
+        first
+            .iter()
+            .copied()
+            .filter(|&p| {
+                phrase[1..].iter().enumerate().all(|(i, w)| self.terms.get(w).is_some_and(|q| q.binary_search(&(p + i as u32 + 1)).is_ok()))
+            })
+            .collect()
+    }
+
+    /// Character ranges where the literal `words` are said: the first word can be the end of a longer word and the
+    /// last one the start of another, as in a substring search. A single word can be in the middle of a longer one.
+    fn literal(&self, words: &[String]) -> Vec<Range<usize>> {
+        let n = words.len();
+        let candidates: Vec<Vec<u32>> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| match i {
+                _ if n == 1 => self.positions(|t| t.contains(w.as_str())),
+                0 => self.positions(|t| t.ends_with(w.as_str())),
+                i if i == n - 1 => self.positions(|t| t.starts_with(w.as_str())),
+                _ => self.terms.get(w).cloned().unwrap_or_default(),
+            })
+            .collect();
+        candidates[0]
+            .iter()
+            .copied()
+            .filter(|&p| (1..n).all(|i| candidates[i].binary_search(&(p + i as u32)).is_ok()))
+            .map(|p| {
+                // the separators around the words belong to the query too
+                let (first, last) = (p as usize, p as usize + n - 1);
+                let start = first.checked_sub(1).map_or(0, |i| self.words[i].end as usize);
+                let end = self.words.get(last + 1).map_or(usize::MAX, |w| w.start as usize);
+                start..end
+            })
+            .collect()
+    }
+}
+
+impl ByteOffsets for IndexedTranscript {
+    fn chars(&self) -> usize {
+        self.chars
+    }
+
+    fn byte(&self, i: usize) -> usize {
+        let data = &self.transcript.data;
+        let Some(&c) = self.checkpoints.get(i / CHECKPOINT_CHARS) else { return data.len() };
+        data[c as usize..].char_indices().nth(i % CHECKPOINT_CHARS).map_or(data.len(), |(b, _)| c as usize + b)
+    }
+}
+
+#[derive(Default)]
+struct Indexed {
+    /// `false` until the first `refresh`.
+    ready: bool,
+    transcripts: HashMap<u32, IndexedTranscript>,
+    /// Episodes saying each folded word.
+    episodes: HashMap<String, HashSet<u32>>,
+}
+
+impl Indexed {
+    fn remove(&mut self, id: u32) {
+        if let Some(t) = self.transcripts.remove(&id) {
+            for term in t.terms.keys() {
+                if let Some(e) = self.episodes.get_mut(term) {
+                    e.remove(&id);
+                    if e.is_empty() {
+                        self.episodes.remove(term);
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, t: IndexedTranscript) {
+        let id = t.transcript.episode_id;
+        self.remove(id);
+        for term in t.terms.keys() {
+            self.episodes.entry(term.clone()).or_default().insert(id);
+        }
+        self.transcripts.insert(id, t);
+    }
+
+    /// Relevance of a word, rarer words weigh more.
+    fn idf(&self, term: &str) -> f64 {
+        let df = self.episodes.get(term).map_or(0, HashSet::len);
+        (1. + self.transcripts.len() as f64 / (1. + df as f64)).ln()
+    }
+}
+
+/// Transcripts kept in memory with the positions of their words.
+///
+/// Words are compared regardless of case and accents, without stemming.
+#[derive(Default)]
+pub struct SegmentIndex {
+    indexed: RwLock<Indexed>,
+}
+
+impl SegmentIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of transcripts in the index.
+    pub fn len(&self) -> usize {
+        self.indexed.read().unwrap().transcripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert(&self, revision: u64, t: EpisodeTranscript) {
+        // tokenizing a transcript takes a while, the index is locked only to add it
+        let t = IndexedTranscript::new(revision, t);
+        self.indexed.write().unwrap().insert(t);
+    }
+
+    /// Bring the index up to date with the transcripts of `db`, returns the number of transcripts indexed again or
+    /// removed.
+    pub async fn refresh(&self, db: &dyn Store) -> Result<usize, StoreError> {
+        let ids: HashSet<u32> = db.get_ids::<EpisodeTranscript>().await?.into_iter().collect();
+        let revisions: HashMap<u32, u64> = db.get_all::<TranscriptRevision>().await?.into_iter().map(|r| (r.episode_id, r.revision)).collect();
+        let revision = |id: &u32| revisions.get(id).copied().unwrap_or(0);
+        let (stale, removed): (Vec<u32>, Vec<u32>) = {
+            let indexed = self.indexed.read().unwrap();
+            (
+                ids.iter().copied().filter(|id| indexed.transcripts.get(id).is_none_or(|t| t.revision != revision(id))).collect(),
+                indexed.transcripts.keys().copied().filter(|id| !ids.contains(id)).collect(),
+            )
+        };
+        debug!("{} transcripts to index, {} to remove", stale.len(), removed.len());
+        for &id in &removed {
+            self.indexed.write().unwrap().remove(id);
+        }
+        for &id in &stale {
+            if let Some(t) = db.get::<EpisodeTranscript>(id).await? {
+                self.insert(revision(&id), t);
+            }
+        }
+        self.indexed.write().unwrap().ready = true;
+        Ok(stale.len() + removed.len())
+    }
+
+    /// Refresh the index from `db` every `period`, until the task is aborted.
+    pub fn spawn_refresh(self: &Arc<Self>, db: Arc<dyn Store>, period: Duration) -> JoinHandle<()> {
+        let index = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match index.refresh(db.as_ref()).await {
+                    Ok(0) => trace!("transcript index up to date"),
+                    Ok(n) => info!("transcript index refreshed, {} transcripts changed", n),
+                    Err(e) => error!("failed to refresh the transcript index: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Index the transcripts `docs`, just written to `db`.
+    async fn update(&self, db: &dyn Store, docs: &[Document]) -> Result<(), StoreError> {
+        for d in docs {
+            let t: EpisodeTranscript = bson::from_document(d.clone())?;
+            let revision = db.get::<TranscriptRevision>(t.episode_id).await?.map_or(0, |r| r.revision);
+            self.insert(revision, t);
+        }
+        Ok(())
+    }
+
+    fn clear(&self) {
+        let mut indexed = self.indexed.write().unwrap();
+        indexed.transcripts.clear();
+        indexed.episodes.clear();
+    }
+
+    /// Episodes whose transcript matches the full-text query `text`, with their relevance, `None` until the index is
+    /// built. The query has the syntax of MongoDB `$text` queries: words are or-ed, `"quoted phrases"` are and-ed and
+    /// `-words` exclude the episodes saying them.
+    pub fn search(&self, text: &str) -> Option<Vec<(u32, f64)>> {
+        let mut phrases = vec![];
+        let mut words = vec![];
+        let mut excluded = vec![];
+        for (i, part) in text.split('"').enumerate() {
+            if i % 2 == 1 {
+                phrases.push(query_words(part));
+                continue;
+            }
+            for w in part.split_whitespace() {
+                match w.strip_prefix('-') {
+                    Some(x) => excluded.extend(query_words(x)),
+                    None => words.extend(query_words(w)),
+                }
+            }
+        }
+        phrases.retain(|p| !p.is_empty());
+
+        let indexed = self.indexed.read().unwrap();
+        if !indexed.ready {
+            return None;
+        }
+        let episodes = |w: &String| indexed.episodes.get(w).into_iter().flatten().copied();
+        let candidates: HashSet<u32> = match phrases.first() {
+            // episodes saying every word of the phrase
+            Some(p) => episodes(&p[0]).filter(|id| p[1..].iter().all(|w| indexed.episodes.get(w).is_some_and(|e| e.contains(id)))).collect(),
+            None => words.iter().flat_map(episodes).collect(),
+        };
+        let idf: HashMap<&String, f64> = words.iter().chain(phrases.iter().flatten()).map(|w| (w, indexed.idf(w))).collect();
+        let mut scored: Vec<(u32, f64)> = candidates
+            .into_iter()
+            .filter(|id| !excluded.iter().any(|w| indexed.episodes.get(w).is_some_and(|e| e.contains(id))))
+            .filter_map(|id| {
+                let t = &indexed.transcripts[&id];
+                let mut score: f64 = words.iter().map(|w| t.count(w) as f64 * idf[w]).sum();
+                for p in &phrases {
+                    let count = t.phrase(p).len();
+                    if count == 0 {
+                        return None;
+                    }
+                    score += count as f64 * p.iter().map(|w| idf[w]).sum::<f64>();
+                }
+                (score > 0.).then_some((id, score))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        Some(scored)
+    }
+
+    /// Occurrences in each episode of the words of `text`, `None` until the index is built.
+    pub fn occurrences(&self, text: &str) -> Option<HashMap<u32, u32>> {
+        let indexed = self.indexed.read().unwrap();
+        if !indexed.ready {
+            return None;
+        }
+        let mut hits: HashMap<u32, u32> = HashMap::new();
+        for w in query_words(text).into_iter().collect::<HashSet<_>>() {
+            for id in indexed.episodes.get(&w).into_iter().flatten() {
+                *hits.entry(*id).or_default() += indexed.transcripts[id].count(&w) as u32;
+            }
+        }
+        Some(hits)
+    }
+
+    /// Revision of the indexed transcripts as a whole, the sum of their revisions, `None` until the index is built.
+    pub fn revision(&self) -> Option<u64> {
+        let indexed = self.indexed.read().unwrap();
+        indexed.ready.then(|| indexed.transcripts.values().map(|t| t.revision).sum())
+    }
+
+    /// Times each indexed transcript says the folded words `phrase` one after the other, along with the `revision` of
+    /// the transcripts counted, `None` until the index is built.
+    pub fn phrase_counts(&self, phrase: &[String]) -> Option<(u64, HashMap<u32, u32>)> {
+        let indexed = self.indexed.read().unwrap();
+        if !indexed.ready {
+            return None;
+        }
+        let revision = indexed.transcripts.values().map(|t| t.revision).sum();
+        Some((revision, indexed.transcripts.iter().map(|(id, t)| (*id, t.phrase(phrase).len() as u32)).collect()))
+    }
+
+    /// Matches of `pattern`, built from `text` by the search, in the transcript of `episode_id`, `None` if the
+    /// transcript isn't indexed. The index narrows the search down when `text` is a literal, the pattern is then run
+    /// on the neighbourhood of the candidates only.
+    pub fn find(&self, episode_id: u32, text: &str, pattern: &Regex) -> Option<Vec<EpisodeOffsetMatch>> {
+        let indexed = self.indexed.read().unwrap();
+        let t = indexed.transcripts.get(&episode_id)?;
+        let data = &t.transcript.data;
+        let words = query_words(text);
+        let mut matches: Vec<Range<usize>> = if regex::escape(text) == text && !words.is_empty() {
+            t.literal(&words)
+                .into_iter()
+                .flat_map(|r| {
+                    let (start, end) = (r.start.min(t.chars), r.end.min(t.chars));
+                    let n = Normalized::new(&data[t.byte(start)..t.byte(end)]);
+                    pattern.find_iter(&n.text).map(|m| n.original_range(m.range())).map(move |m| m.start + start..m.end + start).collect::<Vec<_>>()
+                })
+                .collect()
+        } else {
+            let n = Normalized::new(data);
+            pattern.find_iter(&n.text).map(|m| n.original_range(m.range())).collect()
+        };
+        // neighbourhoods overlap, keep the leftmost of overlapping matches like a search of the whole text
+        matches.sort_by_key(|m| (m.start, m.end));
+        let mut end = 0;
+        matches.retain(|m| {
+            let keep = m.start >= end && !m.is_empty();
+            if keep {
+                end = m.end;
+            }
+            keep
+        });
+        Some(EpisodeOffsetMatch::locate_with(&matches, &t.transcript.timestamps, data, t))
+    }
+
+    /// Segments of the transcript of `episode_id` saying the most words of `text`, `None` if the transcript isn't
+    /// indexed.
+    pub fn snippets(&self, episode_id: u32, text: &str, limit: usize) -> Option<Vec<EpisodeOffsetMatch>> {
+        let indexed = self.indexed.read().unwrap();
+        let t = indexed.transcripts.get(&episode_id)?;
+        let timestamps = &t.transcript.timestamps;
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for w in query_words(text) {
+            for &p in t.terms.get(&w).into_iter().flatten() {
+                let offset = t.words[p as usize].start as usize;
+                *counts.entry(timestamps.partition_point(|s| s.offsets.1 <= offset)).or_default() += 1;
+            }
+        }
+        let mut counts: Vec<(usize, usize)> = counts.into_iter().filter(|(s, _)| *s < timestamps.len()).collect();
+        counts.sort_by_key(|&(s, c)| (std::cmp::Reverse(c), s));
+        counts.truncate(limit);
+        Some(counts
+            .into_iter()
+            .map(|(s, _)| {
+                let Timestamp { time, offsets } = &timestamps[s];
+                let hint = t.transcript.data[t.byte(offsets.0.min(t.chars))..t.byte(offsets.1.min(t.chars))].trim().to_owned();
+                EpisodeOffsetMatch { time: time.clone(), hint, highlight: None }
+            })
+            .collect())
+    }
+}
+
+/// A `Store` searching the transcripts with a `SegmentIndex`, everything else is left to the wrapped store.
+///
+/// Transcripts written through it are indexed right away, call `SegmentIndex::refresh` to build the index and to
+/// pick up the writes of other processes: until the first refresh the searches go to the wrapped store.
+pub struct IndexedStore {
+    inner: Arc<dyn Store>,
+    index: Arc<SegmentIndex>,
+}
+
+impl IndexedStore {
+    pub fn new(inner: Arc<dyn Store>, index: Arc<SegmentIndex>) -> Self {
+        Self { inner, index }
+    }
+}
+
+#[async_trait]
+impl Store for IndexedStore {
+    async fn ensure_index(&self) -> Result<(), StoreError> {
+        self.inner.ensure_index().await
+    }
+
+    async fn get_doc(&self, collection: &str, key: &str, id: Bson) -> Result<Option<Document>, StoreError> {
+        self.inner.get_doc(collection, key, id).await
+    }
+
+    async fn get_docs(&self, collection: &str, key: &str, ids: Vec<Bson>) -> Result<Vec<Document>, StoreError> {
+        self.inner.get_docs(collection, key, ids).await
+    }
+
+    async fn get_ids_raw(&self, collection: &str, key: &str) -> Result<Vec<i64>, StoreError> {
+        self.inner.get_ids_raw(collection, key).await
+    }
+
+    async fn all_docs(&self, collection: &str) -> Result<Vec<Document>, StoreError> {
+        self.inner.all_docs(collection).await
+    }
+
+    async fn insert_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        self.inner.insert_docs(collection, key, docs).await?;
+        match transcripts {
+            Some(t) => self.index.update(self.inner.as_ref(), &t).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn replace_doc(&self, collection: &str, key: &str, id: Bson, doc: Document) -> Result<(), StoreError> {
+        let transcript = (collection == EpisodeTranscript::COLLECTION).then(|| doc.clone());
+        self.inner.replace_doc(collection, key, id, doc).await?;
+        match transcript {
+            Some(t) => self.index.update(self.inner.as_ref(), &[t]).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn replace_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        self.inner.replace_docs(collection, key, docs).await?;
+        match transcripts {
+            Some(t) => self.index.update(self.inner.as_ref(), &t).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn delete_doc(&self, collection: &str, key: &str, id: Bson) -> Result<(), StoreError> {
+        self.inner.delete_doc(collection, key, id).await
+    }
+
+    async fn drop_collection(&self, collection: &str) -> Result<(), StoreError> {
+        self.inner.drop_collection(collection).await?;
+        if collection == EpisodeTranscript::COLLECTION {
+            self.index.clear();
+        }
+        Ok(())
+    }
+
+    async fn search_transcripts(&self, text: &str) -> Result<Vec<ScoredEpisode>, StoreError> {
+        let Some(scored) = self.index.search(text) else {
+            return self.inner.search_transcripts(text).await;
+        };
+        let mut episodes: HashMap<u32, Episode> = self.inner.get_all::<Episode>().await?.into_iter().map(|e| (e.id, e)).collect();
+        Ok(scored.into_iter().filter_map(|(id, score)| episodes.remove(&id).map(|episode| ScoredEpisode { episode, score })).collect())
+    }
+
+    async fn transcript_snippets(&self, episode_id: u32, text: &str, limit: usize) -> Result<Vec<EpisodeOffsetMatch>, StoreError> {
+        match self.index.snippets(episode_id, text, limit) {
+            Some(s) => Ok(s),
+            None => self.inner.transcript_snippets(episode_id, text, limit).await,
+        }
+    }
+
+    async fn search_episodes(&self, query: &MetaQuery) -> Result<Vec<Episode>, StoreError> {
+        self.inner.search_episodes(query).await
+    }
+
+    async fn find_episode_by_title(&self, pattern: &str) -> Result<Option<Episode>, StoreError> {
+        self.inner.find_episode_by_title(pattern).await
+    }
+
+    async fn whitelisted(&self, id: i64) -> Result<bool, StoreError> {
+        self.inner.whitelisted(id).await
+    }
+
+    async fn waitlist(&self) -> Result<Vec<BotUser>, StoreError> {
+        self.inner.waitlist().await
+    }
+
+    async fn beta_list(&self) -> Result<Vec<BotUser>, StoreError> {
+        self.inner.beta_list().await
+    }
+
+    async fn acquire_lock(&self, name: &str, owner: &str, expires: DateTime<Utc>) -> Result<bool, StoreError> {
+        self.inner.acquire_lock(name, owner, expires).await
+    }
+
+    async fn release_lock(&self, name: &str, owner: &str) -> Result<(), StoreError> {
+        self.inner.release_lock(name, owner).await
+    }
+
+    async fn explain(&self, query: ExplainQuery<'_>) -> Result<Option<Explanation>, StoreError> {
+        self.inner.explain(query).await
+    }
+
+    fn segment_index(&self) -> Option<&SegmentIndex> {
+        Some(&self.index)
+    }
+}
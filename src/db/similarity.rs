@@ -0,0 +1,117 @@
+//! Similarity of episodes by content.
+//!
+//! Every episode is described by a TF-IDF vector of the words of its transcript, title and description, kept in the
+//! `episode_vectors` collection. IDF weights depend on every episode, so the vectors are computed all together by
+//! `rebuild`, at the end of each import. Two episodes are as similar as the cosine of their vectors.
+use std::collections::{HashMap, HashSet};
+
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use serde::{Deserialize, Serialize};
+
+use crate::{spreaker::Episode, transcript::EpisodeTranscript};
+
+use super::{fuzzy, PPPData, Store, StoreError};
+
+/// Words kept in the vector of an episode, the heaviest ones.
+const VECTOR_TERMS: usize = 100;
+/// A word of the title counts as this many words of the transcript, one of the description as half of them.
+const TITLE_WEIGHT: u32 = 20;
+const DESCRIPTION_WEIGHT: u32 = TITLE_WEIGHT / 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EpisodeVector {
+    pub episode_id: u32,
+    /// Unit vector, heaviest terms first.
+    pub terms: Vec<WeightedTerm>,
+}
+
+impl PPPData for EpisodeVector {
+    const COLLECTION: &'static str = "episode_vectors";
+    const ID_KEY: &'static str = "episode_id";
+    type IdType = u32;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WeightedTerm {
+    pub term: String,
+    pub weight: f64,
+}
+
+impl EpisodeVector {
+    /// Cosine similarity with `other`, from 0 to 1, and the shared terms, the most relevant to it first.
+    pub fn similarity(&self, other: &Self) -> (f64, Vec<String>) {
+        let weights: HashMap<&str, f64> = other.terms.iter().map(|t| (t.term.as_str(), t.weight)).collect();
+        let mut shared: Vec<(f64, &str)> = self
+            .terms
+            .iter()
+            .filter_map(|t| weights.get(t.term.as_str()).map(|w| (t.weight * w, t.term.as_str())))
+            .collect();
+        shared.sort_by(|a, b| b.0.total_cmp(&a.0));
+        (shared.iter().map(|s| s.0).sum(), shared.into_iter().map(|s| s.1.to_owned()).collect())
+    }
+}
+
+/// Occurrences of the words of `text`, numbers aside, each counting `weight` times.
+fn count_terms(counts: &mut HashMap<String, u32>, text: &str, weight: u32) {
+    for w in fuzzy::words(text) {
+        if !w.bytes().all(|b| b.is_ascii_digit()) {
+            *counts.entry(w).or_default() += weight;
+        }
+    }
+}
+
+/// Compute the vector of every episode again.
+pub async fn rebuild(db: &dyn Store) -> Result<(), StoreError> {
+    let transcripts: HashMap<u32, EpisodeTranscript> = db.get_all::<EpisodeTranscript>().await?.into_iter().map(|t| (t.episode_id, t)).collect();
+    let mut documents: Vec<(u32, HashMap<String, u32>)> = vec![];
+    for e in db.get_all::<Episode>().await? {
+        let mut counts = HashMap::new();
+        if let Some(t) = transcripts.get(&e.id) {
+            count_terms(&mut counts, &t.data, 1);
+        }
+        count_terms(&mut counts, &e.title, TITLE_WEIGHT);
+        count_terms(&mut counts, &e.description, DESCRIPTION_WEIGHT);
+        documents.push((e.id, counts));
+    }
+    drop(transcripts);
+
+    let mut df: HashMap<&str, u32> = HashMap::new();
+    for (_, counts) in &documents {
+        for t in counts.keys() {
+            *df.entry(t.as_str()).or_default() += 1;
+        }
+    }
+    let n = documents.len() as f64;
+    // words said in every episode weigh nothing
+    let idf = |t: &str| (n / df[t] as f64).ln();
+    let vectors: Vec<EpisodeVector> = documents
+        .iter()
+        .map(|(id, counts)| {
+            let mut terms: Vec<WeightedTerm> = counts
+                .iter()
+                .map(|(t, &c)| WeightedTerm { term: t.clone(), weight: (1. + (c as f64).ln()) * idf(t) })
+                .filter(|t| t.weight > 0.)
+                .collect();
+            terms.sort_by(|a, b| b.weight.total_cmp(&a.weight).then_with(|| a.term.cmp(&b.term)));
+            terms.truncate(VECTOR_TERMS);
+            let norm = terms.iter().map(|t| t.weight * t.weight).sum::<f64>().sqrt();
+            for t in &mut terms {
+                t.weight /= norm;
+            }
+            EpisodeVector { episode_id: *id, terms }
+        })
+        .collect();
+
+    info!("computed the vectors of {} episodes", vectors.len());
+    // replaced one at a time, so that `search_similar` keeps working meanwhile
+    let existing: HashSet<u32> = db.get_ids::<EpisodeVector>().await?.into_iter().collect();
+    for v in &vectors {
+        db.update_one_stateless(v.episode_id, v).await?;
+    }
+    for id in existing.difference(&vectors.iter().map(|v| v.episode_id).collect()) {
+        debug!("removing the vector of episode {}, no longer in the database", id);
+        db.delete::<EpisodeVector>(*id).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,482 @@
+use std::{collections::HashMap, ops::Range, path::Path, sync::{Arc, Mutex}};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, doc, Bson, Document};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Transaction, TransactionBehavior};
+use rust_stemmers::{Algorithm, Stemmer};
+use crate::{bot::{BotUser, EpisodeOffsetMatch, MetaQuery}, spreaker::Episode, transcript::{EpisodeTranscript, FromTo}};
+
+use super::{transcripts_written, PPPData, ScoredEpisode, Store, StoreError, LOCKS};
+
+/// SQLite implementation of `Store`, available with the `sqlite` feature.
+///
+/// Documents are kept as relaxed extended JSON in the `documents` table, keyed by collection and id. Transcripts are
+/// also split into their segments and indexed in the `segments` FTS5 table, which backs full-text search.
+pub struct SqliteDatabase {
+    conn: Arc<Mutex<Connection>>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    collection TEXT NOT NULL,
+    id TEXT NOT NULL,
+    body TEXT NOT NULL,
+    PRIMARY KEY (collection, id)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS segments USING fts5(
+    text,
+    episode_id UNINDEXED,
+    from_ms UNINDEXED,
+    to_ms UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+";
+
+impl SqliteDatabase {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Run `f` on the connection in a blocking task.
+    async fn run<R, F>(&self, f: F) -> Result<R, StoreError>
+    where
+        F: FnOnce(&mut Connection) -> Result<R, StoreError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap()))
+            .await
+            .expect("sqlite worker panicked")
+    }
+
+    async fn typed<T: PPPData>(&self) -> Result<Vec<T>, StoreError> {
+        self.all_docs(T::COLLECTION)
+            .await?
+            .into_iter()
+            .map(|d| bson::from_document(d).map_err(StoreError::from))
+            .collect()
+    }
+}
+
+fn id_string(id: &Bson) -> String {
+    match id {
+        Bson::String(s) => s.clone(),
+        Bson::Int32(i) => i.to_string(),
+        Bson::Int64(i) => i.to_string(),
+        o => o.to_string(),
+    }
+}
+
+fn to_json(doc: Document) -> String {
+    Bson::Document(doc).into_relaxed_extjson().to_string()
+}
+
+fn from_json(s: &str) -> Result<Document, StoreError> {
+    let v: serde_json::Value = serde_json::from_str(s).map_err(|e| StoreError::InvalidDocument(e.to_string()))?;
+    match Bson::try_from(v).map_err(|e| StoreError::InvalidDocument(e.to_string()))? {
+        Bson::Document(d) => Ok(d),
+        o => Err(StoreError::InvalidDocument(format!("expected a document, found {}", o.element_type() as u8))),
+    }
+}
+
+fn query_docs(conn: &Connection, sql: &str, params: impl rusqlite::Params) -> Result<Vec<Document>, StoreError> {
+    let mut stmt = conn.prepare(sql)?;
+    let bodies = stmt
+        .query_map(params, |r| r.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    bodies.iter().map(|b| from_json(b)).collect()
+}
+
+/// Replace the indexed segments of a transcript document.
+fn index_transcript(tx: &Transaction, doc: &Document) -> Result<(), StoreError> {
+    let t: EpisodeTranscript = bson::from_document(doc.clone())?;
+    tx.execute("DELETE FROM segments WHERE episode_id = ?1", params![t.episode_id])?;
+    let mut stmt = tx.prepare("INSERT INTO segments (text, episode_id, from_ms, to_ms) VALUES (?1, ?2, ?3, ?4)")?;
+    for (time, text) in t.segments() {
+        stmt.execute(params![text, t.episode_id, time.from.as_millis() as i64, time.to.as_millis() as i64])?;
+    }
+    Ok(())
+}
+
+/// Markers put around the matched tokens by `snippet()`, control characters that can't appear in a transcript.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Remove the markers from a snippet, returning it with the bytes of its first match.
+fn strip_markers(snippet: &str) -> (String, Option<Range<usize>>) {
+    let mut hint = String::with_capacity(snippet.len());
+    let (mut start, mut highlight) = (None, None);
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => start = start.or(Some(hint.len())),
+            MATCH_END => highlight = highlight.or(start.map(|s| s..hint.len())),
+            c => hint.push(c),
+        }
+    }
+    (hint, highlight)
+}
+
+/// A MongoDB `$text`-like query translated to FTS5 syntax.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FtsQuery {
+    /// Expression selecting the matching segments: quoted phrases are required, words are or-ed.
+    pub matching: Option<String>,
+    /// Expression selecting segments whose episodes must be excluded (`-word` or `-"phrase"`).
+    pub excluded: Option<String>,
+}
+
+/// Split a string into lowercase alphanumeric tokens, consistently with the `unicode61` tokenizer.
+fn tokens(s: &str) -> Vec<String> {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+impl FtsQuery {
+    /// Words are reduced with the italian snowball stemmer and matched as prefixes, so that `giochi` also finds
+    /// `gioco` and `giocare`. Very short stems are matched exactly to avoid matching half the dictionary.
+    pub fn parse(text: &str) -> Self {
+        let stemmer = Stemmer::create(Algorithm::Italian);
+        let term = |t: &str| {
+            let stem = stemmer.stem(t);
+            if stem.chars().count() < 3 {
+                format!("\"{}\"", t)
+            } else {
+                format!("\"{}\"*", stem)
+            }
+        };
+        let mut phrases = vec![];
+        let mut words = vec![];
+        let mut excluded = vec![];
+        // a phrase right after a `-` is excluded
+        let mut negated = false;
+        for (i, part) in text.split('"').enumerate() {
+            if i % 2 == 1 {
+                let p = tokens(part);
+                if !p.is_empty() {
+                    let phrase = format!("\"{}\"", p.join(" "));
+                    if negated { excluded.push(phrase) } else { phrases.push(phrase) }
+                }
+                continue
+            }
+            negated = part.ends_with('-') && part.split_whitespace().last() == Some("-");
+            for w in part.split_whitespace() {
+                match w.strip_prefix('-') {
+                    Some(w) => excluded.extend(tokens(w).iter().map(|t| term(t))),
+                    None => words.extend(tokens(w).iter().map(|t| term(t))),
+                }
+            }
+        }
+        let mut matching = phrases;
+        if !words.is_empty() {
+            matching.push(format!("({})", words.join(" OR ")));
+        }
+        Self {
+            matching: (!matching.is_empty()).then(|| matching.join(" AND ")),
+            excluded: (!excluded.is_empty()).then(|| excluded.join(" OR ")),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for SqliteDatabase {
+    async fn ensure_index(&self) -> Result<(), StoreError> {
+        self.run(|c| Ok(c.execute_batch(SCHEMA)?)).await
+    }
+
+    async fn get_doc(&self, collection: &str, _key: &str, id: Bson) -> Result<Option<Document>, StoreError> {
+        let (collection, id) = (collection.to_owned(), id_string(&id));
+        self.run(move |c| {
+            c.query_row("SELECT body FROM documents WHERE collection = ?1 AND id = ?2", params![collection, id], |r| r.get::<_, String>(0))
+                .optional()?
+                .map(|b| from_json(&b))
+                .transpose()
+        }).await
+    }
+
+    async fn get_docs(&self, collection: &str, _key: &str, ids: Vec<Bson>) -> Result<Vec<Document>, StoreError> {
+        let collection = collection.to_owned();
+        self.run(move |c| {
+            let mut stmt = c.prepare_cached("SELECT body FROM documents WHERE collection = ?1 AND id = ?2")?;
+            let mut docs = Vec::with_capacity(ids.len());
+            for id in ids {
+                if let Some(b) = stmt.query_row(params![collection, id_string(&id)], |r| r.get::<_, String>(0)).optional()? {
+                    docs.push(from_json(&b)?);
+                }
+            }
+            Ok(docs)
+        }).await
+    }
+
+    async fn get_ids_raw(&self, collection: &str, _key: &str) -> Result<Vec<i64>, StoreError> {
+        let collection = collection.to_owned();
+        self.run(move |c| {
+            let mut stmt = c.prepare("SELECT id FROM documents WHERE collection = ?1")?;
+            let ids = stmt
+                .query_map(params![collection], |r| r.get::<_, String>(0))?
+                .filter_map(|id| id.map(|id| id.parse::<i64>().ok()).transpose())
+                .collect::<Result<Vec<i64>, _>>()?;
+            Ok(ids)
+        }).await
+    }
+
+    async fn all_docs(&self, collection: &str) -> Result<Vec<Document>, StoreError> {
+        let collection = collection.to_owned();
+        self.run(move |c| query_docs(c, "SELECT body FROM documents WHERE collection = ?1 ORDER BY rowid", params![collection])).await
+    }
+
+    async fn insert_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        let (collection, key) = (collection.to_owned(), key.to_owned());
+        self.run(move |c| {
+            let tx = c.transaction()?;
+            for doc in docs {
+                let id = doc.get(&key).map(id_string).ok_or_else(|| StoreError::InvalidDocument(format!("missing key {}", key)))?;
+                if collection == EpisodeTranscript::COLLECTION {
+                    index_transcript(&tx, &doc)?;
+                }
+                match tx.execute("INSERT INTO documents (collection, id, body) VALUES (?1, ?2, ?3)", params![collection, id, to_json(doc)]) {
+                    Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::ConstraintViolation => {
+                        return Err(StoreError::DuplicateKey(collection))
+                    }
+                    r => r?,
+                };
+            }
+            tx.commit()?;
+            Ok(())
+        }).await?;
+        match transcripts {
+            Some(t) => transcripts_written(self, &t).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn replace_doc(&self, collection: &str, _key: &str, id: Bson, doc: Document) -> Result<(), StoreError> {
+        let transcript = (collection == EpisodeTranscript::COLLECTION).then(|| doc.clone());
+        let (collection, id) = (collection.to_owned(), id_string(&id));
+        self.run(move |c| {
+            let tx = c.transaction()?;
+            if collection == EpisodeTranscript::COLLECTION {
+                index_transcript(&tx, &doc)?;
+            }
+            tx.execute("INSERT OR REPLACE INTO documents (collection, id, body) VALUES (?1, ?2, ?3)", params![collection, id, to_json(doc)])?;
+            tx.commit()?;
+            Ok(())
+        }).await?;
+        if let Some(t) = transcript {
+            transcripts_written(self, &[t]).await?;
+        }
+        Ok(())
+    }
+
+    async fn replace_docs(&self, collection: &str, key: &str, docs: Vec<Document>) -> Result<(), StoreError> {
+        let transcripts = (collection == EpisodeTranscript::COLLECTION).then(|| docs.clone());
+        let (collection, key) = (collection.to_owned(), key.to_owned());
+        self.run(move |c| {
+            let tx = c.transaction()?;
+            for doc in docs {
+                let id = doc.get(&key).map(id_string).ok_or_else(|| StoreError::InvalidDocument(format!("missing key {}", key)))?;
+                if collection == EpisodeTranscript::COLLECTION {
+                    index_transcript(&tx, &doc)?;
+                }
+                tx.execute("INSERT OR REPLACE INTO documents (collection, id, body) VALUES (?1, ?2, ?3)", params![collection, id, to_json(doc)])?;
+            }
+            tx.commit()?;
+            Ok(())
+        }).await?;
+        match transcripts {
+            Some(t) => transcripts_written(self, &t).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn delete_doc(&self, collection: &str, _key: &str, id: Bson) -> Result<(), StoreError> {
+        let (collection, id) = (collection.to_owned(), id_string(&id));
+        self.run(move |c| {
+            c.execute("DELETE FROM documents WHERE collection = ?1 AND id = ?2", params![collection, id])?;
+            Ok(())
+        }).await
+    }
+
+    async fn drop_collection(&self, collection: &str) -> Result<(), StoreError> {
+        let collection = collection.to_owned();
+        self.run(move |c| {
+            let tx = c.transaction()?;
+            if collection == EpisodeTranscript::COLLECTION {
+                tx.execute("DELETE FROM segments", [])?;
+            }
+            tx.execute("DELETE FROM documents WHERE collection = ?1", params![collection])?;
+            tx.commit()?;
+            Ok(())
+        }).await
+    }
+
+    /// Episodes are ranked by the sum of the bm25 scores of their matching segments.
+    async fn search_transcripts(&self, text: &str) -> Result<Vec<ScoredEpisode>, StoreError> {
+        let query = FtsQuery::parse(text);
+        let matching = match query.matching {
+            Some(m) => m,
+            None => return Ok(vec![]),
+        };
+        let scores = self.run(move |c| {
+            let excluded: Vec<u32> = match query.excluded {
+                Some(e) => c
+                    .prepare("SELECT DISTINCT episode_id FROM segments WHERE segments MATCH ?1")?
+                    .query_map(params![e], |r| r.get(0))?
+                    .collect::<Result<_, _>>()?,
+                None => vec![],
+            };
+            // auxiliary functions can't be used in aggregates, so scores are summed here
+            let mut scores: HashMap<u32, f64> = HashMap::new();
+            let mut stmt = c.prepare("SELECT episode_id, bm25(segments) FROM segments WHERE segments MATCH ?1")?;
+            let rows = stmt.query_map(params![matching], |r| Ok((r.get::<_, u32>(0)?, r.get::<_, f64>(1)?)))?;
+            for row in rows {
+                let (id, score) = row?;
+                *scores.entry(id).or_default() += score;
+            }
+            // bm25 is negative, lower is better
+            let mut scores: Vec<(u32, f64)> = scores.into_iter().filter(|(id, _)| !excluded.contains(id)).map(|(id, s)| (id, -s)).collect();
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+            Ok(scores)
+        }).await?;
+        let mut episodes: HashMap<u32, Episode> = self.typed::<Episode>().await?.into_iter().map(|e| (e.id, e)).collect();
+        Ok(scores.into_iter().filter_map(|(id, score)| episodes.remove(&id).map(|episode| ScoredEpisode { episode, score })).collect())
+    }
+
+    async fn transcript_snippets(&self, episode_id: u32, text: &str, limit: usize) -> Result<Vec<EpisodeOffsetMatch>, StoreError> {
+        let matching = match FtsQuery::parse(text).matching {
+            Some(m) => m,
+            None => return Ok(vec![]),
+        };
+        self.run(move |c| {
+            let matches = c
+                .prepare("SELECT from_ms, to_ms, snippet(segments, 0, ?4, ?5, '…', 24) FROM segments
+                    WHERE segments MATCH ?1 AND episode_id = ?2 ORDER BY rank LIMIT ?3")?
+                .query_map(params![matching, episode_id, limit as i64, MATCH_START.to_string(), MATCH_END.to_string()], |r| {
+                    let (hint, highlight) = strip_markers(r.get::<_, String>(2)?.trim());
+                    Ok(EpisodeOffsetMatch {
+                        time: FromTo {
+                            from: std::time::Duration::from_millis(r.get::<_, i64>(0)? as u64),
+                            to: std::time::Duration::from_millis(r.get::<_, i64>(1)? as u64),
+                        },
+                        hint,
+                        highlight,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(matches)
+        }).await
+    }
+
+    async fn search_episodes(&self, query: &MetaQuery) -> Result<Vec<Episode>, StoreError> {
+        Ok(self.typed::<Episode>().await?.into_iter().filter(|e| query.matches(e)).collect())
+    }
+
+    async fn find_episode_by_title(&self, pattern: &str) -> Result<Option<Episode>, StoreError> {
+        let r = match regex::RegexBuilder::new(pattern).case_insensitive(true).build() {
+            Ok(r) => r,
+            Err(_) => return Ok(None),
+        };
+        Ok(self.typed::<Episode>().await?.into_iter().find(|e| r.is_match(&e.title)))
+    }
+
+    async fn whitelisted(&self, id: i64) -> Result<bool, StoreError> {
+        self.run(move |c| {
+            Ok(c.query_row(
+                "SELECT COUNT(*) FROM documents WHERE collection = 'users' AND id = ?1 AND json_extract(body, '$.beta') = 1",
+                params![id.to_string()],
+                |r| r.get::<_, i64>(0),
+            )? != 0)
+        }).await
+    }
+
+    async fn waitlist(&self) -> Result<Vec<BotUser>, StoreError> {
+        self.run(|c| query_docs(c, "SELECT body FROM documents WHERE collection = 'users'
+            AND json_extract(body, '$.waitlist') = 1 AND json_extract(body, '$.beta') = 0 ORDER BY rowid", []))
+            .await?
+            .into_iter()
+            .map(|d| bson::from_document(d).map_err(StoreError::from))
+            .collect()
+    }
+
+    async fn beta_list(&self) -> Result<Vec<BotUser>, StoreError> {
+        self.run(|c| query_docs(c, "SELECT body FROM documents WHERE collection = 'users' AND json_extract(body, '$.beta') = 1 ORDER BY rowid", []))
+            .await?
+            .into_iter()
+            .map(|d| bson::from_document(d).map_err(StoreError::from))
+            .collect()
+    }
+
+    async fn acquire_lock(&self, name: &str, owner: &str, expires: DateTime<Utc>) -> Result<bool, StoreError> {
+        let (name, owner) = (name.to_owned(), owner.to_owned());
+        self.run(move |c| {
+            // IMMEDIATE takes the write lock up front, so other processes can't race between the check and the write
+            let tx = c.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let current = tx
+                .query_row("SELECT body FROM documents WHERE collection = ?1 AND id = ?2", params![LOCKS, name], |r| r.get::<_, String>(0))
+                .optional()?
+                .map(|b| from_json(&b))
+                .transpose()?;
+            if let Some(d) = current {
+                let expired = d.get_datetime("expires_at").is_ok_and(|e| *e < bson::DateTime::now());
+                if d.get_str("owner") != Ok(owner.as_str()) && !expired {
+                    return Ok(false);
+                }
+            }
+            let lock = doc!{"id": &name, "owner": &owner, "expires_at": bson::DateTime::from_millis(expires.timestamp_millis())};
+            tx.execute("INSERT OR REPLACE INTO documents (collection, id, body) VALUES (?1, ?2, ?3)", params![LOCKS, name, to_json(lock)])?;
+            tx.commit()?;
+            Ok(true)
+        }).await
+    }
+
+    async fn release_lock(&self, name: &str, owner: &str) -> Result<(), StoreError> {
+        let (name, owner) = (name.to_owned(), owner.to_owned());
+        self.run(move |c| {
+            c.execute(
+                "DELETE FROM documents WHERE collection = ?1 AND id = ?2 AND json_extract(body, '$.owner') = ?3",
+                params![LOCKS, name, owner],
+            )?;
+            Ok(())
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stems_words_into_prefixes() {
+        let q = FtsQuery::parse("giochi Pizze");
+        assert_eq!(q.matching.as_deref(), Some("(\"gioc\"* OR \"pizz\"*)"));
+        assert_eq!(q.excluded, None);
+        // short stems are matched exactly
+        assert_eq!(FtsQuery::parse("re").matching.as_deref(), Some("(\"re\")"));
+    }
+
+    #[test]
+    fn parse_phrases_and_exclusions() {
+        let q = FtsQuery::parse("\"Super Mario\" zelda -sonic -\"\"");
+        assert_eq!(q.matching.as_deref(), Some("\"super mario\" AND (\"zeld\"*)"));
+        assert_eq!(q.excluded.as_deref(), Some("\"sonic\"*"));
+        // punctuation is dropped like the tokenizer does
+        assert_eq!(FtsQuery::parse("\"l'ananas!\"").matching.as_deref(), Some("\"l ananas\""));
+        assert_eq!(FtsQuery::parse("-pizza"), FtsQuery { matching: None, excluded: Some("\"pizz\"*".to_owned()) });
+        assert_eq!(FtsQuery::parse("  \"\" "), FtsQuery::default());
+        let q = FtsQuery::parse("pizza -\"mangiamo dopo\" \"la pizza\"");
+        assert_eq!(q.matching.as_deref(), Some("\"la pizza\" AND (\"pizz\"*)"));
+        assert_eq!(q.excluded.as_deref(), Some("\"mangiamo dopo\""));
+    }
+
+    #[test]
+    fn strip_markers_returns_the_first_match() {
+        let (hint, highlight) = strip_markers("la \u{2}pizzà\u{3} e la \u{2}pizza\u{3}");
+        assert_eq!(hint, "la pizzà e la pizza");
+        assert_eq!(&hint[highlight.unwrap()], "pizzà");
+        assert_eq!(strip_markers("niente"), ("niente".to_owned(), None));
+    }
+}
@@ -0,0 +1,156 @@
+//! Statistics of the whole show, for `/stats` and `ppp_import stats`.
+//!
+//! Going through every transcript takes a while, so the statistics are computed by `rebuild` at the end of each import
+//! and kept as a single document of the `show_stats` collection. The speaking time of each host would need diarized
+//! transcripts: the segments don't say who is speaking, so it isn't part of them.
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Datelike, Utc};
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use serde::{Deserialize, Serialize};
+
+use crate::{spreaker::Episode, transcript::EpisodeTranscript};
+
+use super::{segments::query_words, topics, PPPData, Store, StoreError};
+
+/// Episodes listed as the longest, shortest, fastest and slowest.
+const RANKED_EPISODES: usize = 5;
+/// Most said words kept.
+const TOP_WORDS: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShowStats {
+    /// Always `ShowStats::ID`, there's a single document.
+    pub id: String,
+    #[serde(with = "crate::serde::bson_datetime")]
+    pub computed_at: DateTime<Utc>,
+    pub episodes: u32,
+    pub duration_ms: u64,
+    /// Episodes with a transcript, and their duration.
+    pub transcripts: u32,
+    pub transcribed_ms: u64,
+    /// Oldest year first.
+    pub years: Vec<YearStats>,
+    pub longest: Vec<EpisodeStat>,
+    pub shortest: Vec<EpisodeStat>,
+    /// Most said words, stop words aside, folded.
+    pub words: Vec<WordCount>,
+    /// Words per minute of all the transcribed episodes together.
+    pub words_per_minute: f64,
+    /// Words per minute of each transcribed episode, the fastest first.
+    pub episode_words_per_minute: Vec<EpisodeStat>,
+}
+
+impl PPPData for ShowStats {
+    const COLLECTION: &'static str = "show_stats";
+    const ID_KEY: &'static str = "id";
+    type IdType = String;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct YearStats {
+    pub year: i32,
+    /// Episodes with a duration.
+    pub episodes: u32,
+    pub average_ms: u64,
+}
+
+/// An episode and the value it's ranked by.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EpisodeStat {
+    pub episode_id: u32,
+    pub title: String,
+    pub url: String,
+    pub value: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WordCount {
+    pub word: String,
+    pub count: u64,
+}
+
+impl ShowStats {
+    pub const ID: &'static str = "show";
+
+    /// Share of the episodes with a transcript, from 0 to 1.
+    pub fn coverage(&self) -> f64 {
+        self.transcripts as f64 / self.episodes.max(1) as f64
+    }
+
+    /// Share of the duration of the show transcribed, from 0 to 1.
+    pub fn duration_coverage(&self) -> f64 {
+        self.transcribed_ms as f64 / self.duration_ms.max(1) as f64
+    }
+
+    /// The episodes with the most words per minute.
+    pub fn fastest(&self) -> impl Iterator<Item = &EpisodeStat> {
+        self.episode_words_per_minute.iter().take(RANKED_EPISODES)
+    }
+
+    /// The episodes with the fewest words per minute, the slowest first.
+    pub fn slowest(&self) -> impl Iterator<Item = &EpisodeStat> {
+        self.episode_words_per_minute.iter().rev().take(RANKED_EPISODES)
+    }
+}
+
+fn episode_stat(e: &Episode, value: f64) -> EpisodeStat {
+    EpisodeStat { episode_id: e.id, title: e.title.clone(), url: e.url(), value }
+}
+
+/// Compute the statistics of the show again.
+pub async fn rebuild(db: &dyn Store) -> Result<ShowStats, StoreError> {
+    let mut episodes = db.get_all::<Episode>().await?;
+    episodes.sort_by_key(|e| (e.published_at, e.id));
+    let by_id: HashMap<u32, &Episode> = episodes.iter().map(|e| (e.id, e)).collect();
+
+    // episodes without a duration would lower the averages
+    let mut years: BTreeMap<i32, (u32, u64)> = BTreeMap::new();
+    for e in episodes.iter().filter(|e| e.duration > 0) {
+        let y = years.entry(e.published_at.year()).or_default();
+        y.0 += 1;
+        y.1 += e.duration as u64;
+    }
+    let mut by_duration: Vec<&Episode> = episodes.iter().filter(|e| e.duration > 0).collect();
+    by_duration.sort_by_key(|e| (std::cmp::Reverse(e.duration), e.id));
+
+    let (mut transcripts, mut transcribed_ms, mut total_words) = (0, 0, 0);
+    let mut words: HashMap<String, u64> = HashMap::new();
+    let mut rates = vec![];
+    for t in db.get_all::<EpisodeTranscript>().await? {
+        let Some(e) = by_id.get(&t.episode_id) else { continue };
+        transcripts += 1;
+        for (w, c) in topics::keywords(&t.data) {
+            *words.entry(w).or_default() += c as u64;
+        }
+        if e.duration > 0 {
+            let n = query_words(&t.data).len() as u64;
+            transcribed_ms += e.duration as u64;
+            total_words += n;
+            rates.push(episode_stat(e, n as f64 / (e.duration as f64 / 60_000.)));
+        }
+    }
+    rates.sort_by(|a, b| b.value.total_cmp(&a.value).then(a.episode_id.cmp(&b.episode_id)));
+    let mut words: Vec<WordCount> = words.into_iter().map(|(word, count)| WordCount { word, count }).collect();
+    words.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.word.cmp(&b.word)));
+    words.truncate(TOP_WORDS);
+
+    let stats = ShowStats {
+        id: ShowStats::ID.to_owned(),
+        computed_at: Utc::now(),
+        episodes: episodes.len() as u32,
+        duration_ms: episodes.iter().map(|e| e.duration as u64).sum(),
+        transcripts,
+        transcribed_ms,
+        years: years.into_iter().map(|(year, (n, ms))| YearStats { year, episodes: n, average_ms: ms / n as u64 }).collect(),
+        longest: by_duration.iter().take(RANKED_EPISODES).map(|e| episode_stat(e, e.duration as f64)).collect(),
+        shortest: by_duration.iter().rev().take(RANKED_EPISODES).map(|e| episode_stat(e, e.duration as f64)).collect(),
+        words,
+        words_per_minute: if transcribed_ms > 0 { total_words as f64 / (transcribed_ms as f64 / 60_000.) } else { 0. },
+        episode_words_per_minute: rates,
+    };
+    info!("computed the statistics of {} episodes, {} transcribed", stats.episodes, stats.transcripts);
+    db.update_one_stateless(stats.id.clone(), &stats).await?;
+    Ok(stats)
+}
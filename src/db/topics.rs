@@ -0,0 +1,357 @@
+//! Topics of the episodes, extracted from their transcripts.
+//!
+//! Descriptions rarely say what an episode is about, so the topics are guessed from what is said: named entities
+//! (games, people, companies) are runs of capitalised words in the middle of a sentence, keywords are the words of
+//! the transcript with the highest TF-IDF. Both are weighed against every other episode, so that what is said in every
+//! episode (the hosts, the show) isn't a topic, and are computed all together by `rebuild` at the end of each import.
+use std::collections::{HashMap, HashSet};
+
+use lazy_static::lazy_static;
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{normalize::fold, spreaker::Episode, transcript::EpisodeTranscript};
+
+use super::{fuzzy, Store, StoreError};
+
+/// Entities and keywords kept for each episode.
+const ENTITIES: usize = 8;
+const KEYWORDS: usize = 8;
+/// Times an entity must be said in an episode to be one of its topics.
+const MIN_ENTITY_COUNT: u32 = 2;
+
+/// Folded Italian (and a few English) words that say nothing about the topic of an episode, including the fillers
+/// of a spoken conversation.
+const STOP_WORDS: &[&str] = &[
+    "a", "ad", "al", "allo", "ai", "agli", "all", "alla", "alle", "anche", "ancora", "allora", "altro", "altri", "altra",
+    "altre", "avere", "abbiamo", "avete", "hanno", "ha", "ho", "hai", "aveva", "avevo", "avuto", "bene", "beh", "boh",
+    "bello", "bella", "belli", "basta", "c", "che", "chi", "ci", "cioe", "come", "con", "cosa", "cose", "cosi", "comunque",
+    "da", "dal", "dallo", "dai", "dagli", "dall", "dalla", "dalle", "dei", "degli", "del", "dell", "della", "delle",
+    "dello", "di", "dice", "dico", "dire", "detto", "dove", "dopo", "due", "e", "ed", "eh", "ehm", "era", "ero", "ecco",
+    "essere", "esatto", "fa", "fare", "fai", "faccio", "fatto", "forse", "fra", "gia", "gli", "giusto", "grazie", "i",
+    "il", "in", "io", "insomma", "invece", "l", "la", "le", "li", "lo", "lui", "lei", "loro", "ma", "magari", "me",
+    "mi", "mio", "mia", "miei", "mie", "molto", "molti", "meno", "mai", "ne", "nel", "nello", "nei", "negli", "nell",
+    "nella", "nelle", "no", "noi", "non", "nostro", "nostra", "niente", "nulla", "o", "ok", "okay", "oppure", "ora",
+    "per", "perche", "pero", "piu", "poi", "poco", "po", "praticamente", "proprio", "puo", "qua", "qui", "quale",
+    "quando", "quanto", "quasi", "quella", "quelle", "quelli", "quello", "questa", "queste", "questi", "questo",
+    "quindi", "se", "sei", "sempre", "senza", "si", "sia", "siamo", "siete", "sono", "sta", "sto", "stai", "stato",
+    "stata", "stati", "stesso", "su", "sul", "sullo", "sui", "sugli", "sull", "sulla", "sulle", "suo", "sua", "tanto",
+    "te", "ti", "tipo", "tra", "tu", "tuo", "tua", "tutto", "tutti", "tutta", "tutte", "un", "una", "uno", "va", "vabbe",
+    "vai", "vero", "voi", "vuoi", "voglio", "volta", "vedere", "visto", "and", "the", "of", "to", "is", "it", "you",
+    "yeah", "oh",
+];
+
+/// Lowercase words allowed within an entity, e.g. `Breath of the Wild`.
+const ENTITY_CONNECTORS: &[&str] = &["of", "the", "de", "von", "van"];
+
+lazy_static! {
+    static ref STOP: HashSet<&'static str> = STOP_WORDS.iter().copied().collect();
+    /// A word and the text before it.
+    static ref TOKEN: Regex = Regex::new(r"([^\p{L}\p{N}]*)([\p{L}\p{N}]+)").unwrap();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub name: String,
+    pub kind: TopicKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TopicKind {
+    /// A game, person, company, ... as written in the transcript.
+    Entity,
+    /// A folded word.
+    Keyword,
+}
+
+/// Occurrences of each named entity of a transcript, by folded name, with its most common spelling.
+type Entities = HashMap<String, (String, u32)>;
+
+/// The named entities of a transcript.
+fn entities(text: &str) -> Entities {
+    // runs of capitalised words, and whether they start a sentence
+    let mut runs: Vec<(Vec<&str>, bool)> = vec![];
+    let mut run: Vec<&str> = vec![];
+    let mut connectors: Vec<&str> = vec![];
+    let mut sentence_start = true;
+    let mut run_start = false;
+    // occurrences of each folded word written in lowercase, and capitalised in the middle of a sentence
+    let mut lowercase: HashMap<String, u32> = HashMap::new();
+    let mut capitalised: HashMap<String, u32> = HashMap::new();
+    for c in TOKEN.captures_iter(text) {
+        let (sep, word) = (c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str());
+        let ends_sentence = sep.contains(['.', '!', '?', '…']);
+        if !run.is_empty() && (ends_sentence || !sep.trim().is_empty()) {
+            runs.push((std::mem::take(&mut run), run_start));
+            connectors.clear();
+        }
+        sentence_start |= ends_sentence;
+        let upper = word.chars().next().is_some_and(char::is_uppercase);
+        let folded = fold(word);
+        if upper {
+            if !sentence_start {
+                *capitalised.entry(folded).or_default() += 1;
+            }
+            if run.is_empty() {
+                run_start = sentence_start;
+            }
+            run.append(&mut connectors);
+            run.push(word);
+        } else if !run.is_empty() && (ENTITY_CONNECTORS.contains(&word) || word.bytes().all(|b| b.is_ascii_digit())) {
+            if ENTITY_CONNECTORS.contains(&word) {
+                connectors.push(word);
+            } else {
+                // e.g. `Switch 2`, the number ends the run
+                run.append(&mut connectors);
+                run.push(word);
+                runs.push((std::mem::take(&mut run), run_start));
+            }
+        } else {
+            *lowercase.entry(folded).or_default() += 1;
+            if !run.is_empty() {
+                runs.push((std::mem::take(&mut run), run_start));
+            }
+            connectors.clear();
+        }
+        sentence_start = false;
+    }
+    if !run.is_empty() {
+        runs.push((run, run_start));
+    }
+
+    let key = |words: &[&str]| fold(&words.join(" "));
+    let mut found: HashMap<String, HashMap<String, u32>> = HashMap::new();
+    let mut initial: Vec<Vec<&str>> = vec![];
+    for (words, starts_sentence) in runs {
+        if starts_sentence {
+            // capitalised because it starts the sentence, unless it's said that way elsewhere too
+            if words.len() > 1 {
+                *found.entry(key(&words[1..])).or_default().entry(words[1..].join(" ")).or_default() += 1;
+            }
+            initial.push(words);
+        } else {
+            *found.entry(key(&words)).or_default().entry(words.join(" ")).or_default() += 1;
+        }
+    }
+    for words in initial {
+        if let Some(f) = found.get_mut(&key(&words)) {
+            *f.entry(words.join(" ")).or_default() += 1;
+        }
+    }
+
+    found
+        .into_iter()
+        .filter(|(k, _)| match k.split_once(' ') {
+            Some(_) => true,
+            // a single word is an entity if it's usually capitalised
+            None => !STOP.contains(k.as_str()) && capitalised.get(k).copied().unwrap_or(0) > lowercase.get(k).copied().unwrap_or(0),
+        })
+        .map(|(k, spellings)| {
+            let count = spellings.values().sum();
+            let name = spellings.into_iter().max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0))).unwrap().0;
+            (k, (name, count))
+        })
+        .filter(|(_, (_, count))| *count >= MIN_ENTITY_COUNT)
+        .collect()
+}
+
+/// Occurrences of the meaningful words of a transcript.
+pub(super) fn keywords(text: &str) -> HashMap<String, u32> {
+    let mut counts = HashMap::new();
+    for w in fuzzy::words(text) {
+        if !STOP.contains(w.as_str()) && !w.bytes().all(|b| b.is_ascii_digit()) {
+            *counts.entry(w).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// The `limit` heaviest of `counts`, weighing `(1 + ln tf) * ln(n / df)`.
+fn top<'a, T>(counts: &'a HashMap<String, T>, count: impl Fn(&T) -> u32, df: &HashMap<&str, u32>, n: f64, limit: usize) -> Vec<&'a String> {
+    let mut scored: Vec<(f64, &String)> = counts
+        .iter()
+        .map(|(k, v)| ((1. + (count(v) as f64).ln()) * (n / df[k.as_str()] as f64).ln(), k))
+        .filter(|(s, _)| *s > 0.)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.into_iter().take(limit).map(|(_, k)| k).collect()
+}
+
+/// Extract the topics of every episode with a transcript again, and store them on the episodes.
+pub async fn rebuild(db: &dyn Store) -> Result<(), StoreError> {
+    let mut extracted: HashMap<u32, (Entities, HashMap<String, u32>)> = HashMap::new();
+    for t in db.get_all::<EpisodeTranscript>().await? {
+        extracted.insert(t.episode_id, (entities(&t.data), keywords(&t.data)));
+    }
+    let mut entity_df: HashMap<&str, u32> = HashMap::new();
+    let mut keyword_df: HashMap<&str, u32> = HashMap::new();
+    for (entities, keywords) in extracted.values() {
+        for k in entities.keys() {
+            *entity_df.entry(k.as_str()).or_default() += 1;
+        }
+        for k in keywords.keys() {
+            *keyword_df.entry(k.as_str()).or_default() += 1;
+        }
+    }
+    let n = extracted.len() as f64;
+
+    let mut updated = 0;
+    for mut e in db.get_all::<Episode>().await? {
+        let topics = match extracted.get(&e.id) {
+            Some((entities, keywords)) => {
+                let entities = top(entities, |e| e.1, &entity_df, n, ENTITIES);
+                // words already part of an entity
+                let named: HashSet<&str> = entities.iter().flat_map(|k| k.split(' ')).collect();
+                let keywords = keywords.iter().filter(|(k, _)| !named.contains(k.as_str())).map(|(k, c)| (k.clone(), *c)).collect();
+                entities
+                    .into_iter()
+                    .map(|k| Topic { name: extracted[&e.id].0[k].0.clone(), kind: TopicKind::Entity })
+                    .chain(top(&keywords, |c| *c, &keyword_df, n, KEYWORDS).into_iter().map(|k| Topic { name: k.clone(), kind: TopicKind::Keyword }))
+                    .collect()
+            }
+            None => vec![],
+        };
+        if topics != e.topics {
+            e.topics = topics;
+            db.update_one_stateless(e.id, &e).await?;
+            updated += 1;
+        }
+    }
+    info!("extracted the topics of {} transcripts, {} episodes updated", extracted.len(), updated);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use crate::{bot::MetaQuery, db::MemoryDatabase, spreaker::PlayCounts, transcript::{FromTo, Segment, Transcript}};
+
+    use super::*;
+
+    /// Names and counts of the entities of `text`, sorted.
+    fn names(text: &str) -> Vec<(String, u32)> {
+        let mut e: Vec<(String, u32)> = entities(text).into_values().collect();
+        e.sort();
+        e
+    }
+
+    fn entity(name: &str, count: u32) -> (String, u32) {
+        (name.to_owned(), count)
+    }
+
+    #[test]
+    fn sentence_start_capitalisation() {
+        // `Oggi` is capitalised only because it starts the sentence, `Zelda` is said in the middle of one too
+        let e = names("Oggi parliamo di Zelda e di Mario. Zelda è il migliore, dice Mario.");
+        assert_eq!(e, vec![entity("Mario", 2), entity("Zelda", 2)]);
+        // the first word of a run starting a sentence is dropped, unless the whole run is said elsewhere
+        let e = names("Super Mario è bello. Anche Super Mario lo è.");
+        assert_eq!(e, vec![entity("Super Mario", 2)]);
+    }
+
+    #[test]
+    fn connectors_within_entities() {
+        let e = names("giochiamo a Breath of the Wild, poi ancora Breath of the Wild tutto il giorno");
+        assert_eq!(e, vec![entity("Breath of the Wild", 2)]);
+        // a connector ending the run isn't part of the entity
+        let e = names("parlo di Pikachu of e di Pikachu the");
+        assert_eq!(e, vec![entity("Pikachu", 2)]);
+    }
+
+    #[test]
+    fn numbers_end_entities() {
+        let e = names("compro la Switch 2 oggi e la Switch 2 Nintendo domani, non la Switch");
+        assert_eq!(e, vec![entity("Switch 2", 2)]);
+        // a number alone isn't an entity
+        assert!(names("sono le 2 e le 2 e mezza").is_empty());
+    }
+
+    #[test]
+    fn capitalised_words_must_outnumber_lowercase_ones() {
+        // said as often capitalised as not, it's probably a common word
+        assert!(names("la Mela e la mela, poi la Mela e la mela").is_empty());
+        assert_eq!(names("la Nintendo e la Nintendo e la nintendo"), vec![entity("Nintendo", 2)]);
+        // the most common spelling is kept
+        assert_eq!(names("la Nintendo e la Nintendo e la NINTENDO"), vec![entity("Nintendo", 3)]);
+        // stop words are never entities
+        assert!(names("e poi Quindi e Quindi").is_empty());
+    }
+
+    #[test]
+    fn entities_said_once_are_dropped() {
+        assert!(names("parliamo di Zelda e di Breath of the Wild").is_empty());
+        assert_eq!(names(&"parliamo di Zelda ".repeat(MIN_ENTITY_COUNT as usize)), vec![entity("Zelda", MIN_ENTITY_COUNT)]);
+    }
+
+    #[test]
+    fn keywords_skip_stop_words_and_numbers() {
+        let k = keywords("Perché la Pizza del 2020 è come la pizza di sempre, Pizza!");
+        assert_eq!(k, HashMap::from([("pizza".to_owned(), 3)]));
+    }
+
+    #[test]
+    fn top_weighs_frequency_against_document_frequency() {
+        let counts: HashMap<String, u32> = [("zelda", 3), ("mario", 1), ("nintendo", 9), ("link", 3)].map(|(k, c)| (k.to_owned(), c)).into();
+        let df = HashMap::from([("zelda", 1), ("mario", 1), ("nintendo", 4), ("link", 2)]);
+        // said in every episode, nintendo weighs nothing
+        assert_eq!(top(&counts, |c| *c, &df, 4., 10), vec!["zelda", "link", "mario"]);
+        assert_eq!(top(&counts, |c| *c, &df, 4., 1), vec!["zelda"]);
+    }
+
+    fn transcript(id: u32, text: &str) -> EpisodeTranscript {
+        let timestamps = FromTo { from: Duration::ZERO, to: Duration::from_secs(1) };
+        (id, Transcript { transcription: vec![Segment { timestamps, text: text.to_owned() }] }).into()
+    }
+
+    #[tokio::test]
+    async fn rebuild_stores_the_topics_on_the_episodes() {
+        let db = MemoryDatabase::new();
+        let db: &dyn Store = &db;
+        let episode = |id| Episode {
+            id,
+            title: format!("episodio {}", id),
+            duration: 3_600_000,
+            show_id: 1,
+            author_id: 1,
+            published_at: Utc::now(),
+            download_url: String::new(),
+            description: String::new(),
+            description_html: String::new(),
+            image_url: None,
+            image_original_url: None,
+            site_url: None,
+            tags: vec![],
+            explicit: false,
+            plays: PlayCounts::default(),
+            chapters: vec![],
+            topics: vec![],
+        };
+        let episodes: Vec<Episode> = [1, 2, 3].map(episode).into();
+        db.insert_stateless(&episodes).await.unwrap();
+        db.update_one_stateless(1, &transcript(1, "oggi giochiamo a Breath of the Wild con Mario, Breath of the Wild è bellissimo dice Mario")).await.unwrap();
+        db.update_one_stateless(2, &transcript(2, "oggi parliamo di cucina, la cucina di Mario e la carbonara di Mario")).await.unwrap();
+        rebuild(db).await.unwrap();
+
+        let first = db.get::<Episode>(1).await.unwrap().unwrap();
+        // said in both episodes, Mario isn't a topic
+        assert_eq!(first.topics.iter().filter(|t| t.kind == TopicKind::Entity).map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["Breath of the Wild"]);
+        // nor are the words of the entities
+        assert!(first.topics.iter().any(|t| t == &Topic { name: "bellissimo".to_owned(), kind: TopicKind::Keyword }));
+        assert!(!first.topics.iter().any(|t| t.name == "breath" || t.name == "mario"));
+        let second = db.get::<Episode>(2).await.unwrap().unwrap();
+        assert!(second.topics.iter().any(|t| t == &Topic { name: "cucina".to_owned(), kind: TopicKind::Keyword }));
+        // no transcript, no topics
+        assert!(db.get::<Episode>(3).await.unwrap().unwrap().topics.is_empty());
+
+        assert!(MetaQuery::parse("topic:breath_of_the").matches(&first));
+        assert!(!MetaQuery::parse("topic:breath_of_the").matches(&second));
+        assert!(MetaQuery::parse("argomento:CUCINA").matches(&second));
+        assert!(!MetaQuery::parse("topic:cucina topic:wild").matches(&second));
+    }
+}
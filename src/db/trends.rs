@@ -0,0 +1,126 @@
+//! How often a word or phrase is said in each episode, over the history of the show.
+//!
+//! Counting a term means going through every transcript, so the counts of each term are kept in the `term_trends`
+//! collection along with the revision of the transcripts they come from, the sum of their `TranscriptRevision`s: they
+//! are counted again only after a transcript is written. The index of `ppp_bot` counts them without loading the
+//! transcripts, the other processes go through the database. The counts left stale by an import are removed by
+//! `prune`, or the collection would keep one document for every term ever asked.
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+#[allow(unused_imports)]
+use log::{debug, info, trace};
+use serde::{Deserialize, Serialize};
+
+use crate::{spreaker::Episode, transcript::EpisodeTranscript};
+
+use super::{segments::{query_words, TranscriptRevision}, PPPData, Store, StoreError};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermTrend {
+    /// Folded words of the term, separated by a space.
+    pub term: String,
+    /// Revision of the transcripts counted.
+    pub revision: u64,
+    /// Every episode with a transcript, oldest first.
+    pub episodes: Vec<TermCount>,
+}
+
+impl PPPData for TermTrend {
+    const COLLECTION: &'static str = "term_trends";
+    const ID_KEY: &'static str = "term";
+    type IdType = String;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TermCount {
+    pub episode_id: u32,
+    #[serde(with = "crate::serde::bson_datetime")]
+    pub published_at: DateTime<Utc>,
+    pub count: u32,
+}
+
+impl TermTrend {
+    /// Times the term is said in all the episodes.
+    pub fn total(&self) -> u32 {
+        self.episodes.iter().map(|e| e.count).sum()
+    }
+
+    /// The `limit` episodes saying the term the most, the oldest first among equals.
+    pub fn peaks(&self, limit: usize) -> Vec<&TermCount> {
+        let mut peaks: Vec<&TermCount> = self.episodes.iter().filter(|e| e.count > 0).collect();
+        peaks.sort_by(|a, b| b.count.cmp(&a.count).then(a.published_at.cmp(&b.published_at)));
+        peaks.truncate(limit);
+        peaks
+    }
+}
+
+/// Times the words `phrase` are said one after the other in `words`.
+fn count_phrase(words: &[String], phrase: &[String]) -> u32 {
+    words.windows(phrase.len()).filter(|w| *w == phrase).count() as u32
+}
+
+/// Counts of the folded words `phrase` in each transcript of `db`, and the revision of the transcripts.
+async fn count(db: &dyn Store, phrase: &[String]) -> Result<(u64, HashMap<u32, u32>), StoreError> {
+    if let Some(counts) = db.segment_index().and_then(|i| i.phrase_counts(phrase)) {
+        return Ok(counts);
+    }
+    let revision = corpus_revision(db).await?;
+    let counts = db
+        .get_all::<EpisodeTranscript>()
+        .await?
+        .into_iter()
+        .map(|t| (t.episode_id, count_phrase(&query_words(&t.data), phrase)))
+        .collect();
+    Ok((revision, counts))
+}
+
+/// Revision of the transcripts of `db` as a whole.
+async fn corpus_revision(db: &dyn Store) -> Result<u64, StoreError> {
+    if let Some(r) = db.segment_index().and_then(|i| i.revision()) {
+        return Ok(r);
+    }
+    Ok(db.get_all::<TranscriptRevision>().await?.iter().map(|r| r.revision).sum())
+}
+
+/// Occurrences of `term` in every episode, `None` if it has no words. Counted again, and cached, only if a transcript
+/// changed since the last time.
+pub async fn trend(db: &dyn Store, term: &str) -> Result<Option<TermTrend>, StoreError> {
+    let words = query_words(term);
+    if words.is_empty() {
+        return Ok(None);
+    }
+    let key = words.join(" ");
+    let revision = corpus_revision(db).await?;
+    if let Some(t) = db.get::<TermTrend>(key.clone()).await?.filter(|t| t.revision == revision) {
+        trace!("trend of `{}` cached", key);
+        return Ok(Some(t));
+    }
+
+    let (revision, counts) = count(db, &words).await?;
+    let mut episodes: Vec<TermCount> = db
+        .get_all::<Episode>()
+        .await?
+        .into_iter()
+        .filter_map(|e| counts.get(&e.id).map(|&count| TermCount { episode_id: e.id, published_at: e.published_at, count }))
+        .collect();
+    episodes.sort_by_key(|e| (e.published_at, e.episode_id));
+    let t = TermTrend { term: key, revision, episodes };
+    debug!("counted `{}` in {} episodes, {} times", t.term, t.episodes.len(), t.total());
+    db.update_one_stateless(t.term.clone(), &t).await?;
+    Ok(Some(t))
+}
+
+/// Remove the cached counts of older revisions of the transcripts, returns how many were removed.
+pub async fn prune(db: &dyn Store) -> Result<usize, StoreError> {
+    let revision = corpus_revision(db).await?;
+    let mut pruned = 0;
+    for t in db.get_all::<TermTrend>().await? {
+        if t.revision != revision {
+            db.delete::<TermTrend>(t.term).await?;
+            pruned += 1;
+        }
+    }
+    debug!("pruned {} stale term trends", pruned);
+    Ok(pruned)
+}
@@ -1,7 +1,7 @@
 use std::{fs::create_dir_all, sync::Arc};
 use std::path::PathBuf;
 
-use power_pizza_bot::spreaker::{SimpleEpisode, SpreakerDownloader, SpreakerResponse};
+use power_pizza_bot::spreaker::{SimpleEpisode, SpreakerData, SpreakerDownloader};
 use reqwest::Client;
 use tokio_stream::StreamExt;
 use lazy_static::lazy_static;
@@ -16,7 +16,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + 'static>> {
     pretty_env_logger::init();
     let cli = Arc::new(Client::new());
 
-    let mut it = SpreakerResponse::<SimpleEpisode>::request(
+    let mut it = SpreakerData::<SimpleEpisode>::request(
         "https://api.spreaker.com/v2/shows/3039391/episodes".to_owned(),
         cli.clone(),
     );
@@ -1,88 +1,126 @@
-use std::{collections::HashSet, sync::Arc, time::Duration};
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
 use tokio::sync::Mutex;
 #[allow(unused_imports)]
 use log::{info,debug,warn,error};
-use crate::db::DB;
-use crate::spreaker::{Episode, SimpleEpisode, SpreakerData};
+use crate::db::Store;
+use crate::spreaker::{Episode, ShowSource, SpreakerApi};
+use crate::status::{ImportRunHandle, ImportStage};
 use tokio_stream::StreamExt;
 
-pub async fn import_database(show: String) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn import_database(db: &dyn Store, show: String, run: &ImportRunHandle) -> Result<(), Box<dyn std::error::Error>> {
+    import_from(db, Arc::new(SpreakerApi::default()), show.parse()?, run).await
+}
+
+/// Import the metadata of `show` from `source`: every episode if the database has none, otherwise the episodes more
+/// recent than the last one imported.
+pub async fn import_from(db: &dyn Store, source: Arc<dyn ShowSource>, show: u32, run: &ImportRunHandle) -> Result<(), Box<dyn std::error::Error>> {
     info!("starting import");
-    match DB.last_modified().await {
-        Some(t) => {
-            info!("last update: {}", t);
-            let ep_ids: HashSet<u32> = DB.get_ids::<Episode>().await?.into_iter().collect();
-            info!("fetching episodes");
-            let mut it = SpreakerData::<SimpleEpisode>::request(
-                format!("https://api.spreaker.com/v2/shows/{}/episodes", show),
-                Arc::new(reqwest::Client::new()),
-            );
-            info!("got {} new episodes", ep_ids.len());
-            let mut new_eps = vec![];
-            while let Some(e) = it.next().await {
-                if ep_ids.contains(&e.id) {
-                    break;
-                }
-                let e = e.get_episode().await?;
-                if !ep_ids.contains(&e.id) {
-                    new_eps.push(e);
-                }
+    info!("fetching show {}", show);
+    let s = source.show(show).await?;
+    db.update_one_stateless(s.id, &s).await?;
+    let ep_ids: HashSet<u32> = db.get_ids::<Episode>().await?.into_iter().collect();
+    if !ep_ids.is_empty() {
+        info!("{} episodes already in database", ep_ids.len());
+        info!("fetching episodes");
+        let mut it = source.episodes(show);
+        let mut new_eps = vec![];
+        while let Some(e) = it.next().await {
+            if ep_ids.contains(&e.id) {
+                break;
             }
-            info!("got {} new episodes", new_eps.len());
-            if !new_eps.is_empty() {
-                DB.insert_stateful::<Episode>(&new_eps).await?;
+            run.lock().unwrap().discovered += 1;
+            match source.episode(&e).await {
+                Ok(e) => new_eps.push(e),
+                Err(err) => {
+                    error!("couldn't fetch episode {}: {}", e.id, err);
+                    run.lock().unwrap().fail(Some(e.id), ImportStage::Metadata, err);
+                }
             }
         }
-        None => {
-            info!("no status document found, initializing database");
-            let cli = Arc::new(reqwest::Client::new());
-            let mut it = SpreakerData::<SimpleEpisode>::request(
-                "https://api.spreaker.com/v2/shows/3039391/episodes".to_owned(),
-                cli.clone(),
-            );
+        info!("got {} new episodes", new_eps.len());
+        if !new_eps.is_empty() {
+            db.insert_stateless::<Episode>(&new_eps).await?;
+            run.lock().unwrap().inserted += new_eps.len() as u32;
+        }
+    } else {
+        info!("no episodes found, initializing database");
+        let mut it = source.episodes(show);
 
-            let mut ep_ids = vec![];
-            while let Some(e) = it.next().await {
-                info!("push episode {} to queue", e.id);
-                // if e.id == 62738419 {
-                //     continue;
-                // }
-                ep_ids.push(e);
-            }
+        let mut ep_ids = vec![];
+        while let Some(e) = it.next().await {
+            info!("push episode {} to queue", e.id);
+            ep_ids.push(e);
+        }
+        run.lock().unwrap().discovered += ep_ids.len() as u32;
 
-            let mut handles = vec![];
-            let eps = Arc::new(Mutex::new(vec![]));
-            for e in ep_ids {
-                let eps = eps.clone();
-                let h = tokio::spawn(async move {
-                    info!("fetching episode {}", e.id);
-                    let e = e.get_episode().await.unwrap();
-                    eps.lock().await.push(e);
-                });
-                handles.push(h);
-                if handles.len() >= 10 {
-                    'a: loop {
-                        for ih in 0..handles.len() {
-                            if handles[ih].is_finished() {
-                                handles.remove(ih);
-                                break 'a;
-                            }
+        let mut handles = vec![];
+        let eps = Arc::new(Mutex::new(vec![]));
+        for e in ep_ids {
+            let eps = eps.clone();
+            let run = run.clone();
+            let source = source.clone();
+            let h = tokio::spawn(async move {
+                info!("fetching episode {}", e.id);
+                match source.episode(&e).await {
+                    Ok(e) => eps.lock().await.push(e),
+                    Err(err) => {
+                        error!("couldn't fetch episode {}: {}", e.id, err);
+                        run.lock().unwrap().fail(Some(e.id), ImportStage::Metadata, err);
+                    }
+                }
+            });
+            handles.push(h);
+            if handles.len() >= 10 {
+                'a: loop {
+                    for ih in 0..handles.len() {
+                        if handles[ih].is_finished() {
+                            handles.remove(ih);
+                            break 'a;
                         }
-                        tokio::time::sleep(Duration::from_millis(100)).await;
                     }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
                 }
             }
-            for h in handles {
-                h.await?;
-            }
+        }
+        for h in handles {
+            h.await?;
+        }
 
-            if !eps.lock().await.is_empty() {
-                DB
-                    .insert_stateful::<Episode>(&eps.lock().await)            
-                    .await?;
-            }
+        if !eps.lock().await.is_empty() {
+            db
+                .insert_stateless::<Episode>(&eps.lock().await)            
+                .await?;
+            run.lock().unwrap().inserted += eps.lock().await.len() as u32;
         }
     }
 
     Ok(())
 }
+
+/// Fetch again from `source` the metadata of the episodes of `show` already in the database: the ones imported before
+/// a field was added (tags, chapters, play counts, ...) lack it. The topics, extracted from the transcripts, are kept.
+/// Returns the number of episodes updated and of the ones that couldn't be fetched.
+pub async fn refresh_metadata(db: &dyn Store, source: Arc<dyn ShowSource>, show: u32) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+    let mut known: HashMap<u32, Episode> = db.get_all::<Episode>().await?.into_iter().map(|e| (e.id, e)).collect();
+    info!("refreshing the metadata of {} episodes", known.len());
+    let (mut updated, mut failed) = (0, 0);
+    let mut it = source.episodes(show);
+    while let Some(e) = it.next().await {
+        let Some(old) = known.remove(&e.id) else { continue };
+        match source.episode(&e).await {
+            Ok(mut new) => {
+                new.topics = old.topics;
+                db.update_one_stateless(new.id, &new).await?;
+                updated += 1;
+            }
+            Err(err) => {
+                error!("couldn't fetch episode {}: {}", e.id, err);
+                failed += 1;
+            }
+        }
+    }
+    if !known.is_empty() {
+        warn!("{} episodes no longer on the show: {:?}", known.len(), known.keys().collect::<Vec<_>>());
+    }
+    Ok((updated, failed))
+}
@@ -6,3 +6,12 @@ pub mod import;
 pub mod transcript;
 pub mod bot;
 pub mod config;
+pub mod migrations;
+pub mod audio;
+pub mod artifacts;
+pub mod daemon;
+pub mod progress;
+pub mod metrics;
+pub mod api;
+pub mod web;
+pub mod normalize;
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use axum::{http::StatusCode, routing::get, Router};
+use lazy_static::lazy_static;
+use log::{error, info};
+use prometheus::{register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, Gauge, HistogramVec, IntCounter, IntCounterVec, TextEncoder};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::progress::{Progress, ProgressEvent};
+use crate::status::ImportStage;
+
+lazy_static! {
+    pub static ref COMMANDS: IntCounterVec = register_int_counter_vec!(
+        "ppp_bot_commands_total", "Commands received, by command", &["command"]
+    ).unwrap();
+    pub static ref BOT_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ppp_bot_errors_total", "Errors while replying, by BotError variant", &["error"]
+    ).unwrap();
+    pub static ref WHITELIST_DENIALS: IntCounter = register_int_counter!(
+        "ppp_bot_whitelist_denials_total", "Commands refused to users not in the beta"
+    ).unwrap();
+    pub static ref SEARCH_SECONDS: HistogramVec = register_histogram_vec!(
+        "ppp_search_duration_seconds", "Search latency, by kind of search", &["kind"]
+    ).unwrap();
+    pub static ref SEARCH_RESULTS: HistogramVec = register_histogram_vec!(
+        "ppp_search_results", "Results returned by a search, by kind of search", &["kind"],
+        vec![0., 1., 2., 5., 10., 20., 50., 100.]
+    ).unwrap();
+    pub static ref SEARCH_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ppp_search_errors_total", "Failed searches, by SearchError variant", &["error"]
+    ).unwrap();
+    pub static ref IMPORT_STAGE_SECONDS: HistogramVec = register_histogram_vec!(
+        "ppp_import_stage_duration_seconds", "Duration of the import jobs, by stage", &["stage"],
+        vec![0.1, 1., 10., 30., 60., 300., 600., 1800., 3600., 7200.]
+    ).unwrap();
+    pub static ref IMPORT_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "ppp_import_failures_total", "Failed import jobs, by stage", &["stage"]
+    ).unwrap();
+    pub static ref IMPORT_LAST_RUN: Gauge = register_gauge!(
+        "ppp_import_last_run_timestamp_seconds", "End of the last completed import run"
+    ).unwrap();
+}
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Mark the process as ready (or not) to serve, reported by `/readyz`.
+pub fn set_ready(ready: bool) {
+    READY.store(ready, Ordering::Relaxed);
+}
+
+/// Record the latency and the number of results of a search started at `start`.
+pub fn observe_search<T, E: MetricLabel>(kind: &str, start: Instant, res: &Result<T, E>, count: impl FnOnce(&T) -> usize) {
+    SEARCH_SECONDS.with_label_values(&[kind]).observe(start.elapsed().as_secs_f64());
+    match res {
+        Ok(r) => SEARCH_RESULTS.with_label_values(&[kind]).observe(count(r) as f64),
+        Err(e) => SEARCH_ERRORS.with_label_values(&[e.label()]).inc(),
+    }
+}
+
+/// Name of an enum variant, used as a label value.
+pub trait MetricLabel {
+    fn label(&self) -> &'static str;
+}
+
+/// Record the duration of the import jobs from the progress events. Failures are counted by `ImportRun::fail`.
+pub fn spawn_import(progress: &Progress) -> JoinHandle<()> {
+    let mut rx = progress.subscribe();
+    tokio::spawn(async move {
+        let mut started: HashMap<(u32, ImportStage), Instant> = HashMap::new();
+        loop {
+            match rx.recv().await {
+                Ok(ProgressEvent::Started { episode, stage }) => {
+                    started.insert((episode, stage), Instant::now());
+                }
+                Ok(ProgressEvent::Finished { episode, stage }) => {
+                    if let Some(t) = started.remove(&(episode, stage)) {
+                        IMPORT_STAGE_SECONDS.with_label_values(&[&stage.to_string()]).observe(t.elapsed().as_secs_f64());
+                    }
+                }
+                Ok(ProgressEvent::Failed { episode, stage, .. }) => {
+                    started.remove(&(episode, stage));
+                }
+                Ok(ProgressEvent::Done) | Err(broadcast::error::RecvError::Closed) => break,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            }
+        }
+    })
+}
+
+/// Serve `/metrics`, `/healthz` and `/readyz` on `addr` in the background.
+pub async fn serve(addr: &str) -> std::io::Result<JoinHandle<()>> {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(ready));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("serving metrics on {}", addr);
+    Ok(tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("metrics server stopped: {}", e);
+        }
+    }))
+}
+
+async fn metrics() -> Result<String, StatusCode> {
+    let mut buf = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buf)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    String::from_utf8(buf).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn ready() -> (StatusCode, &'static str) {
+    if READY.load(Ordering::Relaxed) {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+    }
+}
@@ -0,0 +1,156 @@
+//! Numbered data migrations.
+//!
+//! Every migration runs at most once per database: applied migrations are recorded in the `migrations` collection and
+//! skipped afterwards. Processes starting together take turns through a database lock, migrations must still be
+//! idempotent since an interrupted one runs again. New migrations are appended to `MIGRATIONS` with the next id, never
+//! renumbered.
+//!
+//! Migrations only change the shape of the documents: data derived from the transcripts is rebuilt by
+//! `ppp_import rebuild-derived`.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+use mongodb::bson::{self, Bson};
+use serde::{Deserialize, Serialize};
+
+use crate::{bot::BotUser, db::{lock_owner, PPPData, Store, StoreError}, spreaker::Episode, status::{ImportRun, ImportSource, ImportVersions}};
+
+type MigrationFn = for<'a> fn(&'a dyn Store) -> BoxFuture<'a, Result<(), StoreError>>;
+
+pub struct Migration {
+    pub id: u32,
+    pub name: &'static str,
+    run: MigrationFn,
+}
+
+pub static MIGRATIONS: &[Migration] = &[
+    Migration { id: 1, name: "users_drop_notified", run: |db| Box::pin(users_drop_notified(db)) },
+    Migration { id: 2, name: "episodes_published_at_date", run: |db| Box::pin(episodes_published_at_date(db)) },
+    Migration { id: 3, name: "status_singleton", run: |db| Box::pin(status_singleton(db)) },
+    Migration { id: 4, name: "status_to_import_runs", run: |db| Box::pin(status_to_import_runs(db)) },
+    // 5 to 8 built the derived data, they may be recorded as applied: the next migration is 9
+];
+
+/// Database lock held while applying the migrations.
+const MIGRATIONS_LOCK: &str = "migrations";
+/// Longer than the migrations take, so that a process dying while applying them doesn't block the others for long.
+const LOCK_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AppliedMigration {
+    pub id: u32,
+    pub name: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+impl PPPData for AppliedMigration {
+    const COLLECTION: &'static str = "migrations";
+    const ID_KEY: &'static str = "id";
+    type IdType = u32;
+}
+
+/// Current schema version of the database, that is the id of the last applied migration.
+pub async fn version(db: &dyn Store) -> Result<u32, StoreError> {
+    Ok(db.get_ids::<AppliedMigration>().await?.into_iter().max().unwrap_or(0))
+}
+
+/// Apply the pending migrations in order, returning the ids of the applied ones. Waits for other processes applying
+/// them to finish first.
+pub async fn run(db: &dyn Store) -> Result<Vec<u32>, StoreError> {
+    let owner = lock_owner();
+    while !db.acquire_lock(MIGRATIONS_LOCK, &owner, Utc::now() + LOCK_TTL).await? {
+        info!("waiting for another process to apply the migrations");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let res = apply(db).await;
+    db.release_lock(MIGRATIONS_LOCK, &owner).await?;
+    res
+}
+
+async fn apply(db: &dyn Store) -> Result<Vec<u32>, StoreError> {
+    let applied = db.get_ids::<AppliedMigration>().await?;
+    let mut done = vec![];
+    for m in MIGRATIONS.iter().filter(|m| !applied.contains(&m.id)) {
+        info!("applying migration {} ({})", m.id, m.name);
+        (m.run)(db).await?;
+        db.update_one_stateless(m.id, &AppliedMigration {
+            id: m.id,
+            name: m.name.to_owned(),
+            applied_at: Utc::now(),
+        }).await?;
+        done.push(m.id);
+    }
+    if done.is_empty() {
+        debug!("database schema is up to date (version {})", version(db).await?);
+    }
+    Ok(done)
+}
+
+/// The `notified` flag of users was never read.
+async fn users_drop_notified(db: &dyn Store) -> Result<(), StoreError> {
+    for mut d in db.all_docs(BotUser::COLLECTION).await? {
+        if d.remove("notified").is_some() {
+            let id = d.get(BotUser::ID_KEY).cloned().unwrap_or(Bson::Null);
+            db.replace_doc(BotUser::COLLECTION, BotUser::ID_KEY, id, d).await?;
+        }
+    }
+    Ok(())
+}
+
+/// `published_at` used to be an integer unix timestamp, store it as a proper date so that it can be queried.
+async fn episodes_published_at_date(db: &dyn Store) -> Result<(), StoreError> {
+    for mut d in db.all_docs(Episode::COLLECTION).await? {
+        let ts = match d.get("published_at") {
+            Some(Bson::Int64(t)) => *t,
+            Some(Bson::Int32(t)) => *t as i64,
+            _ => continue,
+        };
+        d.insert("published_at", bson::DateTime::from_millis(ts * 1000));
+        let id = d.get(Episode::ID_KEY).cloned().unwrap_or(Bson::Null);
+        db.replace_doc(Episode::COLLECTION, Episode::ID_KEY, id, d).await?;
+    }
+    Ok(())
+}
+
+/// Singleton document that recorded the time of the last update, replaced by `ImportRun`.
+#[derive(Deserialize, Serialize)]
+struct LegacyStatus {
+    #[serde(with = "chrono::serde::ts_seconds")]
+    last_update: DateTime<Utc>,
+}
+
+const LEGACY_STATUS: &str = "status";
+
+async fn legacy_status(db: &dyn Store) -> Result<Vec<LegacyStatus>, StoreError> {
+    db.all_docs(LEGACY_STATUS)
+        .await?
+        .into_iter()
+        .map(|d| bson::from_document(d).map_err(StoreError::from))
+        .collect()
+}
+
+/// Older versions could leave more than one status document around, keep only the most recent.
+async fn status_singleton(db: &dyn Store) -> Result<(), StoreError> {
+    let status = legacy_status(db).await?;
+    if status.len() > 1 {
+        let last = status.into_iter().max_by_key(|s| s.last_update).unwrap();
+        db.drop_collection(LEGACY_STATUS).await?;
+        db.insert_docs(LEGACY_STATUS, "last_update", vec![bson::to_document(&last)?]).await?;
+    }
+    Ok(())
+}
+
+/// Keep the time of the last update as a finished `ImportRun`, then drop the status collection.
+async fn status_to_import_runs(db: &dyn Store) -> Result<(), StoreError> {
+    if let Some(s) = legacy_status(db).await?.into_iter().max_by_key(|s| s.last_update) {
+        let mut run = ImportRun::start(ImportSource::Legacy, ImportVersions::default());
+        run.id = s.last_update.timestamp_millis();
+        run.started_at = s.last_update;
+        run.finished_at = Some(s.last_update);
+        db.update_one_stateless(run.id, &run).await?;
+    }
+    db.drop_collection(LEGACY_STATUS).await
+}
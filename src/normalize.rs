@@ -0,0 +1,62 @@
+use std::ops::Range;
+
+use unidecode::{unidecode, unidecode_char};
+
+/// `s` transliterated to ASCII and lowercased: the form used to compare text regardless of accents and case.
+pub fn fold(s: &str) -> String {
+    unidecode(s).to_lowercase()
+}
+
+/// ASCII transliteration of a text, remembering where each of its bytes comes from in the original.
+///
+/// A character can transliterate to several bytes (`æ` to `ae`) or to none (combining marks), so offsets in
+/// `text` can't be used on the original directly: `original_range` converts them to character offsets, the
+/// unit of `Timestamp::offsets`.
+#[derive(Debug)]
+pub struct Normalized {
+    pub text: String,
+    /// Index of the original character each byte of `text` comes from, followed by the number of characters.
+    map: Vec<usize>,
+}
+
+impl Normalized {
+    pub fn new(s: &str) -> Self {
+        let mut text = String::with_capacity(s.len());
+        let mut map = Vec::with_capacity(s.len() + 1);
+        let mut chars = 0;
+        for (i, c) in s.chars().enumerate() {
+            let t = unidecode_char(c);
+            text.push_str(t);
+            map.extend(std::iter::repeat_n(i, t.len()));
+            chars = i + 1;
+        }
+        map.push(chars);
+        Self { text, map }
+    }
+
+    /// Characters of the original text that produced the bytes `range` of `text`.
+    ///
+    /// A range starting or ending within the transliteration of a character covers the whole character, an
+    /// empty range maps to an empty range.
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.map[range.start.min(self.text.len())];
+        if range.is_empty() {
+            return start..start;
+        }
+        start..self.map[range.end.min(self.text.len()) - 1] + 1
+    }
+}
+
+/// `pattern` made to match a `Normalized` text: non-ASCII characters are replaced by their (escaped)
+/// transliteration, the regex syntax is left untouched.
+pub fn normalize_pattern(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            out.push_str(&regex::escape(unidecode_char(c)));
+        }
+    }
+    out
+}
@@ -0,0 +1,311 @@
+use std::{collections::HashMap, fmt::Display, io::IsTerminal, time::{Duration, Instant}};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+#[allow(unused_imports)]
+use log::{debug, warn};
+use teloxide::{prelude::Requester, types::{ChatId, MessageId}, Bot};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::status::ImportStage;
+
+/// Event emitted by the import jobs.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    Queued { episode: u32, stage: ImportStage },
+    Started { episode: u32, stage: ImportStage },
+    /// Bytes of the audio file downloaded so far, and the expected total.
+    Downloaded { episode: u32, bytes: u64, total: Option<u64> },
+    /// Length of the audio just transcribed.
+    Transcribed { episode: u32, audio_seconds: f64 },
+    Finished { episode: u32, stage: ImportStage },
+    Failed { episode: u32, stage: ImportStage, error: String },
+    /// The import is over, consumers should stop.
+    Done,
+}
+
+/// Sending side of the progress events, cheap to clone. Events are dropped when nobody is listening.
+#[derive(Clone)]
+pub struct Progress {
+    tx: broadcast::Sender<ProgressEvent>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self { tx: broadcast::channel(1024).0 }
+    }
+}
+
+impl Progress {
+    pub fn emit(&self, event: ProgressEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Callback for `download_file_with_progress`, emitting `Downloaded` at most once per MiB.
+    pub fn download_callback(&self, episode: u32) -> impl Fn(u64, Option<u64>) + Send + Sync + '_ {
+        let last = std::sync::atomic::AtomicU64::new(0);
+        move |bytes, total| {
+            let mib = bytes >> 20;
+            if last.swap(mib, std::sync::atomic::Ordering::Relaxed) != mib || Some(bytes) == total {
+                self.emit(ProgressEvent::Downloaded { episode, bytes, total });
+            }
+        }
+    }
+}
+
+const STAGES: [ImportStage; 4] = [ImportStage::Download, ImportStage::Transcribe, ImportStage::Convert, ImportStage::Insert];
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StageCounts {
+    pub queued: usize,
+    pub active: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+/// Running totals built from the events, shared by the displays.
+pub struct ProgressSummary {
+    started: Instant,
+    stages: HashMap<ImportStage, StageCounts>,
+    /// Duration in seconds of the episodes, to estimate the audio left to transcribe.
+    durations: HashMap<u32, f64>,
+    pending_audio: HashMap<u32, f64>,
+    transcribing_since: HashMap<u32, Instant>,
+    transcribed_audio: f64,
+    transcribe_time: Duration,
+    downloaded: HashMap<u32, u64>,
+}
+
+impl ProgressSummary {
+    pub fn new(durations: HashMap<u32, f64>) -> Self {
+        Self {
+            started: Instant::now(),
+            stages: STAGES.iter().map(|s| (*s, StageCounts::default())).collect(),
+            durations,
+            pending_audio: HashMap::new(),
+            transcribing_since: HashMap::new(),
+            transcribed_audio: 0.,
+            transcribe_time: Duration::ZERO,
+            downloaded: HashMap::new(),
+        }
+    }
+
+    pub fn apply(&mut self, event: &ProgressEvent) {
+        match *event {
+            ProgressEvent::Queued { episode, stage } => {
+                self.stage(stage).queued += 1;
+                // downloads are transcribed next
+                if matches!(stage, ImportStage::Download | ImportStage::Transcribe) {
+                    self.pending_audio.insert(episode, self.durations.get(&episode).copied().unwrap_or_default());
+                }
+            }
+            ProgressEvent::Started { episode, stage } => {
+                let s = self.stage(stage);
+                s.queued = s.queued.saturating_sub(1);
+                s.active += 1;
+                if stage == ImportStage::Transcribe {
+                    self.transcribing_since.insert(episode, Instant::now());
+                }
+            }
+            ProgressEvent::Downloaded { episode, bytes, .. } => {
+                self.downloaded.insert(episode, bytes);
+            }
+            ProgressEvent::Transcribed { episode, audio_seconds } => {
+                self.transcribed_audio += audio_seconds;
+                if let Some(t) = self.transcribing_since.remove(&episode) {
+                    self.transcribe_time += t.elapsed();
+                }
+                self.pending_audio.remove(&episode);
+            }
+            ProgressEvent::Finished { episode, stage } | ProgressEvent::Failed { episode, stage, .. } => {
+                let s = self.stage(stage);
+                s.active = s.active.saturating_sub(1);
+                if matches!(event, ProgressEvent::Failed { .. }) {
+                    s.failed += 1;
+                    // its audio won't be transcribed
+                    self.pending_audio.remove(&episode);
+                } else {
+                    s.done += 1;
+                }
+            }
+            ProgressEvent::Done => {}
+        }
+    }
+
+    fn stage(&mut self, stage: ImportStage) -> &mut StageCounts {
+        self.stages.entry(stage).or_default()
+    }
+
+    pub fn counts(&self, stage: ImportStage) -> StageCounts {
+        self.stages.get(&stage).copied().unwrap_or_default()
+    }
+
+    /// Measured transcription speed, in audio seconds per wall-clock second.
+    pub fn speed(&self) -> Option<f64> {
+        (self.transcribed_audio > 0. && !self.transcribe_time.is_zero())
+            .then(|| self.transcribed_audio / self.transcribe_time.as_secs_f64())
+    }
+
+    /// Time left to transcribe the queued audio at the measured speed.
+    pub fn eta(&self) -> Option<Duration> {
+        let left: f64 = self.pending_audio.values().sum();
+        self.speed().map(|s| Duration::from_secs_f64(left / s))
+    }
+
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded.values().sum()
+    }
+}
+
+fn format_secs(d: Duration) -> String {
+    let s = d.as_secs();
+    format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+impl Display for ProgressSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for stage in STAGES {
+            let c = self.counts(stage);
+            writeln!(f, "{}: {} done, {} active, {} queued, {} failed", stage, c.done, c.active, c.queued, c.failed)?;
+        }
+        write!(f, "downloaded {} MiB", self.downloaded_bytes() >> 20)?;
+        if let Some(s) = self.speed() {
+            write!(f, ", transcribing at {:.1}x", s)?;
+        }
+        if let Some(eta) = self.eta() {
+            write!(f, ", ETA {}", format_secs(eta))?;
+        }
+        write!(f, ", elapsed {}", format_secs(self.started.elapsed()))
+    }
+}
+
+/// Draw a progress bar per stage on the terminal, if stderr is one.
+pub fn spawn_terminal(progress: &Progress, durations: HashMap<u32, f64>) -> Option<JoinHandle<()>> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    let mut rx = progress.subscribe();
+    Some(tokio::spawn(async move {
+        let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stderr());
+        let style = ProgressStyle::with_template("{prefix:>10} [{bar:30}] {pos}/{len} {msg}").unwrap().progress_chars("=> ");
+        let bars: HashMap<ImportStage, ProgressBar> = STAGES
+            .iter()
+            .map(|s| {
+                let b = multi.add(ProgressBar::new(0).with_style(style.clone()).with_prefix(s.to_string()));
+                (*s, b)
+            })
+            .collect();
+        let mut summary = ProgressSummary::new(durations);
+        loop {
+            let event = match rx.recv().await {
+                Ok(ProgressEvent::Done) | Err(broadcast::error::RecvError::Closed) => break,
+                Ok(e) => e,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            };
+            summary.apply(&event);
+            for (stage, bar) in &bars {
+                let c = summary.counts(*stage);
+                bar.set_length((c.queued + c.active + c.done + c.failed) as u64);
+                bar.set_position((c.done + c.failed) as u64);
+                if c.failed > 0 {
+                    bar.set_message(format!("{} failed", c.failed));
+                }
+            }
+            let mut msg = format!("{} MiB", summary.downloaded_bytes() >> 20);
+            if let Some(eta) = summary.eta() {
+                msg.push_str(&format!(", ETA {}", format_secs(eta)));
+            }
+            bars[&ImportStage::Transcribe].set_message(msg);
+        }
+        for b in bars.values() {
+            b.finish();
+        }
+    }))
+}
+
+/// Interval between two edits of the Telegram message, to stay well within the rate limits.
+const TELEGRAM_UPDATE: Duration = Duration::from_secs(15);
+
+/// Keep a message in `chat` updated with the summary of the import.
+pub fn spawn_telegram(progress: &Progress, durations: HashMap<u32, f64>, bot: Bot, chat: ChatId) -> JoinHandle<()> {
+    let mut rx = progress.subscribe();
+    tokio::spawn(async move {
+        let mut summary = ProgressSummary::new(durations);
+        let mut message: Option<MessageId> = None;
+        let mut last = String::new();
+        let mut tick = tokio::time::interval(TELEGRAM_UPDATE);
+        loop {
+            let done = tokio::select! {
+                e = rx.recv() => match e {
+                    Ok(ProgressEvent::Done) | Err(broadcast::error::RecvError::Closed) => true,
+                    Ok(e) => {
+                        summary.apply(&e);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                },
+                _ = tick.tick() => false,
+            };
+            let text = format!("{}\n{}", if done { "Import completed" } else { "Import in progress" }, summary);
+            if text != last {
+                let res = match message {
+                    Some(id) => bot.edit_message_text(chat, id, &text).await.map(|_| ()),
+                    None => bot.send_message(chat, &text).await.map(|m| message = Some(m.id)),
+                };
+                if let Err(e) = res {
+                    warn!("couldn't update progress message: {}", e);
+                }
+                last = text;
+            }
+            if done {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f64, b: f64) -> bool {
+        (a - b).abs() < b * 0.01
+    }
+
+    #[test]
+    fn summary_counts_speed_and_eta() {
+        let mut s = ProgressSummary::new([(1, 600.), (2, 1200.), (3, 300.)].into_iter().collect());
+        assert!(s.speed().is_none() && s.eta().is_none());
+        for episode in [1, 2, 3] {
+            s.apply(&ProgressEvent::Queued { episode, stage: ImportStage::Download });
+        }
+        s.apply(&ProgressEvent::Started { episode: 1, stage: ImportStage::Download });
+        s.apply(&ProgressEvent::Downloaded { episode: 1, bytes: 3 << 20, total: Some(3 << 20) });
+        s.apply(&ProgressEvent::Finished { episode: 1, stage: ImportStage::Download });
+        s.apply(&ProgressEvent::Started { episode: 3, stage: ImportStage::Download });
+        s.apply(&ProgressEvent::Downloaded { episode: 3, bytes: 1 << 20, total: None });
+        s.apply(&ProgressEvent::Failed { episode: 3, stage: ImportStage::Download, error: "404".to_owned() });
+        let c = s.counts(ImportStage::Download);
+        assert_eq!((c.queued, c.active, c.done, c.failed), (1, 0, 1, 1));
+        assert_eq!(s.downloaded_bytes(), 4 << 20);
+
+        s.apply(&ProgressEvent::Queued { episode: 1, stage: ImportStage::Transcribe });
+        s.apply(&ProgressEvent::Started { episode: 1, stage: ImportStage::Transcribe });
+        // as if the transcription took 10 seconds
+        s.transcribing_since.insert(1, Instant::now() - Duration::from_secs(10));
+        s.apply(&ProgressEvent::Transcribed { episode: 1, audio_seconds: 600. });
+        s.apply(&ProgressEvent::Finished { episode: 1, stage: ImportStage::Transcribe });
+        let c = s.counts(ImportStage::Transcribe);
+        assert_eq!((c.queued, c.active, c.done, c.failed), (0, 0, 1, 0));
+
+        // 600 seconds of audio in 10, only episode 2 is left: the failed download won't be transcribed
+        let speed = s.speed().unwrap();
+        assert!(approx(speed, 60.), "speed {}", speed);
+        let eta = s.eta().unwrap().as_secs_f64();
+        assert!(approx(eta, 20.), "eta {}", eta);
+        assert!(s.to_string().contains("transcribing at 60.0x, ETA 0:00:20"), "{}", s);
+    }
+}
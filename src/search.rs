@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use mongodb::bson::Bson;
+use serde_json::{json, Value};
+use power_pizza_bot::{api::{EpisodeSummary, MatchResponse, TranscriptHitResponse}, bot::{MagicQuery, MetaQuery, SearchError, Timings}, config::CONFIG, db::{self, ExplainQuery, Store}};
+
+static USAGE: &str = "usage: ppp_search [options] <command> <query...>
+
+commands:
+    meta <query>                search episodes by title and description, `key:value` filters allowed
+    all <query>                 full-text search across all transcripts
+    one <episode> <regex>       regex search in the transcript of an episode (id, number or title)
+    magic <query>               resolve an episode id, number or title to an episode id
+
+options:
+    --json                      print the results, timings and plans as JSON
+    --explain                   print the database commands and their query plans";
+
+/// Snippets printed for each episode found by `all`.
+const SNIPPETS_PER_RESULT: usize = 2;
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    json: bool,
+    explain: bool,
+}
+
+/// Options and the other arguments, the command and its query. `None` if the help was asked for.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Option<(Options, Vec<String>)> {
+    let mut options = Options::default();
+    let mut rest = vec![];
+    for a in args {
+        match a.as_str() {
+            "--json" => options.json = true,
+            "--explain" => options.explain = true,
+            "-h" | "--help" => return None,
+            _ => rest.push(a),
+        }
+    }
+    Some((options, rest))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pretty_env_logger::init();
+
+    let (options, args) = parse_args(std::env::args().skip(1)).unwrap_or_else(|| usage(0));
+    let (command, rest) = args.split_first().unwrap_or_else(|| usage(2));
+    let db = db::connect(&CONFIG.db);
+    let db = db.as_ref();
+
+    let mut timings = Timings::new();
+    let mut explain: Vec<ExplainQuery> = vec![];
+    let query = rest.join(" ");
+    // outlive the borrows in `explain`
+    let meta_query;
+    let magic;
+    let result: Result<Value, SearchError> = match command.as_str() {
+        "meta" if !rest.is_empty() => {
+            meta_query = MetaQuery::parse(&query);
+            explain.push(ExplainQuery::Episodes(&meta_query));
+            db.search_meta_timed(query.clone(), &mut timings)
+                .await
+                .map(|r| json!(r.iter().map(|r| EpisodeSummary::from(&r.episode)).collect::<Vec<_>>()))
+        }
+        "all" if !rest.is_empty() => {
+            explain.push(ExplainQuery::Transcripts(&query));
+            match db.search_transcript_all_timed(query.clone(), &mut timings).await {
+                Ok(mut r) => {
+                    let s = db.add_snippets(&mut r, &query, SNIPPETS_PER_RESULT).await;
+                    timings.lap("snippets");
+                    s.map(|_| json!(r.into_iter().map(TranscriptHitResponse::from).collect::<Vec<_>>()))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        "one" if rest.len() >= 2 => {
+            magic = MagicQuery::parse(&rest[0]);
+            if let MagicQuery::Title(t) = &magic {
+                explain.push(ExplainQuery::EpisodeByTitle(t));
+            }
+            match db.magic_episode_search(rest[0].clone()).await {
+                Ok(id) => {
+                    timings.lap("resolve episode");
+                    explain.push(ExplainQuery::Transcript(id));
+                    db.search_transcript_one_timed(id, rest[1..].join(" "), &mut timings)
+                        .await
+                        .map(|r| json!({
+                            "episode": EpisodeSummary::from(&r.episode),
+                            "matches": r.matches.into_iter().map(MatchResponse::from).collect::<Vec<_>>(),
+                        }))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        "magic" if !rest.is_empty() => {
+            magic = MagicQuery::parse(&query);
+            if let MagicQuery::Title(t) = &magic {
+                explain.push(ExplainQuery::EpisodeByTitle(t));
+            }
+            let r = db.magic_episode_search(query.clone()).await.map(|id| json!(id));
+            timings.lap("resolve episode");
+            r
+        }
+        _ => usage(2),
+    };
+
+    let plans = if options.explain { explain_all(db, &explain).await? } else { vec![] };
+    if options.json {
+        let out = json!({
+            "command": command,
+            "query": rest,
+            "results": result.as_ref().ok(),
+            "error": result.as_ref().err().map(|e| e.respond_client()),
+            "timings": timings.steps.iter().map(|(s, d)| json!({"step": s, "ms": ms(*d)})).collect::<Vec<_>>(),
+            "total_ms": ms(timings.total()),
+            "explain": plans,
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+    } else {
+        match &result {
+            Ok(r) => print_human(command, r),
+            Err(e) => println!("{}", e.respond_client()),
+        }
+        println!("\ntimings: {}", timings);
+        for p in &plans {
+            println!("\n{}", serde_json::to_string_pretty(p)?);
+        }
+    }
+    if result.is_err() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn usage(code: i32) -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(code)
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.
+}
+
+/// Commands and plans of `queries`, as relaxed extended JSON.
+async fn explain_all(db: &dyn Store, queries: &[ExplainQuery<'_>]) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let mut plans = vec![];
+    for q in queries {
+        match db.explain(*q).await? {
+            Some(e) => plans.push(json!({
+                "command": Bson::Document(e.command).into_relaxed_extjson(),
+                "plan": Bson::Document(e.plan).into_relaxed_extjson(),
+            })),
+            None => {
+                eprintln!("the {:?} backend can't explain queries", CONFIG.db.backend);
+                break;
+            }
+        }
+    }
+    Ok(plans)
+}
+
+fn print_human(command: &str, r: &Value) {
+    let episode = |e: &Value| format!("{:>10}  {}  {}", e["id"], e["published_at"].as_str().unwrap_or_default().get(..10).unwrap_or_default(), e["title"].as_str().unwrap_or_default());
+    match command {
+        "one" => {
+            println!("{}", episode(&r["episode"]));
+            print_matches(&r["matches"]);
+        }
+        "all" => {
+            let results = r.as_array().cloned().unwrap_or_default();
+            for e in &results {
+                println!("{}  (score {:.3}, {} hits)", episode(e), e["score"].as_f64().unwrap_or_default(), e["hits"]);
+                print_matches(&e["snippets"]);
+            }
+            println!("{} results", results.len());
+        }
+        "magic" => println!("{}", r),
+        _ => {
+            let results = r.as_array().cloned().unwrap_or_default();
+            for e in &results {
+                println!("{}", episode(e));
+            }
+            println!("{} results", results.len());
+        }
+    }
+}
+
+fn print_matches(matches: &Value) {
+    for m in matches.as_array().into_iter().flatten() {
+        let t = |k: &str| format_ms(m[k].as_u64().unwrap_or_default());
+        println!("  [{} - {}] {}", t("from_ms"), t("to_ms"), m["hint"].as_str().unwrap_or_default().replace('\n', " "));
+    }
+}
+
+fn format_ms(ms: u64) -> String {
+    let s = ms / 1000;
+    format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> Option<(Options, Vec<String>)> {
+        parse_args(a.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn options_anywhere_in_the_arguments() {
+        assert_eq!(args(&["all", "pizza"]), Some((Options::default(), vec!["all".to_owned(), "pizza".to_owned()])));
+        assert_eq!(
+            args(&["--json", "one", "12", "--explain", "zelda"]),
+            Some((Options { json: true, explain: true }, vec!["one".to_owned(), "12".to_owned(), "zelda".to_owned()])),
+        );
+        assert_eq!(args(&[]), Some((Options::default(), vec![])));
+        assert_eq!(args(&["meta", "-h"]), None);
+        assert_eq!(args(&["--help"]), None);
+        // unknown options are part of the query
+        assert_eq!(args(&["all", "--pizza"]).unwrap().1, vec!["all".to_owned(), "--pizza".to_owned()]);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_ms(0), "0:00:00");
+        assert_eq!(format_ms(61_999), "0:01:01");
+        assert_eq!(format_ms(3_723_000), "1:02:03");
+        assert_eq!(ms(Duration::from_micros(1500)), 1.5);
+        assert_eq!(ms(Duration::from_secs(2)), 2000.);
+    }
+}
@@ -1,14 +1,22 @@
-pub(crate) mod naive_datetime {
+/// Store dates as BSON datetimes. Integer unix timestamps, written by versions before the `episodes_published_at_date`
+/// migration, are still accepted when reading.
+pub(crate) mod bson_datetime {
     use chrono::{DateTime, Utc};
-    use serde::{Deserialize, Deserializer, Serializer};
+    use mongodb::bson::{self, Bson};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
     pub fn serialize<S>(date: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error>
         where S: Serializer {
-        s.serialize_i64(date.timestamp())
+        bson::DateTime::from_millis(date.timestamp_millis()).serialize(s)
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
         where D: Deserializer<'de> {
-        Ok(DateTime::<Utc>::from_timestamp(i64::deserialize(deserializer)?, 0).expect("Invalid timestamp found in db"))
+        match Bson::deserialize(deserializer)? {
+            Bson::DateTime(d) => DateTime::<Utc>::from_timestamp_millis(d.timestamp_millis()),
+            Bson::Int64(t) => DateTime::<Utc>::from_timestamp(t, 0),
+            Bson::Int32(t) => DateTime::<Utc>::from_timestamp(t as i64, 0),
+            o => return Err(D::Error::custom(format!("expected a date, found {}", o))),
+        }.ok_or_else(|| D::Error::custom("Invalid timestamp found in db"))
     }
 }
@@ -1,5 +1,5 @@
-use reqwest::Client;
-use std::{collections::VecDeque, fs::File, io::Write, path::PathBuf, sync::{Arc, Mutex}};
+use reqwest::{header::{HeaderMap, HeaderName, CONTENT_RANGE, ETAG, IF_RANGE, RANGE}, Client, StatusCode};
+use std::{collections::VecDeque, fs::{read_to_string, remove_file, rename, write, File, OpenOptions}, io::Write, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
 use tokio::{sync::Notify, task::JoinHandle};
 use tokio_stream::StreamExt;
 #[allow(unused_imports)]
@@ -16,6 +16,9 @@ pub struct SpreakerDownloader {
 
 type Worker = JoinHandle<Result<(), SpreakerError>>;
 
+/// Progress callback of `download_file_with_progress`.
+pub type ProgressFn<'a> = dyn Fn(u64, Option<u64>) + Send + Sync + 'a;
+
 impl SpreakerDownloader {
     pub fn new(cli: Arc<Client>, jobs: usize, output: PathBuf) -> Self {
         let queue = Arc::new(Mutex::new(VecDeque::new()));
@@ -123,19 +126,9 @@ impl SpreakerDownloader {
 
     async fn _download_inner(cli: Arc<Client>, ep: SimpleEpisode, output: Arc<PathBuf>) -> Result<(), SpreakerError> {
         info!("starting downlod for episode {}", ep.id);
-        let req = cli.get(&ep.download_url).send().await.map_err(SpreakerError::RequestError)?;
-        debug!("generated request for episode {}", ep.id);
         let output = output.join(format!("{} - {}.mp3", ep.id, ep.title));
-        if output.exists() && output.is_file() && output.metadata().unwrap().len() == req.content_length().unwrap() {
+        if download_file(&cli, &ep.download_url, &output).await? == DownloadOutcome::AlreadyPresent {
             info!("episode {} already downloaded", ep.id);
-            return Ok(())
-        }
-        let mut file = File::create(output).map_err(SpreakerError::IOError)?;
-        let mut res = req.bytes_stream();
-        while let Some(v) = res.next().await {
-            let v = v.map_err(SpreakerError::RequestError)?;
-            trace!("writing chunk {}", v.len());
-            file.write(&v).map_err(SpreakerError::IOError)?;
         }
         debug!("worker for episode {} finished", ep.id);
         Ok(())
@@ -146,3 +139,221 @@ impl SpreakerDownloader {
         self.manager.await.map_err(SpreakerError::Runtime)?
     }
 }
+
+/// Result of a call to [`download_file`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// The file was (fully or partially) fetched, `u64` is the final size in bytes.
+    Downloaded(u64),
+    /// A complete file was already present at the destination.
+    AlreadyPresent,
+}
+
+const MAX_ATTEMPTS: usize = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Download `url` into `output`, resuming an interrupted download if possible.
+///
+/// Data is written to `{output}.part`, the ETag of the remote resource is kept beside it in `{output}.etag` so that a
+/// partial file is only resumed (with an HTTP `Range` request) if it belongs to the same resource. Once the whole body
+/// has been received its length and ETag are checked and the partial file is atomically renamed to `output`.
+///
+/// Files written by older versions straight to `output` may be incomplete, so an existing `output` is only kept if its
+/// length or the ETag recorded when it was downloaded match the remote resource, otherwise it's downloaded again.
+pub async fn download_file(cli: &Client, url: &str, output: &Path) -> Result<DownloadOutcome, SpreakerError> {
+    download_file_with_progress(cli, url, output, &|_, _| {}).await
+}
+
+/// Like `download_file`, calling `progress` with the bytes written so far and the expected total after every chunk.
+pub async fn download_file_with_progress(cli: &Client, url: &str, output: &Path, progress: &ProgressFn<'_>) -> Result<DownloadOutcome, SpreakerError> {
+    // without a HEAD response the size and the ETag are unknown: the file is fetched with a plain GET, from scratch
+    let (expected, etag) = match cli.head(url).send().await.and_then(|r| r.error_for_status()) {
+        Ok(head) => (head.content_length().filter(|l| *l > 0), header_string(head.headers(), ETAG)),
+        Err(e) => {
+            warn!("HEAD {} failed, size and ETag unknown: {}", url, e);
+            (None, None)
+        }
+    };
+    debug!("remote resource {}: length {:?}, etag {:?}", url, expected, etag);
+
+    let part = sibling(output, "part");
+    let etag_file = sibling(output, "etag");
+    if output.is_file() {
+        let len = output.metadata().map_err(SpreakerError::IOError)?.len();
+        if is_complete(len, read_to_string(&etag_file).ok().as_deref(), expected, etag.as_deref()) {
+            debug!("{:?} already downloaded", output);
+            return Ok(DownloadOutcome::AlreadyPresent)
+        }
+        warn!("{:?} exists but its size {} doesn't match {:?} nor its ETag {:?}, downloading again", output, len, expected, etag);
+    }
+
+    let resumable = match (&etag, read_to_string(&etag_file).ok()) {
+        (Some(remote), Some(local)) => *remote == local,
+        _ => false,
+    };
+    if !resumable {
+        if part.exists() {
+            debug!("discarding stale partial download {:?}", part);
+            remove_file(&part).map_err(SpreakerError::IOError)?;
+        }
+        match &etag {
+            Some(e) => write(&etag_file, e).map_err(SpreakerError::IOError)?,
+            None => if etag_file.exists() { remove_file(&etag_file).map_err(SpreakerError::IOError)? },
+        }
+    }
+
+    let mut attempt = 0;
+    let total = loop {
+        attempt += 1;
+        match _download_attempt(cli, url, &part, etag.as_deref(), expected, progress).await {
+            Ok(total) => break total,
+            Err(e @ (
+                SpreakerError::RequestError(_)
+                | SpreakerError::IOError(_)
+                | SpreakerError::LengthMismatch { .. }
+                | SpreakerError::InvalidResponse(_)
+            )) if attempt < MAX_ATTEMPTS => {
+                warn!("download of {} interrupted (attempt {}/{}): {}, resuming in {:?}", url, attempt, MAX_ATTEMPTS, e, RETRY_DELAY);
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    // the ETag file stays, to recognise the complete file later
+    rename(&part, output).map_err(SpreakerError::IOError)?;
+    debug!("{:?} downloaded ({} bytes)", output, total);
+    Ok(DownloadOutcome::Downloaded(total))
+}
+
+async fn _download_attempt(cli: &Client, url: &str, part: &Path, etag: Option<&str>, expected: Option<u64>, progress: &ProgressFn<'_>) -> Result<u64, SpreakerError> {
+    let offset = part.metadata().map(|m| m.len()).unwrap_or(0);
+    if offset > 0 && expected == Some(offset) {
+        return Ok(offset)
+    }
+    let mut req = cli.get(url);
+    if offset > 0 {
+        debug!("resuming {} from byte {}", url, offset);
+        req = req.header(RANGE, format!("bytes={}-", offset));
+        if let Some(e) = etag {
+            req = req.header(IF_RANGE, e);
+        }
+    }
+    let res = req.send().await?;
+
+    let (mut file, start, total) = match res.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let (start, total) = header_string(res.headers(), CONTENT_RANGE)
+                .as_deref()
+                .and_then(parse_content_range)
+                .ok_or(SpreakerError::InvalidResponse("missing or malformed Content-Range"))?;
+            if start != offset {
+                return Err(SpreakerError::InvalidResponse("Content-Range does not match requested offset"))
+            }
+            (OpenOptions::new().append(true).open(part).map_err(SpreakerError::IOError)?, offset, total.or(expected))
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // the partial file is bigger than the resource, start over
+            remove_file(part).map_err(SpreakerError::IOError)?;
+            return Err(SpreakerError::InvalidResponse("range not satisfiable, partial download discarded"))
+        }
+        s if s.is_success() => {
+            let total = res.content_length().filter(|l| *l > 0).or(expected);
+            (File::create(part).map_err(SpreakerError::IOError)?, 0, total)
+        }
+        s => return Err(SpreakerError::HttpStatus(s)),
+    };
+
+    let received_etag = header_string(res.headers(), ETAG);
+    if let (Some(e), Some(r)) = (etag, &received_etag) {
+        if e != r {
+            remove_file(part).map_err(SpreakerError::IOError)?;
+            return Err(SpreakerError::EtagMismatch { expected: e.to_owned(), got: r.clone() })
+        }
+    }
+
+    let mut written = start;
+    let mut stream = res.bytes_stream();
+    while let Some(v) = stream.next().await {
+        let v = v?;
+        trace!("writing chunk {}", v.len());
+        file.write_all(&v).map_err(SpreakerError::IOError)?;
+        written += v.len() as u64;
+        progress(written, total);
+    }
+    file.sync_all().map_err(SpreakerError::IOError)?;
+
+    match total {
+        Some(t) if t != written => {
+            if written > t {
+                remove_file(part).map_err(SpreakerError::IOError)?;
+            }
+            Err(SpreakerError::LengthMismatch { expected: t, got: written })
+        }
+        _ => Ok(written),
+    }
+}
+
+/// Whether a file of `len` bytes, downloaded when the resource had ETag `local_etag`, is the remote resource of
+/// length `expected` and ETag `etag`. A known length must match, and so must the ETags when both are known; without a
+/// length the ETags alone decide, and the file can't be trusted without either.
+fn is_complete(len: u64, local_etag: Option<&str>, expected: Option<u64>, etag: Option<&str>) -> bool {
+    match (expected, etag) {
+        (Some(e), Some(r)) => e == len && local_etag.is_none_or(|l| l == r),
+        (Some(e), None) => e == len,
+        (None, Some(r)) => local_etag == Some(r),
+        (None, None) => false,
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_owned())
+}
+
+/// Parse a `Content-Range: bytes {start}-{end}/{total}` header into `(start, total)`.
+fn parse_content_range(v: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = v.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()))
+}
+
+fn sibling(output: &Path, ext: &str) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(ext);
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_range() {
+        assert_eq!(parse_content_range("bytes 100-199/200"), Some((100, Some(200))));
+        assert_eq!(parse_content_range("bytes 0-99/*"), Some((0, None)));
+        assert_eq!(parse_content_range("bytes */200"), None);
+        assert_eq!(parse_content_range("items 0-99/200"), None);
+        assert_eq!(parse_content_range("bytes 0-99"), None);
+    }
+
+    #[test]
+    fn sibling_appends_the_extension() {
+        assert_eq!(sibling(Path::new("audio/123.mp3"), "part"), Path::new("audio/123.mp3.part"));
+        assert_eq!(sibling(Path::new("1 - Titolo. Con punti.mp3"), "etag"), Path::new("1 - Titolo. Con punti.mp3.etag"));
+    }
+
+    #[test]
+    fn complete_only_when_length_or_etag_confirm_it() {
+        assert!(is_complete(10, None, Some(10), None));
+        assert!(!is_complete(9, None, Some(10), None));
+        assert!(is_complete(10, None, Some(10), Some("a")));
+        assert!(is_complete(10, Some("a"), Some(10), Some("a")));
+        // same size, but a different resource
+        assert!(!is_complete(10, Some("b"), Some(10), Some("a")));
+        assert!(is_complete(10, Some("a"), None, Some("a")));
+        assert!(!is_complete(10, None, None, Some("a")));
+        // nothing to compare with, e.g. a failed HEAD
+        assert!(!is_complete(10, Some("a"), None, None));
+    }
+}
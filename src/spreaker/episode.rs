@@ -1,9 +1,11 @@
 use chrono::{DateTime, NaiveDateTime, Utc};
+use log::debug;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::db::PPPData;
+use crate::db::{topics::Topic, PPPData};
 
-use super::SimpleEpisode;
+use super::{SimpleEpisode, SpreakerError, SpreakerResponse, API_URL};
 
 #[derive(Deserialize, Debug, Serialize)]
 pub struct Episode {
@@ -13,11 +15,54 @@ pub struct Episode {
     pub duration: u32,
     pub show_id: u32,
     pub author_id: u32,
-    #[serde(with = "crate::serde::naive_datetime")]
+    #[serde(with = "crate::serde::bson_datetime")]
     pub published_at: DateTime<Utc>,
     pub download_url: String,
     pub description: String,
     pub description_html: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub image_original_url: Option<String>,
+    #[serde(default)]
+    pub site_url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub explicit: bool,
+    #[serde(default)]
+    pub plays: PlayCounts,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    /// Extracted from the transcript, see `db::topics`.
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+}
+
+/// Play statistics as reported by Spreaker at the time of the last import.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct PlayCounts {
+    #[serde(default, alias = "plays_count")]
+    pub total: u64,
+    #[serde(default, alias = "plays_ondemand_count")]
+    pub ondemand: u64,
+    #[serde(default, alias = "plays_live_count")]
+    pub live: u64,
+    #[serde(default, alias = "likes_count")]
+    pub likes: u64,
+    #[serde(default, alias = "downloads_count")]
+    pub downloads: u64,
+}
+
+/// A chapter (or cuepoint) of an episode. `starts_at` is in milliseconds from the start of the audio.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Chapter {
+    pub starts_at: u64,
+    pub title: String,
+    #[serde(default)]
+    pub external_url: Option<String>,
+    #[serde(default)]
+    pub image_url: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -31,6 +76,18 @@ pub struct ProtoEpisode {
     pub download_url: String,
     pub description: String,
     pub description_html: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub image_original_url: Option<String>,
+    #[serde(default)]
+    pub site_url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub explicit: bool,
+    #[serde(flatten)]
+    pub plays: PlayCounts,
 }
 
 impl From<ProtoEpisode> for Episode {
@@ -46,6 +103,14 @@ impl From<ProtoEpisode> for Episode {
             download_url: p.download_url,
             description: p.description,
             description_html: p.description_html,
+            image_url: p.image_url,
+            image_original_url: p.image_original_url,
+            site_url: p.site_url,
+            tags: p.tags,
+            explicit: p.explicit,
+            plays: p.plays,
+            chapters: vec![],
+            topics: vec![],
         }
     }
 }
@@ -56,6 +121,35 @@ impl PPPData for Episode {
     type IdType = u32;
 }
 
+impl Episode {
+    /// Link to the episode page, falling back to the canonical spreaker url if the api did not provide one.
+    pub fn url(&self) -> String {
+        self.site_url
+            .clone()
+            .unwrap_or_else(|| format!("https://www.spreaker.com/episode/{}", self.id))
+    }
+
+    /// Fetch the chapters of this episode from the api. Episodes without chapters get an empty list.
+    pub async fn fetch_chapters(&mut self, cli: &Client) -> Result<(), SpreakerError> {
+        let res = cli
+            .get(format!("{}/episodes/{}/chapters?limit=100", API_URL, self.id))
+            .send()
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            debug!("no chapters found for episode {}", self.id);
+            self.chapters = vec![];
+            return Ok(())
+        }
+        let res = res
+            .error_for_status()?
+            .json::<SpreakerResponse<Chapter>>()
+            .await
+            .map_err(SpreakerError::JsonError)?;
+        self.chapters = res.response.items;
+        Ok(())
+    }
+}
+
 
 #[derive(Deserialize, Debug)]
 pub struct EpisodeResponse<T> {
@@ -6,6 +6,10 @@ pub enum SpreakerError {
     JsonError(reqwest::Error),
     Runtime(tokio::task::JoinError),
     IOError(std::io::Error),
+    HttpStatus(reqwest::StatusCode),
+    InvalidResponse(&'static str),
+    LengthMismatch { expected: u64, got: u64 },
+    EtagMismatch { expected: String, got: String },
 }
 
 impl From<reqwest::Error> for SpreakerError {
@@ -21,6 +25,10 @@ impl Display for SpreakerError {
             SpreakerError::JsonError(e) => write!(f, "Json error: {}", e),
             SpreakerError::Runtime(e) => write!(f, "Runtime error: {}", e),
             SpreakerError::IOError(e) => write!(f, "IO error: {}", e),
+            SpreakerError::HttpStatus(s) => write!(f, "Unexpected HTTP status: {}", s),
+            SpreakerError::InvalidResponse(e) => write!(f, "Invalid response: {}", e),
+            SpreakerError::LengthMismatch { expected, got } => write!(f, "Length mismatch: expected {} bytes, got {}", expected, got),
+            SpreakerError::EtagMismatch { expected, got } => write!(f, "ETag mismatch: expected {}, got {}", expected, got),
         }
     }
 }
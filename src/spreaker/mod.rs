@@ -3,11 +3,15 @@ mod downloader;
 mod episode;
 mod simple_episode;
 mod paginator;
+mod show;
+mod source;
 
 pub use error::SpreakerError;
-pub use downloader::SpreakerDownloader;
-pub use episode::{ProtoEpisode, Episode};
+pub use downloader::{download_file, download_file_with_progress, DownloadOutcome, ProgressFn, SpreakerDownloader};
+pub use episode::{Chapter, Episode, PlayCounts, ProtoEpisode};
 pub use simple_episode::SimpleEpisode;
+pub use show::{Show, ShowAuthor};
+pub use source::{ShowSource, SpreakerApi};
 
 use std::sync::Arc;
 use paginator::SpreakerDataIter;
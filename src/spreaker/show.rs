@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db::PPPData;
+
+use super::{SpreakerError, API_URL};
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Show {
+    #[serde(alias = "show_id")]
+    pub id: u32,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    pub author_id: u32,
+    #[serde(default)]
+    pub author: Option<ShowAuthor>,
+    #[serde(default)]
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub image_original_url: Option<String>,
+    #[serde(default)]
+    pub site_url: Option<String>,
+    #[serde(default)]
+    pub website_url: Option<String>,
+    #[serde(default)]
+    pub explicit: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ShowAuthor {
+    #[serde(alias = "user_id")]
+    pub id: u32,
+    pub fullname: String,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+impl PPPData for Show {
+    const COLLECTION: &'static str = "shows";
+    const ID_KEY: &'static str = "id";
+    type IdType = u32;
+}
+
+#[derive(Deserialize, Debug)]
+struct ShowResponse {
+    response: ShowShow,
+}
+
+#[derive(Deserialize, Debug)]
+struct ShowShow {
+    show: Show,
+}
+
+impl Show {
+    pub async fn fetch(id: u32) -> Result<Self, SpreakerError> {
+        let resp = reqwest::get(format!("{}/shows/{}", API_URL, id))
+            .await?
+            .error_for_status()?
+            .json::<ShowResponse>()
+            .await
+            .map_err(SpreakerError::JsonError)?;
+        Ok(resp.response.show)
+    }
+
+    pub fn author_name(&self) -> Option<&str> {
+        self.author.as_ref().map(|a| a.fullname.as_str())
+    }
+}
@@ -1,3 +1,4 @@
+use log::warn;
 use serde::Deserialize;
 
 use super::{ProtoEpisode, SpreakerError, API_URL};
@@ -22,6 +23,10 @@ impl SimpleEpisode {
 
     pub async fn get_episode(&self) -> Result<Episode, SpreakerError> {
         let resp = reqwest::get(format!("{}/episodes/{}", API_URL, self.id)).await?.json::<EpisodeResponse<ProtoEpisode>>().await?;
-        Ok(resp.into_inner())
+        let mut episode = resp.into_inner();
+        if let Err(e) = episode.fetch_chapters(&reqwest::Client::new()).await {
+            warn!("couldn't fetch chapters for episode {}: {}", episode.id, e);
+        }
+        Ok(episode)
     }
 }
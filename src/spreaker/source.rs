@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use reqwest::Client;
+
+use super::{Episode, Show, SimpleEpisode, SpreakerData, SpreakerError, API_URL};
+
+/// Where `import::import_from` gets the show and its episodes: the Spreaker API, or fixed data in the tests.
+#[async_trait]
+pub trait ShowSource: Send + Sync {
+    async fn show(&self, id: u32) -> Result<Show, SpreakerError>;
+
+    /// Episodes of the show, the most recent first.
+    fn episodes(&self, show_id: u32) -> BoxStream<'static, SimpleEpisode>;
+
+    /// Full metadata of an episode, chapters included.
+    async fn episode(&self, e: &SimpleEpisode) -> Result<Episode, SpreakerError>;
+}
+
+#[derive(Default)]
+pub struct SpreakerApi {
+    cli: Arc<Client>,
+}
+
+impl SpreakerApi {
+    pub fn new(cli: Arc<Client>) -> Self {
+        Self { cli }
+    }
+}
+
+#[async_trait]
+impl ShowSource for SpreakerApi {
+    async fn show(&self, id: u32) -> Result<Show, SpreakerError> {
+        Show::fetch(id).await
+    }
+
+    fn episodes(&self, show_id: u32) -> BoxStream<'static, SimpleEpisode> {
+        Box::pin(SpreakerData::<SimpleEpisode>::request(format!("{}/shows/{}/episodes", API_URL, show_id), self.cli.clone()))
+    }
+
+    async fn episode(&self, e: &SimpleEpisode) -> Result<Episode, SpreakerError> {
+        e.get_episode().await
+    }
+}
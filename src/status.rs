@@ -1,37 +1,159 @@
+use std::{fmt::Display, sync::{Arc, Mutex}};
+
 use chrono::{DateTime, Utc};
-use mongodb::{bson::doc, Database};
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
+
+use crate::db::PPPData;
+
+/// Record of one run of the import pipeline, stored in the `import_runs` collection.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportRun {
+    /// Start time in milliseconds since the epoch, doubles as the id of the run.
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub source: ImportSource,
+    /// New episodes found on the source.
+    pub discovered: u32,
+    /// Episodes whose metadata was inserted in the database.
+    pub inserted: u32,
+    pub downloaded: u32,
+    pub transcribed: u32,
+    /// Transcripts inserted in the database.
+    pub transcripts_inserted: u32,
+    pub failures: Vec<ImportFailure>,
+    pub versions: ImportVersions,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportSource {
+    /// Regular import from the Spreaker api.
+    Spreaker,
+    /// Offline rebuild from the transcript cache.
+    Cache,
+    /// Created by the `status_to_import_runs` migration from the old status document.
+    Legacy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportFailure {
+    pub episode_id: Option<u32>,
+    pub stage: ImportStage,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStage {
+    Metadata,
+    Download,
+    Transcribe,
+    Convert,
+    Insert,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Status {
-    #[serde(with = "chrono::serde::ts_seconds")]
-    pub last_update: DateTime<Utc>,
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ImportVersions {
+    /// Version of this crate.
+    pub importer: String,
+    /// Database schema version, see `migrations`.
+    pub schema: u32,
+    /// Transcription service used.
+    pub transcriber: String,
 }
 
-impl Default for Status {
-    fn default() -> Self {
+impl PPPData for ImportRun {
+    const COLLECTION: &'static str = "import_runs";
+    const ID_KEY: &'static str = "id";
+    type IdType = i64;
+}
+
+/// Shared handle to the run in progress, updated by the import jobs.
+pub type ImportRunHandle = Arc<Mutex<ImportRun>>;
+
+impl ImportRun {
+    pub fn start(source: ImportSource, versions: ImportVersions) -> Self {
+        let started_at = Utc::now();
         Self {
-            last_update: Utc::now(),
+            id: started_at.timestamp_millis(),
+            started_at,
+            finished_at: None,
+            source,
+            discovered: 0,
+            inserted: 0,
+            downloaded: 0,
+            transcribed: 0,
+            transcripts_inserted: 0,
+            failures: vec![],
+            versions,
         }
     }
+
+    pub fn handle(self) -> ImportRunHandle {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn fail(&mut self, episode_id: Option<u32>, stage: ImportStage, error: impl Display) {
+        crate::metrics::IMPORT_FAILURES.with_label_values(&[&stage.to_string()]).inc();
+        self.failures.push(ImportFailure { episode_id, stage, error: error.to_string() });
+    }
+
+    pub fn finish(&mut self) {
+        let now = Utc::now();
+        crate::metrics::IMPORT_LAST_RUN.set(now.timestamp_millis() as f64 / 1000.);
+        self.finished_at = Some(now);
+    }
+
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        self.finished_at.map(|f| f - self.started_at)
+    }
 }
 
-impl Status {
-    pub async fn from_db(db: &Database) -> Result<Option<Self>, mongodb::error::Error> {
-        let status_n = db
-            .collection::<Status>("status")
-            .count_documents(doc!{})
-            .await?;
+impl Display for ImportStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Metadata => write!(f, "metadata"),
+            Self::Download => write!(f, "download"),
+            Self::Transcribe => write!(f, "transcribe"),
+            Self::Convert => write!(f, "convert"),
+            Self::Insert => write!(f, "insert"),
+        }
+    }
+}
 
-        match status_n {
-            0 => Ok(None),
-            1 => Ok(db
-                .collection::<Status>("status")
-                .find_one(doc!{})
-                .await?
-            ),
-            _ => panic!("Too many status documents in the database"),
+impl Display for ImportSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spreaker => write!(f, "spreaker"),
+            Self::Cache => write!(f, "cache"),
+            Self::Legacy => write!(f, "legacy"),
         }
     }
 }
 
+impl Display for ImportRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "run {} ({}) started {}", self.id, self.source, self.started_at.format("%Y-%m-%d %H:%M:%S"))?;
+        match self.duration() {
+            Some(d) => writeln!(f, ", took {}s", d.num_seconds())?,
+            None => writeln!(f, ", not finished")?,
+        }
+        writeln!(
+            f,
+            "discovered {}, inserted {}, downloaded {}, transcribed {}, transcripts inserted {}",
+            self.discovered, self.inserted, self.downloaded, self.transcribed, self.transcripts_inserted
+        )?;
+        write!(f, "importer {}, schema {}, transcriber {}", self.versions.importer, self.versions.schema, self.versions.transcriber)?;
+        if !self.failures.is_empty() {
+            write!(f, "\n{} failures:", self.failures.len())?;
+            for e in &self.failures {
+                match e.episode_id {
+                    Some(id) => write!(f, "\n  {} {}: {}", e.stage, id, e.error)?,
+                    None => write!(f, "\n  {}: {}", e.stage, e.error)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
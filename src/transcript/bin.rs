@@ -1,58 +1,441 @@
-use std::{collections::HashSet, fs::{read_dir, read_to_string}, sync::Arc};
+use std::{collections::{HashMap, HashSet}, future::Future, path::Path, sync::{Arc, Mutex}, time::Duration};
+use chrono::Utc;
+use mongodb::bson;
+use tokio::{signal::unix::{signal, SignalKind}, sync::{mpsc, Notify}};
 use log::{debug, error, info, warn};
-use power_pizza_bot::{config::CONFIG, db::DB, import::import_database, spreaker::Episode, transcript::{EpisodeTranscript, JobManager, Transcript}};
+use teloxide::{types::ChatId, Bot};
+use power_pizza_bot::{artifacts::{self, Artifact}, progress::{self, Progress}, config::CONFIG, daemon::{self, ControlCommand, DaemonState, RunLock, Schedule}, db::{self, fuzzy, similarity, stats::{self, ShowStats}, topics, trends, Store}, import::{self, import_database}, metrics, migrations, spreaker::{Episode, SpreakerApi}, status::{ImportRun, ImportRunHandle, ImportSource, ImportStage, ImportVersions}, transcript::{cache, EpisodeTranscript, JobManager}};
+
+static USAGE: &str = "usage: ppp_import [command]
+
+commands:
+    (none)                      import new episodes and transcribe the missing ones
+    --daemon                    keep running, importing on the configured schedule
+    control <run|status|stop>   send a command to the running daemon
+    migrate                     apply the pending database migrations and exit
+    rebuild-derived             compute again the fuzzy index, similarity, topics and statistics from the transcripts
+    refresh-metadata            fetch again the metadata of the episodes already imported, filling the fields added since
+    rebuild [archive]           offline: reinsert the transcripts of the cache, taking episodes from a backup archive if given
+    gc [--dry-run]              apply the retention policies of the audio and transcript files, reporting the space reclaimed
+    stats [--refresh] [--json]  print the statistics of the show computed by the last import, or compute them again
+    export <file> [coll...]     write the given collections (default: all) to a backup archive
+    restore <file> [coll...]    load the given collections (default: all) from a backup archive, replacing existing documents
+    copy-to-sqlite [path]       copy the configured MongoDB database into a SQLite database (`sqlite` feature)";
+
+/// Name of the database lock preventing overlapping imports.
+const IMPORT_LOCK: &str = "import";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     pretty_env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|a| a.as_str()) {
+        None => oneshot().await,
+        Some("--daemon") => daemon().await,
+        Some("control") => control(args.get(1).map_or("status", |c| c.as_str())).await,
+        Some("migrate") => migrate().await,
+        Some("rebuild-derived") => rebuild_derived().await,
+        Some("refresh-metadata") => refresh_metadata().await,
+        Some("rebuild") => rebuild(args.get(1)).await,
+        Some("gc") => gc(args.get(1).is_some_and(|a| a == "--dry-run")).await,
+        Some("stats") => show_stats(args[1..].iter().any(|a| a == "--refresh"), args[1..].iter().any(|a| a == "--json")).await,
+        Some(c @ ("export" | "restore")) => match args.get(1) {
+            Some(path) => backup(c, path, &args[2..]).await,
+            None => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2)
+            }
+        },
+        #[cfg(feature = "sqlite")]
+        Some("copy-to-sqlite") => copy_to_sqlite(args.get(1)).await,
+        Some(c) => {
+            error!("unknown command: {}", c);
+            eprintln!("{}", USAGE);
+            std::process::exit(2)
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+async fn copy_to_sqlite(path: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    use power_pizza_bot::db::{copy_store, PPPDatabase, SqliteDatabase};
+
+    let path = path.unwrap_or(&CONFIG.db.sqlite_path);
+    info!("copying mongodb database {}:{} into {}", CONFIG.db.host, CONFIG.db.port, path);
+    let from = PPPDatabase::new(&CONFIG.db);
+    let to = SqliteDatabase::open(path)?;
+    copy_store(&from, &to).await?;
+    info!("copy completed");
+    Ok(())
+}
+
+async fn backup(cmd: &str, path: &str, collections: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let collections = db::backup::select_collections(collections)?;
+    let db = db::connect(&CONFIG.db);
+    if cmd == "export" {
+        let manifest = db::backup::export(db.as_ref(), Path::new(path), &collections).await?;
+        info!("exported {} documents to {}", manifest.collections.iter().map(|c| c.documents).sum::<usize>(), path);
+        return Ok(());
+    }
+    db.ensure_index().await?;
+    // the restored documents replace the ones an import would be writing
+    let Some(lock) = RunLock::acquire(db.clone(), IMPORT_LOCK).await? else {
+        return Err("an import is running, try again later".into());
+    };
+    let res = async {
+        db::backup::restore(db.as_ref(), Path::new(path), &collections).await?;
+        // the archive may come from an older schema
+        let applied = migrations::run(db.as_ref()).await?;
+        info!("restored {}, applied {} migrations", path, applied.len());
+        // the restored episodes come without the derived data, or with the one of the archive
+        similarity::rebuild(db.as_ref()).await?;
+        topics::rebuild(db.as_ref()).await?;
+        trends::prune(db.as_ref()).await?;
+        stats::rebuild(db.as_ref()).await?;
+        Ok::<_, Box<dyn std::error::Error>>(())
+    }.await;
+    lock.release().await?;
+    res
+}
+
+async fn rebuild(metadata: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let db = db::connect(&CONFIG.db);
+    db.ensure_index().await?;
+    migrations::run(db.as_ref()).await?;
+    // the transcripts are replaced, imports must not write them meanwhile
+    let Some(lock) = RunLock::acquire(db.clone(), IMPORT_LOCK).await? else {
+        return Err("an import is running, try again later".into());
+    };
+    let res = rebuild_cache(&db, metadata).await;
+    lock.release().await?;
+    res
+}
+
+/// Reinsert the transcripts of the cache, recording the run even when it fails.
+async fn rebuild_cache(db: &Arc<dyn Store>, metadata: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let run = ImportRun::start(ImportSource::Cache, ImportVersions {
+        importer: env!("CARGO_PKG_VERSION").to_string(),
+        schema: migrations::version(db.as_ref()).await?,
+        transcriber: String::new(),
+    });
+    db.update_one_stateless(run.id, &run).await?;
+    let run = run.handle();
+    let res = cache::rebuild(db.as_ref(), &CONFIG.import.transcript_dir, metadata.map(Path::new), &run).await;
+
+    // as for imports, a failed rebuild is finished too
+    let run = {
+        let mut run = run.lock().unwrap();
+        if let Err(e) = &res {
+            run.fail(None, ImportStage::Insert, e);
+        }
+        run.finish();
+        run.clone()
+    };
+    db.update_one_stateless(run.id, &run).await?;
+    info!("{}", res?);
+    similarity::rebuild(db.as_ref()).await?;
+    topics::rebuild(db.as_ref()).await?;
+    trends::prune(db.as_ref()).await?;
+    stats::rebuild(db.as_ref()).await?;
+    Ok(())
+}
+
+async fn show_stats(refresh: bool, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let db = db::connect(&CONFIG.db);
+    let stats = match db.get::<ShowStats>(ShowStats::ID.to_owned()).await? {
+        Some(s) if !refresh => s,
+        _ => stats::rebuild(db.as_ref()).await?,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&bson::to_bson(&stats)?.into_relaxed_extjson())?);
+        return Ok(());
+    }
+    let hours = |ms: u64| ms as f64 / 3_600_000.;
+    let duration = |ms: f64| {
+        let s = ms as u64 / 1000;
+        format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+    };
+    println!("computed at {}", stats.computed_at.format("%Y-%m-%d %H:%M"));
+    println!("episodes: {}, {:.1} hours", stats.episodes, hours(stats.duration_ms));
+    println!(
+        "transcribed: {} episodes ({:.1}%), {:.1} hours ({:.1}%)",
+        stats.transcripts,
+        stats.coverage() * 100.,
+        hours(stats.transcribed_ms),
+        stats.duration_coverage() * 100.,
+    );
+    println!("\naverage duration by year:");
+    for y in &stats.years {
+        println!("    {}  {}  ({} episodes)", y.year, duration(y.average_ms as f64), y.episodes);
+    }
+    for (name, episodes) in [("longest", &stats.longest), ("shortest", &stats.shortest)] {
+        println!("\n{} episodes:", name);
+        for e in episodes {
+            println!("    {:>10}  {}  {}", e.episode_id, duration(e.value), e.title);
+        }
+    }
+    println!("\nmost said words:");
+    println!("    {}", stats.words.iter().map(|w| format!("{} ({})", w.word, w.count)).collect::<Vec<_>>().join(", "));
+    println!("\nwords per minute: {:.0}", stats.words_per_minute);
+    for e in &stats.episode_words_per_minute {
+        println!("    {:>10}  {:>5.0}  {}", e.episode_id, e.value, e.title);
+    }
+    Ok(())
+}
+
+async fn gc(dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let db = db::connect(&CONFIG.db);
+    let stats = artifacts::gc(db.as_ref(), dry_run).await?;
+    for s in &stats {
+        info!("{}", s);
+    }
+    info!(
+        "{} MiB {}",
+        stats.iter().map(|s| s.reclaimed).sum::<u64>() >> 20,
+        if dry_run { "would be reclaimed" } else { "reclaimed" }
+    );
+    Ok(())
+}
+
+async fn refresh_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let db = db::connect(&CONFIG.db);
+    db.ensure_index().await?;
+    let Some(lock) = RunLock::acquire(db.clone(), IMPORT_LOCK).await? else {
+        return Err("an import is running, try again later".into());
+    };
+    let res = import::refresh_metadata(db.as_ref(), Arc::new(SpreakerApi::default()), CONFIG.import.show_id).await;
+    lock.release().await?;
+    let (updated, failed) = res?;
+    info!("refreshed {} episodes, {} couldn't be fetched", updated, failed);
+    // durations and descriptions may have changed
+    similarity::rebuild(db.as_ref()).await?;
+    stats::rebuild(db.as_ref()).await?;
+    Ok(())
+}
+
+async fn migrate() -> Result<(), Box<dyn std::error::Error>> {
+    let db = db::connect(&CONFIG.db);
+    db.ensure_index().await?;
+    let applied = migrations::run(db.as_ref()).await?;
+    info!("applied {} migrations, database at version {}", applied.len(), migrations::version(db.as_ref()).await?);
+    Ok(())
+}
+
+async fn rebuild_derived() -> Result<(), Box<dyn std::error::Error>> {
+    let db = db::connect(&CONFIG.db);
+    db.ensure_index().await?;
+    migrations::run(db.as_ref()).await?;
+    // the fuzzy index is rebuilt from scratch, imports must not write transcripts meanwhile
+    let Some(lock) = RunLock::acquire(db.clone(), IMPORT_LOCK).await? else {
+        return Err("an import is running, try again later".into());
+    };
+    let res = async {
+        info!("rebuilding the fuzzy index");
+        fuzzy::rebuild(db.as_ref()).await?;
+        info!("computing the similarity and the topics of the episodes");
+        similarity::rebuild(db.as_ref()).await?;
+        topics::rebuild(db.as_ref()).await?;
+        stats::rebuild(db.as_ref()).await
+    }.await;
+    lock.release().await?;
+    res?;
+    info!("derived data rebuilt");
+    Ok(())
+}
+
+async fn oneshot() -> Result<(), Box<dyn std::error::Error>> {
+    serve_metrics().await?;
+    let db = db::connect(&CONFIG.db);
+    // the import lock relies on the unique index of the locks
+    db.ensure_index().await?;
+    metrics::set_ready(true);
+    locked_import(&db, std::future::pending()).await?;
+    Ok(())
+}
+
+async fn control(command: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let reply = daemon::send_control(Path::new(&CONFIG.import.daemon.control_socket), command).await?;
+    println!("{}", reply);
+    Ok(())
+}
+
+/// Import on the configured schedule until SIGTERM, SIGINT or a `stop` command.
+/// The import in progress is completed before exiting, a second signal interrupts it: the run is recorded as failed and
+/// the lock released, the downloads and the transcript cache let the next run pick up from there.
+async fn daemon() -> Result<(), Box<dyn std::error::Error>> {
+    serve_metrics().await?;
+    let db = db::connect(&CONFIG.db);
+    db.ensure_index().await?;
+    metrics::set_ready(true);
+    let schedule = Schedule::from_config(&CONFIG.import.daemon)?;
+    let socket = Path::new(&CONFIG.import.daemon.control_socket);
+    let state = Arc::new(Mutex::new(DaemonState::default()));
+    let (tx, mut rx) = mpsc::channel(4);
+    let server = daemon::serve_control(socket, tx, state.clone())?;
+    let mut term = signal(SignalKind::terminate())?;
+    let mut int = signal(SignalKind::interrupt())?;
+
+    loop {
+        let next = schedule.next(Utc::now());
+        state.lock().unwrap().next_run = next;
+        let wait = next.map(|n| (n - Utc::now()).to_std().unwrap_or_default());
+        info!("next import at {:?}", next);
+        let stop = tokio::select! {
+            _ = tokio::time::sleep(wait.unwrap_or(Duration::MAX)), if wait.is_some() => false,
+            Some(c) = rx.recv() => c == ControlCommand::Stop,
+            _ = term.recv() => true,
+            _ = int.recv() => true,
+        };
+        if stop {
+            break;
+        }
+
+        state.lock().unwrap().running = true;
+        let cancel = Notify::new();
+        let run = locked_import(&db, cancel.notified());
+        tokio::pin!(run);
+        let mut stop = false;
+        let res = loop {
+            let signal = tokio::select! {
+                r = &mut run => break r,
+                Some(c) = rx.recv(), if !stop => {
+                    stop = c == ControlCommand::Stop;
+                    false
+                }
+                _ = term.recv() => true,
+                _ = int.recv() => true,
+            };
+            if signal && stop {
+                info!("interrupting the current import");
+                cancel.notify_one();
+            } else if signal || stop {
+                stop = true;
+                info!("finishing the current import before exiting, signal again to stop now");
+            }
+        };
+        let mut s = state.lock().unwrap();
+        s.running = false;
+        match res {
+            Ok(Some(id)) => s.last_run = Some(id),
+            Ok(None) => {}
+            Err(e) => error!("import failed: {}", e),
+        }
+        if stop {
+            break;
+        }
+    }
+
+    info!("stopping");
+    server.abort();
+    let _ = std::fs::remove_file(socket);
+    Ok(())
+}
+
+/// Start the metrics endpoint, if configured.
+async fn serve_metrics() -> std::io::Result<()> {
+    if let Some(addr) = &CONFIG.metrics.import {
+        metrics::serve(addr).await?;
+    }
+    Ok(())
+}
+
+/// Run an import holding the database lock, `None` if another import is in progress. The import is interrupted when
+/// `cancel` resolves.
+async fn locked_import(db: &Arc<dyn Store>, cancel: impl Future<Output = ()>) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+    let Some(lock) = RunLock::acquire(db.clone(), IMPORT_LOCK).await? else {
+        warn!("another import is running, skipping");
+        return Ok(None);
+    };
+    let stop = async {
+        tokio::select! {
+            _ = lock.lost() => "lost the import lock",
+            _ = cancel => "import interrupted",
+        }
+    };
+    let res = import(db, stop).await;
+    lock.release().await?;
+    res.map(Some)
+}
+
+/// Import the new episodes, stopping when `stop` resolves with the reason: e.g. the lock was lost, and another import
+/// may be running.
+async fn import(db: &Arc<dyn Store>, stop: impl Future<Output = &'static str>) -> Result<i64, Box<dyn std::error::Error>> {
     info!("check for missing directories");
     if !CONFIG.import.check_dirs() {
-        error!("missing directories");
-        return Ok(());
+        return Err("missing directories".into());
     }
 
-    import_database(CONFIG.import.show_id.to_string()).await?;
+    migrations::run(db.as_ref()).await?;
+    let run = ImportRun::start(ImportSource::Spreaker, ImportVersions {
+        importer: env!("CARGO_PKG_VERSION").to_string(),
+        schema: migrations::version(db.as_ref()).await?,
+        transcriber: CONFIG.import.transcriber_url.clone(),
+    });
+    db.update_one_stateless(run.id, &run).await?;
+    let run = run.handle();
+    let mut stage = ImportStage::Metadata;
+    // the downloads and the transcript cache let the next run pick up from where this one stopped
+    let res = tokio::select! {
+        r = import_episodes(db, &run, &mut stage) => r,
+        reason = stop => Err(reason.into()),
+    };
+
+    // the run is finished even when the import is interrupted, or it would look in progress forever
+    let run = {
+        let mut run = run.lock().unwrap();
+        if let Err(e) = &res {
+            run.fail(None, stage, e);
+        }
+        run.finish();
+        run.clone()
+    };
+    info!("{}", run);
+    db.update_one_stateless(run.id, &run).await?;
+    res?;
+
+    if run.inserted > 0 || run.transcripts_inserted > 0 {
+        info!("computing the similarity and the topics of the episodes");
+        similarity::rebuild(db.as_ref()).await?;
+        topics::rebuild(db.as_ref()).await?;
+        trends::prune(db.as_ref()).await?;
+    }
+    stats::rebuild(db.as_ref()).await?;
+
+    for s in artifacts::gc(db.as_ref(), false).await? {
+        debug!("{}", s);
+    }
+
+    Ok(run.id)
+}
+
+/// Import the new episodes of the show and the missing transcripts, `stage` is updated as the import goes.
+async fn import_episodes(db: &Arc<dyn Store>, run: &ImportRunHandle, stage: &mut ImportStage) -> Result<(), Box<dyn std::error::Error>> {
+    import_database(db.as_ref(), CONFIG.import.show_id.to_string(), run).await?;
+    *stage = ImportStage::Download;
 
     // check for missing transcripts
-    let episodes = DB.get_ids::<Episode>().await.unwrap();
-    let transcripts: HashSet<u32> = DB.get_ids::<EpisodeTranscript>().await?.into_iter().collect();
+    let episodes = db.get_ids::<Episode>().await?;
+    let transcripts: HashSet<u32> = db.get_ids::<EpisodeTranscript>().await?.into_iter().collect();
 
     // collect cached transcripts
-    let cached_transcripts: HashSet<u32> = read_dir(CONFIG.import.transcript_dir.clone())
-        .unwrap()
-        .filter_map(|entry| {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
-                match path.file_stem().unwrap().to_string_lossy().parse::<u32>() {
-                    Ok(id) => Some(id),
-                    Err(e) => {
-                        warn!("invalid file name: {:?}: {:?}", path, e);
-                        None
-                    }
-                }
-            } else {
-                None
-            }
-        })
-        .collect();
+    let cached_transcripts = cache::scan(&CONFIG.import.transcript_dir)?;
+    for p in &cached_transcripts.invalid_names {
+        warn!("invalid file name: {:?}", p);
+    }
+    let cached_transcripts = cached_transcripts.ids();
 
-    let audio_files: HashSet<u32> = read_dir(CONFIG.import.wav_dir.clone())
-        .unwrap()
-        .filter_map(|entry| {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            if path.is_file() && path.extension().is_some_and(|ext| ext == "wav") {
-                Some(path.file_stem().unwrap().to_string_lossy().parse::<u32>().unwrap())
-            } else {
-                None
-            }
-        })
-        .collect();
+    let audio_files: HashSet<u32> = Artifact::Wav.scan()?.into_iter().map(|f| f.id).collect();
 
     let cli = Arc::new(reqwest::Client::new());
-    let converter = JobManager::new(Arc::clone(&cli));
+    let progress = Progress::default();
+    let durations: HashMap<u32, f64> = db.get_all::<Episode>().await?.into_iter().map(|e| (e.id, e.duration as f64 / 1000.)).collect();
+    let displays: Vec<_> = progress::spawn_terminal(&progress, durations.clone())
+        .into_iter()
+        .chain(CONFIG.tg.progress_chat.map(|c| progress::spawn_telegram(&progress, durations, Bot::new(&CONFIG.tg.token), ChatId(c))))
+        .chain(CONFIG.metrics.import.as_ref().map(|_| metrics::spawn_import(&progress)))
+        .collect();
+    let converter = JobManager::new(db.clone(), Arc::clone(&cli), run.clone(), progress.clone());
     let mut to_convert = vec![];
     let mut to_transcribe = vec![];
     let mut to_download = vec![];
@@ -60,9 +443,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         if !transcripts.contains(&e) {
             // info!("Transcript missing for episode {}", e);
             if cached_transcripts.contains(&e) {
-                let t = serde_json::from_str::<Transcript>(&read_to_string(format!("{}/{}.json", CONFIG.import.transcript_dir, e)).unwrap()).unwrap();
-                info!("transcript cache found for {}: add to convert list", e);
-                to_convert.push((e, t));
+                let cached = match artifacts::fetch(db.as_ref(), &cli, Artifact::Transcript, e, &progress).await {
+                    Ok(p) => cache::load_cached(&p).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                match cached {
+                    Ok(t) => {
+                        info!("transcript cache found for {}: add to convert list", e);
+                        to_convert.push((e, t));
+                    }
+                    Err(err) => {
+                        warn!("corrupt transcript cache for {}, transcribing again: {}", e, err);
+                        if audio_files.contains(&e) {
+                            to_transcribe.push(e);
+                        } else {
+                            to_download.push(e);
+                        }
+                    }
+                }
             } else if !audio_files.contains(&e) {
                 warn!("transcript cache and audio file missing for {}: add to download list", e);
                 to_download.push(e);
@@ -84,6 +482,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     converter.wait().await?;
-
+    for d in displays {
+        d.await?;
+    }
     Ok(())
 }
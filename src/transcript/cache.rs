@@ -0,0 +1,182 @@
+use std::{collections::HashSet, fmt::Display, fs::read_dir, path::{Path, PathBuf}};
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use crate::db::{self, backup::{self, BackupError}, PPPData, Store, StoreError};
+use crate::spreaker::{Episode, Show};
+use crate::status::{ImportRunHandle, ImportStage};
+use super::data::{EpisodeTranscript, Transcript, TranscriptAlt};
+
+/// Read a cached transcript, written either by the importer (`Transcript`) or as the raw response of the
+/// transcription service (`TranscriptAlt`).
+pub fn load_cached(path: &Path) -> Result<Transcript, CacheError> {
+    let buf = std::fs::read_to_string(path)?;
+    let t = match serde_json::from_str::<Transcript>(&buf) {
+        Ok(t) => t,
+        Err(e) => match serde_json::from_str::<TranscriptAlt>(&buf) {
+            Ok(t) => t.into(),
+            // report the error of the format we write ourselves
+            Err(_) => return Err(CacheError::Parse(e)),
+        }
+    };
+    validate(&t)?;
+    Ok(t)
+}
+
+fn validate(t: &Transcript) -> Result<(), CacheError> {
+    if t.transcription.is_empty() {
+        return Err(CacheError::Invalid("no segments".to_string()));
+    }
+    for (i, s) in t.transcription.iter().enumerate() {
+        if s.timestamps.from > s.timestamps.to {
+            return Err(CacheError::Invalid(format!("segment {} ends before it starts", i)));
+        }
+    }
+    Ok(())
+}
+
+/// Content of the transcript cache directory.
+#[derive(Debug, Default)]
+pub struct CacheScan {
+    pub entries: Vec<(u32, PathBuf)>,
+    /// `.json` files whose name isn't an episode id.
+    pub invalid_names: Vec<PathBuf>,
+}
+
+impl CacheScan {
+    pub fn ids(&self) -> HashSet<u32> {
+        self.entries.iter().map(|(id, _)| *id).collect()
+    }
+}
+
+pub fn scan(dir: &str) -> Result<CacheScan, CacheError> {
+    let mut scan = CacheScan::default();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        match path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u32>().ok()) {
+            Some(id) => scan.entries.push((id, path)),
+            None => scan.invalid_names.push(path),
+        }
+    }
+    scan.entries.sort();
+    Ok(scan)
+}
+
+/// Outcome of `rebuild`.
+#[derive(Debug, Default)]
+pub struct RebuildReport {
+    pub inserted: Vec<u32>,
+    /// Cache files of episodes missing from the database.
+    pub orphans: Vec<PathBuf>,
+    pub corrupt: Vec<(PathBuf, CacheError)>,
+    pub invalid_names: Vec<PathBuf>,
+}
+
+impl Display for RebuildReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} transcripts inserted, {} orphans, {} corrupt, {} invalid names", self.inserted.len(), self.orphans.len(), self.corrupt.len(), self.invalid_names.len())?;
+        for p in &self.orphans {
+            write!(f, "\n  orphan: {}", p.display())?;
+        }
+        for (p, e) in &self.corrupt {
+            write!(f, "\n  corrupt: {}: {}", p.display(), e)?;
+        }
+        for p in &self.invalid_names {
+            write!(f, "\n  invalid name: {}", p.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Rebuild the transcripts collection from the cache in `dir` without touching the network.
+///
+/// Episode metadata is read from the shows and episodes of the backup archive at `metadata` if given, otherwise
+/// the episodes already in the database are used. Existing transcripts are replaced.
+pub async fn rebuild(db: &dyn Store, dir: &str, metadata: Option<&Path>, run: &ImportRunHandle) -> Result<RebuildReport, CacheError> {
+    if let Some(m) = metadata {
+        info!("loading episode metadata from {}", m.display());
+        backup::restore(db, m, &[(Show::COLLECTION, Show::ID_KEY), (Episode::COLLECTION, Episode::ID_KEY)]).await?;
+    }
+    let episodes: HashSet<u32> = db.get_ids::<Episode>().await?.into_iter().collect();
+    run.lock().unwrap().discovered = episodes.len() as u32;
+
+    let scan = scan(dir)?;
+    info!("found {} cached transcripts", scan.entries.len());
+    let mut report = RebuildReport { invalid_names: scan.invalid_names, ..Default::default() };
+    db::bulk_write(db, async {
+        for (id, path) in scan.entries {
+            if !episodes.contains(&id) {
+                warn!("no episode for cached transcript {}", path.display());
+                report.orphans.push(path);
+                continue;
+            }
+            let t = match load_cached(&path) {
+                Ok(t) => t,
+                Err(e) => {
+                    warn!("corrupt cached transcript {}: {}", path.display(), e);
+                    run.lock().unwrap().fail(Some(id), ImportStage::Convert, &e);
+                    report.corrupt.push((path, e));
+                    continue;
+                }
+            };
+            debug!("inserting transcript of episode {}", id);
+            let t: EpisodeTranscript = (id, t).into();
+            db.update_one_stateless(id, &t).await?;
+            run.lock().unwrap().transcripts_inserted += 1;
+            report.inserted.push(id);
+        }
+        Ok::<_, CacheError>(())
+    }).await?;
+    Ok(report)
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+    Store(StoreError),
+    Backup(BackupError),
+}
+
+impl Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "IO error: {}", e),
+            Self::Parse(e) => write!(f, "Parse error: {}", e),
+            Self::Invalid(e) => write!(f, "Invalid transcript: {}", e),
+            Self::Store(e) => write!(f, "Database error: {}", e),
+            Self::Backup(e) => write!(f, "Backup error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<std::io::Error> for CacheError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CacheError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<StoreError> for CacheError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<BackupError> for CacheError {
+    fn from(e: BackupError) -> Self {
+        Self::Backup(e)
+    }
+}
@@ -1,7 +1,7 @@
 use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
-use serde_with::{DurationMilliSeconds, DurationSeconds};
+use serde_with::{DurationMilliSeconds, DurationSecondsWithFrac};
 
 use crate::db::PPPData;
 
@@ -22,12 +22,14 @@ pub struct Segment {
     pub text: String,
 }
 
+/// A segment of the `verbose_json` response of the transcription service, whose times are seconds with a fractional
+/// part: `DurationSeconds<f64>` would round them to whole seconds.
 #[serde_as]
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SegmentAlt {
-    #[serde_as(as = "DurationSeconds<f64>")]
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
     pub start: Duration,
-    #[serde_as(as = "DurationSeconds<f64>")]
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
     pub end: Duration,
     pub text: String,
 }
@@ -60,6 +62,22 @@ impl PPPData for EpisodeTranscript {
     type IdType = u32;
 }
 
+impl EpisodeTranscript {
+    /// The segments of the transcript as `(time, text)` pairs, slicing `data` with the char offsets of the timestamps.
+    pub fn segments(&self) -> Vec<(&FromTo, &str)> {
+        let bounds: Vec<usize> = self.data
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(self.data.len()))
+            .collect();
+        let last = bounds.len() - 1;
+        self.timestamps
+            .iter()
+            .map(|t| (&t.time, &self.data[bounds[t.offsets.0.min(last)]..bounds[t.offsets.1.min(last)]]))
+            .collect()
+    }
+}
+
 impl From<(u32, Transcript)> for EpisodeTranscript {
     fn from(transcript: (u32, Transcript)) -> Self {
         let (episode_id, transcript) = transcript;
@@ -102,3 +120,26 @@ impl From<TranscriptAlt> for Transcript {
         Self { transcription }
     }
 }
+
+impl From<&EpisodeTranscript> for Transcript {
+    fn from(transcript: &EpisodeTranscript) -> Self {
+        let transcription = transcript
+            .segments()
+            .into_iter()
+            .map(|(ts, text)| Segment { timestamps: ts.clone(), text: text.to_owned() })
+            .collect();
+        Self { transcription }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_alt_keeps_fractional_seconds() {
+        let s: SegmentAlt = serde_json::from_str(r#"{"start": 1.25, "end": 2.5, "text": " ciao"}"#).unwrap();
+        assert_eq!(s.start, Duration::from_millis(1250));
+        assert_eq!(s.end, Duration::from_millis(2500));
+    }
+}
@@ -1,24 +1,27 @@
-use std::io::Write;
 use std::fmt::Display;
 use std::sync::{Arc, Mutex};
 use log::debug;
 #[allow(unused_imports)]
 use log::{error, info, warn};
-use futures_util::stream::StreamExt;
 
+use crate::artifacts::{self, Artifact, ArtifactError};
 use crate::config::CONFIG;
-use crate::db::DB;
-use crate::spreaker::Episode;
+use crate::db::{Store, StoreError};
 use crate::transcript::data::TranscriptAlt;
 use tokio::sync::Semaphore;
 
 
 use tokio::task::JoinHandle;
 
-use super::data::{EpisodeTranscript, Transcript}; type JobContainer<T> = Mutex<Vec<JoinHandle<Result<T, JobManagerError>>>>;
+use crate::progress::{Progress, ProgressEvent};
+use crate::status::{ImportRunHandle, ImportStage};
+use super::data::{EpisodeTranscript, Transcript}; type JobContainer<T> = Mutex<Vec<(u32, JoinHandle<Result<T, JobManagerError>>)>>;
 
 pub struct JobManager {
+    db: Arc<dyn Store>,
     cli: Arc<reqwest::Client>,
+    run: ImportRunHandle,
+    progress: Progress,
     conv_sem: Arc<Semaphore>,
     tran_sem: Arc<Semaphore>,
     down_sem: Arc<Semaphore>,
@@ -30,9 +33,12 @@ pub struct JobManager {
 }
 
 impl JobManager {
-    pub fn new(cli: Arc<reqwest::Client>) -> Self {
+    pub fn new(db: Arc<dyn Store>, cli: Arc<reqwest::Client>, run: ImportRunHandle, progress: Progress) -> Self {
         Self {
+            db,
             cli,
+            run,
+            progress,
             conv_sem: Arc::new(Semaphore::new(MAX_CONVERT_JOBS)),
             tran_sem: Arc::new(Semaphore::new(MAX_TRANSCRIBE_JOBS)),
             down_sem: Arc::new(Semaphore::new(MAX_DOWNLOAD_JOBS)),
@@ -46,38 +52,45 @@ impl JobManager {
 
     pub fn run_convert(&self, id: u32, transcript: Transcript) {
         debug!("enqueuing convert job for episode {}", id);
-        let conv = Self::_run_convert(id, transcript, self.conv_sem.clone());
+        self.progress.emit(ProgressEvent::Queued { episode: id, stage: ImportStage::Convert });
+        let conv = Self::_run_convert(id, transcript, self.conv_sem.clone(), self.progress.clone());
         let handle = tokio::spawn(conv);
-        self.conv_jobs.lock().unwrap().push(handle);
+        self.conv_jobs.lock().unwrap().push((id, handle));
     }
 
     pub fn run_transcribe(&self, id: u32) {
         debug!("enqueuing transcribe job for episode {}", id);
-        let tran = Self::_run_transcribe(id, self.cli.clone(), self.tran_sem.clone());
+        self.progress.emit(ProgressEvent::Queued { episode: id, stage: ImportStage::Transcribe });
+        let tran = Self::_run_transcribe(id, self.db.clone(), self.cli.clone(), self.tran_sem.clone(), self.progress.clone());
         let handle = tokio::spawn(tran);
-        self.tran_jobs.lock().unwrap().push(handle);
+        self.tran_jobs.lock().unwrap().push((id, handle));
     }
 
     pub fn run_download(&self, id: u32) {
         debug!("enqueuing download job for episode {}", id);
-        let down = Self::_run_download(id, self.cli.clone(), self.down_sem.clone());
+        self.progress.emit(ProgressEvent::Queued { episode: id, stage: ImportStage::Download });
+        let down = Self::_run_download(id, self.db.clone(), self.cli.clone(), self.down_sem.clone(), self.progress.clone());
         let handle = tokio::spawn(down);
-        self.down_jobs.lock().unwrap().push(handle);
+        self.down_jobs.lock().unwrap().push((id, handle));
     }
 
-    async fn _run_convert(id: u32, transcript: Transcript, sem: Arc<Semaphore>) -> Result<EpisodeTranscript, JobManagerError> {
+    async fn _run_convert(id: u32, transcript: Transcript, sem: Arc<Semaphore>, progress: Progress) -> Result<EpisodeTranscript, JobManagerError> {
         let _permit = sem.acquire().await.unwrap();
         info!("converting episode {}", id);
+        progress.emit(ProgressEvent::Started { episode: id, stage: ImportStage::Convert });
         let transcript = (id, transcript).into();
+        progress.emit(ProgressEvent::Finished { episode: id, stage: ImportStage::Convert });
         drop(_permit);
         Ok(transcript)
     }
 
-    async fn _run_transcribe(id: u32, cli: Arc<reqwest::Client>, sem: Arc<Semaphore>) -> Result<(u32, Transcript), JobManagerError> {
+    async fn _run_transcribe(id: u32, db: Arc<dyn Store>, cli: Arc<reqwest::Client>, sem: Arc<Semaphore>, progress: Progress) -> Result<(u32, Transcript), JobManagerError> {
         let _permit = sem.acquire().await.unwrap();
-        let f = format!("{}/{}.wav", CONFIG.import.wav_dir, id);
-        // warn!("using file: {}", f);
+        // the audio may have been evicted since it was downloaded
+        let f = artifacts::fetch(db.as_ref(), &cli, Artifact::Wav, id, &progress).await?;
         info!("transcribing espisode {}", id);
+        progress.emit(ProgressEvent::Started { episode: id, stage: ImportStage::Transcribe });
+        let audio_seconds = hound::WavReader::open(&f).map(|r| r.duration() as f64 / r.spec().sample_rate as f64).unwrap_or_default();
         let t = loop {
             match cli
                 .post(CONFIG.import.transcriber_url.as_str())
@@ -99,80 +112,91 @@ impl JobManager {
             }
         };
         let t: Transcript = t.into();
-        let cache_f = format!("{}/{}.json", CONFIG.import.transcript_dir, id);
-        debug!("writing transcript cache: {}", cache_f);
+        let cache_f = Artifact::Transcript.path(id);
+        debug!("writing transcript cache: {}", cache_f.display());
         let cache = std::fs::File::create(cache_f)?;
         serde_json::to_writer(cache, &t)?;
+        progress.emit(ProgressEvent::Transcribed { episode: id, audio_seconds });
+        progress.emit(ProgressEvent::Finished { episode: id, stage: ImportStage::Transcribe });
         drop(_permit);
         Ok((id, t))
     }
 
-    async fn _run_download(id: u32, cli: Arc<reqwest::Client>, sem: Arc<Semaphore>) -> Result<u32, JobManagerError> {
+    async fn _run_download(id: u32, db: Arc<dyn Store>, cli: Arc<reqwest::Client>, sem: Arc<Semaphore>, progress: Progress) -> Result<u32, JobManagerError> {
         let _permit = sem.acquire().await.unwrap();
         info!("downloading episode {}", id);
-        let e = DB.get::<Episode>(id).await?.unwrap();
-        let url = e.download_url;
-        let res = cli.get(&url).send().await?;
-        let mp3 = format!("{}/{}.mp3", CONFIG.import.download_dir, e.id);
-        debug!("download output: {}", mp3);
-        let wav = format!("{}/{}.wav", CONFIG.import.wav_dir, e.id);
-        debug!("wav output: {}", wav);
-        if res.status().is_success() {
-            let mut file = std::fs::File::create(&mp3)?;
-            let mut stream =  res.bytes_stream();
-            while let Some(item) = stream.next().await {
-                let chunk = item?;
-                file.write_all(&chunk)?;
-            }
-        }
-        match tokio::process::Command::new("ffmpeg")
-            .args(["-i", &mp3, "-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le", &wav])
-            .status()
-            .await? {
-            s if s.success() => {
-                std::fs::remove_file(&mp3)?;
-            }
-            s => {
-                error!("couldn't convert episode {} from mp3 to wav: {}", id, s);
-            }
-        }
+        progress.emit(ProgressEvent::Started { episode: id, stage: ImportStage::Download });
+        let wav = artifacts::fetch(db.as_ref(), &cli, Artifact::Wav, id, &progress).await?;
+        debug!("wav output: {}", wav.display());
+        progress.emit(ProgressEvent::Finished { episode: id, stage: ImportStage::Download });
         drop(_permit);
         Ok(id)
     }
 
-    async fn _run_insert_db(e: EpisodeTranscript, sem: Arc<Semaphore>) -> Result<(), JobManagerError> {
+    async fn _run_insert_db(e: EpisodeTranscript, db: Arc<dyn Store>, sem: Arc<Semaphore>, progress: Progress) -> Result<(), JobManagerError> {
         let _permit = sem.acquire().await.unwrap();
         info!("inserting episode {} into database", e.episode_id);
-        DB.insert_stateless(&[e]).await?;
+        let id = e.episode_id;
+        progress.emit(ProgressEvent::Started { episode: id, stage: ImportStage::Insert });
+        db.insert_stateless(&[e]).await?;
+        artifacts::after_insert(id)?;
+        progress.emit(ProgressEvent::Finished { episode: id, stage: ImportStage::Insert });
         drop(_permit);
         Ok(())
     }
 
+    /// Waits for every job, chaining each stage into the next one.
+    /// A failed job is recorded in the import run and doesn't stop the others.
     pub async fn wait(self) -> Result<(), JobManagerError> {
-        for j in self.down_jobs.into_inner().unwrap().into_iter() {
-            let id = j.await??;
-            let job = Self::_run_transcribe(id, self.cli.clone(), self.tran_sem.clone());
-            self.tran_jobs.lock()?.push(tokio::spawn(job));
+        let jobs = std::mem::take(&mut *self.down_jobs.lock()?);
+        for (id, j) in jobs {
+            let Some(id) = self.record(id, ImportStage::Download, j.await) else { continue };
+            self.run.lock()?.downloaded += 1;
+            self.progress.emit(ProgressEvent::Queued { episode: id, stage: ImportStage::Transcribe });
+            let job = Self::_run_transcribe(id, self.db.clone(), self.cli.clone(), self.tran_sem.clone(), self.progress.clone());
+            self.tran_jobs.lock()?.push((id, tokio::spawn(job)));
         }
 
-        for j in self.tran_jobs.into_inner().unwrap().into_iter() {
-            let (id, t) = j.await??;
-            let job = Self::_run_convert(id, t, self.conv_sem.clone());
-            self.conv_jobs.lock()?.push(tokio::spawn(job));
+        let jobs = std::mem::take(&mut *self.tran_jobs.lock()?);
+        for (id, j) in jobs {
+            let Some((id, t)) = self.record(id, ImportStage::Transcribe, j.await) else { continue };
+            self.run.lock()?.transcribed += 1;
+            self.progress.emit(ProgressEvent::Queued { episode: id, stage: ImportStage::Convert });
+            let job = Self::_run_convert(id, t, self.conv_sem.clone(), self.progress.clone());
+            self.conv_jobs.lock()?.push((id, tokio::spawn(job)));
         }
 
-        for j in self.conv_jobs.into_inner().unwrap().into_iter() {
-            let e = j.await??;
-            let job = Self::_run_insert_db(e, self.insd_sem.clone());
-            self.insd_jobs.lock()?.push(tokio::spawn(job));
+        let jobs = std::mem::take(&mut *self.conv_jobs.lock()?);
+        for (id, j) in jobs {
+            let Some(e) = self.record(id, ImportStage::Convert, j.await) else { continue };
+            self.progress.emit(ProgressEvent::Queued { episode: id, stage: ImportStage::Insert });
+            let job = Self::_run_insert_db(e, self.db.clone(), self.insd_sem.clone(), self.progress.clone());
+            self.insd_jobs.lock()?.push((id, tokio::spawn(job)));
         }
 
-        for j in self.insd_jobs.into_inner().unwrap().into_iter() {
-            j.await??;
+        let jobs = std::mem::take(&mut *self.insd_jobs.lock()?);
+        for (id, j) in jobs {
+            if self.record(id, ImportStage::Insert, j.await).is_some() {
+                self.run.lock()?.transcripts_inserted += 1;
+            }
         }
 
+        self.progress.emit(ProgressEvent::Done);
         Ok(())
     }
+
+    /// Unwrap the result of a job, recording its failure in the import run.
+    fn record<T>(&self, id: u32, stage: ImportStage, res: Result<Result<T, JobManagerError>, tokio::task::JoinError>) -> Option<T> {
+        let err = match res {
+            Ok(Ok(t)) => return Some(t),
+            Ok(Err(e)) => e,
+            Err(e) => e.into(),
+        };
+        error!("{} job for episode {} failed: {}", stage, id, err);
+        self.progress.emit(ProgressEvent::Failed { episode: id, stage, error: err.to_string() });
+        self.run.lock().unwrap().fail(Some(id), stage, err);
+        None
+    }
 }
 
 static MAX_CONVERT_JOBS: usize = 4;
@@ -185,9 +209,10 @@ pub enum JobManagerError {
     Reqwest(reqwest::Error),
     Io(std::io::Error),
     Tokio(tokio::task::JoinError),
-    Mongo(mongodb::error::Error),
+    Store(StoreError),
     Mutex,
     Serde(serde_json::Error),
+    Artifact(ArtifactError),
 }
 
 impl Display for JobManagerError {
@@ -197,8 +222,9 @@ impl Display for JobManagerError {
             Self::Io(e) => write!(f, "IO error: {}", e),
             Self::Tokio(e) => write!(f, "Tokio error: {}", e),
             Self::Mutex => write!(f, "Mutex error"),
-            Self::Mongo(e) => write!(f, "MongoDB error: {}", e),
+            Self::Store(e) => write!(f, "Database error: {}", e),
             Self::Serde(e) => write!(f, "Serde error: {}", e),
+            Self::Artifact(e) => write!(f, "Artifact error: {}", e),
         }
     }
 
@@ -230,9 +256,9 @@ impl<T> From<std::sync::PoisonError<T>> for JobManagerError {
     }
 }
 
-impl From<mongodb::error::Error> for JobManagerError {
-    fn from(e: mongodb::error::Error) -> Self {
-        Self::Mongo(e)
+impl From<StoreError> for JobManagerError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
     }
 }
 
@@ -241,3 +267,9 @@ impl From<serde_json::Error> for JobManagerError {
         Self::Serde(e)
     }
 }
+
+impl From<ArtifactError> for JobManagerError {
+    fn from(e: ArtifactError) -> Self {
+        Self::Artifact(e)
+    }
+}
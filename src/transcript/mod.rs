@@ -1,3 +1,4 @@
+pub mod cache;
 mod data;
 mod jobs;
 
@@ -0,0 +1,19 @@
+use log::info;
+use power_pizza_bot::{config::CONFIG, db, migrations, web};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pretty_env_logger::init();
+    let db = db::connect(&CONFIG.db);
+    info!("ensuring database indexes");
+    db.ensure_index().await?;
+    info!("applying database migrations");
+    migrations::run(db.as_ref()).await?;
+
+    let listener = tokio::net::TcpListener::bind(&CONFIG.web.listen).await?;
+    info!("listening on {}", CONFIG.web.listen);
+    axum::serve(listener, web::router(db))
+        .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap_or_default() })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+mod pages;
+
+use std::{cmp::Reverse, collections::BTreeSet, fmt::Display, sync::Arc};
+
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, routing::get, Router};
+use chrono::Datelike;
+use log::error;
+use maud::Markup;
+use serde::Deserialize;
+
+use crate::bot::{MetaQuery, SearchError, TranscriptSearchResult};
+use crate::db::{Store, StoreError};
+use crate::spreaker::Episode;
+use crate::transcript::EpisodeTranscript;
+
+use pages::Filters;
+
+const EPISODES_PER_PAGE: usize = 30;
+const HITS_PER_PAGE: usize = 10;
+const SNIPPETS_PER_HIT: usize = 3;
+/// Minimum length of a search query, as in the bot.
+const MIN_QUERY_LEN: usize = 3;
+
+/// Routes of `ppp_web`.
+pub fn router(db: Arc<dyn Store>) -> Router {
+    Router::new()
+        .route("/", get(episodes))
+        .route("/episodio/{id}", get(episode))
+        .route("/cerca", get(search))
+        .with_state(db)
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    tag: String,
+    year: Option<String>,
+    page: Option<usize>,
+}
+
+/// Episode list, most recent first, filtered by `MetaQuery`, tag and year.
+async fn episodes(State(db): State<Arc<dyn Store>>, Query(params): Query<ListQuery>) -> Result<Markup, WebError> {
+    let all = db.get_all::<Episode>().await?;
+    let tags: Vec<String> = all.iter().flat_map(|e| e.tags.iter().map(|t| t.to_lowercase())).collect::<BTreeSet<_>>().into_iter().collect();
+    let years: Vec<i32> = all.iter().map(|e| e.published_at.year()).collect::<BTreeSet<_>>().into_iter().rev().collect();
+    // empty when the select is left on "all"
+    let year = params.year.as_deref().and_then(|y| y.parse::<i32>().ok());
+
+    let mut query = MetaQuery::parse(&params.q);
+    if !params.tag.is_empty() {
+        query.tags.push(params.tag.to_lowercase());
+    }
+    let mut episodes = if query == MetaQuery::default() { all } else { db.search_episodes(&query).await? };
+    episodes.retain(|e| year.is_none_or(|y| e.published_at.year() == y));
+    episodes.sort_by_key(|e| Reverse(e.published_at));
+
+    let total = episodes.len();
+    let (page, pages) = page_bounds(params.page, total, EPISODES_PER_PAGE);
+    let episodes: Vec<Episode> = episodes.into_iter().skip((page - 1) * EPISODES_PER_PAGE).take(EPISODES_PER_PAGE).collect();
+    let year_param = year.map(|y| y.to_string()).unwrap_or_default();
+    let nav = pages::pagination(page, pages, |p| {
+        pages::link("/", &[("q", &params.q), ("tag", &params.tag), ("year", &year_param), ("page", &p.to_string())])
+    });
+    let filters = Filters { q: &params.q, tag: &params.tag, year, tags: &tags, years: &years };
+    Ok(pages::layout("Episodi", pages::episode_list(&filters, &episodes, total, nav)))
+}
+
+#[derive(Deserialize)]
+struct EpisodeQuery {
+    #[serde(default)]
+    q: String,
+}
+
+/// Episode with its transcript in timestamped paragraphs, the words of `q` highlighted.
+async fn episode(State(db): State<Arc<dyn Store>>, Path(id): Path<u32>, Query(params): Query<EpisodeQuery>) -> Result<Markup, WebError> {
+    let e = db.get::<Episode>(id).await?.ok_or(WebError::NotFound)?;
+    let t = db.get::<EpisodeTranscript>(id).await?;
+    Ok(pages::layout(&e.title, pages::episode_page(&e, t.as_ref(), &params.q)))
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    #[serde(default)]
+    q: String,
+    page: Option<usize>,
+}
+
+/// Full-text search across the transcripts, with the best snippets of each episode linking to their time.
+async fn search(State(db): State<Arc<dyn Store>>, Query(params): Query<SearchParams>) -> Result<Markup, WebError> {
+    let q = params.q.trim();
+    if q.is_empty() {
+        return Ok(pages::layout("Cerca", pages::search_page(q, None, &[], 0, Markup::default())));
+    }
+    if q.chars().count() < MIN_QUERY_LEN {
+        let error = Some("La ricerca deve essere di almeno 3 caratteri");
+        return Ok(pages::layout("Cerca", pages::search_page(q, error, &[], 0, Markup::default())));
+    }
+    let results = match db.search_transcript_all(q.to_owned()).await {
+        Ok(r) => r,
+        Err(SearchError::NoResults) => vec![],
+        Err(e) => return Err(e.into()),
+    };
+
+    let total = results.len();
+    let (page, pages) = page_bounds(params.page, total, HITS_PER_PAGE);
+    let mut hits: Vec<TranscriptSearchResult> = results.into_iter().skip((page - 1) * HITS_PER_PAGE).take(HITS_PER_PAGE).collect();
+    db.add_snippets(&mut hits, q, SNIPPETS_PER_HIT).await?;
+    let nav = pages::pagination(page, pages, |p| pages::link("/cerca", &[("q", q), ("page", &p.to_string())]));
+    Ok(pages::layout(q, pages::search_page(q, None, &hits, total, nav)))
+}
+
+/// The requested page clamped to the existing ones (counting from 1), and the number of pages.
+fn page_bounds(page: Option<usize>, total: usize, per_page: usize) -> (usize, usize) {
+    let pages = total.div_ceil(per_page).max(1);
+    (page.unwrap_or(1).clamp(1, pages), pages)
+}
+
+#[derive(Debug)]
+pub enum WebError {
+    Store(StoreError),
+    Search(SearchError),
+    NotFound,
+}
+
+impl Display for WebError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Store(e) => write!(f, "Database error: {}", e),
+            Self::Search(e) => write!(f, "Search error: {:?}", e),
+            Self::NotFound => write!(f, "Not found"),
+        }
+    }
+}
+
+impl std::error::Error for WebError {}
+
+impl IntoResponse for WebError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound => (StatusCode::NOT_FOUND, pages::error_page("Pagina non trovata")).into_response(),
+            Self::Search(e) => {
+                error!("search failed: {:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, pages::error_page(e.respond_client())).into_response()
+            }
+            Self::Store(e) => {
+                error!("database error: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, pages::error_page("Errore del database")).into_response()
+            }
+        }
+    }
+}
+
+impl From<StoreError> for WebError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<SearchError> for WebError {
+    fn from(e: SearchError) -> Self {
+        Self::Search(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_bounds_clamps_the_page() {
+        assert_eq!(page_bounds(None, 95, 30), (1, 4));
+        assert_eq!(page_bounds(Some(4), 95, 30), (4, 4));
+        assert_eq!(page_bounds(Some(9), 95, 30), (4, 4));
+        assert_eq!(page_bounds(Some(0), 95, 30), (1, 4));
+        assert_eq!(page_bounds(Some(2), 90, 30), (2, 3));
+        // no results still make one empty page
+        assert_eq!(page_bounds(Some(3), 0, 30), (1, 1));
+    }
+}
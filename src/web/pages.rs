@@ -0,0 +1,353 @@
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use regex::Regex;
+
+use crate::bot::TranscriptSearchResult;
+use crate::spreaker::Episode;
+use crate::transcript::{EpisodeTranscript, FromTo};
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; max-width: 50em; margin: 0 auto; padding: 0 1em; line-height: 1.5; color: #222; }
+header nav { padding: 1em 0; border-bottom: 1px solid #ddd; }
+a { color: #b3261e; }
+.meta { color: #666; font-size: .9em; }
+.tag { background: #f3e3e1; border-radius: .3em; padding: 0 .4em; margin-right: .3em; text-decoration: none; }
+form.filters { display: flex; flex-wrap: wrap; gap: .5em; margin: 1em 0; }
+ul.episodes { list-style: none; padding: 0; }
+ul.episodes li { margin: .8em 0; }
+audio { width: 100%; position: sticky; top: 0; background: #fff; }
+.transcript p { margin: 0 0 1em; }
+.ts { font-family: monospace; font-size: .85em; margin-right: .5em; }
+.line { cursor: pointer; }
+.line:hover { background: #f5f5f5; }
+.line.current { background: #fff3c4; }
+mark { background: #ffd54f; }
+.pages { display: flex; justify-content: space-between; margin: 1em 0 2em; }
+";
+
+/// Seek the player when a line or a timestamp is clicked, and to `#t=<seconds>` on load.
+const SCRIPT: &str = "
+const player = document.getElementById('player');
+const lines = [...document.querySelectorAll('.line[data-t]')];
+function seek(t, play) {
+  const go = () => { player.currentTime = t; if (play) player.play(); };
+  if (player.readyState > 0) go(); else player.addEventListener('loadedmetadata', go, { once: true });
+  const line = lines.filter(l => +l.dataset.t <= t).pop();
+  if (line) line.scrollIntoView({ block: 'center' });
+}
+document.querySelectorAll('[data-t]').forEach(e => e.addEventListener('click', ev => {
+  ev.preventDefault();
+  history.replaceState(null, '', '#t=' + e.dataset.t);
+  seek(+e.dataset.t, true);
+}));
+player.addEventListener('timeupdate', () => {
+  const current = lines.filter(l => +l.dataset.t <= player.currentTime).pop();
+  lines.forEach(l => l.classList.toggle('current', l === current));
+});
+const hash = location.hash.match(/^#t=(\\d+)/);
+if (hash) seek(+hash[1], false);
+else document.querySelector('mark')?.scrollIntoView({ block: 'center' });
+";
+
+pub fn layout(title: &str, body: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="it" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { (title) " · Power Pizza" }
+                style { (PreEscaped(STYLE)) }
+            }
+            body {
+                header {
+                    nav {
+                        a href="/" { "Episodi" }
+                        " · "
+                        a href="/cerca" { "Cerca nelle trascrizioni" }
+                    }
+                }
+                main { (body) }
+            }
+        }
+    }
+}
+
+/// `path` with the query string `params`, skipping the empty ones.
+pub fn link(path: &str, params: &[(&str, &str)]) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params.iter().filter(|(_, v)| !v.is_empty()))
+        .finish();
+    if query.is_empty() {
+        path.to_owned()
+    } else {
+        format!("{}?{}", path, query)
+    }
+}
+
+pub fn format_time(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Case-insensitive regex matching any word of `query`, used to highlight it.
+pub fn highlighter(query: &str) -> Option<Regex> {
+    let words: Vec<String> = query.split_whitespace().filter(|w| w.chars().count() > 1).map(regex::escape).collect();
+    if words.is_empty() {
+        return None;
+    }
+    Regex::new(&format!("(?i){}", words.join("|"))).ok()
+}
+
+/// `text` with the matches of `hl` wrapped in `<mark>`.
+pub fn highlight(text: &str, hl: Option<&Regex>) -> Markup {
+    let Some(hl) = hl else {
+        return html! { (text) };
+    };
+    // (text before the match, match)
+    let mut parts = vec![];
+    let mut last = 0;
+    for m in hl.find_iter(text) {
+        parts.push((&text[last..m.start()], m.as_str()));
+        last = m.end();
+    }
+    html! {
+        @for (before, m) in parts {
+            (before)
+            mark { (m) }
+        }
+        (&text[last..])
+    }
+}
+
+/// Options of the filters of the episode list.
+pub struct Filters<'a> {
+    pub q: &'a str,
+    pub tag: &'a str,
+    pub year: Option<i32>,
+    pub tags: &'a [String],
+    pub years: &'a [i32],
+}
+
+pub fn episode_list(filters: &Filters, episodes: &[Episode], total: usize, pages: Markup) -> Markup {
+    html! {
+        h1 { "Episodi" }
+        form.filters method="get" action="/" {
+            input type="search" name="q" value=(filters.q) placeholder="Titolo o descrizione, es. capitolo:undertale";
+            select name="tag" {
+                option value="" { "Tutti i tag" }
+                @for t in filters.tags {
+                    option value=(t) selected[t == filters.tag] { (t) }
+                }
+            }
+            select name="year" {
+                option value="" { "Tutti gli anni" }
+                @for y in filters.years {
+                    option value=(y) selected[Some(*y) == filters.year] { (y) }
+                }
+            }
+            button type="submit" { "Filtra" }
+        }
+        p.meta { (total) " episodi" }
+        ul.episodes {
+            @for e in episodes {
+                li {
+                    a href={ "/episodio/" (e.id) } { strong { (e.title) } }
+                    br;
+                    span.meta { (e.published_at.format("%d/%m/%Y")) " · " (format_time(e.duration as u64 / 1000)) }
+                }
+            }
+        }
+        (pages)
+    }
+}
+
+/// Links to the previous and next page, `href(page)` builds the link of a page.
+pub fn pagination(page: usize, pages: usize, href: impl Fn(usize) -> String) -> Markup {
+    html! {
+        @if pages > 1 {
+            nav.pages {
+                span { @if page > 1 { a href=(href(page - 1)) { "← Precedenti" } } }
+                span.meta { "Pagina " (page) " di " (pages) }
+                span { @if page < pages { a href=(href(page + 1)) { "Successivi →" } } }
+            }
+        }
+    }
+}
+
+/// A paragraph of the transcript: consecutive segments, each with its start in seconds.
+pub struct Paragraph<'a> {
+    pub start: u64,
+    pub lines: Vec<(u64, &'a str)>,
+}
+
+/// Longest paragraph, and the pause between two segments that starts a new one.
+const PARAGRAPH_SECS: u64 = 60;
+const PAUSE_SECS: u64 = 2;
+
+pub fn paragraphs(t: &EpisodeTranscript) -> Vec<Paragraph<'_>> {
+    let mut paragraphs: Vec<Paragraph> = vec![];
+    let mut prev_end = 0;
+    for (FromTo { from, to }, text) in t.segments() {
+        let (start, end) = (from.as_secs(), to.as_secs());
+        match paragraphs.last_mut() {
+            Some(p) if start < p.start + PARAGRAPH_SECS && start < prev_end + PAUSE_SECS => p.lines.push((start, text)),
+            _ => paragraphs.push(Paragraph { start, lines: vec![(start, text)] }),
+        }
+        prev_end = end;
+    }
+    paragraphs
+}
+
+pub fn episode_page(e: &Episode, transcript: Option<&EpisodeTranscript>, q: &str) -> Markup {
+    let hl = highlighter(q);
+    html! {
+        h1 { (e.title) }
+        p.meta {
+            (e.published_at.format("%d/%m/%Y")) " · " (format_time(e.duration as u64 / 1000))
+            " · " a href=(e.url()) { "Spreaker" }
+        }
+        @if !e.tags.is_empty() {
+            p {
+                @for t in &e.tags {
+                    a.tag href=(link("/", &[("tag", t)])) { (t) }
+                }
+            }
+        }
+        @if !e.topics.is_empty() {
+            p {
+                "Argomenti: "
+                @for t in &e.topics {
+                    a.tag href=(link("/", &[("q", &format!("topic:{}", t.name.replace(' ', "_")))])) { (t.name) }
+                }
+            }
+        }
+        audio #player controls preload="metadata" src=(e.download_url) {}
+        @if !e.chapters.is_empty() {
+            h2 { "Capitoli" }
+            ul {
+                @for c in &e.chapters {
+                    li {
+                        a.ts href={ "#t=" (c.starts_at / 1000) } data-t=(c.starts_at / 1000) { (format_time(c.starts_at / 1000)) }
+                        (c.title)
+                    }
+                }
+            }
+        }
+        details {
+            summary { "Descrizione" }
+            @for p in e.description.split("\n\n") {
+                p { (p) }
+            }
+        }
+        h2 { "Trascrizione" }
+        form method="get" {
+            input type="search" name="q" value=(q) placeholder="Evidenzia nella trascrizione";
+            button type="submit" { "Evidenzia" }
+        }
+        @match transcript {
+            Some(t) => {
+                div.transcript {
+                    @for p in paragraphs(t) {
+                        p {
+                            a.ts href={ "#t=" (p.start) } data-t=(p.start) { (format_time(p.start)) }
+                            @for (start, text) in &p.lines {
+                                span.line data-t=(start) { (highlight(text, hl.as_ref())) }
+                            }
+                        }
+                    }
+                }
+            }
+            None => p.meta { "La trascrizione di questo episodio non è ancora disponibile." }
+        }
+        script { (PreEscaped(SCRIPT)) }
+    }
+}
+
+pub fn search_page(q: &str, error: Option<&str>, hits: &[TranscriptSearchResult], total: usize, pages: Markup) -> Markup {
+    let hl = highlighter(q);
+    html! {
+        h1 { "Cerca nelle trascrizioni" }
+        form.filters method="get" action="/cerca" {
+            input type="search" name="q" value=(q) placeholder="Cosa hanno detto?" autofocus;
+            button type="submit" { "Cerca" }
+        }
+        @if let Some(e) = error {
+            p { (e) }
+        } @else if !q.is_empty() {
+            p.meta { (total) " episodi" }
+            ul.episodes {
+                @for h in hits {
+                    li {
+                        a href=(link(&format!("/episodio/{}", h.episode.id), &[("q", q)])) { strong { (h.episode.title) } }
+                        span.meta { " · " (h.episode.published_at.format("%d/%m/%Y")) " · " (h.hits) " occorrenze" }
+                        ul {
+                            @for s in &h.snippets {
+                                li {
+                                    a.ts href={ (link(&format!("/episodio/{}", h.episode.id), &[("q", q)])) "#t=" (s.time.from.as_secs()) } {
+                                        (format_time(s.time.from.as_secs()))
+                                    }
+                                    (highlight(&s.hint, hl.as_ref()))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            (pages)
+        }
+    }
+}
+
+pub fn error_page(message: &str) -> Markup {
+    layout("Errore", html! {
+        h1 { "Errore" }
+        p { (message) }
+        p { a href="/" { "Torna agli episodi" } }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::transcript::{Segment, Transcript};
+
+    use super::*;
+
+    /// Transcript made of `(from, to, text)` segments, times in seconds.
+    fn transcript(segments: &[(u64, u64, &str)]) -> EpisodeTranscript {
+        let transcription = segments
+            .iter()
+            .map(|&(from, to, text)| Segment {
+                timestamps: FromTo { from: Duration::from_secs(from), to: Duration::from_secs(to) },
+                text: format!(" {}", text),
+            })
+            .collect();
+        (1, Transcript { transcription }).into()
+    }
+
+    fn starts(p: &[Paragraph]) -> Vec<(u64, Vec<u64>)> {
+        p.iter().map(|p| (p.start, p.lines.iter().map(|(s, _)| *s).collect())).collect()
+    }
+
+    #[test]
+    fn paragraphs_break_on_pauses() {
+        let t = transcript(&[(0, 5, "ciao"), (6, 10, "a tutti"), (12, 15, "dopo una pausa"), (16, 20, "fine")]);
+        let p = paragraphs(&t);
+        assert_eq!(starts(&p), vec![(0, vec![0, 6]), (12, vec![12, 16])]);
+        assert_eq!(p[0].lines[1].1.trim(), "a tutti");
+    }
+
+    #[test]
+    fn paragraphs_break_after_a_minute() {
+        let segments: Vec<(u64, u64, &str)> = (0..30).map(|i| (i * 5, i * 5 + 5, "parola")).collect();
+        let t = transcript(&segments);
+        let p = paragraphs(&t);
+        assert_eq!(p.iter().map(|p| p.start).collect::<Vec<_>>(), vec![0, 60, 120]);
+        assert_eq!(p.iter().map(|p| p.lines.len()).sum::<usize>(), 30);
+        assert!(paragraphs(&transcript(&[])).is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use axum::{body::{to_bytes, Body}, http::{header, Request, StatusCode}, Router};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use power_pizza_bot::api::{self, API_KEY_HEADER, DEFAULT_LIMIT, MAX_LIMIT};
+use power_pizza_bot::config::{ApiConfig, ApiKeyConfig};
+use power_pizza_bot::db::{MemoryDatabase, Store};
+
+mod common;
+use common::{episode, transcript};
+
+const KEY: &str = "segreta";
+
+/// The API on `episodes` episodes, the first two with a transcript, and a key allowing `per_minute` requests.
+async fn app(episodes: u32, per_minute: u32) -> Router {
+    let db = MemoryDatabase::new();
+    let s: &dyn Store = &db;
+    s.insert_stateless(&(1..=episodes).map(|id| episode(id, &format!("Puntata {}", id))).collect::<Vec<_>>()).await.unwrap();
+    s.update_one_stateless(1, &transcript(1, &["oggi parliamo di pizza", "la pizza con l'ananas"])).await.unwrap();
+    s.update_one_stateless(2, &transcript(2, &["giochiamo alla console"])).await.unwrap();
+    let config = ApiConfig {
+        keys: vec![ApiKeyConfig { name: "test".to_owned(), key: KEY.to_owned(), per_minute }],
+        ..Default::default()
+    };
+    api::router(Arc::new(db), &config)
+}
+
+async fn get(app: &Router, uri: &str, key: Option<&str>) -> (StatusCode, Value) {
+    let mut req = Request::get(uri);
+    if let Some(k) = key {
+        req = req.header(API_KEY_HEADER, k);
+    }
+    let res = app.clone().oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+    let status = res.status();
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn ids(page: &Value) -> Vec<u64> {
+    page["items"].as_array().unwrap().iter().map(|e| e["id"].as_u64().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn requests_need_a_valid_key() {
+    let app = app(3, 100).await;
+    let (status, body) = get(&app, "/v1/episodes", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["error"].as_str().unwrap().contains("API key"));
+    assert_eq!(get(&app, "/v1/episodes", Some("sbagliata")).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(get(&app, "/v1/episodes", Some(KEY)).await.0, StatusCode::OK);
+    // the documentation is public
+    assert_eq!(get(&app, "/openapi.json", None).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn requests_over_quota_are_limited() {
+    let app = app(3, 2).await;
+    for _ in 0..2 {
+        assert_eq!(get(&app, "/v1/episodes", Some(KEY)).await.0, StatusCode::OK);
+    }
+    let res = app
+        .clone()
+        .oneshot(Request::get("/v1/episodes").header(API_KEY_HEADER, KEY).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry: u64 = res.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry), "retry after {}", retry);
+}
+
+#[tokio::test]
+async fn pages_are_clamped() {
+    let app = app(150, 100).await;
+    let (_, page) = get(&app, "/v1/episodes", Some(KEY)).await;
+    assert_eq!((page["total"].as_u64(), page["limit"].as_u64()), (Some(150), Some(DEFAULT_LIMIT as u64)));
+    // most recent first
+    assert_eq!(ids(&page)[..3], [150, 149, 148]);
+
+    let (_, page) = get(&app, "/v1/episodes?limit=1000", Some(KEY)).await;
+    assert_eq!(page["limit"].as_u64(), Some(MAX_LIMIT as u64));
+    assert_eq!(ids(&page).len(), MAX_LIMIT);
+    let (_, page) = get(&app, "/v1/episodes?limit=0&offset=10", Some(KEY)).await;
+    assert_eq!(ids(&page), vec![140]);
+    let (_, page) = get(&app, "/v1/episodes?offset=200", Some(KEY)).await;
+    assert!(ids(&page).is_empty());
+    assert_eq!(page["total"].as_u64(), Some(150));
+}
+
+#[tokio::test]
+async fn unknown_episodes_are_not_found() {
+    let app = app(3, 100).await;
+    assert_eq!(get(&app, "/v1/episodes/2", Some(KEY)).await.1["title"], "Puntata 2");
+    for uri in ["/v1/episodes/99", "/v1/episodes/99/transcript", "/v1/episodes/3/transcript", "/v1/episodes/99/search?q=pizza"] {
+        let (status, body) = get(&app, uri, Some(KEY)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+        assert!(body["error"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn no_results_is_an_empty_page() {
+    let app = app(3, 100).await;
+    let (status, page) = get(&app, "/v1/search/transcripts?q=sushi", Some(KEY)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((page["total"].as_u64(), page["items"].as_array().map(Vec::len)), (Some(0), Some(0)));
+    let (status, page) = get(&app, "/v1/search/meta?q=sushi", Some(KEY)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["total"].as_u64(), Some(0));
+    // an episode without matches is returned with no matches
+    let (status, r) = get(&app, "/v1/episodes/2/search?q=pizza", Some(KEY)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(r["episode"]["id"].as_u64(), Some(2));
+    assert_eq!(r["matches"]["total"].as_u64(), Some(0));
+
+    let (status, page) = get(&app, "/v1/search/transcripts?q=pizza", Some(KEY)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ids(&page), vec![1]);
+    assert_eq!(get(&app, "/v1/search/meta?q=pi", Some(KEY)).await.0, StatusCode::BAD_REQUEST);
+}
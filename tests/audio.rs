@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use power_pizza_bot::audio::{self, SAMPLE_RATE};
+
+/// Directory removed when the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ppp-audio-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write `seconds` of a 440 Hz sine, 16 bit stereo at `rate`, the right channel at half the volume.
+fn sine(path: &Path, rate: u32, seconds: u32) {
+    let spec = WavSpec { channels: 2, sample_rate: rate, bits_per_sample: 16, sample_format: SampleFormat::Int };
+    let mut w = WavWriter::create(path, spec).unwrap();
+    for i in 0..rate * seconds {
+        let s = (i as f32 * 440. * std::f32::consts::TAU / rate as f32).sin() * 0.5;
+        w.write_sample((s * i16::MAX as f32) as i16).unwrap();
+        w.write_sample((s * 0.5 * i16::MAX as f32) as i16).unwrap();
+    }
+    w.finalize().unwrap();
+}
+
+#[test]
+fn decode_resamples_to_16khz_mono() {
+    let dir = TempDir::new("resample");
+    let (input, output) = (dir.0.join("in.wav"), dir.0.join("out.wav"));
+    sine(&input, 44100, 2);
+
+    audio::decode(&input, &output).unwrap();
+    let mut r = WavReader::open(&output).unwrap();
+    let spec = r.spec();
+    assert_eq!((spec.channels, spec.sample_rate, spec.bits_per_sample), (1, SAMPLE_RATE, 16));
+    assert_eq!(r.duration(), 2 * SAMPLE_RATE);
+    // the channels are mixed: the peak is the mean of the two, 0.375
+    let peak = r.samples::<i16>().map(|s| s.unwrap().unsigned_abs()).max().unwrap() as f32 / i16::MAX as f32;
+    assert!((peak - 0.375).abs() < 0.02, "peak {}", peak);
+    assert!(!dir.0.join("out.wav.part").exists());
+}
+
+#[test]
+fn decode_keeps_16khz_audio_as_is() {
+    let dir = TempDir::new("same");
+    let (input, output) = (dir.0.join("in.wav"), dir.0.join("out.wav"));
+    sine(&input, SAMPLE_RATE, 1);
+
+    audio::decode(&input, &output).unwrap();
+    assert_eq!(WavReader::open(&output).unwrap().duration(), SAMPLE_RATE);
+}
+
+#[test]
+fn decode_errors_leave_no_files() {
+    let dir = TempDir::new("corrupt");
+    let (input, output) = (dir.0.join("in.mp3"), dir.0.join("out.wav"));
+    std::fs::write(&input, b"not audio at all").unwrap();
+    // left by an earlier conversion
+    std::fs::write(dir.0.join("out.wav.part"), b"stale").unwrap();
+
+    assert!(audio::decode(&input, &output).is_err());
+    assert!(!output.exists());
+    assert!(!dir.0.join("out.wav.part").exists());
+}
@@ -0,0 +1,40 @@
+use chrono::Utc;
+
+use power_pizza_bot::db::{backup, MemoryDatabase, Store, COLLECTIONS};
+use power_pizza_bot::migrations::{self, AppliedMigration};
+use power_pizza_bot::spreaker::Episode;
+
+mod common;
+use common::{episode, transcript};
+
+fn applied(ids: std::ops::RangeInclusive<u32>) -> Vec<AppliedMigration> {
+    ids.map(|id| AppliedMigration { id, name: format!("migration {}", id), applied_at: Utc::now() }).collect()
+}
+
+#[tokio::test]
+async fn restore_replaces_the_migrations_and_merges_the_rest() {
+    let path = std::env::temp_dir().join(format!("ppp-backup-{}.tar", std::process::id()));
+    let old = MemoryDatabase::new();
+    let old: &dyn Store = &old;
+    old.insert_stateless(&applied(1..=2)).await.unwrap();
+    old.insert_stateless(&[episode(1, "Prima")]).await.unwrap();
+    old.update_one_stateless(1, &transcript(1, &["pizza all'ananas"])).await.unwrap();
+    backup::export(old, &path, &COLLECTIONS).await.unwrap();
+
+    let db = MemoryDatabase::new();
+    let db: &dyn Store = &db;
+    db.insert_stateless(&applied(1..=5)).await.unwrap();
+    db.insert_stateless(&[episode(1, "Vecchia"), episode(2, "Seconda")]).await.unwrap();
+    let manifest = backup::restore(db, &path, &COLLECTIONS).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(manifest.schema, 2);
+    assert_eq!(migrations::version(db).await.unwrap(), 2);
+    let mut episodes = db.get_all::<Episode>().await.unwrap();
+    episodes.sort_by_key(|e| e.id);
+    assert_eq!(episodes.iter().map(|e| e.title.as_str()).collect::<Vec<_>>(), vec!["Prima", "Seconda"]);
+
+    // the fuzzy index is rebuilt at the end of the restore
+    let r = db.search_transcript_fuzzy("anannas".to_owned()).await.unwrap();
+    assert_eq!(r.iter().map(|r| r.episode.id).collect::<Vec<_>>(), vec![1]);
+}
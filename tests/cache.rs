@@ -0,0 +1,116 @@
+use std::{path::PathBuf, time::Duration};
+
+use power_pizza_bot::db::{MemoryDatabase, Store};
+use power_pizza_bot::status::{ImportRun, ImportSource, ImportStage, ImportVersions};
+use power_pizza_bot::transcript::{cache::{self, CacheError}, EpisodeTranscript};
+
+mod common;
+use common::episode;
+
+/// Transcript cache directory removed when the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ppp-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn dir(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Written by the importer.
+const GOOD: &str = r#"{"transcription": [{"offsets": {"from": 0, "to": 1500}, "text": " ciao a tutti"}, {"offsets": {"from": 1500, "to": 3000}, "text": " oggi pizza"}]}"#;
+/// Raw response of the transcription service, with times in seconds.
+const RAW: &str = r#"{"segments": [{"start": 0.5, "end": 1.25, "text": " buonasera"}]}"#;
+
+/// A cache with: good transcripts of episodes 1 and 2, corrupt ones of 3, 4 and 5, the orphan transcript of episode
+/// 9, a `.json` file that isn't named after an episode and files that aren't transcripts.
+fn cache(name: &str) -> TempDir {
+    let dir = TempDir::new(name);
+    dir.write("1.json", GOOD);
+    dir.write("2.json", RAW);
+    dir.write("3.json", "{\"transcription\": [");
+    dir.write("4.json", r#"{"transcription": [{"offsets": {"from": 2000, "to": 1000}, "text": " al contrario"}]}"#);
+    dir.write("5.json", r#"{"transcription": []}"#);
+    dir.write("9.json", GOOD);
+    dir.write("appunti.json", GOOD);
+    dir.write("1.txt", "non un transcript");
+    std::fs::create_dir(dir.0.join("6.json")).unwrap();
+    dir
+}
+
+#[test]
+fn load_cached_reads_both_formats() {
+    let dir = TempDir::new("load");
+    let t = cache::load_cached(&dir.write("1.json", GOOD)).unwrap();
+    assert_eq!(t.transcription.len(), 2);
+    assert_eq!(t.transcription[1].timestamps.from, Duration::from_millis(1500));
+
+    let t = cache::load_cached(&dir.write("2.json", RAW)).unwrap();
+    assert_eq!(t.transcription.len(), 1);
+    assert_eq!((t.transcription[0].timestamps.from, t.transcription[0].timestamps.to), (Duration::from_millis(500), Duration::from_millis(1250)));
+    assert_eq!(t.transcription[0].text, " buonasera");
+}
+
+#[test]
+fn load_cached_rejects_corrupt_transcripts() {
+    let dir = TempDir::new("corrupt");
+    assert!(matches!(cache::load_cached(&dir.write("1.json", "{\"transcription\": [")), Err(CacheError::Parse(_))));
+    assert!(matches!(cache::load_cached(&dir.write("2.json", r#"{"transcription": []}"#)), Err(CacheError::Invalid(_))));
+    let backwards = r#"{"transcription": [{"offsets": {"from": 0, "to": 1000}, "text": " a"}, {"offsets": {"from": 2000, "to": 1000}, "text": " b"}]}"#;
+    match cache::load_cached(&dir.write("3.json", backwards)) {
+        Err(CacheError::Invalid(e)) => assert!(e.contains("segment 1"), "{}", e),
+        r => panic!("{:?}", r),
+    }
+    assert!(matches!(cache::load_cached(&dir.0.join("missing.json")), Err(CacheError::Io(_))));
+}
+
+#[test]
+fn scan_finds_transcripts_and_invalid_names() {
+    let dir = cache("scan");
+    let scan = cache::scan(dir.dir()).unwrap();
+    assert_eq!(scan.entries.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 9]);
+    assert_eq!(scan.entries[0].1, dir.0.join("1.json"));
+    assert_eq!(scan.invalid_names, vec![dir.0.join("appunti.json")]);
+    assert!(cache::scan(dir.0.join("missing").to_str().unwrap()).is_err());
+}
+
+#[tokio::test]
+async fn rebuild_inserts_the_good_transcripts() {
+    let dir = cache("rebuild");
+    let db = MemoryDatabase::new();
+    let db: &dyn Store = &db;
+    db.insert_stateless(&(1..=5).map(|id| episode(id, "Puntata")).collect::<Vec<_>>()).await.unwrap();
+    let run = ImportRun::start(ImportSource::Cache, ImportVersions::default()).handle();
+
+    let report = cache::rebuild(db, dir.dir(), None, &run).await.unwrap();
+    assert_eq!(report.inserted, vec![1, 2]);
+    assert_eq!(report.orphans, vec![dir.0.join("9.json")]);
+    assert_eq!(report.corrupt.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>(), vec![dir.0.join("3.json"), dir.0.join("4.json"), dir.0.join("5.json")]);
+    assert_eq!(report.invalid_names, vec![dir.0.join("appunti.json")]);
+
+    let mut ids = db.get_ids::<EpisodeTranscript>().await.unwrap();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2]);
+    assert_eq!(db.get::<EpisodeTranscript>(1).await.unwrap().unwrap().segments().len(), 2);
+    let run = run.lock().unwrap();
+    assert_eq!((run.discovered, run.transcripts_inserted), (5, 2));
+    assert_eq!(run.failures.iter().map(|f| (f.episode_id, f.stage)).collect::<Vec<_>>(), vec![(Some(3), ImportStage::Convert), (Some(4), ImportStage::Convert), (Some(5), ImportStage::Convert)]);
+}
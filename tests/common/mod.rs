@@ -0,0 +1,44 @@
+//! Data shared by the tests running against `MemoryDatabase`.
+#![allow(dead_code)]
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+
+use power_pizza_bot::spreaker::{Episode, PlayCounts};
+use power_pizza_bot::transcript::{EpisodeTranscript, FromTo, Segment, Transcript};
+
+pub const SHOW_ID: u32 = 1;
+
+/// Episode `id` of the show, published on day `id` of 2020.
+pub fn episode(id: u32, title: &str) -> Episode {
+    Episode {
+        id,
+        title: title.to_owned(),
+        duration: 3_600_000,
+        show_id: SHOW_ID,
+        author_id: 1,
+        published_at: Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::days(id as i64),
+        download_url: format!("https://example.com/{}.mp3", id),
+        description: format!("descrizione di {}", title),
+        description_html: String::new(),
+        image_url: None,
+        image_original_url: None,
+        site_url: None,
+        tags: vec![],
+        explicit: false,
+        plays: PlayCounts::default(),
+        chapters: vec![],
+        topics: vec![],
+    }
+}
+
+/// Transcript of episode `id` made of `segments`, the i-th one said at second i. Like the ones of the transcription
+/// service, each segment starts with a space.
+pub fn transcript(id: u32, segments: &[&str]) -> EpisodeTranscript {
+    let transcription = segments
+        .iter()
+        .enumerate()
+        .map(|(i, text)| Segment { timestamps: FromTo { from: Duration::from_secs(i as u64), to: Duration::from_secs(i as u64 + 1) }, text: format!(" {}", text) })
+        .collect();
+    EpisodeTranscript::from((id, Transcript { transcription }))
+}
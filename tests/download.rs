@@ -0,0 +1,135 @@
+use std::{path::PathBuf, sync::{Arc, Mutex}};
+
+use axum::{extract::State, http::{header, HeaderMap, Method, StatusCode}, response::{IntoResponse, Response}, routing::get, Router};
+
+use power_pizza_bot::spreaker::{download_file, DownloadOutcome};
+
+const ETAG: &str = "\"v1\"";
+
+/// Range and If-Range headers of the GET requests received.
+type Requests = Arc<Mutex<Vec<(Option<String>, Option<String>)>>>;
+
+fn body() -> Vec<u8> {
+    (0..10_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+async fn audio(State(requests): State<Requests>, method: Method, headers: HeaderMap) -> Response {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(String::from);
+    let (range, if_range) = (header(header::RANGE), header(header::IF_RANGE));
+    if method == Method::GET {
+        requests.lock().unwrap().push((range.clone(), if_range.clone()));
+    }
+    let body = body();
+    let start = range.and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok());
+    match start {
+        Some(start) if if_range.as_deref().is_none_or(|e| e == ETAG) => (
+            StatusCode::PARTIAL_CONTENT,
+            [(header::ETAG, ETAG.to_owned()), (header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, body.len() - 1, body.len()))],
+            body[start..].to_vec(),
+        ).into_response(),
+        _ => ([(header::ETAG, ETAG)], body).into_response(),
+    }
+}
+
+/// Serve `body()` at `/audio.mp3`, and at `/nohead.mp3` refusing HEAD requests.
+async fn server() -> (String, Requests) {
+    let requests = Requests::default();
+    let app = Router::new()
+        .route("/audio.mp3", get(audio))
+        .route("/nohead.mp3", get(audio).head(|| async { StatusCode::METHOD_NOT_ALLOWED }))
+        .with_state(requests.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}", addr), requests)
+}
+
+/// Directory removed when the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ppp-download-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn download_then_already_present() {
+    let (url, requests) = server().await;
+    let dir = TempDir::new("fresh");
+    let output = dir.0.join("1.mp3");
+    let cli = reqwest::Client::new();
+
+    let r = download_file(&cli, &format!("{}/audio.mp3", url), &output).await.unwrap();
+    assert_eq!(r, DownloadOutcome::Downloaded(body().len() as u64));
+    assert_eq!(std::fs::read(&output).unwrap(), body());
+    assert!(!dir.0.join("1.mp3.part").exists());
+    assert_eq!(std::fs::read_to_string(dir.0.join("1.mp3.etag")).unwrap(), ETAG);
+
+    let r = download_file(&cli, &format!("{}/audio.mp3", url), &output).await.unwrap();
+    assert_eq!(r, DownloadOutcome::AlreadyPresent);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn resume_a_partial_download_of_the_same_resource() {
+    let (url, requests) = server().await;
+    let dir = TempDir::new("resume");
+    let output = dir.0.join("1.mp3");
+    std::fs::write(dir.0.join("1.mp3.part"), &body()[..4000]).unwrap();
+    std::fs::write(dir.0.join("1.mp3.etag"), ETAG).unwrap();
+
+    let r = download_file(&reqwest::Client::new(), &format!("{}/audio.mp3", url), &output).await.unwrap();
+    assert_eq!(r, DownloadOutcome::Downloaded(body().len() as u64));
+    assert_eq!(std::fs::read(&output).unwrap(), body());
+    assert_eq!(*requests.lock().unwrap(), vec![(Some("bytes=4000-".to_owned()), Some(ETAG.to_owned()))]);
+}
+
+#[tokio::test]
+async fn discard_a_partial_download_of_another_resource() {
+    let (url, requests) = server().await;
+    let dir = TempDir::new("stale");
+    let output = dir.0.join("1.mp3");
+    std::fs::write(dir.0.join("1.mp3.part"), [0u8; 4000]).unwrap();
+    std::fs::write(dir.0.join("1.mp3.etag"), "\"v0\"").unwrap();
+
+    download_file(&reqwest::Client::new(), &format!("{}/audio.mp3", url), &output).await.unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), body());
+    assert_eq!(*requests.lock().unwrap(), vec![(None, None)]);
+}
+
+#[tokio::test]
+async fn download_again_a_truncated_file() {
+    let (url, _) = server().await;
+    let dir = TempDir::new("truncated");
+    let output = dir.0.join("1.mp3");
+    // left by a version writing straight to the final name
+    std::fs::write(&output, &body()[..4000]).unwrap();
+
+    let r = download_file(&reqwest::Client::new(), &format!("{}/audio.mp3", url), &output).await.unwrap();
+    assert_eq!(r, DownloadOutcome::Downloaded(body().len() as u64));
+    assert_eq!(std::fs::read(&output).unwrap(), body());
+}
+
+#[tokio::test]
+async fn without_head_an_existing_file_is_downloaded_again() {
+    let (url, requests) = server().await;
+    let dir = TempDir::new("nohead");
+    let output = dir.0.join("1.mp3");
+    std::fs::write(&output, &body()[..4000]).unwrap();
+
+    let r = download_file(&reqwest::Client::new(), &format!("{}/nohead.mp3", url), &output).await.unwrap();
+    assert_eq!(r, DownloadOutcome::Downloaded(body().len() as u64));
+    assert_eq!(std::fs::read(&output).unwrap(), body());
+    // a plain GET, without the ETag nothing can be resumed
+    assert_eq!(*requests.lock().unwrap(), vec![(None, None)]);
+}
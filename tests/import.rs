@@ -0,0 +1,132 @@
+use std::{collections::HashSet, sync::Arc};
+
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream};
+
+use power_pizza_bot::db::{MemoryDatabase, Store};
+use power_pizza_bot::db::topics::{Topic, TopicKind};
+use power_pizza_bot::import::{import_from, refresh_metadata};
+use power_pizza_bot::spreaker::{Episode, Show, ShowSource, SimpleEpisode, SpreakerError};
+use power_pizza_bot::status::{ImportRun, ImportRunHandle, ImportSource, ImportStage, ImportVersions};
+
+mod common;
+use common::{episode, SHOW_ID};
+
+/// The show of the tests, with episodes `1..=episodes` of which the ones in `failing` can't be fetched.
+struct FakeSource {
+    episodes: u32,
+    failing: HashSet<u32>,
+}
+
+#[async_trait]
+impl ShowSource for FakeSource {
+    async fn show(&self, id: u32) -> Result<Show, SpreakerError> {
+        Ok(Show {
+            id,
+            title: "Power Pizza".to_owned(),
+            description: String::new(),
+            author_id: 1,
+            author: None,
+            image_url: None,
+            image_original_url: None,
+            site_url: None,
+            website_url: None,
+            explicit: false,
+        })
+    }
+
+    fn episodes(&self, _show_id: u32) -> BoxStream<'static, SimpleEpisode> {
+        let episodes: Vec<SimpleEpisode> = (1..=self.episodes)
+            .rev()
+            .map(|id| SimpleEpisode { id, download_url: episode(id, "").download_url, title: format!("Puntata {}", id), remaining: Default::default() })
+            .collect();
+        Box::pin(stream::iter(episodes))
+    }
+
+    async fn episode(&self, e: &SimpleEpisode) -> Result<Episode, SpreakerError> {
+        if self.failing.contains(&e.id) {
+            return Err(SpreakerError::InvalidResponse("episode not found"));
+        }
+        let mut episode = episode(e.id, &e.title);
+        episode.tags = vec!["pizza".to_owned()];
+        Ok(episode)
+    }
+}
+
+fn run() -> ImportRunHandle {
+    ImportRun::start(ImportSource::Spreaker, ImportVersions::default()).handle()
+}
+
+async fn import(db: &dyn Store, episodes: u32, failing: &[u32]) -> ImportRun {
+    let source = Arc::new(FakeSource { episodes, failing: failing.iter().copied().collect() });
+    let run = run();
+    import_from(db, source, SHOW_ID, &run).await.unwrap();
+    let run = run.lock().unwrap().clone();
+    run
+}
+
+async fn episode_ids(db: &dyn Store) -> Vec<u32> {
+    let mut ids = db.get_ids::<Episode>().await.unwrap();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn first_import_fetches_every_episode() {
+    let db: &dyn Store = &MemoryDatabase::new();
+    let run = import(db, 25, &[]).await;
+
+    assert_eq!(run.discovered, 25);
+    assert_eq!(run.inserted, 25);
+    assert!(run.failures.is_empty());
+    assert_eq!(episode_ids(db).await, (1..=25).collect::<Vec<_>>());
+    assert_eq!(db.get::<Show>(SHOW_ID).await.unwrap().unwrap().title, "Power Pizza");
+}
+
+#[tokio::test]
+async fn later_imports_stop_at_the_last_known_episode() {
+    let db: &dyn Store = &MemoryDatabase::new();
+    import(db, 10, &[]).await;
+    let run = import(db, 13, &[]).await;
+
+    assert_eq!(run.discovered, 3);
+    assert_eq!(run.inserted, 3);
+    assert_eq!(episode_ids(db).await, (1..=13).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn episodes_that_cant_be_fetched_are_recorded() {
+    let db: &dyn Store = &MemoryDatabase::new();
+    let run = import(db, 5, &[2]).await;
+
+    assert_eq!(run.discovered, 5);
+    assert_eq!(run.inserted, 4);
+    assert_eq!(run.failures.len(), 1);
+    assert_eq!(run.failures[0].episode_id, Some(2));
+    assert_eq!(run.failures[0].stage, ImportStage::Metadata);
+    assert_eq!(episode_ids(db).await, vec![1, 3, 4, 5]);
+
+    let run = import(db, 7, &[6]).await;
+    assert_eq!(run.discovered, 2);
+    assert_eq!(run.inserted, 1);
+    assert_eq!(run.failures[0].episode_id, Some(6));
+}
+
+#[tokio::test]
+async fn refresh_metadata_fills_the_new_fields_and_keeps_the_topics() {
+    let db: &dyn Store = &MemoryDatabase::new();
+    let mut old = episode(1, "Vecchio titolo");
+    old.topics = vec![Topic { name: "Zelda".to_owned(), kind: TopicKind::Entity }];
+    db.insert_stateless(&[old, episode(2, "Puntata 2"), episode(9, "Rimossa")]).await.unwrap();
+
+    let source = Arc::new(FakeSource { episodes: 3, failing: [2].into() });
+    assert_eq!(refresh_metadata(db, source, SHOW_ID).await.unwrap(), (1, 1));
+
+    let e = db.get::<Episode>(1).await.unwrap().unwrap();
+    assert_eq!(e.title, "Puntata 1");
+    assert_eq!(e.tags, vec!["pizza"]);
+    assert_eq!(e.topics[0].name, "Zelda");
+    assert!(db.get::<Episode>(2).await.unwrap().unwrap().tags.is_empty());
+    // episodes missing from the database aren't added
+    assert_eq!(episode_ids(db).await, vec![1, 2, 9]);
+}
@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use chrono::Utc;
+
+use power_pizza_bot::bot::{BotUser, SearchError};
+use power_pizza_bot::db::{fuzzy::{self, IndexedWord, WordKey}, similarity::{self, EpisodeVector}, trends::{self, TermTrend}, MemoryDatabase, Store};
+use power_pizza_bot::spreaker::Episode;
+
+mod common;
+use common::{episode, transcript};
+
+/// Three episodes, the first two with a transcript.
+async fn store() -> MemoryDatabase {
+    let db = MemoryDatabase::new();
+    let s: &dyn Store = &db;
+    let mut pizza = episode(1, "Pizza all'ananas");
+    pizza.tags = vec!["Cucina".to_owned()];
+    s.insert_stateless(&[pizza, episode(2, "Videogiochi e console"), episode(3, "Speciale Natale")]).await.unwrap();
+    s.update_one_stateless(1, &transcript(1, &["oggi parliamo di pizza", "la pizza con l'ananas è buonissima", "fine"])).await.unwrap();
+    s.update_one_stateless(2, &transcript(2, &["giochiamo alla console", "la pizza la mangiamo dopo"])).await.unwrap();
+    db
+}
+
+fn user(id: i64, beta: bool, waitlist: bool) -> BotUser {
+    BotUser { id, username: None, first_name: format!("utente {}", id), beta, waitlist, timestamp: Utc::now() }
+}
+
+fn ids<T>(r: &[T], id: impl Fn(&T) -> u32) -> Vec<u32> {
+    r.iter().map(id).collect()
+}
+
+#[tokio::test]
+async fn search_meta_matches_titles_and_filters() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+
+    let r = db.search_meta("ananas".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id), vec![1]);
+    let r = db.search_meta("descrizione tag:cucina".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id), vec![1]);
+    assert!(matches!(db.search_meta("tag:sport".to_owned()).await, Err(SearchError::NoResults)));
+}
+
+#[tokio::test]
+async fn magic_search_by_id_and_title() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+
+    assert_eq!(db.magic_episode_search("12345".to_owned()).await.unwrap(), 12345);
+    assert_eq!(db.magic_episode_search("natale".to_owned()).await.unwrap(), 3);
+    assert!(matches!(db.magic_episode_search("pasqua".to_owned()).await, Err(SearchError::NoResults)));
+}
+
+#[tokio::test]
+async fn search_transcript_all_ranks_by_occurrences() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+
+    let r = db.search_transcript_all("pizza".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id), vec![1, 2]);
+    assert_eq!(r[0].hits, 2);
+    assert_eq!(r[1].hits, 1);
+    let r = db.search_transcript_all("pizza -console".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id), vec![1]);
+    assert!(matches!(db.search_transcript_all("natale".to_owned()).await, Err(SearchError::NoResults)));
+}
+
+#[tokio::test]
+async fn search_transcript_one_finds_the_segments() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+
+    let r = db.search_transcript_one(1, "ananas".to_owned()).await.unwrap();
+    assert_eq!(r.len(), 1);
+    assert_eq!(r.matches[0].time.from, Duration::from_secs(1));
+    assert!(matches!(db.search_transcript_one(3, "pizza".to_owned()).await, Err(SearchError::EpisodeNotFound(3))));
+    assert!(matches!(db.search_transcript_one(2, "ananas".to_owned()).await, Err(SearchError::NoResults)));
+}
+
+#[tokio::test]
+async fn search_transcript_fuzzy_tolerates_typos() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+
+    let r = db.search_transcript_fuzzy("anannas".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id), vec![1]);
+    assert_eq!(r[0].words, vec!["ananas"]);
+}
+
+#[tokio::test]
+async fn rewritten_transcripts_leave_the_fuzzy_index() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+    db.update_one_stateless(1, &transcript(1, &["oggi parliamo di pizza margherita"])).await.unwrap();
+
+    assert!(matches!(db.search_transcript_fuzzy("anannas".to_owned()).await, Err(SearchError::NoResults)));
+    let r = db.search_transcript_fuzzy("margerita".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id), vec![1]);
+    let r = db.search_transcript_fuzzy("pizza".to_owned()).await.unwrap();
+    assert_eq!(ids(&r, |r| r.episode.id).len(), 2);
+}
+
+/// Words and keys of the fuzzy index, sorted.
+async fn fuzzy_index(db: &dyn Store) -> (Vec<(String, Vec<(u32, u32)>)>, Vec<(String, Vec<String>)>) {
+    let mut words: Vec<_> = db
+        .get_all::<IndexedWord>()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|w| !w.episodes.is_empty())
+        .map(|w| {
+            let mut e: Vec<_> = w.episodes.iter().map(|c| (c.episode_id, c.count)).collect();
+            e.sort();
+            (w.word, e)
+        })
+        .collect();
+    words.sort();
+    let mut keys: Vec<_> = db
+        .get_all::<WordKey>()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|mut k| {
+            k.words.sort();
+            (k.key, k.words)
+        })
+        .collect();
+    keys.sort();
+    (words, keys)
+}
+
+#[tokio::test]
+async fn fuzzy_index_updates_match_a_rebuild() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+    // a new transcript sharing words with the others, and one written again
+    db.update_one_stateless(3, &transcript(3, &["a natale la pizza e la console"])).await.unwrap();
+    db.update_one_stateless(2, &transcript(2, &["giochiamo alla console", "niente pizza"])).await.unwrap();
+
+    let updated = fuzzy_index(db).await;
+    fuzzy::rebuild(db).await.unwrap();
+    assert_eq!(updated, fuzzy_index(db).await);
+    assert_eq!(updated.0.iter().find(|(w, _)| w == "pizza").unwrap().1, vec![(1, 2), (2, 1), (3, 1)]);
+}
+
+#[tokio::test]
+async fn trends_are_cached_until_a_transcript_changes() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+    let t = trends::trend(db, "Pizza").await.unwrap().unwrap();
+    assert_eq!((t.term.as_str(), t.total()), ("pizza", 3));
+    trends::trend(db, "console").await.unwrap();
+    assert!(trends::trend(db, "...").await.unwrap().is_none());
+    assert_eq!(trends::prune(db).await.unwrap(), 0);
+    assert_eq!(db.get_all::<TermTrend>().await.unwrap().len(), 2);
+
+    db.update_one_stateless(3, &transcript(3, &["pizza a natale"])).await.unwrap();
+    // a stale count isn't used, and is pruned
+    assert_eq!(trends::trend(db, "pizza").await.unwrap().unwrap().total(), 4);
+    assert_eq!(trends::prune(db).await.unwrap(), 1);
+    let cached: Vec<String> = db.get_all::<TermTrend>().await.unwrap().into_iter().map(|t| t.term).collect();
+    assert_eq!(cached, vec!["pizza"]);
+}
+
+#[tokio::test]
+async fn users_by_status() {
+    let db = MemoryDatabase::new();
+    let db: &dyn Store = &db;
+    db.insert_stateless(&[user(1, true, false), user(2, false, true), user(3, false, false)]).await.unwrap();
+
+    assert!(db.whitelisted(1).await.unwrap());
+    assert!(!db.whitelisted(2).await.unwrap());
+    assert!(!db.whitelisted(4).await.unwrap());
+    assert_eq!(db.waitlist().await.unwrap().iter().map(|u| u.id).collect::<Vec<_>>(), vec![2]);
+    assert_eq!(db.beta_list().await.unwrap().iter().map(|u| u.id).collect::<Vec<_>>(), vec![1]);
+
+    let mut u = user(2, true, false);
+    u.username = Some("pizzaiolo".to_owned());
+    db.update_one_stateless(2, &u).await.unwrap();
+    assert!(db.whitelisted(2).await.unwrap());
+    assert!(db.waitlist().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn locks_have_a_single_owner() {
+    let db = MemoryDatabase::new();
+    let db: &dyn Store = &db;
+    let expires = Utc::now() + chrono::Duration::minutes(1);
+
+    assert!(db.acquire_lock("import", "a", expires).await.unwrap());
+    assert!(!db.acquire_lock("import", "b", expires).await.unwrap());
+    assert!(db.acquire_lock("import", "a", expires).await.unwrap());
+    db.release_lock("import", "a").await.unwrap();
+    assert!(db.acquire_lock("import", "b", expires).await.unwrap());
+    assert!(db.acquire_lock("import", "a", Utc::now()).await.is_ok_and(|ok| !ok));
+}
+
+#[tokio::test]
+async fn similarity_rebuild_replaces_the_vectors() {
+    let db = store().await;
+    let db: &dyn Store = &db;
+    db.insert_stateless(&[episode(4, "Pizza al taglio")]).await.unwrap();
+    similarity::rebuild(db).await.unwrap();
+    assert_eq!(db.get_ids::<EpisodeVector>().await.unwrap().len(), 4);
+    let r = db.search_similar(1, 5).await.unwrap();
+    assert_eq!(r[0].episode.id, 4);
+
+    db.delete::<Episode>(4).await.unwrap();
+    similarity::rebuild(db).await.unwrap();
+    let mut ids = db.get_ids::<EpisodeVector>().await.unwrap();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3]);
+}
@@ -0,0 +1,100 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{TimeZone, Utc};
+use mongodb::bson::{self, doc, Document};
+
+use power_pizza_bot::bot::BotUser;
+use power_pizza_bot::db::{MemoryDatabase, PPPData, Store};
+use power_pizza_bot::migrations::{self, MIGRATIONS};
+use power_pizza_bot::spreaker::Episode;
+use power_pizza_bot::status::{ImportRun, ImportSource};
+
+mod common;
+use common::episode;
+
+/// A database as left by the versions before the migrations: users with the `notified` flag, `published_at` as a
+/// unix timestamp and several status documents.
+async fn legacy(db: &dyn Store) {
+    let user = |id: i64, notified: bool| {
+        let mut u = bson::to_document(&BotUser { id, username: None, first_name: "utente".to_owned(), beta: true, waitlist: false, timestamp: Utc::now() }).unwrap();
+        u.insert("notified", notified);
+        u
+    };
+    db.insert_docs(BotUser::COLLECTION, BotUser::ID_KEY, vec![user(1, true), user(2, false)]).await.unwrap();
+    let mut e = bson::to_document(&episode(1, "Prima")).unwrap();
+    e.insert("published_at", 1_600_000_000i64);
+    let mut e32 = bson::to_document(&episode(2, "Seconda")).unwrap();
+    e32.insert("published_at", 1_600_000_000i32);
+    db.insert_docs(Episode::COLLECTION, Episode::ID_KEY, vec![e, e32, bson::to_document(&episode(3, "Terza")).unwrap()]).await.unwrap();
+    let status: Vec<Document> = [1_500_000_000i64, 1_700_000_000, 1_600_000_000].iter().map(|t| doc!{"last_update": t}).collect();
+    db.insert_docs("status", "last_update", status).await.unwrap();
+}
+
+fn sorted(docs: Vec<Document>) -> Vec<String> {
+    let mut docs: Vec<String> = docs.iter().map(Document::to_string).collect();
+    docs.sort();
+    docs
+}
+
+#[tokio::test]
+async fn migrations_convert_legacy_documents_once() {
+    let db = MemoryDatabase::new();
+    let db: &dyn Store = &db;
+    legacy(db).await;
+
+    let applied = migrations::run(db).await.unwrap();
+    assert_eq!(applied, MIGRATIONS.iter().map(|m| m.id).collect::<Vec<_>>());
+    assert_eq!(migrations::version(db).await.unwrap(), 4);
+
+    let users = db.all_docs(BotUser::COLLECTION).await.unwrap();
+    assert_eq!(users.len(), 2);
+    assert!(users.iter().all(|u| !u.contains_key("notified")));
+    assert_eq!(db.get::<BotUser>(1).await.unwrap().unwrap().first_name, "utente");
+
+    let mut episodes = db.get_all::<Episode>().await.unwrap();
+    episodes.sort_by_key(|e| e.id);
+    let legacy = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+    assert_eq!(episodes.iter().map(|e| e.published_at).collect::<Vec<_>>(), vec![legacy, legacy, episode(3, "").published_at]);
+
+    // the most recent status is kept as a finished run, the status collection is gone
+    let runs = db.get_all::<ImportRun>().await.unwrap();
+    assert_eq!(runs.len(), 1);
+    let last = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+    assert_eq!((runs[0].source, runs[0].started_at, runs[0].finished_at), (ImportSource::Legacy, last, Some(last)));
+    assert!(db.all_docs("status").await.unwrap().is_empty());
+
+    // running them again changes nothing
+    let snapshot = |c: &'static str| async move { sorted(db.all_docs(c).await.unwrap()) };
+    let before = (snapshot(BotUser::COLLECTION).await, snapshot(Episode::COLLECTION).await, snapshot(ImportRun::COLLECTION).await);
+    assert!(migrations::run(db).await.unwrap().is_empty());
+    let after = (snapshot(BotUser::COLLECTION).await, snapshot(Episode::COLLECTION).await, snapshot(ImportRun::COLLECTION).await);
+    assert_eq!(before, after);
+    assert_eq!(migrations::version(db).await.unwrap(), 4);
+}
+
+#[tokio::test]
+async fn migrations_of_an_empty_database() {
+    let db = MemoryDatabase::new();
+    let db: &dyn Store = &db;
+    assert_eq!(migrations::run(db).await.unwrap().len(), MIGRATIONS.len());
+    assert!(db.get_all::<ImportRun>().await.unwrap().is_empty());
+    assert!(migrations::run(db).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn migrations_wait_for_the_lock() {
+    let db = Arc::new(MemoryDatabase::new());
+    assert!(db.acquire_lock("migrations", "other", Utc::now() + chrono::Duration::minutes(5)).await.unwrap());
+    let run = {
+        let db = db.clone();
+        tokio::spawn(async move { migrations::run(db.as_ref()).await.unwrap() })
+    };
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!run.is_finished());
+    assert_eq!(migrations::version(db.as_ref()).await.unwrap(), 0);
+
+    db.release_lock("migrations", "other").await.unwrap();
+    assert_eq!(run.await.unwrap().len(), MIGRATIONS.len());
+    // and release it when done
+    assert!(db.acquire_lock("migrations", "other", Utc::now() + chrono::Duration::minutes(5)).await.unwrap());
+}
@@ -0,0 +1,94 @@
+use std::{ops::Range, time::Duration};
+
+use proptest::prelude::*;
+use regex::RegexBuilder;
+
+use power_pizza_bot::bot::EpisodeOffsetMatch;
+use power_pizza_bot::normalize::{fold, normalize_pattern, Normalized};
+use power_pizza_bot::transcript::{EpisodeTranscript, FromTo, Segment, Transcript};
+
+/// Italian with accents and punctuation, plus some characters transliterated to several or no bytes.
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-zA-Z àèéìòùÀÈÉÌÒÙçñ'.,?!]{0,80}",
+        "[a-z æœßø北ё€\u{300}\u{301}\u{1F355}]{0,40}",
+        any::<String>(),
+    ]
+}
+
+fn chars(s: &str, r: Range<usize>) -> String {
+    s.chars().skip(r.start).take(r.len()).collect()
+}
+
+/// A transcript made of `segments`, the i-th one said at second i.
+fn transcript(segments: Vec<String>) -> EpisodeTranscript {
+    let transcription = segments
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| Segment { timestamps: FromTo { from: Duration::from_secs(i as u64), to: Duration::from_secs(i as u64 + 1) }, text })
+        .collect();
+    EpisodeTranscript::from((1, Transcript { transcription }))
+}
+
+proptest! {
+    #[test]
+    fn normalized_text_is_the_transliteration(s in text()) {
+        prop_assert_eq!(Normalized::new(&s).text.to_lowercase(), fold(&s));
+    }
+
+    #[test]
+    fn original_ranges_are_bounded_and_monotonic(s in text(), a in any::<prop::sample::Index>(), b in any::<prop::sample::Index>()) {
+        let n = Normalized::new(&s);
+        let len = n.text.len();
+        let (a, b) = if len == 0 { (0, 0) } else { (a.index(len + 1), b.index(len + 1)) };
+        let (a, b) = (a.min(b), a.max(b));
+        let r = n.original_range(a..b);
+        prop_assert!(r.start <= r.end && r.end <= s.chars().count());
+        prop_assert!(n.original_range(a..a).start <= n.original_range(b..b).start);
+        prop_assert!(n.original_range(a..a).start <= r.start);
+        // the original characters produce at least the normalised bytes
+        prop_assert!(fold(&chars(&s, r)).contains(&n.text[a..b].to_lowercase()));
+    }
+
+    #[test]
+    fn any_substring_is_found(s in text(), a in any::<prop::sample::Index>(), b in any::<prop::sample::Index>()) {
+        let count = s.chars().count();
+        let (a, b) = (a.index(count + 1), b.index(count + 1));
+        let sub = chars(&s, a.min(b)..a.max(b));
+        let r = RegexBuilder::new(&normalize_pattern(&regex::escape(&sub))).case_insensitive(true).build().unwrap();
+        let n = Normalized::new(&s);
+        let found: Vec<Range<usize>> = r.find_iter(&n.text).map(|m| n.original_range(m.range())).collect();
+        prop_assert!(!found.is_empty());
+        for f in found {
+            prop_assert!(fold(&chars(&s, f)).contains(&fold(&sub)));
+        }
+    }
+
+    #[test]
+    fn matches_are_located_in_their_segment(
+        segments in prop::collection::vec(text(), 0..8),
+        matches in prop::collection::vec((0usize..400, 0usize..20), 0..8),
+    ) {
+        let t = transcript(segments);
+        let count = t.data.chars().count();
+        let matches: Vec<Range<usize>> = matches.into_iter().map(|(start, len)| start..start + len).collect();
+        let located = EpisodeOffsetMatch::locate(&matches, &t.timestamps, &t.data);
+
+        let inside: Vec<&Range<usize>> = matches.iter().filter(|m| m.start < count).collect();
+        prop_assert_eq!(located.len(), inside.len());
+        for (m, l) in inside.into_iter().zip(&located) {
+            let segment = &t.timestamps[l.time.from.as_secs() as usize];
+            prop_assert!(segment.offsets.0 <= m.start && m.start < segment.offsets.1);
+            let h = l.highlight.clone().unwrap();
+            prop_assert_eq!(&l.hint[h], chars(&t.data, m.start..m.end.min(count)));
+        }
+    }
+
+    #[test]
+    fn accents_are_ignored_by_the_search(s in "[a-zàèéìòù ]{1,40}") {
+        let n = Normalized::new(&s);
+        let pattern = normalize_pattern(&regex::escape(&s.to_uppercase()));
+        let m = RegexBuilder::new(&pattern).case_insensitive(true).build().unwrap().find(&n.text).unwrap();
+        prop_assert_eq!(n.original_range(m.range()), 0..s.chars().count());
+    }
+}
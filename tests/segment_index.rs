@@ -0,0 +1,150 @@
+use std::{ops::Range, sync::Arc};
+
+use power_pizza_bot::bot::SearchError;
+use power_pizza_bot::db::{segments::{IndexedStore, SegmentIndex}, MemoryDatabase, Store};
+use power_pizza_bot::spreaker::Episode;
+use power_pizza_bot::transcript::EpisodeTranscript;
+
+mod common;
+use common::{episode, transcript};
+
+/// Segments longer than the distance between two checkpoints of the index, with accents and characters of several
+/// bytes that transliterate to several letters.
+const ACCENTED: [&str; 6] = [
+    "Perché la città è così bella? Perché sì, e la pizza margherita è la più buona di tutte quante",
+    "il Pokémon più forte è Mewtwo, in 日本 ne hanno fatti tantissimi — più di mille ormai",
+    "l'ananas sulla pizza? perché no, dice il pizzaiolo di Città di Castello 🍕🍕 con la pizza",
+    "Ærith e Çiçek parlano di caffè, tè e perché no di una pizza margherita",
+    "fine",
+    "la pizza la pizza la pizza",
+];
+
+/// A `MemoryDatabase` with three episodes and their transcripts, and an `IndexedStore` over it whose index is built.
+async fn stores() -> (Arc<MemoryDatabase>, IndexedStore, Arc<SegmentIndex>) {
+    let inner = Arc::new(MemoryDatabase::new());
+    let db: &dyn Store = inner.as_ref();
+    db.insert_stateless(&[episode(1, "Prima"), episode(2, "Seconda"), episode(3, "Terza")]).await.unwrap();
+    db.update_one_stateless(1, &transcript(1, &ACCENTED)).await.unwrap();
+    db.update_one_stateless(2, &transcript(2, &["giochiamo alla console", "la pizza la mangiamo dopo", "niente ananas"])).await.unwrap();
+    db.update_one_stateless(3, &transcript(3, &["la pizza margherita e basta", "pizzaiolo"])).await.unwrap();
+    let index = Arc::new(SegmentIndex::new());
+    let store = IndexedStore::new(inner.clone(), index.clone());
+    assert_eq!(index.refresh(inner.as_ref()).await.unwrap(), 3);
+    (inner, store, index)
+}
+
+type Match = (u64, u64, String, Option<Range<usize>>);
+
+async fn one(db: &dyn Store, id: u32, text: &str) -> Vec<Match> {
+    match db.search_transcript_one(id, text.to_owned()).await {
+        Ok(r) => r.matches.into_iter().map(|m| (m.time.from.as_secs(), m.time.to.as_secs(), m.hint, m.highlight)).collect(),
+        Err(SearchError::NoResults) => vec![],
+        Err(e) => panic!("search of {:?} failed: {:?}", text, e),
+    }
+}
+
+async fn search(db: &dyn Store, text: &str) -> Vec<u32> {
+    let mut ids: Vec<u32> = db.search_transcripts(text).await.unwrap().into_iter().map(|s| s.episode.id).collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn find_matches_the_database_search() {
+    let (inner, store, _) = stores().await;
+    let queries = [
+        "perche", "Perché", "citta", "pokemon", "pizza", "la pizza", "PIZZA MARGHERITA", "zz", "e", "più di", "ri ben",
+        "aerith", "cicek", "caffe, te", "bella\\? perche", "pizza la pizza", "pi.za", "pizz[ae]", "piu|fine", "🍕",
+    ];
+    for q in queries {
+        for id in 1..=3 {
+            let db = one(inner.as_ref(), id, q).await;
+            assert_eq!(one(&store, id, q).await, db, "query {:?} in episode {}", q, id);
+            if id == 1 && q != "🍕" {
+                assert!(!db.is_empty(), "query {:?} in episode {}", q, id);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn highlight_is_the_match_in_the_original_text() {
+    let (_, store, _) = stores().await;
+    let m = one(&store, 1, "citta di castello").await;
+    assert_eq!(m.len(), 1);
+    assert_eq!(&m[0].2[m[0].3.clone().unwrap()], "Città di Castello");
+    assert_eq!((m[0].0, m[0].1), (2, 3));
+    let m = one(&store, 1, "pokemon piu").await;
+    assert_eq!(&m[0].2[m[0].3.clone().unwrap()], "Pokémon più");
+}
+
+#[tokio::test]
+async fn search_has_the_semantics_of_the_database() {
+    let (inner, store, index) = stores().await;
+    let queries = [
+        "pizza", "console", "pizza console", "\"la pizza\"", "\"pizza margherita\"", "\"la pizza\" \"pizza margherita\"",
+        "pizza -ananas", "pizza -console -fine", "\"pizza margherita\" -ananas", "-pizza", "perché", "sushi",
+    ];
+    for q in queries {
+        assert!(index.search(q).is_some());
+        assert_eq!(search(&store, q).await, search(inner.as_ref(), q).await, "query {:?}", q);
+    }
+    // episode 1 says it the most
+    assert_eq!(store.search_transcripts("pizza").await.unwrap()[0].episode.id, 1);
+}
+
+#[tokio::test]
+async fn searches_go_to_the_store_until_the_first_refresh() {
+    let inner = Arc::new(MemoryDatabase::new());
+    let index = Arc::new(SegmentIndex::new());
+    let store = IndexedStore::new(inner.clone(), index.clone());
+    let db: &dyn Store = &store;
+    db.insert_stateless(&[episode(1, "Prima")]).await.unwrap();
+    db.update_one_stateless(1, &transcript(1, &["la pizza"])).await.unwrap();
+
+    assert!(index.search("pizza").is_none());
+    assert_eq!(search(db, "pizza").await, vec![1]);
+    // the transcripts written through the store are indexed right away
+    assert_eq!(index.len(), 1);
+    assert_eq!(index.refresh(inner.as_ref()).await.unwrap(), 0);
+    assert_eq!(index.search("pizza").unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn refresh_follows_the_writes_of_other_processes() {
+    let (inner, store, index) = stores().await;
+    let db: &dyn Store = inner.as_ref();
+    assert_eq!(index.refresh(db).await.unwrap(), 0);
+
+    // another process rewrites a transcript, bumping its revision
+    db.update_one_stateless(2, &transcript(2, &["parliamo di zelda"])).await.unwrap();
+    assert!(search(&store, "zelda").await.is_empty());
+    assert_eq!(index.refresh(db).await.unwrap(), 1);
+    assert_eq!(search(&store, "zelda").await, vec![2]);
+    assert_eq!(search(&store, "console").await, Vec::<u32>::new());
+    assert_eq!(one(&store, 2, "zelda").await.len(), 1);
+
+    // and deletes one
+    db.delete::<EpisodeTranscript>(3).await.unwrap();
+    assert_eq!(index.refresh(db).await.unwrap(), 1);
+    assert_eq!(index.len(), 2);
+    assert_eq!(search(&store, "pizzaiolo").await, vec![1]);
+
+    db.delete::<Episode>(1).await.unwrap();
+    db.insert_stateless(&[episode(4, "Quarta")]).await.unwrap();
+    db.update_one_stateless(4, &transcript(4, &["zelda e link"])).await.unwrap();
+    assert_eq!(index.refresh(db).await.unwrap(), 1);
+    // episodes that no longer exist aren't returned
+    assert_eq!(search(&store, "zelda").await, vec![2, 4]);
+    assert!(search(&store, "pizzaiolo").await.is_empty());
+}
+
+#[tokio::test]
+async fn snippets_are_the_segments_saying_the_most_words() {
+    let (_, store, _) = stores().await;
+    let s = store.transcript_snippets(1, "pizza margherita", 3).await.unwrap();
+    let s: Vec<(u64, &str)> = s.iter().map(|m| (m.time.from.as_secs(), m.hint.as_str())).collect();
+    // segments 2 and 3 say two words each, the first one wins
+    assert_eq!(s, vec![(5, ACCENTED[5]), (0, ACCENTED[0]), (2, ACCENTED[2])]);
+    assert!(store.transcript_snippets(1, "sushi", 3).await.unwrap().is_empty());
+}
@@ -0,0 +1,161 @@
+#![cfg(feature = "sqlite")]
+use std::path::PathBuf;
+
+use chrono::{Duration, Utc};
+
+use power_pizza_bot::db::{PPPData, SqliteDatabase, Store, StoreError};
+use power_pizza_bot::spreaker::Episode;
+use power_pizza_bot::transcript::EpisodeTranscript;
+
+mod common;
+use common::{episode, transcript};
+
+/// Database file removed when the test ends.
+struct TempDb(PathBuf);
+
+impl TempDb {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ppp-sqlite-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Self(path)
+    }
+
+    fn open(&self) -> SqliteDatabase {
+        SqliteDatabase::open(&self.0).unwrap()
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Three episodes, the first two with a transcript.
+async fn store(db: &dyn Store) {
+    db.insert_stateless(&[episode(1, "Pizza all'ananas"), episode(2, "Videogiochi e console"), episode(3, "Speciale Natale")]).await.unwrap();
+    db.update_one_stateless(1, &transcript(1, &["oggi parliamo di pizza", "la pizza con l'ananas è buonissima", "fine"])).await.unwrap();
+    db.update_one_stateless(2, &transcript(2, &["giochiamo alla console", "la pizza la mangiamo dopo"])).await.unwrap();
+}
+
+async fn search(db: &dyn Store, text: &str) -> Vec<u32> {
+    db.search_transcripts(text).await.unwrap().iter().map(|s| s.episode.id).collect()
+}
+
+#[tokio::test]
+async fn insert_replace_and_delete() {
+    let file = TempDb::new("crud");
+    {
+        let db = file.open();
+        let db: &dyn Store = &db;
+        store(db).await;
+
+        assert!(matches!(db.insert_stateless(&[episode(1, "Doppione")]).await, Err(StoreError::DuplicateKey(_))));
+        let mut ids = db.get_ids::<Episode>().await.unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        db.update_one_stateless(3, &episode(3, "Speciale Capodanno")).await.unwrap();
+        assert_eq!(db.get::<Episode>(3).await.unwrap().unwrap().title, "Speciale Capodanno");
+        db.delete::<Episode>(3).await.unwrap();
+        assert!(db.get::<Episode>(3).await.unwrap().is_none());
+    }
+    // the data survives reopening the file
+    let db = file.open();
+    let db: &dyn Store = &db;
+    assert_eq!(db.get_all::<Episode>().await.unwrap().len(), 2);
+    assert_eq!(db.get::<EpisodeTranscript>(2).await.unwrap().unwrap().segments().len(), 2);
+}
+
+#[tokio::test]
+async fn get_and_replace_many() {
+    let file = TempDb::new("many");
+    let db = file.open();
+    let db: &dyn Store = &db;
+    store(db).await;
+
+    let mut found: Vec<u32> = db.get_many::<Episode>(vec![1, 3, 4]).await.unwrap().iter().map(|e| e.id).collect();
+    found.sort();
+    assert_eq!(found, vec![1, 3]);
+
+    db.update_many_stateless(&[episode(3, "Speciale Capodanno"), episode(4, "Nuova")]).await.unwrap();
+    assert_eq!(db.get::<Episode>(3).await.unwrap().unwrap().title, "Speciale Capodanno");
+    assert_eq!(db.get_ids::<Episode>().await.unwrap().len(), 4);
+    // transcripts written together are indexed
+    db.update_many_stateless(&[transcript(1, &["parliamo di zelda"]), transcript(3, &["zelda a natale"])]).await.unwrap();
+    assert_eq!(search(db, "zelda").await.len(), 2);
+    assert!(search(db, "ananas").await.is_empty());
+}
+
+#[tokio::test]
+async fn replacing_a_transcript_reindexes_it() {
+    let file = TempDb::new("reindex");
+    let db = file.open();
+    let db: &dyn Store = &db;
+    store(db).await;
+
+    assert_eq!(search(db, "console").await, vec![2]);
+    db.update_one_stateless(2, &transcript(2, &["parliamo di zelda"])).await.unwrap();
+    assert!(search(db, "console").await.is_empty());
+    assert_eq!(search(db, "zelda").await, vec![2]);
+
+    db.drop_collection(EpisodeTranscript::COLLECTION).await.unwrap();
+    assert!(search(db, "zelda").await.is_empty());
+}
+
+#[tokio::test]
+async fn search_transcripts_with_phrases_and_exclusions() {
+    let file = TempDb::new("search");
+    let db = file.open();
+    let db: &dyn Store = &db;
+    store(db).await;
+
+    // episode 1 says pizza twice, it ranks first
+    assert_eq!(search(db, "pizza").await, vec![1, 2]);
+    // stems match other forms of the word, accents are ignored
+    assert_eq!(search(db, "giocare").await, vec![2]);
+    assert_eq!(search(db, "buonissìma").await, vec![1]);
+    assert_eq!(search(db, "\"la pizza la\"").await, vec![2]);
+    assert_eq!(search(db, "pizza -ananas").await, vec![2]);
+    assert_eq!(search(db, "pizza -\"mangiamo dopo\"").await, vec![1]);
+    assert!(search(db, "-pizza").await.is_empty());
+    assert!(search(db, "sushi").await.is_empty());
+}
+
+#[tokio::test]
+async fn snippets_highlight_the_match() {
+    let file = TempDb::new("snippets");
+    let db = file.open();
+    let db: &dyn Store = &db;
+    store(db).await;
+
+    let s = db.transcript_snippets(1, "ananas", 5).await.unwrap();
+    assert_eq!(s.len(), 1);
+    assert_eq!(s[0].hint, "la pizza con l'ananas è buonissima");
+    assert_eq!(&s[0].hint[s[0].highlight.clone().unwrap()], "ananas");
+    assert_eq!((s[0].time.from.as_secs(), s[0].time.to.as_secs()), (1, 2));
+
+    assert_eq!(db.transcript_snippets(1, "pizza", 1).await.unwrap().len(), 1);
+    assert_eq!(db.transcript_snippets(1, "pizza", 5).await.unwrap().len(), 2);
+    assert!(db.transcript_snippets(3, "pizza", 5).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn locks() {
+    let file = TempDb::new("locks");
+    let db = file.open();
+    let db: &dyn Store = &db;
+    let later = Utc::now() + Duration::minutes(5);
+
+    assert!(db.acquire_lock("import", "a", later).await.unwrap());
+    assert!(db.acquire_lock("import", "a", later).await.unwrap());
+    assert!(!db.acquire_lock("import", "b", later).await.unwrap());
+    assert!(db.acquire_lock("other", "b", later).await.unwrap());
+    // only the owner releases it
+    db.release_lock("import", "b").await.unwrap();
+    assert!(!db.acquire_lock("import", "b", later).await.unwrap());
+    db.release_lock("import", "a").await.unwrap();
+    assert!(db.acquire_lock("import", "b", Utc::now() - Duration::seconds(1)).await.unwrap());
+    // an expired lock can be taken over
+    assert!(db.acquire_lock("import", "a", later).await.unwrap());
+}
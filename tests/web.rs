@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{body::{to_bytes, Body}, http::{Request, StatusCode}, Router};
+use tower::ServiceExt;
+
+use power_pizza_bot::db::{MemoryDatabase, Store};
+use power_pizza_bot::web;
+
+mod common;
+use common::{episode, transcript};
+
+/// 35 episodes of 2020 and one of 2021, the first two with a transcript, the second tagged.
+async fn app() -> Router {
+    let db = MemoryDatabase::new();
+    let s: &dyn Store = &db;
+    let mut episodes: Vec<_> = (1..=35).chain([400]).map(|id| episode(id, &format!("Puntata {}", id))).collect();
+    episodes[1].tags = vec!["Nintendo".to_owned()];
+    s.insert_stateless(&episodes).await.unwrap();
+    s.update_one_stateless(1, &transcript(1, &["oggi parliamo di pizza", "la pizza con l'ananas"])).await.unwrap();
+    s.update_one_stateless(2, &transcript(2, &["giochiamo alla console"])).await.unwrap();
+    web::router(Arc::new(db))
+}
+
+async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+    let res = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+    let status = res.status();
+    let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn episode_list_with_filters_and_pages() {
+    let app = app().await;
+    let (status, page) = get(&app, "/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("36 episodi"));
+    assert!(page.contains("Pagina 1 di 2"));
+    // most recent first
+    assert!(page.find("/episodio/400").unwrap() < page.find("/episodio/35\"").unwrap());
+    assert!(!page.contains("/episodio/1\""));
+
+    // past the last page
+    let (_, page) = get(&app, "/?page=9").await;
+    assert!(page.contains("Pagina 2 di 2"));
+    assert!(page.contains("/episodio/1\""));
+
+    let (_, page) = get(&app, "/?tag=nintendo").await;
+    assert!(page.contains("1 episodi"));
+    assert!(page.contains("/episodio/2\""));
+    let (_, page) = get(&app, "/?year=2021").await;
+    assert!(page.contains("1 episodi"));
+    assert!(page.contains("/episodio/400\""));
+    // the select left on all the years
+    assert!(get(&app, "/?year=").await.1.contains("36 episodi"));
+    assert!(get(&app, "/?q=sushi").await.1.contains("0 episodi"));
+}
+
+#[tokio::test]
+async fn episode_page_with_its_transcript() {
+    let app = app().await;
+    let (status, page) = get(&app, "/episodio/1?q=pizza").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("<h1>Puntata 1</h1>"));
+    assert!(page.contains("<mark>pizza</mark>"));
+    assert!(page.contains("l'ananas"));
+
+    let (status, page) = get(&app, "/episodio/3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("non è ancora disponibile"));
+
+    let (status, page) = get(&app, "/episodio/999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(page.contains("Pagina non trovata"));
+}
+
+#[tokio::test]
+async fn search_transcripts() {
+    let app = app().await;
+    let (status, page) = get(&app, "/cerca").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!page.contains("episodi</p>"));
+    assert!(get(&app, "/cerca?q=pi").await.1.contains("almeno 3 caratteri"));
+    let (status, page) = get(&app, "/cerca?q=sushi").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("0 episodi"));
+
+    let (_, page) = get(&app, "/cerca?q=pizza").await;
+    assert!(page.contains("1 episodi"));
+    assert!(page.contains("/episodio/1?q=pizza"));
+    assert!(page.contains("<mark>pizza</mark>"));
+    assert!(!page.contains("/episodio/2?"));
+}